@@ -0,0 +1,185 @@
+//! Compare the decoding speed of the samples of `tests/data/frameworks`
+//! with owned strings and with shared strings and kind ids.
+//!
+//! Run with `cargo bench --bench frameworks`.
+
+#![feature(test)]
+
+extern crate binjs;
+extern crate glob;
+extern crate test;
+
+use binjs::generic::io::decode::Decoder;
+use binjs::generic::FromJSON;
+use binjs::io::*;
+use binjs::io::multipart::{ TreeTokenReader, TreeTokenWriter, WriteOptions };
+use binjs::meta::spec::{ Spec, SpecBuilder, SpecOptions };
+use binjs::source::*;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{ Deserializer, Serializer };
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use std::io::Cursor;
+use std::rc::Rc;
+
+use test::Bencher;
+
+/// A `TokenReader` that only implements the methods returning owned strings,
+/// so that strings and kinds are copied out of the tables of the underlying
+/// reader, and kinds have no id.
+struct OwnedReader<R> where R: TokenReader {
+    reader: R,
+}
+impl<R> TokenReader for OwnedReader<R> where R: TokenReader {
+    type Error = R::Error;
+    type ListGuard = R::ListGuard;
+    type TaggedGuard = R::TaggedGuard;
+    type UntaggedGuard = R::UntaggedGuard;
+
+    fn poison(&mut self) {
+        self.reader.poison()
+    }
+
+    fn string(&mut self) -> Result<Option<String>, Self::Error> {
+        self.reader.string()
+    }
+
+    fn categorized_string(&mut self, category: StringCategory) -> Result<Option<String>, Self::Error> {
+        self.reader.categorized_string(category)
+    }
+
+    fn string_enum(&mut self, strings: &[&str]) -> Result<usize, Self::Error> {
+        self.reader.string_enum(strings)
+    }
+
+    fn float(&mut self) -> Result<Option<f64>, Self::Error> {
+        self.reader.float()
+    }
+
+    fn bool(&mut self) -> Result<Option<bool>, Self::Error> {
+        self.reader.bool()
+    }
+
+    fn offset(&mut self) -> Result<u32, Self::Error> {
+        self.reader.offset()
+    }
+
+    fn list(&mut self) -> Result<(u32, Self::ListGuard), Self::Error> {
+        self.reader.list()
+    }
+
+    fn tagged_tuple(&mut self) -> Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error> {
+        self.reader.tagged_tuple()
+    }
+
+    fn untagged_tuple(&mut self) -> Result<Self::UntaggedGuard, Self::Error> {
+        self.reader.untagged_tuple()
+    }
+}
+
+/// Encode each sample of `tests/data/frameworks`.
+fn encode_frameworks() -> Vec<Box<[u8]>> {
+    let path = format!("{}/tests/data/frameworks/*.js", env!("CARGO_MANIFEST_DIR"));
+    let parser = Shift::new();
+    let mut encoded = vec![];
+    for entry in glob::glob(&path)
+        .expect("Invalid glob pattern")
+    {
+        let entry = entry.expect("Invalid entry");
+        let json = parser.parse_file(entry)
+            .expect("Could not parse source");
+        let mut ast = Script::import(&json)
+            .expect("Could not import AST");
+        AnnotationVisitor::new()
+            .annotate_script(&mut ast);
+
+        let mut serializer = Serializer::new(TreeTokenWriter::new(WriteOptions::default()));
+        serializer.serialize(&ast)
+            .expect("Could not encode AST");
+        let (data, _) = serializer.done()
+            .expect("Could not finalize AST encoding");
+        encoded.push(data);
+    }
+    encoded
+}
+
+fn es6_spec() -> Spec {
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    })
+}
+
+fn total_bytes(encoded: &[Box<[u8]>]) -> u64 {
+    encoded.iter()
+        .map(|data| data.len() as u64)
+        .sum()
+}
+
+#[bench]
+fn bench_deserialize_frameworks_owned(bencher: &mut Bencher) {
+    let encoded = encode_frameworks();
+    bencher.bytes = total_bytes(&encoded);
+    bencher.iter(|| {
+        for data in &encoded {
+            let reader = TreeTokenReader::new(Cursor::new(data))
+                .expect("Could not decode AST container");
+            let mut deserializer = Deserializer::new(OwnedReader { reader });
+            let decoded : Script = deserializer.deserialize()
+                .expect("Could not decode");
+            test::black_box(decoded);
+        }
+    });
+}
+
+#[bench]
+fn bench_deserialize_frameworks_shared(bencher: &mut Bencher) {
+    let encoded = encode_frameworks();
+    bencher.bytes = total_bytes(&encoded);
+    bencher.iter(|| {
+        for data in &encoded {
+            let reader = TreeTokenReader::new(Cursor::new(data))
+                .expect("Could not decode AST container");
+            let mut deserializer = Deserializer::new(reader);
+            let decoded : Script = deserializer.deserialize()
+                .expect("Could not decode");
+            test::black_box(decoded);
+        }
+    });
+}
+
+#[bench]
+fn bench_decode_frameworks_owned(bencher: &mut Bencher) {
+    let encoded = encode_frameworks();
+    let spec = es6_spec();
+    bencher.bytes = total_bytes(&encoded);
+    bencher.iter(|| {
+        for data in &encoded {
+            let reader = TreeTokenReader::new(Cursor::new(data))
+                .expect("Could not decode AST container");
+            let decoded = Decoder::new(&spec, OwnedReader { reader })
+                .decode()
+                .expect("Could not decode");
+            test::black_box(decoded);
+        }
+    });
+}
+
+#[bench]
+fn bench_decode_frameworks_shared(bencher: &mut Bencher) {
+    let encoded = encode_frameworks();
+    let spec = es6_spec();
+    bencher.bytes = total_bytes(&encoded);
+    bencher.iter(|| {
+        for data in &encoded {
+            let reader = TreeTokenReader::new(Cursor::new(data))
+                .expect("Could not decode AST container");
+            let decoded = Decoder::new(&spec, reader)
+                .decode()
+                .expect("Could not decode");
+            test::black_box(decoded);
+        }
+    });
+}
@@ -0,0 +1,119 @@
+//! Compare the decoding speed of the representations of integers.
+//!
+//! Run with `cargo bench --bench integers`.
+
+#![feature(test)]
+
+extern crate binjs;
+extern crate test;
+
+use binjs::generic::FromJSON;
+use binjs::io::*;
+use binjs::io::bytes::varnum::{ IntegerCodec, IntegerEncoding };
+use binjs::io::multipart::{ TreeTokenReader, TreeTokenWriter, WriteOptions };
+use binjs::source::*;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{ Deserializer, Serializer };
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use std::io::Cursor;
+
+use test::Bencher;
+
+/// The number of integers decoded by each iteration.
+const SAMPLE_LEN: usize = 10_000;
+
+/// A sample of integers with the distribution of a tree: mostly small entries in
+/// the tables and short lists, some larger entries, a few offsets.
+fn sample() -> Vec<u64> {
+    (0..SAMPLE_LEN as u64)
+        .map(|i| {
+            let hash = i.wrapping_mul(2_654_435_761);
+            match i % 100 {
+                0 => hash % (1 << 28),
+                1...10 => hash % (1 << 14),
+                _ => hash % (1 << 7),
+            }
+        })
+        .collect()
+}
+
+fn bench_decode_sample(bencher: &mut Bencher, encoding: IntegerEncoding) {
+    let mut data = vec![];
+    for value in sample() {
+        encoding.write_u64(&mut data, value)
+            .expect("Could not write integer");
+    }
+    bencher.bytes = data.len() as u64;
+    bencher.iter(|| {
+        let mut reader = Cursor::new(&data);
+        let mut sum : u64 = 0;
+        for _ in 0..SAMPLE_LEN {
+            sum = sum.wrapping_add(encoding.read_u64(&mut reader)
+                .expect("Could not read integer"));
+        }
+        sum
+    });
+}
+
+#[bench]
+fn bench_decode_sample_varnum(bencher: &mut Bencher) {
+    bench_decode_sample(bencher, IntegerEncoding::VarNum)
+}
+
+#[bench]
+fn bench_decode_sample_leb128(bencher: &mut Bencher) {
+    bench_decode_sample(bencher, IntegerEncoding::Leb128)
+}
+
+#[bench]
+fn bench_decode_sample_prefix_varint(bencher: &mut Bencher) {
+    bench_decode_sample(bencher, IntegerEncoding::PrefixVarint)
+}
+
+/// Decode a framework from `tests/data/frameworks`, encoded with `encoding`.
+fn bench_decode_framework(bencher: &mut Bencher, encoding: IntegerEncoding) {
+    let path = format!("{}/tests/data/frameworks/moment.min.js", env!("CARGO_MANIFEST_DIR"));
+    let json = Shift::new()
+        .parse_file(path)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let writer = TreeTokenWriter::new(WriteOptions {
+        integers: encoding,
+        .. WriteOptions::default()
+    });
+    let mut serializer = Serializer::new(writer);
+    serializer.serialize(&ast)
+        .expect("Could not encode AST");
+    let (data, _) = serializer.done()
+        .expect("Could not finalize AST encoding");
+
+    bencher.bytes = data.len() as u64;
+    bencher.iter(|| {
+        let reader = TreeTokenReader::new(Cursor::new(&data))
+            .expect("Could not decode AST container");
+        let mut deserializer = Deserializer::new(reader);
+        let decoded : Script = deserializer.deserialize()
+            .expect("Could not decode");
+        decoded
+    });
+}
+
+#[bench]
+fn bench_decode_framework_varnum(bencher: &mut Bencher) {
+    bench_decode_framework(bencher, IntegerEncoding::VarNum)
+}
+
+#[bench]
+fn bench_decode_framework_leb128(bencher: &mut Bencher) {
+    bench_decode_framework(bencher, IntegerEncoding::Leb128)
+}
+
+#[bench]
+fn bench_decode_framework_prefix_varint(bencher: &mut Bencher) {
+    bench_decode_framework(bencher, IntegerEncoding::PrefixVarint)
+}
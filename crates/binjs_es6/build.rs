@@ -42,7 +42,8 @@ fn main() {
         });
 
     // Generate source code.
-    let exporter = RustExporter::new(spec);
+    let exporter = RustExporter::new(spec)
+        .with_manual_deserialization(&["BinaryExpression"]);
     let code = exporter.to_rust_source();
 
     // Export strongly-typed source.
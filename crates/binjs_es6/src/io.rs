@@ -1,19 +1,94 @@
-use binjs_io::{ Deserialization, Guard, TokenReader, TokenReaderError };
-pub use binjs_io::{ Serialization, TokenSerializer, TokenWriter };
+use ast::{ ASTNode, BinaryExpression, BinaryOperator, Expression };
+use binjs_io::{ CategorizedDeserialization, DEFAULT_MAX_DECODE_DEPTH, Deserialization, Guard, InnerDeserialization, StringCategory, TokenReader, TokenReaderError, TupleKind };
+pub use binjs_io::{ CategorizedSerialization, Serialization, TokenSerializer, TokenWriter };
 use binjs_shared::Offset;
 
+use std;
+use std::collections::HashMap;
+
+/// The maximal number of items allocated ahead of time when reading a list.
+/// Longer lists are grown as items are actually read.
+const MAX_PREALLOCATED_ITEMS: usize = 1024;
+
 
 
 /// A structure used for deserialization purposes.
 pub struct Deserializer<R> where R: TokenReader {
-    pub reader: R
+    pub reader: R,
+
+    /// The number of nodes currently being read.
+    depth: usize,
+
+    /// The maximal number of nested nodes.
+    max_depth: usize,
+
+    /// The interfaces of the kinds read so far, by kind id.
+    kinds: HashMap<u32, Option<ASTNode>>,
 }
 impl<R> Deserializer<R> where R: TokenReader {
     pub fn new(reader: R) -> Self {
+        Self::with_max_depth(reader, DEFAULT_MAX_DECODE_DEPTH)
+    }
+
+    /// Create a deserializer that fails with `TokenReaderError::TooDeep`
+    /// instead of reading more than `max_depth` nested nodes.
+    pub fn with_max_depth(reader: R, max_depth: usize) -> Self {
         Self {
-            reader
+            reader,
+            depth: 0,
+            max_depth,
+            kinds: HashMap::new(),
         }
     }
+
+    /// Start reading a tagged tuple, and return the interface of its kind,
+    /// or `None` if its kind is not an interface, e.g. null.
+    ///
+    /// If the reader provides kind ids, each kind is looked up only once.
+    pub fn tagged_tuple_node(&mut self) -> Result<(Option<ASTNode>, TupleKind, R::TaggedGuard), R::Error> {
+        let (kind, guard) = self.reader.tagged_tuple_kind()?;
+        let cached = match kind.id {
+            Some(id) => self.kinds.get(&id).cloned(),
+            None => None
+        };
+        let node = match cached {
+            Some(node) => node,
+            None => {
+                let node = ASTNode::from_name(&kind.name);
+                if let Some(id) = kind.id {
+                    self.kinds.insert(id, node);
+                }
+                node
+            }
+        };
+        Ok((node, kind, guard))
+    }
+
+    /// Start reading a node.
+    ///
+    /// Fails, poisoning the reader, if this node is nested too deeply.
+    pub fn enter(&mut self) -> Result<(), R::Error> {
+        if self.depth >= self.max_depth {
+            let max = self.max_depth;
+            return self.fail(TokenReaderError::TooDeep { max })
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Stop reading a node.
+    pub fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Fail with an error detected by the deserializer rather than by the reader.
+    ///
+    /// The reader is poisoned, so that the guards of the enclosing
+    /// nodes may be dropped without being consumed.
+    fn fail<T>(&mut self, err: TokenReaderError) -> Result<T, R::Error> {
+        self.reader.poison();
+        Err(From::from(err))
+    }
 }
 
 
@@ -26,7 +101,7 @@ impl<R> Deserialization<R, bool> for Deserializer<R> where R: TokenReader {
     fn deserialize(&mut self) -> Result<bool, R::Error> {
         let maybe = self.reader.bool()?;
         match maybe {
-            None => Err(From::from(TokenReaderError::EmptyBool)),
+            None => self.fail(TokenReaderError::EmptyBool),
             Some(x) => Ok(x)
         }
     }
@@ -40,7 +115,7 @@ impl<R> Deserialization<R, f64> for Deserializer<R> where R: TokenReader {
     fn deserialize(&mut self) -> Result<f64, R::Error> {
         let maybe = self.reader.float()?;
         match maybe {
-            None => Err(From::from(TokenReaderError::EmptyBool)),
+            None => self.fail(TokenReaderError::EmptyBool),
             Some(x) => Ok(x)
         }
     }
@@ -59,23 +134,147 @@ impl<R> Deserialization<R, String> for Deserializer<R> where R: TokenReader {
     fn deserialize(&mut self) -> Result<String, R::Error> {
         let maybe = self.reader.string()?;
         match maybe {
-            None => Err(From::from(TokenReaderError::EmptyString)),
+            None => self.fail(TokenReaderError::EmptyString),
+            Some(x) => Ok(x)
+        }
+    }
+}
+impl<R> CategorizedDeserialization<R, Option<String>> for Deserializer<R> where R: TokenReader {
+    fn deserialize_categorized(&mut self, category: StringCategory) -> Result<Option<String>, R::Error> {
+        self.reader.categorized_string(category)
+    }
+}
+impl<R> CategorizedDeserialization<R, String> for Deserializer<R> where R: TokenReader {
+    fn deserialize_categorized(&mut self, category: StringCategory) -> Result<String, R::Error> {
+        let maybe = self.reader.categorized_string(category)?;
+        match maybe {
+            None => self.fail(TokenReaderError::EmptyString),
             Some(x) => Ok(x)
         }
     }
 }
+impl<R> CategorizedDeserialization<R, Vec<String>> for Deserializer<R> where R: TokenReader {
+    fn deserialize_categorized(&mut self, category: StringCategory) -> Result<Vec<String>, R::Error> {
+        let (len, guard) = self.reader.list()?;
+        // Don't trust `len` blindly before allocating.
+        let mut result = Vec::with_capacity(std::cmp::min(len as usize, MAX_PREALLOCATED_ITEMS));
+        for _ in 0..len {
+            match self.deserialize_categorized(category) {
+                Ok(item) => result.push(item),
+                Err(err) => {
+                    self.reader.poison();
+                    return Err(err)
+                }
+            }
+        }
+        guard.done()?;
+        Ok(result)
+    }
+}
 impl<R, T> Deserialization<R, Vec<T>> for Deserializer<R> where R: TokenReader, Self: Deserialization<R, T> {
     fn deserialize(&mut self) -> Result<Vec<T>, R::Error> {
         let (len, guard) = self.reader.list()?;
-        let mut result = Vec::with_capacity(len as usize);
+        // Don't trust `len` blindly before allocating.
+        let mut result = Vec::with_capacity(std::cmp::min(len as usize, MAX_PREALLOCATED_ITEMS));
         for _ in 0..len {
-            result.push(self.deserialize()?);
+            match self.deserialize() {
+                Ok(item) => result.push(item),
+                Err(err) => {
+                    self.reader.poison();
+                    return Err(err)
+                }
+            }
         }
         guard.done()?;
         Ok(result)
     }
 }
 
+/// `true` if `fields`, as returned by `TokenReader::tagged_tuple`, is the order of
+/// the fields of `BinaryExpression` in the grammar.
+fn in_grammar_order(fields: Option<&[String]>) -> bool {
+    match fields {
+        None => true,
+        Some(fields) => fields == &BinaryExpression::FIELDS[..]
+    }
+}
+
+/// Minifiers produce very long chains of binary operators, e.g. `a + b + c + ...`,
+/// which are parsed as left-deep trees. To avoid overflowing the stack, we walk
+/// the left operands iteratively. Such nodes are not counted against the maximal
+/// depth of the deserializer.
+///
+/// Nodes whose fields were reordered by the writer are read recursively.
+impl<R> InnerDeserialization<R, BinaryExpression> for Deserializer<R> where R: TokenReader {
+    fn deserialize_inner(&mut self, fields: Option<&[String]>) -> Result<BinaryExpression, R::Error> {
+        if let Some(fields) = fields {
+            if !in_grammar_order(Some(fields)) {
+                return self.deserialize_reordered_binary_expression(fields)
+            }
+        }
+
+        // The operators and guards of the `BinaryExpression`s along the left spine,
+        // outermost first. The guard of the outermost node is owned by the caller.
+        let mut operators : Vec<BinaryOperator> = vec![self.deserialize()?];
+        let mut guards = vec![];
+
+        let mut left = loop {
+            let (node, kind, guard) = self.tagged_tuple_node()?;
+            if node == Some(ASTNode::BinaryExpression) && in_grammar_order(kind.fields.as_ref().map(|fields| &fields[..])) {
+                guards.push(guard);
+                operators.push(self.deserialize()?);
+                continue;
+            }
+            self.enter()?;
+            let result = self.deserialize_expression_of_kind(node, kind.fields.as_ref().map(|fields| &fields[..]));
+            self.exit();
+            if result.is_err() {
+                self.reader.poison();
+            }
+            guard.done()?;
+            break result?;
+        };
+
+        // Now read the right operands, innermost first.
+        while let Some(guard) = guards.pop() {
+            let operator = operators.pop()
+                .expect("Each nested BinaryExpression has an operator");
+            let right : Expression = self.deserialize()?;
+            guard.done()?;
+            left = Expression::BinaryExpression(Box::new(BinaryExpression {
+                operator,
+                left,
+                right,
+            }));
+        }
+
+        let operator = operators.pop()
+            .expect("The outermost BinaryExpression has an operator");
+        let right : Expression = self.deserialize()?;
+        Ok(BinaryExpression {
+            operator,
+            left,
+            right,
+        })
+    }
+}
+
+/// Drop an expression, without recursing along chains of binary operators.
+///
+/// Dropping an `Expression` normally recurses once per nested node. As the
+/// `Deserializer` accepts arbitrarily long chains of binary operators, dropping
+/// the resulting tree that way may overflow the stack.
+pub fn drop_expression(expression: Expression) {
+    let mut stack = vec![expression];
+    while let Some(expression) = stack.pop() {
+        if let Expression::BinaryExpression(binary) = expression {
+            let binary = *binary;
+            stack.push(binary.left);
+            stack.push(binary.right);
+        }
+    }
+}
+
 
 
 /// A structure used for deserialization purposes.
@@ -164,6 +363,28 @@ impl<'a, W> Serialization<W, &'a String> for Serializer<W> where W: TokenWriter
          self.writer.string(Some(&*value))
    }
 }
+impl<'a, W> CategorizedSerialization<W, &'a Option<String>> for Serializer<W> where W: TokenWriter {
+    fn serialize_categorized(&mut self, category: StringCategory, value: &'a Option<String>) -> Result<W::Tree, W::Error> {
+        match *value {
+            None => self.writer.categorized_string(category, None),
+            Some(ref str) => self.writer.categorized_string(category, Some(&*str))
+        }
+    }
+}
+impl<'a, W> CategorizedSerialization<W, &'a String> for Serializer<W> where W: TokenWriter {
+    fn serialize_categorized(&mut self, category: StringCategory, value: &'a String) -> Result<W::Tree, W::Error> {
+        self.writer.categorized_string(category, Some(&*value))
+    }
+}
+impl<'a, W> CategorizedSerialization<W, &'a Vec<String>> for Serializer<W> where W: TokenWriter {
+    fn serialize_categorized(&mut self, category: StringCategory, value: &'a Vec<String>) -> Result<W::Tree, W::Error> {
+        let mut children = Vec::with_capacity(value.len());
+        for child in value {
+            children.push(self.writer.categorized_string(category, Some(&*child))?);
+        }
+        self.writer.list(children)
+    }
+}
 impl<'a, W> Serialization<W, &'a Offset> for Serializer<W> where W: TokenWriter {
     fn serialize(&mut self, _: &'a Offset) -> Result<W::Tree, W::Error> {
          self.writer.offset()
@@ -180,4 +401,4 @@ impl<'a, W, T> Serialization<W, &'a Vec<T>> for Serializer<W> where W: TokenWrit
         self.writer.list(children)
     }
 }
-*/
\ No newline at end of file
+*/
@@ -13,6 +13,18 @@ enum BindingKind {
     Implicit,
 }
 
+/// The name of a `ClassExpression`, which is bound only within the class itself.
+#[derive(Debug)]
+struct ClassExpressionName {
+    name: String,
+
+    /// `true` if the name was already free in a nested function before we entered the class.
+    was_free_in_nested_functions: bool,
+
+    /// `true` if the name was already free in the current function before we entered the class.
+    was_free_in_function: bool,
+}
+
 #[derive(Default)]
 pub struct AnnotationVisitor {
     // The following are stacks.
@@ -22,6 +34,7 @@ pub struct AnnotationVisitor {
     binding_kind_stack: Vec<BindingKind>,
     apparent_direct_eval_stack: Vec<bool>,
     free_names_in_function_stack: Vec<HashSet<String>>,
+    class_expression_names_stack: Vec<Option<ClassExpressionName>>,
 
     // Whenever we pop from `free_names_in_function_stack`,
     // we transfer everything here.
@@ -177,6 +190,31 @@ impl AnnotationVisitor {
             None
         }
     }
+
+    fn push_class_expression_name(&mut self, name: Option<&String>) {
+        let entry = name.map(|name| ClassExpressionName {
+            name: name.clone(),
+            was_free_in_nested_functions: self.free_names_in_nested_functions.contains(name),
+            was_free_in_function: self.free_names_in_function_stack.last()
+                .unwrap()
+                .contains(name),
+        });
+        self.class_expression_names_stack.push(entry);
+    }
+    fn pop_class_expression_name(&mut self) {
+        // Uses of the name of a class expression within the class (e.g. in methods)
+        // refer to the class itself, so they are neither free nor captured.
+        if let Some(entry) = self.class_expression_names_stack.pop().unwrap() {
+            if !entry.was_free_in_nested_functions {
+                self.free_names_in_nested_functions.remove(&entry.name);
+            }
+            if !entry.was_free_in_function {
+                self.free_names_in_function_stack.last_mut()
+                    .unwrap()
+                    .remove(&entry.name);
+            }
+        }
+    }
 }
 
 impl Visitor<()> for AnnotationVisitor {
@@ -207,8 +245,10 @@ impl Visitor<()> for AnnotationVisitor {
             | Some(&PathItem { interface: ASTNode::EagerMethod, field: ASTField::Name})
             | Some(&PathItem { interface: ASTNode::EagerGetter, field: ASTField::Name})
             | Some(&PathItem { interface: ASTNode::EagerSetter, field: ASTField::Name})
+            | Some(&PathItem { interface: ASTNode::ClassDeclaration, field: ASTField::Name})
+            | Some(&PathItem { interface: ASTNode::ClassExpression, field: ASTField::Name})
             => {
-                // Function names are special.
+                // Function and class names are special.
                 // They are handled in the respective `exit_*` methods.
                 return Ok(())
             }
@@ -269,6 +309,47 @@ impl Visitor<()> for AnnotationVisitor {
         Ok(())
     }
 
+    // Imports are lexical bindings of the module.
+    fn enter_import(&mut self, _path: &Path, _node: &mut Import) -> Result<(), ()> {
+        self.binding_kind_stack.push(BindingKind::Lex);
+        Ok(())
+    }
+    fn exit_import(&mut self, _path: &Path, _node: &mut Import) -> Result<(), ()> {
+        assert_matches!(self.binding_kind_stack.pop(), Some(BindingKind::Lex));
+        Ok(())
+    }
+
+    fn enter_import_namespace(&mut self, _path: &Path, _node: &mut ImportNamespace) -> Result<(), ()> {
+        self.binding_kind_stack.push(BindingKind::Lex);
+        Ok(())
+    }
+    fn exit_import_namespace(&mut self, _path: &Path, _node: &mut ImportNamespace) -> Result<(), ()> {
+        assert_matches!(self.binding_kind_stack.pop(), Some(BindingKind::Lex));
+        Ok(())
+    }
+
+    // Classes
+    fn exit_class_declaration(&mut self, path: &Path, node: &mut ClassDeclaration) -> Result<(), ()> {
+        // A class declaration is always a lexical declaration, including at toplevel.
+        debug!(target: "annotating", "exit_class_declaration {} at {:?}", node.name.name, path);
+        self.lex_names_stack.last_mut()
+            .unwrap()
+            .insert(node.name.name.clone());
+        Ok(())
+    }
+
+    fn enter_class_expression(&mut self, _path: &Path, node: &mut ClassExpression) -> Result<(), ()> {
+        let name = node.name.as_ref()
+            .map(|name| &name.name);
+        self.push_class_expression_name(name);
+        Ok(())
+    }
+    fn exit_class_expression(&mut self, _path: &Path, _node: &mut ClassExpression) -> Result<(), ()> {
+        // If the class has a name, it's not a free name.
+        self.pop_class_expression_name();
+        Ok(())
+    }
+
     // Try/Catch
     fn enter_catch_clause(&mut self, _path: &Path, _node: &mut CatchClause) -> Result<(), ()> {
         self.binding_kind_stack.push(BindingKind::Implicit);
@@ -436,13 +517,14 @@ impl Visitor<()> for AnnotationVisitor {
         node.body_scope = self.pop_var_scope(path, Some(name));
         // Anything we do from this point affects the scope outside the function.
 
-        // 1. If the declaration is at the toplevel, the name is declared as a `var`.
+        // 1. If the declaration is at the toplevel of a script, the name is declared as a `var`.
         // 2. If the declaration is in a function's toplevel block, the name is declared as a `var`.
-        // 3. Otherwise, the name is declared as a `let`.
+        // 3. Otherwise, including at the toplevel of a module, the name is declared as a `let`.
         let name = name.to_string();
         debug!(target: "annotating", "exit_function_declaration sees {} at {:?}", node.name.name, path.get(0));
         match path.get(0) {
-            None => {
+            None
+            | Some(&PathItem { field: ASTField::Statements, interface: ASTNode::Script }) => {
                 // Case 1.
                 debug!(target: "annotating", "exit_function_declaration says it's a var (case 1)");
                 self.var_names_stack.last_mut()
@@ -470,6 +552,35 @@ impl Visitor<()> for AnnotationVisitor {
 }
 
 
+/// Collect the names introduced by a `Binding`, including through
+/// (possibly nested) object and array patterns.
+///
+/// Names that appear in initializers (e.g. the name of a function
+/// expression used as a default value) are not collected.
+#[derive(Default)]
+struct BindingNamesCollector {
+    names: Vec<String>,
+}
+impl Visitor<()> for BindingNamesCollector {
+    fn exit_binding_identifier(&mut self, path: &Path, node: &mut BindingIdentifier) -> Result<(), ()> {
+        // The walk starts at the root of the binding, so we are part
+        // of the pattern iff every step of the path is a pattern step.
+        for i in 0..path.len() {
+            match path.get(i) {
+                Some(&PathItem { interface: ASTNode::ArrayBinding, .. })
+                | Some(&PathItem { interface: ASTNode::ObjectBinding, .. })
+                | Some(&PathItem { interface: ASTNode::BindingPropertyIdentifier, field: ASTField::Binding })
+                | Some(&PathItem { interface: ASTNode::BindingPropertyProperty, field: ASTField::Binding })
+                | Some(&PathItem { interface: ASTNode::BindingWithInitializer, field: ASTField::Binding })
+                => { /* Still in the pattern */ }
+                _ => return Ok(())
+            }
+        }
+        self.names.push(node.name.clone());
+        Ok(())
+    }
+}
+
 /// Perform a second pass to cleanup incorrect instances of `eval`.
 struct EvalCleanupAnnotator {
     /// `true` if name `eval` was bound at this level or higher in the tree.
@@ -560,12 +671,11 @@ impl Visitor<()> for EvalCleanupAnnotator {
     fn enter_catch_clause(&mut self, _path: &Path, node: &mut CatchClause) -> Result<(), ()> {
         // Don't forget that the implicitly declared variable may mask `eval`.
         let mut has_eval_binding = *self.eval_bindings.last().unwrap();
-        match node.binding {
-            Binding::BindingIdentifier(ref binding) => {
-                has_eval_binding = has_eval_binding || &binding.name == "eval";
-            }
-            _ => unimplemented!() // FIXME: Patterns may also mask `eval`.
-        }
+        let mut collector = BindingNamesCollector::default();
+        node.binding.walk(&mut Path::new(), &mut collector)?;
+        has_eval_binding = has_eval_binding || collector.names.iter()
+            .find(|name| *name == "eval")
+            .is_some();
         self.eval_bindings.push(has_eval_binding);
         Ok(())
     }
@@ -642,7 +752,32 @@ impl AnnotationVisitor {
         script.walk(&mut Path::new(), &mut cleanup)
             .expect("Could not walk script for eval cleanup");
     }
+    pub fn annotate_module(&mut self, module: &mut Module) {
+        // Annotate.
+        // At this stage, we may have false positives for `hasDirectEval`.
+        module.walk(&mut Path::new(), self)
+            .expect("Could not walk module");
+
+        // Cleanup false positives for `hasDirectEval`.
+        let mut cleanup = EvalCleanupAnnotator {
+            eval_bindings: vec![false]
+        };
+        module.walk(&mut Path::new(), &mut cleanup)
+            .expect("Could not walk module for eval cleanup");
+    }
     pub fn annotate(&mut self, ast: &mut JSON) {
+        if let Some("Module") = ast["type"].as_str() {
+            // Import module
+            let mut module = Module::import(ast)
+                .expect("Invalid module"); // FIXME: Error values would be nicer.
+
+            self.annotate_module(&mut module);
+
+            // Reexport the AST to JSON.
+            *ast = module.export();
+            return;
+        }
+
         // Import script
         let mut script = Script::import(ast)
             .expect("Invalid script"); // FIXME: Error values would be nicer.
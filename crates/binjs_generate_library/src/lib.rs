@@ -27,16 +27,30 @@ pub struct RustExporter {
     ///
     /// Used to generate code that will create dynamically
     /// an instance of `Spec`.
-    spec: Spec
+    spec: Spec,
+
+    /// Interfaces whose `InnerDeserialization` is implemented by hand.
+    manual_deserialization: HashSet<String>,
 }
 impl RustExporter {
     /// Create a Rust exporter from the original specifications.
     pub fn new(spec: Spec) -> Self {
         RustExporter {
-            spec
+            spec,
+            manual_deserialization: HashSet::new(),
         }
     }
 
+    /// Do not generate `InnerDeserialization` for the interfaces `names`.
+    ///
+    /// The crate including the generated source must implement it instead,
+    /// e.g. to avoid recursion when decoding some deeply nested nodes.
+    pub fn with_manual_deserialization(mut self, names: &[&str]) -> Self {
+        self.manual_deserialization
+            .extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
     /// Generate the dynamic version of a `Type`.
     fn type_(type_: &Type, prefix: &str) -> String {
         let spec = Self::type_spec(type_.spec(), prefix);
@@ -110,7 +124,7 @@ impl RustExporter {
         ast_buffer.push_str("
 use binjs_shared;
 use binjs_shared::{ FromJSON, FromJSONError, Offset, ToJSON };
-use binjs_io::{ Deserialization, Guard, InnerDeserialization, Serialization, TokenReader, TokenReaderError, TokenWriter };
+use binjs_io::{ CategorizedDeserialization, CategorizedSerialization, Deserialization, Guard, InnerDeserialization, Serialization, StringCategory, TokenReader, TokenReaderError, TokenWriter };
 
 use io::*;
 
@@ -160,12 +174,21 @@ use json::JsonValue as JSON;
             for name in names.drain(..) {
                 let string_enum = source.get(&name).unwrap();
                 let name = name.to_class_cases();
-                let definition = format!("#[derive(PartialEq, Debug, Clone)]\npub enum {name} {{\n{values}\n    }}\n",
+                let definition = format!("#[derive(PartialEq, Debug, Clone)]\npub enum {name} {{\n{values}\n    }}\n
+impl {name} {{
+    /// The values of the enum, in the order of the grammar.
+    pub const STRINGS: [&'static str; {len}] = [{strings}];
+}}\n",
                     name = name,
                     values = string_enum.strings()
                         .iter()
                         .map(|s| format!("     {}", ToCases::to_cpp_enum_case(s)))
-                        .format(",\n"));
+                        .format(",\n"),
+                    len = string_enum.strings().len(),
+                    strings = string_enum.strings()
+                        .iter()
+                        .map(|s| format!("\"{}\"", s))
+                        .format(", "));
                 let to_json = format!("
 impl ToJSON for {name} {{
     fn export(&self) -> JSON {{
@@ -187,9 +210,8 @@ impl ToJSON for {name} {{
                 let from_reader = format!("
 impl<R> Deserializer<R> where R: TokenReader {{
     fn deserialize_variant_{lowercase_name}_aux(&mut self) -> Result<{name}, R::Error> where R: TokenReader {{
-        let key = self.reader.string()?;
-        match key {{
-            None => Err(From::from(TokenReaderError::EmptyVariant)),
+        let index = self.reader.string_enum(&{name}::STRINGS)?;
+        match index {{
 {variants}
             _ => Err(From::from(TokenReaderError::InvalidValue)),
         }}
@@ -214,10 +236,11 @@ impl<R> Deserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
                     lowercase_name = name.to_rust_identifier_case(),
                     variants = string_enum.strings()
                         .iter()
-                        .map(|s| format!("            Some(ref s) if s == \"{string}\" => Ok({name}::{typed}),",
+                        .enumerate()
+                        .map(|(i, s)| format!("            {index} => Ok({name}::{typed}),",
                             name = name,
                             typed = s.to_cpp_enum_case(),
-                            string = s))
+                            index = i))
                         .format("\n")
                     );
 
@@ -247,20 +270,21 @@ impl FromJSON for {name} {{
 impl<'a, W> Serialization<W, &'a {name}> for Serializer<W> where W: TokenWriter {{
     fn serialize(&mut self, value: &'a {name}) -> Result<W::Tree, W::Error> {{
         debug!(target: \"serialize_es6\", \"Serializing string enum {name}\");
-        let str = match *value {{
+        let index = match *value {{
 {variants}
         }};
-        (self as &mut Serialization<W, &'a str>).serialize(str)
+        self.writer.string_enum(&{name}::STRINGS, index)
     }}
 }}
 ",
                     name = name,
                     variants = string_enum.strings()
                         .iter()
-                        .map(|s| format!("            {name}::{typed} => \"{string}\"",
+                        .enumerate()
+                        .map(|(i, s)| format!("            {name}::{typed} => {index}",
                             name = name,
                             typed = s.to_cpp_enum_case(),
-                            string = s,
+                            index = i,
                         ))
                         .format(",\n")
                 );
@@ -324,15 +348,24 @@ impl Walker for {name} {{
                             .format(",\n"));
 
                 let from_reader = format!("
+impl<R> Deserializer<R> where R: TokenReader {{
+    /// Deserialize the contents of a {name}, once its kind and the order of
+    /// its fields, if any, have been read.
+    pub fn deserialize_{lowercase_name}_of_kind(&mut self, kind: Option<ASTNode>, fields: Option<&[String]>) -> Result<{name}, R::Error> {{
+        match kind {{
+{variants}
+            _ => Err(From::from(TokenReaderError::BadEnumVariant))
+        }}
+    }}
+}}
 impl<R> Deserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
     fn deserialize(&mut self) -> Result<{name}, R::Error> {{
         debug!(target: \"deserialize_es6\", \"Deserializing sum {name}\");
-        let (kind, _, guard) = self.reader.tagged_tuple()?;
-        debug!(target: \"deserialize_es6\", \"Deserializing sum {name}, found {{}}\", kind);
-        let result = match kind.as_str() {{
-{variants}
-            _ => Err(From::from(TokenReaderError::BadEnumVariant))
-        }};
+        let (node, kind, guard) = self.tagged_tuple_node()?;
+        debug!(target: \"deserialize_es6\", \"Deserializing sum {name}, found {{}}\", kind.name);
+        self.enter()?;
+        let result = self.deserialize_{lowercase_name}_of_kind(node, kind.fields.as_ref().map(|fields| &fields[..]));
+        self.exit();
         if result.is_err() {{
             self.reader.poison();
         }}
@@ -343,12 +376,14 @@ impl<R> Deserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
 impl<R> Deserialization<R, Option<{name}>> for Deserializer<R> where R: TokenReader {{
     fn deserialize(&mut self) -> Result<Option<{name}>, R::Error> {{
         debug!(target: \"deserialize_es6\", \"Deserializing optional sum {name}\");
-        let (kind, _, guard) = self.reader.tagged_tuple()?;
-        let result = match kind.as_str() {{
-{variants_some}
-            \"{null}\" => Ok(None),
-            _ => Err(From::from(TokenReaderError::BadEnumVariant))
+        let (node, kind, guard) = self.tagged_tuple_node()?;
+        self.enter()?;
+        let result = match node {{
+            None if &*kind.name == \"{null}\" => Ok(None),
+            _ => self.deserialize_{lowercase_name}_of_kind(node, kind.fields.as_ref().map(|fields| &fields[..]))
+                .map(Some)
         }};
+        self.exit();
         if result.is_err() {{
             self.reader.poison();
         }}
@@ -358,32 +393,16 @@ impl<R> Deserialization<R, Option<{name}>> for Deserializer<R> where R: TokenRea
 }}
 ",
                                 name = name,
+                                lowercase_name = name.to_rust_identifier_case(),
                                 variants = sum.types()
                                     .iter()
                                     .map(|t| {
                                         if let TypeSpec::NamedType(ref case) = *t {
-                                            format!("           \"{case}\" => {{
-                self.deserialize_inner()
+                                            format!("           Some(ASTNode::{constructor}) => {{
+                self.deserialize_inner(fields)
                     .map(|r| {name}::{constructor}(Box::new(r)))
             }}",
                                                 name = name,
-                                                case = case,
-                                                constructor = case.to_class_cases())
-                                        } else {
-                                            panic!("We should only have named types in sums at this stage");
-                                        }
-                                    })
-                                    .format("\n"),
-                                variants_some = sum.types()
-                                    .iter()
-                                    .map(|t| {
-                                        if let TypeSpec::NamedType(ref case) = *t {
-                                            format!("           \"{case}\" => {{
-            self.deserialize_inner()
-                .map(|r| Some({name}::{constructor}(Box::new(r))))
-        }}",
-                                                name = name,
-                                                case = case,
                                                 constructor = case.to_class_cases())
                                         } else {
                                             panic!("We should only have named types in sums at this stage");
@@ -578,50 +597,164 @@ impl<'a, W> Serialization<W, &'a {name}> for Serializer<W> where W: TokenWriter
                 }
             }
         }
-        fn print_ast_interfaces(buffer: &mut String, source: &HashMap<NodeName, Rc<Interface>>, null_name: &str) {
+        /// If `type_` is a string, an optional string or a list of strings, designated
+        /// by a typedef, the name of this typedef.
+        ///
+        /// Such strings are (de)serialized with the category of their typedef.
+        fn string_type_name(spec: &Spec, type_: &Type) -> Option<NodeName> {
+            let type_ = match *type_.spec() {
+                TypeSpec::Array { ref contents, .. } if !contents.is_optional() => &**contents,
+                _ => type_
+            };
+            if let TypeSpec::NamedType(ref name) = *type_.spec() {
+                if let Some(NamedType::Typedef(ref typedef)) = spec.get_type_by_name(name) {
+                    if let Some(IsNullable { content: Primitive::String, .. }) = typedef.get_primitive(spec) {
+                        return Some(name.clone())
+                    }
+                }
+            }
+            None
+        }
+        /// The Rust type of a field.
+        fn field_type_name(field: &Field) -> String {
+            if field.type_().is_optional() {
+                TypeName::type_(field.type_())
+            } else {
+                match *field.type_().spec() {
+                    TypeSpec::NamedType(ref contents) => contents.to_class_cases(),
+                    TypeSpec::Boolean => "bool".to_string(),
+                    TypeSpec::Number => "f64".to_string(),
+                    TypeSpec::String => "String".to_string(),
+                    TypeSpec::Void => "()".to_string(),
+                    TypeSpec::Offset => "Offset".to_string(),
+                    _ => TypeName::type_(field.type_())
+                }
+            }
+        }
+        fn print_ast_interfaces(buffer: &mut String, spec: &Spec, source: &HashMap<NodeName, Rc<Interface>>, null_name: &str, manual_deserialization: &HashSet<String>) {
             let mut names : Vec<_> = source.keys()
                 .collect();
             names.sort();
             for name in &names {
                 let interface = source.get(name).unwrap();
                 let name = name.to_class_cases();
-                let definition = format!("#[derive(PartialEq, Debug, Clone)]\npub struct {name} {{\n{fields}\n}}\n",
-                    fields = interface.contents().fields()
-                        .iter()
-                        .map(|field| {
-                            let spec =
-                                if field.type_().is_optional() {
-                                    TypeName::type_(field.type_())
-                                } else {
-                                    match *field.type_().spec() {
-                                        TypeSpec::NamedType(ref contents) => contents.to_class_cases(),
-                                        TypeSpec::Boolean => "bool".to_string(),
-                                        TypeSpec::Number => "f64".to_string(),
-                                        TypeSpec::String => "String".to_string(),
-                                        TypeSpec::Void => "()".to_string(),
-                                        TypeSpec::Offset => "Offset".to_string(),
-                                        _ => TypeName::type_(field.type_())
-                                    }
-                                };
-                            format!("    pub {name}: {contents}",
-                                name = field.name().to_rust_identifier_case(),
-                                contents = spec)
-                        })
+                let fields = interface.contents().fields();
+                let definition = format!("#[derive(PartialEq, Debug, Clone)]\npub struct {name} {{\n{fields}\n}}\n
+impl {name} {{
+    /// The fields of the node, in the order of the grammar.
+    pub const FIELDS: [&'static str; {len}] = [{field_names}];
+}}\n",
+                    fields = fields.iter()
+                        .map(|field| format!("    pub {name}: {contents}",
+                            name = field.name().to_rust_identifier_case(),
+                            contents = field_type_name(field)))
                         .format(",\n"),
+                    len = fields.len(),
+                    field_names = fields.iter()
+                        .map(|field| format!("\"{}\"", field.name().to_str()))
+                        .format(", "),
                     name = name);
 
+                // The expression reading a field.
+                let read_field = |field: &Field| match string_type_name(spec, field.type_()) {
+                    Some(type_name) => format!("(self.deserialize_categorized(StringCategory::of_type(\"{type_name}\")) as Result<_, R::Error>)?",
+                        type_name = type_name.to_str()),
+                    None => "(self.deserialize() as Result<_, R::Error>)?".to_string()
+                };
+                let reordered_from_reader = format!("
+impl<R> Deserializer<R> where R: TokenReader {{
+    /// Deserialize the contents of a {name} whose fields were written in the order `fields`.
+    ///
+    /// Omitted optional fields are `None`.
+    pub fn deserialize_reordered_{lowercase_name}(&mut self, fields: &[String]) -> Result<{name}, R::Error> {{
+{declarations}
+        for field in fields {{
+            match field.as_str() {{
+{reads}
+                _ => return Err(From::from(TokenReaderError::BadField {{
+                    kind: \"{name}\".to_string(),
+                    field: field.clone(),
+                }}))
+            }}
+        }}
+        Ok({name} {{
+{fields}
+        }})
+    }}
+}}
+",
+                    name = name,
+                    lowercase_name = name.to_rust_identifier_case()
+                        .trim_right_matches('_'),
+                    declarations = fields.iter()
+                        .map(|field| format!("        let mut field_{rust_name}: Option<{type_name}> = None;",
+                            rust_name = field.name().to_rust_identifier_case(),
+                            type_name = field_type_name(field)))
+                        .format("\n"),
+                    reads = fields.iter()
+                        .map(|field| format!("                \"{field_name}\" if field_{rust_name}.is_none() => field_{rust_name} = Some({read}),",
+                            field_name = field.name().to_str(),
+                            rust_name = field.name().to_rust_identifier_case(),
+                            read = read_field(field)))
+                        .format("\n"),
+                    fields = fields.iter()
+                        .map(|field| format!("            {rust_name}: match field_{rust_name} {{
+                Some(value) => value,
+                None => {missing}
+            }},",
+                            rust_name = field.name().to_rust_identifier_case(),
+                            missing = if field.type_().is_optional() {
+                                "None".to_string()
+                            } else {
+                                format!("return Err(From::from(TokenReaderError::MissingField {{
+                    kind: \"{name}\".to_string(),
+                    field: \"{field_name}\".to_string(),
+                }}))",
+                                    name = name,
+                                    field_name = field.name().to_str())
+                            }))
+                        .format("\n"));
+
+                let inner_from_reader =
+                    if manual_deserialization.contains(&name) {
+                        format!("// InnerDeserialization for {name} is implemented by hand.\n",
+                            name = name)
+                    } else {
+                        format!("
+impl<R> InnerDeserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
+    fn deserialize_inner(&mut self, fields: Option<&[String]>) -> Result<{name}, R::Error> where R: TokenReader {{
+        match fields {{
+            Some(fields) if fields != &{name}::FIELDS[..] => self.deserialize_reordered_{lowercase_name}(fields),
+            _ => Ok({name} {{
+{fields}
+            }})
+        }}
+    }}
+}}
+",
+                            name = name,
+                            lowercase_name = name.to_rust_identifier_case()
+                                .trim_right_matches('_'),
+                            fields = fields.iter()
+                                .map(|field| format!("                {name}: {read},",
+                                    name = field.name().to_rust_identifier_case(),
+                                    read = read_field(field)))
+                                .format("\n"))
+                    };
                 let from_reader = format!("
 impl<R> Deserializer<R> where R: TokenReader {{
     fn deserialize_tuple_{lowercase_name}(&mut self) -> Result<{name}, R::Error> where R: TokenReader {{
-        let (kind, _, guard) = self.reader.tagged_tuple()?;
+        let (node, kind, guard) = self.tagged_tuple_node()?;
+        self.enter()?;
         let result =
-            if let \"{name}\" = kind.as_str() {{
+            if let Some(ASTNode::{name}) = node {{
                 debug!(target: \"deserialize_es6\", \"Deserializing tagged tuple {name}: present\");
-                self.deserialize_inner()
+                self.deserialize_inner(kind.fields.as_ref().map(|fields| &fields[..]))
             }} else {{
                 debug!(target: \"deserialize_es6\", \"Deserializing tagged tuple {name}: error\");
                 Err(From::from(TokenReaderError::BadEnumVariant))
             }};
+        self.exit();
         if result.is_err() {{
             self.reader.poison();
         }}
@@ -630,14 +763,8 @@ impl<R> Deserializer<R> where R: TokenReader {{
     }}
 }}
 
-impl<R> InnerDeserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
-    fn deserialize_inner(&mut self) -> Result<{name}, R::Error> where R: TokenReader {{
-        Ok({name} {{
-{fields}
-        }})
-    }}
-}}
-
+{reordered}
+{inner}
 impl<R> Deserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
     fn deserialize(&mut self) -> Result<{name}, R::Error> {{
         debug!(target: \"deserialize_es6\", \"Deserializing tagged tuple {name}\");
@@ -647,18 +774,20 @@ impl<R> Deserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
 impl<R> Deserialization<R, Option<{name}>> for Deserializer<R> where R: TokenReader {{
     fn deserialize(&mut self) -> Result<Option<{name}>, R::Error> {{
         debug!(target: \"deserialize_es6\", \"Deserializing optional tuple {name}\");
-        let (kind, _, guard) = self.reader.tagged_tuple()?;
-        let result = match kind.as_str() {{
-            \"{name}\" => {{
+        let (node, kind, guard) = self.tagged_tuple_node()?;
+        self.enter()?;
+        let result = match node {{
+            Some(ASTNode::{name}) => {{
                 debug!(target: \"deserialize_es6\", \"Deserializing optional tuple {name}: present\");
-                self.deserialize_inner().map(Some)
+                self.deserialize_inner(kind.fields.as_ref().map(|fields| &fields[..])).map(Some)
             }}
-            \"{null}\" => {{
+            None if &*kind.name == \"{null}\" => {{
                 debug!(target: \"deserialize_es6\", \"Deserializing optional tuple {name}: absent\");
                 Ok(None)
             }},
             _ => Err(From::from(TokenReaderError::BadEnumVariant))
         }};
+        self.exit();
         if result.is_err() {{
             self.reader.poison();
         }}
@@ -671,12 +800,8 @@ impl<R> Deserialization<R, Option<{name}>> for Deserializer<R> where R: TokenRea
                     null = null_name,
                     lowercase_name = name.to_rust_identifier_case()
                         .trim_right_matches('_'),
-                    fields = interface.contents()
-                        .fields()
-                        .iter()
-                        .map(|field| format!("            {name}: (self.deserialize() as Result<_, R::Error>)?,",
-                            name = field.name().to_rust_identifier_case()))
-                        .format("\n")
+                    reordered = reordered_from_reader,
+                    inner = inner_from_reader,
                     );
                     let len = interface.contents()
                         .fields()
@@ -708,9 +833,15 @@ impl<'a, W> Serialization<W, &'a {name}> for Serializer<W> where W: TokenWriter
                         fields = interface.contents()
                             .fields()
                             .iter()
-                            .map(|field| format!("        children.push((\"{field_name}\", (self as &mut Serialization<W, &'a _>).serialize(&value.{rust_field_name})?));",
-                                field_name = field.name().to_str(),
-                                rust_field_name = field.name().to_rust_identifier_case()))
+                            .map(|field| match string_type_name(spec, field.type_()) {
+                                Some(type_name) => format!("        children.push((\"{field_name}\", (self as &mut CategorizedSerialization<W, &'a _>).serialize_categorized(StringCategory::of_type(\"{type_name}\"), &value.{rust_field_name})?));",
+                                    field_name = field.name().to_str(),
+                                    type_name = type_name.to_str(),
+                                    rust_field_name = field.name().to_rust_identifier_case()),
+                                None => format!("        children.push((\"{field_name}\", (self as &mut Serialization<W, &'a _>).serialize(&value.{rust_field_name})?));",
+                                    field_name = field.name().to_str(),
+                                    rust_field_name = field.name().to_rust_identifier_case())
+                            })
                             .format("\n")
                     );
 
@@ -797,10 +928,22 @@ impl Walker for {name} {{
 
             let interfaces_enum = format!("#[derive(Clone, Copy, Debug, PartialEq, Eq)]\npub enum ASTNode {{
 {interfaces}
+}}\n
+impl ASTNode {{
+    /// The interface named `name` in the grammar, if any.
+    pub fn from_name(name: &str) -> Option<Self> {{
+        match name {{
+{cases}
+            _ => None
+        }}
+    }}
 }}\n\n\n",
                 interfaces = names.iter()
                     .map(|name| format!("    {}", name.to_class_cases()))
-                    .format(",\n")
+                    .format(",\n"),
+                cases = names.iter()
+                    .map(|name| format!("            \"{}\" => Some(ASTNode::{}),", name.to_str(), name.to_class_cases()))
+                    .format("\n")
             );
 
             // Now generate the interface visitors
@@ -909,7 +1052,7 @@ impl<T> Walker for Vec<T> where T: Walker {{
         ast_buffer.push_str("\n\n// Interfaces and interface names (by lexicographical order)\n");
         print_struct_names(&mut struct_buffer, self.spec.interfaces_by_name().keys());
         print_impl_names(&mut impl_buffer, self.spec.interfaces_by_name().keys());
-        print_ast_interfaces(&mut ast_buffer, deanonymized.interfaces_by_name(), self.spec.get_null_name().to_str());
+        print_ast_interfaces(&mut ast_buffer, &deanonymized, deanonymized.interfaces_by_name(), self.spec.get_null_name().to_str(), &self.manual_deserialization);
 
         struct_buffer.push_str("\n\n\n    // Field names (by lexicographical order)\n");
         impl_buffer.push_str("\n\n\n            // Field names (by lexicographical order)\n");
@@ -0,0 +1,119 @@
+//! Structural diff between two ASTs.
+
+use syntax::{ ASTError, ComparisonVisitor, ComparisonWalker, WalkPath };
+use util::type_of;
+
+use binjs_meta::spec::*;
+
+use std;
+
+use json::JsonValue as JSON;
+
+/// A single difference between two ASTs.
+#[derive(Debug)]
+pub enum Change {
+    /// An item was inserted in a list.
+    ///
+    /// `index` is the position of the item in the right AST.
+    Insert {
+        path: WalkPath,
+        index: usize,
+        value: JSON,
+    },
+
+    /// An item was removed from a list.
+    ///
+    /// `index` is the position of the item in the left AST.
+    Delete {
+        path: WalkPath,
+        index: usize,
+        value: JSON,
+    },
+
+    /// A value was replaced by another value.
+    Update {
+        path: WalkPath,
+        before: JSON,
+        after: JSON,
+    }
+}
+impl Change {
+    pub fn path(&self) -> &WalkPath {
+        match *self {
+            Change::Insert { ref path, .. }
+            | Change::Delete { ref path, .. }
+            | Change::Update { ref path, .. } => path
+        }
+    }
+}
+
+/// Print a value without dumping the entire subtree.
+fn summary(value: &JSON) -> String {
+    if value.is_object() {
+        if let Some(kind) = value["type"].as_str() {
+            return kind.to_string();
+        }
+    }
+    if value.is_object() || value.is_array() {
+        return type_of(value);
+    }
+    value.dump()
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Change::Insert { ref path, index, ref value } =>
+                write!(f, "+ {:?}[{}] {}", path, index, summary(value)),
+            Change::Delete { ref path, index, ref value } =>
+                write!(f, "- {:?}[{}] {}", path, index, summary(value)),
+            Change::Update { ref path, ref before, ref after } =>
+                write!(f, "~ {:?} {} => {}", path, summary(before), summary(after)),
+        }
+    }
+}
+
+/// Compute a structural diff between two ASTs, restricting comparison
+/// to the items that appear in the grammar.
+///
+/// Lists are aligned, so that inserting or removing a statement is
+/// reported as such rather than as a change to every subsequent statement.
+pub struct Diff {
+    changes: Vec<Change>,
+}
+impl Diff {
+    /// Compute the list of changes needed to go from `left` to `right`.
+    ///
+    /// This method assumes that both items are full ASTs.
+    pub fn diff(syntax: &Spec, left: &JSON, right: &JSON) -> Result<Vec<Change>, ASTError> {
+        let diff = ComparisonWalker::new(syntax, Diff { changes: vec![] })
+            .with_list_alignment()
+            .compare(left, right)?;
+        Ok(diff.changes)
+    }
+}
+impl ComparisonVisitor for Diff {
+    fn update(&mut self, path: &WalkPath, _json_path: &[String], left: &JSON, right: &JSON) {
+        self.changes.push(Change::Update {
+            path: path.clone(),
+            before: left.clone(),
+            after: right.clone(),
+        });
+    }
+
+    fn delete(&mut self, path: &WalkPath, _json_path: &[String], index: usize, value: &JSON) {
+        self.changes.push(Change::Delete {
+            path: path.clone(),
+            index,
+            value: value.clone(),
+        });
+    }
+
+    fn insert(&mut self, path: &WalkPath, _json_path: &[String], index: usize, value: &JSON) {
+        self.changes.push(Change::Insert {
+            path: path.clone(),
+            index,
+            value: value.clone(),
+        });
+    }
+}
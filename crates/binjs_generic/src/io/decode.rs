@@ -1,13 +1,14 @@
 //! With the help of an underlying `TokenReader`, decode a stream of bytes
 //! to a JSON matching a specific grammar.
 
-use binjs_io::{ Guard, TokenReader };
+use binjs_io::{ Guard, StringCategory, TokenReader, TokenReaderError };
 use binjs_meta::spec::*;
 
 use json;
 use json::JsonValue as JSON;
 use json::object::Object as Object;
 
+use std;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::rc::Rc;
@@ -32,16 +33,111 @@ pub struct Decoder<'a, E> where E: TokenReader {
     extractor: E,
     grammar: &'a Spec,
 
-    /// Latest value decoded. Used for debugging/troubleshooting.
+    /// Latest scalar value decoded. Used for debugging/troubleshooting.
     latest: JSON,
+
+    /// The number of objects currently being decoded.
+    depth: usize,
+
+    /// The maximal number of nested objects.
+    max_depth: usize,
+
+    /// The names of the kinds read so far, by kind id.
+    kinds: HashMap<u32, NodeName>,
+}
+
+/// An object or a list being decoded.
+///
+/// The decoder doesn't recurse, so that deeply nested ASTs, e.g. long chains
+/// of binary operators, cannot overflow the stack. Instead, it keeps the
+/// objects and lists being decoded on a stack of `Frame`s, innermost last.
+enum Frame<'a, E> where E: TokenReader {
+    List {
+        contents: &'a Type,
+
+        /// The number of items that remain to be decoded.
+        remaining: u32,
+        values: Vec<JSON>,
+        guard: E::ListGuard,
+    },
+    Object {
+        interface: &'a Interface,
+        object: Object,
+
+        /// The fields that remain to be decoded, in the order of the stream, last first.
+        pending: Vec<(&'a str, &'a Type)>,
+
+        /// The field being decoded.
+        current: Option<&'a str>,
+
+        /// The optional fields that are absent from the stream.
+        absent: Vec<&'a str>,
+        guard: E::TaggedGuard,
+    },
+}
+impl<'a, E> Frame<'a, E> where E: TokenReader {
+    /// The type of the next value to decode, or `None` if all values have been decoded.
+    fn next_type(&mut self) -> Option<&'a Type> {
+        match *self {
+            Frame::List { ref contents, ref mut remaining, .. } => {
+                if *remaining == 0 {
+                    return None
+                }
+                *remaining -= 1;
+                Some(*contents)
+            }
+            Frame::Object { ref mut pending, ref mut current, .. } => {
+                pending.pop()
+                    .map(|(name, type_)| {
+                        *current = Some(name);
+                        type_
+                    })
+            }
+        }
+    }
+
+    /// Store the value returned for the latest `next_type()`.
+    fn push(&mut self, value: JSON) {
+        match *self {
+            Frame::List { ref mut values, .. } => values.push(value),
+            Frame::Object { ref mut object, ref mut current, .. } => {
+                let name = current.take()
+                    .expect("A value can only be pushed after `next_type()`");
+                object.insert(name, value);
+            }
+        }
+    }
+}
+
+/// The first step of decoding a value.
+enum Step<'a, E> where E: TokenReader {
+    /// A value that contains neither objects nor lists, fully decoded.
+    Value(JSON),
+
+    /// An object or list, whose contents remain to be decoded.
+    Frame(Frame<'a, E>),
 }
 
 impl<'a, E> Decoder<'a, E> where E: TokenReader {
+    /// Create a decoder.
+    ///
+    /// The decoder itself doesn't limit the depth of the AST, as it doesn't
+    /// recurse. Use the limits of the `TokenReader` to bound memory use, and
+    /// `util::drop_json` to drop very deep results.
     pub fn new(grammar: &'a Spec, extractor: E) -> Self {
+        Self::with_max_depth(grammar, extractor, std::usize::MAX)
+    }
+
+    /// Create a decoder that fails with `TokenReaderError::TooDeep`
+    /// instead of decoding more than `max_depth` nested objects.
+    pub fn with_max_depth(grammar: &'a Spec, extractor: E, max_depth: usize) -> Self {
         Decoder {
             extractor,
             grammar,
-            latest: JSON::Null
+            latest: JSON::Null,
+            depth: 0,
+            max_depth,
+            kinds: HashMap::new(),
         }
     }
     fn register(&mut self, value: JSON) -> JSON {
@@ -57,56 +153,154 @@ impl<'a, E> Decoder<'a, E> where E: TokenReader {
         &self.latest
     }
 
+    /// Start reading a tagged tuple, and return the name of its kind in the grammar.
+    ///
+    /// If the extractor provides kind ids, each kind is looked up in the grammar
+    /// only once.
+    fn tagged_tuple(&mut self) -> Result<(NodeName, Option<Rc<Box<[String]>>>, E::TaggedGuard), Error<E::Error>> {
+        let (kind, guard) = self.extractor.tagged_tuple_kind()
+            .map_err(Error::TokenReaderError)?;
+        debug!(target: "decoder", "decoder: found kind {:?}", kind.name);
+        if let Some(id) = kind.id {
+            if let Some(name) = self.kinds.get(&id) {
+                return Ok((name.clone(), kind.fields, guard))
+            }
+        }
+        let name = match self.grammar.get_node_name(&kind.name).cloned() {
+            Some(name) => name,
+            None => return Err(self.raise_error(Error::NoSuchInterface(kind.name.to_string())))
+        };
+        if let Some(id) = kind.id {
+            self.kinds.insert(id, name.clone());
+        }
+        Ok((name, kind.fields, guard))
+    }
+
     pub fn decode(&mut self) -> Result<JSON, Error<E::Error>> {
-        let start = self.grammar.get_root();
-        self.decode_from_named_type(&start, false)
+        let root = self.grammar.get_root_name().clone();
+        let step = self.start_named_type(&root, false)?;
+        self.run(step)
+    }
+
+    /// Decode the contents of an object, once its kind and the order of its
+    /// fields, if any, have been read.
+    pub fn decode_object_contents(&mut self, interface: &'a Interface, field_names: Option<Rc<Box<[String]>>>, guard: E::TaggedGuard) -> Result<JSON, Error<E::Error>> {
+        let frame = self.start_object(interface, field_names, guard)?;
+        self.run(Step::Frame(frame))
+    }
+
+    pub fn decode_from_type(&mut self, kind: &'a Type, is_optional: bool) -> Result<JSON, Error<E::Error>> {
+        let step = self.start_type(kind, is_optional)?;
+        self.run(step)
+    }
+
+    /// Decode the contents of the objects and lists, until the value started by `step` is complete.
+    fn run(&mut self, step: Step<'a, E>) -> Result<JSON, Error<E::Error>> {
+        let depth = self.depth;
+        let result = self.run_frames(step);
+        if result.is_err() {
+            // The frames have been dropped.
+            self.depth = depth;
+        }
+        result
     }
-    fn decode_from_named_type(&mut self, named: &NamedType, is_optional: bool) -> Result<JSON, Error<E::Error>> {
-        match *named {
-            NamedType::Typedef(ref type_) =>
-                return self.decode_from_type(type_, is_optional),
-            NamedType::StringEnum(ref enum_) => {
-                let string = self.extractor.string()
-                    .map_err(Error::TokenReaderError)?
-                    .ok_or_else(|| self.raise_error(Error::UnexpectedValue("null string".to_owned())))?;
-                for candidate in enum_.strings() {
-                    if candidate == &string {
-                        return Ok(self.register(json::from(string)));
+    fn run_frames(&mut self, step: Step<'a, E>) -> Result<JSON, Error<E::Error>> {
+        let mut stack = match step {
+            Step::Value(value) => return Ok(value),
+            Step::Frame(frame) => vec![frame]
+        };
+        loop {
+            let next = stack.last_mut()
+                .expect("The stack contains at least the outermost frame")
+                .next_type();
+            let value = match next {
+                Some(type_) => {
+                    match self.start_type(type_, false)? {
+                        Step::Value(value) => value,
+                        Step::Frame(frame) => {
+                            stack.push(frame);
+                            continue;
+                        }
                     }
                 }
-                return Err(self.raise_error(Error::UnexpectedValue(format!("\"{}\"", string))))
-            }
-            NamedType::Interface(ref interface) => {
-                // 1. Get the the interface.
-                let (object_name, mapped_field_names, guard) = self.extractor.tagged_tuple()
-                    .map_err(Error::TokenReaderError)?;
-                debug!(target: "decoder", "decoder: found kind {:?} while looking for {:?}", object_name, interface.name().to_str());
-
-                // 2. If necessary, substitute null to any interface.
-                // FIXME: Check above that `null` is acceptable.
-                if object_name == self.grammar.get_null_name().to_str() {
-                    debug!(target: "decoder", "decoder: substituted null => {}", interface.name().to_str());
-                    guard.done()
-                        .map_err(Error::TokenReaderError)?;
-                    return Ok(self.register(JSON::Null))
+                None => {
+                    let frame = stack.pop()
+                        .expect("The stack contains at least the outermost frame");
+                    let value = self.finish(frame)?;
+                    if stack.is_empty() {
+                        return Ok(value)
+                    }
+                    value
                 }
+            };
+            stack.last_mut()
+                .expect("The stack contains at least the outermost frame")
+                .push(value);
+        }
+    }
 
-                // 3. Check that the object is appropriate here.
-                if object_name != interface.name().to_str() {
-                    return Err(self.raise_error(Error::UnexpectedValue(format!("Object named {} instead of {}",
-                        object_name,
-                        interface.name().to_str()))));
-                }
+    /// Start decoding a value of the named type `name`.
+    fn start_named_type(&mut self, name: &NodeName, is_optional: bool) -> Result<Step<'a, E>, Error<E::Error>> {
+        let grammar = self.grammar;
+        if let Some(interface) = grammar.get_interface_by_name(name) {
+            // 1. Get the the interface.
+            let (object_name, mapped_field_names, guard) = self.tagged_tuple()?;
+            debug!(target: "decoder", "decoder: found kind {:?} while looking for {:?}", object_name, interface.name().to_str());
+
+            // 2. If necessary, substitute null to any interface.
+            // FIXME: Check above that `null` is acceptable.
+            if object_name == *grammar.get_null_name() {
+                debug!(target: "decoder", "decoder: substituted null => {}", interface.name().to_str());
+                guard.done()
+                    .map_err(Error::TokenReaderError)?;
+                return Ok(Step::Value(self.register(JSON::Null)))
+            }
 
-                // 4. Parse within interface.
-                self.decode_object_contents(interface, mapped_field_names, guard)
+            // 3. Check that the object is appropriate here.
+            if object_name != *interface.name() {
+                return Err(self.raise_error(Error::UnexpectedValue(format!("Object named {} instead of {}",
+                    object_name,
+                    interface.name().to_str()))));
             }
+
+            // 4. Parse within interface.
+            return self.start_object(interface, mapped_field_names, guard)
+                .map(Step::Frame)
+        }
+        if let Some(enum_) = grammar.string_enums_by_name().get(name) {
+            let strings: Vec<_> = enum_.strings()
+                .iter()
+                .map(String::as_str)
+                .collect();
+            let index = self.extractor.string_enum(&strings)
+                .map_err(Error::TokenReaderError)?;
+            return Ok(Step::Value(self.register(json::from(strings[index]))))
         }
+        if let Some(type_) = grammar.typedefs_by_name().get(name) {
+            if let Some(IsNullable { is_nullable, content: Primitive::String }) = type_.get_primitive(grammar) {
+                // Strings are read with the category of their named type.
+                let extracted = self.extractor.categorized_string(StringCategory::of_type(name.to_str()))
+                    .map_err(Error::TokenReaderError)?;
+                return self.decode_string(extracted, is_optional || is_nullable)
+                    .map(Step::Value)
+            }
+            return self.start_type(type_, is_optional)
+        }
+        Err(self.raise_error(Error::NoSuchType(name.to_string().clone())))
     }
-    pub fn decode_object_contents(&mut self, interface: &Interface, field_names: Option<Rc<Box<[String]>>>, guard: E::TaggedGuard) -> Result<JSON, Error<E::Error>> {
+
+    /// Start decoding the contents of an object, once its kind and the order
+    /// of its fields, if any, have been read.
+    fn start_object(&mut self, interface: &'a Interface, field_names: Option<Rc<Box<[String]>>>, guard: E::TaggedGuard) -> Result<Frame<'a, E>, Error<E::Error>> {
         debug!(target: "decode", "decode_object_contents: Interface {:?} ", interface.name());
-        let mut object = Object::new();
+        if self.depth >= self.max_depth {
+            let max = self.max_depth;
+            return Err(self.raise_error(Error::TokenReaderError(From::from(TokenReaderError::TooDeep { max }))));
+        }
 
+        let grammar = self.grammar;
+        let mut pending = vec![];
+        let mut absent = vec![];
         if let Some(field_names) = field_names {
             // Determine all the fields that we were expecting.
             let mut expected: HashMap<_,_> = interface.contents()
@@ -120,58 +314,88 @@ impl<'a, E> Decoder<'a, E> where E: TokenReader {
 
             // Read the fields **in the order** in which they appear in the stream.
             for field in field_names.as_ref().iter() {
-                debug!(target: "decode", "decode_object_contents: Looking at field {:?} ", field);
-                let field_name = self.grammar.get_field_name(field)
+                let field_name = grammar.get_field_name(field)
                     .ok_or_else(|| self.raise_error(Error::NoSuchField(field.clone())))?;
-                let type_ =
-                    if let Some(type_) = expected.remove(field_name) {
-                        type_
-                    } else {
-                        debug!(target: "decode", "decode_object_contents: I didn't expect field {:?}.", field);
-                        self.extractor.poison();
-                        return Err(self.raise_error(Error::NoSuchField(field.clone())))
-                    };
-                let item = self.decode_from_type(type_, false)?;
-                object.insert(field, item);
+                if let Some(type_) = expected.remove(field_name) {
+                    pending.push((field_name.to_str(), type_));
+                } else {
+                    debug!(target: "decode", "decode_object_contents: I didn't expect field {:?}.", field);
+                    return Err(self.raise_error(Error::NoSuchField(field.clone())))
+                }
             }
             debug!(target: "decode", "decode_object_contents: Remaining fields {:?} ", expected);
 
             // Any field missing? Find out if there is a default value.
-            for (name, type_) in expected.drain() {
-                let name = name.to_str();
-                if type_.is_optional() {
-                    object.insert(name, JSON::Null);
+            for field in interface.contents().fields() {
+                if !expected.contains_key(field.name()) {
+                    continue;
+                }
+                if field.type_().is_optional() {
+                    absent.push(field.name().to_str());
                 } else {
-                    self.extractor.poison();
                     return Err(self.raise_error(Error::MissingField {
-                        name: name.to_string(),
+                        name: field.name().to_str().to_string(),
                         kind: interface.name().to_string().clone()
                     }))
                 }
             }
-
         } else {
             // Read the fields **in the order** in which they appear in the spec.
             for field in interface.contents().fields() {
-                debug!(target: "decode", "decode_object_contents: Looking at field {:?} with type {:?}",
-                    field.name(),
-                    field.type_());
-                let item = self.decode_from_type(field.type_(), false)?;
-                object.insert(field.name().to_str(), item);
+                pending.push((field.name().to_str(), field.type_()));
             }
         }
+        pending.reverse();
 
+        self.depth += 1;
+        Ok(Frame::Object {
+            interface,
+            object: Object::new(),
+            pending,
+            current: None,
+            absent,
+            guard,
+        })
+    }
 
-        // Don't forget `"type"`.
-        debug!(target: "decode", "decode_object_contents: Adding type");
-        object.insert("type", json::from(interface.name().to_str()));
+    /// Complete an object or a list, once all its values have been decoded.
+    fn finish(&mut self, frame: Frame<'a, E>) -> Result<JSON, Error<E::Error>> {
+        match frame {
+            Frame::List { values, guard, .. } => {
+                guard.done()
+                    .map_err(Error::TokenReaderError)?;
+                Ok(JSON::Array(values))
+            }
+            Frame::Object { interface, mut object, absent, guard, .. } => {
+                self.depth -= 1;
+                for name in absent {
+                    object.insert(name, JSON::Null);
+                }
 
-        guard.done()
-            .map_err(Error::TokenReaderError)?;
+                // Don't forget `"type"`.
+                debug!(target: "decode", "decode_object_contents: Adding type");
+                object.insert("type", json::from(interface.name().to_str()));
+
+                guard.done()
+                    .map_err(Error::TokenReaderError)?;
+                Ok(JSON::Object(object))
+            }
+        }
+    }
 
-        Ok(self.register(JSON::Object(object)))
+    fn decode_string(&mut self, extracted: Option<String>, is_optional: bool) -> Result<JSON, Error<E::Error>> {
+        match extracted {
+            None if is_optional =>
+                Ok(self.register(JSON::Null)),
+            None =>
+                Err(self.raise_error(Error::UnexpectedValue("null string".to_owned()))),
+            Some(string) =>
+                Ok(self.register(json::from(string))),
+        }
     }
-    pub fn decode_from_type(&mut self, kind: &Type, is_optional: bool) -> Result<JSON, Error<E::Error>> {
+
+    /// Start decoding a value of type `kind`.
+    fn start_type(&mut self, kind: &'a Type, is_optional: bool) -> Result<Step<'a, E>, Error<E::Error>> {
         use binjs_meta::spec::TypeSpec::*;
         debug!(target: "decoder", "decode: {:?}", kind);
         let is_optional = kind.is_optional() || is_optional;
@@ -182,25 +406,18 @@ impl<'a, E> Decoder<'a, E> where E: TokenReader {
                 if len == 0 && !supports_empty {
                     return Err(self.raise_error(Error::InvalidValue("Empty list".to_string())));
                 }
-                let mut values = Vec::with_capacity(len as usize);
-                for _ in 0..len {
-                    values.push(self.decode_from_type(kind, false)?);
-                }
-                guard.done()
-                    .map_err(Error::TokenReaderError)?;
-                Ok(self.register(JSON::Array(values)))
+                Ok(Step::Frame(Frame::List {
+                    contents: kind,
+                    remaining: len,
+                    values: Vec::with_capacity(len as usize),
+                    guard,
+                }))
             }
             String => {
                 let extracted = self.extractor.string()
                     .map_err(Error::TokenReaderError)?;
-                match extracted {
-                    None if is_optional =>
-                        Ok(self.register(JSON::Null)),
-                    None =>
-                        Err(self.raise_error(Error::UnexpectedValue("null string".to_owned()))),
-                    Some(string) =>
-                        Ok(self.register(json::from(string))),
-                }
+                self.decode_string(extracted, is_optional)
+                    .map(Step::Value)
             }
             Boolean => {
                 let extracted = self.extractor.bool()
@@ -209,13 +426,13 @@ impl<'a, E> Decoder<'a, E> where E: TokenReader {
                     None =>
                         Err(self.raise_error(Error::UnexpectedValue("null bool".to_owned()))),
                     Some(b) =>
-                        Ok(self.register(json::from(b)))
+                        Ok(Step::Value(self.register(json::from(b))))
                 }
             }
             Offset => {
                 let offset = self.extractor.offset()
                     .map_err(Error::TokenReaderError)?;
-                Ok(self.register(json::from(offset)))
+                Ok(Step::Value(self.register(json::from(offset))))
             }
             Number => {
                 let extracted = self.extractor.float()
@@ -224,55 +441,51 @@ impl<'a, E> Decoder<'a, E> where E: TokenReader {
                     None =>
                         Err(self.raise_error(Error::UnexpectedValue("null float".to_owned()))),
                     Some(f) =>
-                        Ok(self.register(json::from(f)))
+                        Ok(Step::Value(self.register(json::from(f))))
                 }
             }
             NamedType(ref name) => {
-                let named_type = self.grammar.get_type_by_name(name)
-                    .ok_or_else(|| self.raise_error(Error::NoSuchType(name.to_string().clone())))?;
-                let result = self.decode_from_named_type(&named_type, is_optional)?;
-                if let JSON::Null = result {
+                let step = self.start_named_type(name, is_optional)?;
+                if let Step::Value(JSON::Null) = step {
                     if !is_optional {
                         return Err(self.raise_error(Error::UnexpectedValue("null".to_string())));
                     }
                 }
-                Ok(result)
+                Ok(step)
             }
             TypeSum(ref sum) => {
                 // The `sum` is necessarily a sum of interfaces, so this must be an object.
                 // 1. Get the the interface.
-                let (interface_name, mapped_field_names, guard) = self.extractor.tagged_tuple()
-                    .map_err(Error::TokenReaderError)?;
-                debug!(target: "decoder", "decoder: found kind {:?}", interface_name);
-                let interface_node_name = self.grammar.get_node_name(&interface_name)
-                    .ok_or_else(|| Error::NoSuchInterface(interface_name.to_string().clone()))?;
+                let (interface_node_name, mapped_field_names, guard) = self.tagged_tuple()?;
 
-                if interface_node_name == self.grammar.get_null_name() {
+                if interface_node_name == *self.grammar.get_null_name() {
                     if is_optional {
                         guard.done()
                             .map_err(Error::TokenReaderError)?;
-                        return Ok(self.register(JSON::Null))
+                        return Ok(Step::Value(self.register(JSON::Null)))
                     }
                 }
-                let interface = self.grammar.get_interface_by_name(&interface_node_name)
-                    .ok_or_else(|| self.raise_error(Error::NoSuchInterface(interface_name.to_string().clone())))?;
+                let grammar = self.grammar;
+                let interface = grammar.get_interface_by_name(&interface_node_name)
+                    .ok_or_else(|| self.raise_error(Error::NoSuchInterface(interface_node_name.to_string().clone())))?;
 
                 // 2. Check that the interface somehow belongs in `sum`
                 if sum.types().iter()
                     .find(|type_| {
-                        type_.get_interface(self.grammar, interface_node_name)
+                        type_.get_interface(self.grammar, &interface_node_name)
                             .is_some()
                     }).is_none()
                 {
                     return Err(self.raise_error(Error::UnexpectedValue(format!("Unexpected interface {interface} doesn't fit in sum {sum:?}",
-                        interface = interface_name,
+                        interface = interface_node_name,
                         sum = sum.types()))));
                 }
 
                 // 3. Parse within interface.
-                self.decode_object_contents(interface, mapped_field_names, guard)
+                self.start_object(interface, mapped_field_names, guard)
+                    .map(Step::Frame)
             }
-            Void => Ok(self.register(JSON::Null))
+            Void => Ok(Step::Value(self.register(JSON::Null)))
         }
     }
-}
\ No newline at end of file
+}
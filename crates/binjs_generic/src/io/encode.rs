@@ -1,6 +1,6 @@
 use util::type_of;
 
-use binjs_io::TokenWriter;
+use binjs_io::{ StringCategory, TokenWriter };
 use binjs_meta::spec::*;
 
 use std;
@@ -69,11 +69,13 @@ impl<'a, B, Tree, E> Encoder<'a, B, Tree, E> where B: TokenWriter<Tree=Tree, Err
                         expected: format!("String (one of {:?}), while treating {:?}", enum_, node),
                         got: type_of(&value)
                     })?;
-                if enum_.strings()
+                let strings: Vec<_> = enum_.strings()
                     .iter()
-                    .find(|c| c == &string)
-                    .is_some() {
-                    return self.builder.borrow_mut().string(Some(&string))
+                    .map(String::as_str)
+                    .collect();
+                if let Some(index) = strings.iter()
+                    .position(|c| *c == string) {
+                    return self.builder.borrow_mut().string_enum(&strings, index)
                         .map_err(Error::TokenWriterError)
                 }
                 Err(Error::NoSuchLiteral {
@@ -163,7 +165,20 @@ impl<'a, B, Tree, E> Encoder<'a, B, Tree, E> where B: TokenWriter<Tree=Tree, Err
             (&NamedType(ref name), _) => {
                 let named_type = self.grammar.get_type_by_name(name)
                     .ok_or_else(|| Error::NoSuchType(name.to_string().clone()))?;
-                return self.encode_from_named_type(value, &named_type, node, is_optional)
+                if let Some(is_nullable) = self.string_typedef(&named_type) {
+                    // Strings are written with the category of their named type.
+                    let category = StringCategory::of_type(name.to_str());
+                    if let Some(string) = value.as_str() {
+                        return self.builder.borrow_mut().categorized_string(category, Some(string))
+                            .map_err(Error::TokenWriterError)
+                    }
+                    if value.is_null() && (is_optional || is_nullable) {
+                        return self.builder.borrow_mut().categorized_string(category, None)
+                            .map_err(Error::TokenWriterError)
+                    }
+                } else {
+                    return self.encode_from_named_type(value, &named_type, node, is_optional)
+                }
             }
             (&TypeSum(ref sum), &JSON::Object(ref object)) => {
                 // Sums may only be used to encode objects or null.
@@ -191,6 +206,15 @@ impl<'a, B, Tree, E> Encoder<'a, B, Tree, E> where B: TokenWriter<Tree=Tree, Err
             got: value.dump()
         })
     }
+    /// If `named` is a typedef for a string, whether this string may be null.
+    fn string_typedef(&self, named: &NamedType) -> Option<bool> {
+        if let NamedType::Typedef(ref type_) = *named {
+            if let Some(IsNullable { is_nullable, content: Primitive::String }) = type_.get_primitive(self.grammar) {
+                return Some(is_nullable)
+            }
+        }
+        None
+    }
     fn encode_structure<'b>(&self, object: &'b Object, fields: &'b [Field], node: &NodeName) -> Result<Vec<(&'b str, B::Tree)>, Error<E>> {
         let mut result = Vec::with_capacity(fields.len());
         'fields: for field in fields {
@@ -16,6 +16,9 @@ extern crate rand;
 
 pub mod annotate;
 
+/// Structural diffs between ASTs.
+pub mod diff;
+
 /// Generic instance of `Spec` representing the es6 AST.
 pub mod es6;
 
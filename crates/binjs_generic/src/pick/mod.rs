@@ -1,5 +1,13 @@
 use util::pick;
 
+/// Generating random ASTs that are also valid JavaScript.
+pub mod valid;
+pub use self::valid::ValidPicker;
+
+/// Generating random ASTs that resemble a corpus.
+pub mod model;
+pub use self::model::{ Model, ModelPicker };
+
 use binjs_meta::spec::*;
 
 use json;
@@ -95,7 +103,8 @@ impl Pick for Type {
 impl Pick for Interface {
     /// Generate a random instance of this interface matching the syntax.
     fn random<T: rand::Rng>(&self, syntax: &Spec, rng: &mut T, depth_limit: isize) -> JSON {
-        let mut obj = json::object::Object::with_capacity(self.contents().fields().len());
+        let mut obj = json::object::Object::with_capacity(self.contents().fields().len() + 1);
+        obj.insert("type", json::from(self.name().to_str()));
         for field in self.contents().fields() {
             let value = field.type_().random(syntax, rng, depth_limit - 1);
             obj.insert(field.name().to_str(), value);
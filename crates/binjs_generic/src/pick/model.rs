@@ -0,0 +1,460 @@
+//! A probability model of ASTs, learnt from a corpus.
+//!
+//! For each (interface, field), the model records how often each kind of value
+//! appears in the field, the lengths of lists and the values of strings. A
+//! `ModelPicker` then samples from the model to produce ASTs that resemble the
+//! corpus, falling back to the uniform choices of `Picker` where the model has
+//! no data.
+
+use pick::Pick;
+use util::pick;
+
+use binjs_meta::spec::*;
+
+use json;
+use json::JsonValue as JSON;
+use json::object::Object;
+use rand;
+
+use std;
+use std::collections::BTreeMap;
+use std::io::{ Read, Write };
+
+/// Kinds of values that are not interfaces.
+pub const KIND_NULL: &'static str = "null";
+pub const KIND_STRING: &'static str = "string";
+pub const KIND_NUMBER: &'static str = "number";
+pub const KIND_BOOLEAN: &'static str = "boolean";
+
+/// Used when the model has no data for a list.
+const MAX_ARRAY_LEN: usize = 16;
+
+/// Used when the model has no data for a string.
+const MAX_STRING_LEN: usize = 10;
+
+/// Number of occurrences of each value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution<T: Ord> {
+    counts: BTreeMap<T, u64>,
+    total: u64,
+}
+impl<T: Ord> Default for Distribution<T> {
+    fn default() -> Self {
+        Distribution {
+            counts: BTreeMap::new(),
+            total: 0,
+        }
+    }
+}
+impl<T: Ord> Distribution<T> {
+    pub fn add(&mut self, value: T) {
+        self.add_n(value, 1)
+    }
+
+    pub fn add_n(&mut self, value: T, n: u64) {
+        *self.counts.entry(value).or_insert(0) += n;
+        self.total += n;
+    }
+
+    pub fn count(&self, value: &T) -> u64 {
+        self.counts.get(value)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn iter(&self) -> std::collections::btree_map::Iter<T, u64> {
+        self.counts.iter()
+    }
+
+    /// Sample a value among those accepted by `filter`, with a probability
+    /// proportional to its number of occurrences.
+    ///
+    /// Returns `None` if no value is accepted.
+    pub fn sample<R: rand::Rng, F: Fn(&T) -> bool>(&self, rng: &mut R, filter: F) -> Option<&T> {
+        let total : u64 = self.counts.iter()
+            .filter(|&(value, _)| filter(value))
+            .map(|(_, count)| count)
+            .sum();
+        if total == 0 {
+            return None
+        }
+        let mut index = rng.gen_range(0, total);
+        for (value, &count) in &self.counts {
+            if !filter(value) {
+                continue;
+            }
+            if index < count {
+                return Some(value)
+            }
+            index -= count;
+        }
+        unreachable!()
+    }
+
+    /// Keep only the `max` most frequent values.
+    pub fn truncate(&mut self, max: usize) where T: Clone {
+        if self.counts.len() <= max {
+            return
+        }
+        let mut by_count : Vec<_> = self.counts.iter()
+            .map(|(value, &count)| (count, value.clone()))
+            .collect();
+        // Stable sort, so ties are broken by value.
+        by_count.sort_by(|a, b| b.0.cmp(&a.0));
+        by_count.truncate(max);
+        self.counts = BTreeMap::new();
+        self.total = 0;
+        for (count, value) in by_count {
+            self.add_n(value, count);
+        }
+    }
+}
+
+/// The distributions observed in a field.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldModel {
+    /// The kinds of values, i.e. the name of an interface or one of the
+    /// `KIND_*` constants. For a list, the kinds of its items.
+    pub kinds: Distribution<String>,
+
+    /// The lengths of lists.
+    pub lengths: Distribution<usize>,
+
+    /// The values of strings, including string enums.
+    pub strings: Distribution<String>,
+}
+impl FieldModel {
+    fn learn(&mut self, value: &JSON) {
+        let kind = match *value {
+            JSON::Null => KIND_NULL,
+            JSON::Boolean(_) => KIND_BOOLEAN,
+            JSON::Number(_) => KIND_NUMBER,
+            JSON::Object(ref object) => match object.get("type").and_then(JSON::as_str) {
+                Some(kind) => kind,
+                None => return
+            },
+            JSON::Array(ref items) => {
+                self.lengths.add(items.len());
+                for item in items {
+                    self.learn(item);
+                }
+                return
+            }
+            _ => {
+                let string = value.as_str()
+                    .expect("Unexpected JSON value");
+                self.strings.add(string.to_string());
+                KIND_STRING
+            }
+        };
+        self.kinds.add(kind.to_string());
+    }
+
+    fn to_json(&self) -> JSON {
+        let mut result = Object::new();
+        result.insert("kinds", Self::distribution_to_json(&self.kinds));
+        result.insert("lengths", Self::distribution_to_json(&self.lengths));
+        result.insert("strings", Self::distribution_to_json(&self.strings));
+        JSON::Object(result)
+    }
+
+    fn distribution_to_json<T: Ord + ToString>(distribution: &Distribution<T>) -> JSON {
+        let mut result = Object::new();
+        for (value, &count) in distribution.iter() {
+            result.insert(&value.to_string(), json::from(count));
+        }
+        JSON::Object(result)
+    }
+
+    fn from_json(value: &JSON) -> Result<Self, std::io::Error> {
+        let mut result = FieldModel::default();
+        for (value, count) in Self::entries(&value["kinds"])? {
+            result.kinds.add_n(value.to_string(), count);
+        }
+        for (value, count) in Self::entries(&value["lengths"])? {
+            let length = value.parse()
+                .map_err(|_| invalid_data(format!("Invalid length {:?}", value)))?;
+            result.lengths.add_n(length, count);
+        }
+        for (value, count) in Self::entries(&value["strings"])? {
+            result.strings.add_n(value.to_string(), count);
+        }
+        Ok(result)
+    }
+
+    fn entries(value: &JSON) -> Result<Vec<(&str, u64)>, std::io::Error> {
+        let mut result = vec![];
+        match *value {
+            JSON::Object(ref object) => {
+                for (value, count) in object.iter() {
+                    let count = count.as_u64()
+                        .ok_or_else(|| invalid_data(format!("Invalid count {:?} for {:?}", count, value)))?;
+                    result.push((value, count));
+                }
+            }
+            JSON::Null => {}
+            _ => return Err(invalid_data(format!("Expected a distribution, got {}", value)))
+        }
+        Ok(result)
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// A probability model of ASTs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Model {
+    /// The kinds of roots.
+    root: FieldModel,
+
+    /// The model of each (interface, field).
+    fields: BTreeMap<String, BTreeMap<String, FieldModel>>,
+}
+impl Model {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn root(&self) -> &FieldModel {
+        &self.root
+    }
+
+    pub fn field(&self, interface: &str, field: &str) -> Option<&FieldModel> {
+        self.fields.get(interface)
+            .and_then(|fields| fields.get(field))
+    }
+
+    /// Add an AST to the model.
+    pub fn learn(&mut self, ast: &JSON) {
+        self.root.learn(ast);
+        self.learn_fields(ast);
+    }
+
+    fn learn_fields(&mut self, value: &JSON) {
+        match *value {
+            JSON::Object(ref object) => {
+                let interface = match object.get("type").and_then(JSON::as_str) {
+                    Some(interface) => interface,
+                    None => return
+                };
+                for (name, field) in object.iter() {
+                    if name == "type" {
+                        continue;
+                    }
+                    self.fields.entry(interface.to_string())
+                        .or_insert_with(BTreeMap::new)
+                        .entry(name.to_string())
+                        .or_insert_with(FieldModel::default)
+                        .learn(field);
+                    self.learn_fields(field);
+                }
+            }
+            JSON::Array(ref items) => {
+                for item in items {
+                    self.learn_fields(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Keep only the `max` most frequent strings of each field, to limit the
+    /// size of the model.
+    pub fn truncate_strings(&mut self, max: usize) {
+        self.root.strings.truncate(max);
+        for fields in self.fields.values_mut() {
+            for field in fields.values_mut() {
+                field.strings.truncate(max);
+            }
+        }
+    }
+
+    /// Save the model, as JSON.
+    pub fn save<W: Write>(&self, out: &mut W) -> Result<(), std::io::Error> {
+        let mut fields = Object::new();
+        for (interface, interface_fields) in &self.fields {
+            let mut result = Object::new();
+            for (name, field) in interface_fields {
+                result.insert(name, field.to_json());
+            }
+            fields.insert(interface, JSON::Object(result));
+        }
+        let mut result = Object::new();
+        result.insert("root", self.root.to_json());
+        result.insert("fields", JSON::Object(fields));
+        out.write_all(JSON::Object(result).pretty(2).as_bytes())
+    }
+
+    /// Load a model saved with `save`.
+    pub fn load<R: Read>(mut input: R) -> Result<Self, std::io::Error> {
+        let mut source = String::new();
+        input.read_to_string(&mut source)?;
+        let json = json::parse(&source)
+            .map_err(|err| invalid_data(format!("Invalid model: {:?}", err)))?;
+
+        let mut result = Model::new();
+        result.root = FieldModel::from_json(&json["root"])?;
+        if let JSON::Object(ref fields) = json["fields"] {
+            for (interface, interface_fields) in fields.iter() {
+                let interface_fields = match *interface_fields {
+                    JSON::Object(ref object) => object,
+                    _ => return Err(invalid_data(format!("Invalid fields for {}", interface)))
+                };
+                let mut models = BTreeMap::new();
+                for (name, field) in interface_fields.iter() {
+                    models.insert(name.to_string(), FieldModel::from_json(field)?);
+                }
+                result.fields.insert(interface.to_string(), models);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Generate random ASTs by sampling from a `Model`.
+pub struct ModelPicker<'a> {
+    model: &'a Model,
+}
+impl<'a> ModelPicker<'a> {
+    pub fn new(model: &'a Model) -> Self {
+        ModelPicker {
+            model
+        }
+    }
+}
+impl<'a> Pick for ModelPicker<'a> {
+    /// Generate a random AST matching the grammar.
+    ///
+    /// `depth_limit` is used as *hint* to control the depth of the tree
+    fn random<T: rand::Rng>(&self, syntax: &Spec, rng: &mut T, depth_limit: isize) -> JSON {
+        let mut sampler = Sampler {
+            model: self.model,
+            spec: syntax,
+            rng,
+        };
+        let root = TypeSpec::NamedType(syntax.get_root_name().clone());
+        sampler.type_spec(&root, Some(self.model.root()), depth_limit)
+    }
+}
+
+struct Sampler<'a, T: 'a + rand::Rng> {
+    model: &'a Model,
+    spec: &'a Spec,
+    rng: &'a mut T,
+}
+impl<'a, T: rand::Rng> Sampler<'a, T> {
+    /// Generate a value of type `type_` for a field modelled by `field`, if any.
+    fn type_(&mut self, type_: &Type, field: Option<&'a FieldModel>, depth: isize) -> JSON {
+        if type_.is_optional() {
+            if depth <= 0 {
+                return JSON::Null
+            }
+            let is_null = match field {
+                Some(field) if field.kinds.total() > 0 =>
+                    self.rng.gen_range(0, field.kinds.total()) < field.kinds.count(&KIND_NULL.to_string()),
+                // Same as `Picker`.
+                _ => self.rng.gen_range(0, 10) > 0
+            };
+            if is_null {
+                return JSON::Null
+            }
+        }
+        self.type_spec(type_.spec(), field, depth)
+    }
+
+    fn type_spec(&mut self, spec: &TypeSpec, field: Option<&'a FieldModel>, depth: isize) -> JSON {
+        match *spec {
+            TypeSpec::Array { supports_empty, contents: ref type_ } => {
+                if supports_empty && depth <= 0 {
+                    return array![]
+                }
+                let min = if supports_empty { 0 } else { 1 };
+                let sampled = field.and_then(|field| field.lengths.sample(&mut *self.rng, |&len| len >= min))
+                    .cloned();
+                let len = match sampled {
+                    Some(len) => len,
+                    None => self.rng.gen_range(min, MAX_ARRAY_LEN)
+                };
+                let mut buf = Vec::with_capacity(len);
+                for _ in 0..len {
+                    buf.push(self.type_(type_, field, depth - 1));
+                }
+                JSON::Array(buf)
+            }
+            TypeSpec::NamedType(ref name) =>
+                self.named_type(name, field, depth),
+            TypeSpec::TypeSum(ref sum) => {
+                let spec = self.spec;
+                let sampled = field.and_then(|field| field.kinds.sample(&mut *self.rng, |kind| {
+                    spec.get_node_name(kind)
+                        .map(|name| sum.interfaces().contains(name))
+                        .unwrap_or(false)
+                }));
+                match sampled.and_then(|kind| spec.get_node_name(kind)) {
+                    Some(name) => self.named_type(name, field, depth),
+                    None => {
+                        let type_ = pick(&mut *self.rng, sum.types());
+                        self.type_spec(type_, field, depth)
+                    }
+                }
+            }
+            TypeSpec::Boolean =>
+                JSON::Boolean(self.rng.gen()),
+            TypeSpec::String => {
+                let sampled = field.and_then(|field| field.strings.sample(&mut *self.rng, |_| true));
+                match sampled {
+                    Some(string) => json::from(string.as_str()),
+                    None => {
+                        let len = self.rng.gen_range(0, MAX_STRING_LEN);
+                        let string : String = self.rng.gen_ascii_chars().take(len).collect();
+                        json::from(string)
+                    }
+                }
+            }
+            TypeSpec::Number =>
+                json::from(self.rng.next_f64()),
+            TypeSpec::Void =>
+                JSON::Null,
+            TypeSpec::Offset =>
+                json::from(self.rng.gen_range(0, u32::max_value()))
+        }
+    }
+
+    fn named_type(&mut self, name: &NodeName, field: Option<&'a FieldModel>, depth: isize) -> JSON {
+        match self.spec.get_type_by_name(name) {
+            Some(NamedType::Interface(ref interface)) =>
+                self.interface(interface, depth),
+            Some(NamedType::Typedef(ref type_)) =>
+                self.type_(type_, field, depth),
+            Some(NamedType::StringEnum(ref string_enum)) => {
+                let strings = string_enum.strings();
+                let sampled = field.and_then(|field| field.strings.sample(&mut *self.rng, |string| strings.contains(string)));
+                let string = match sampled {
+                    Some(string) => string,
+                    None => pick(&mut *self.rng, strings)
+                };
+                json::from(string.as_str())
+            }
+            None => panic!("Could not find named type {:?}", name)
+        }
+    }
+
+    fn interface(&mut self, interface: &Interface, depth: isize) -> JSON {
+        let model = self.model;
+        let name = interface.name().to_str();
+        let mut obj = Object::with_capacity(interface.contents().fields().len() + 1);
+        obj.insert("type", json::from(name));
+        for field in interface.contents().fields() {
+            let field_name = field.name().to_str();
+            let value = self.type_(field.type_(), model.field(name, field_name), depth - 1);
+            obj.insert(field_name, value);
+        }
+        JSON::Object(obj)
+    }
+}
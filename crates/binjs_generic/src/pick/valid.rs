@@ -0,0 +1,423 @@
+//! A `Pick` producing ASTs that are valid JavaScript.
+//!
+//! `Picker` only guarantees that its output matches the grammar, so it may
+//! produce `break` outside of loops, identifiers that are not identifiers,
+//! invalid regular expressions, etc. `ValidPicker` tracks the context of the
+//! node being generated to avoid early errors:
+//!
+//! - bindings receive fresh names, so declarations never conflict, while
+//!   references pick either bound names or a few global names;
+//! - `return`, `break`, `continue`, `yield`, `await` and `new.target` only
+//!   appear where they are allowed;
+//! - declarations only appear in statement lists, and constants and
+//!   destructuring declarations have an initializer;
+//! - regular expressions are picked from a small subset of the grammar;
+//! - strict code (i.e. classes) contains neither `with` nor `delete`.
+//!
+//! To keep the rules simple, the output never contains directives, `super`,
+//! labelled `continue`, modules or skippable nodes. Scope annotations are
+//! computed by `AnnotationVisitor` once the AST is complete.
+
+use pick::Pick;
+use util::pick;
+
+use binjs_es6::scopes::AnnotationVisitor;
+use binjs_meta::spec::*;
+
+use json;
+use json::JsonValue as JSON;
+use json::object::Object;
+use rand;
+
+/// Names that may be referenced without being bound in the AST.
+const GLOBAL_NAMES: [&'static str; 6] = ["a", "b", "x", "y", "Math", "Object"];
+
+/// Names used for properties.
+const PROPERTY_NAMES: [&'static str; 6] = ["a", "b", "foo", "bar", "length", "value"];
+
+/// Flags of regular expressions, in the order in which they are written.
+const REGEXP_FLAGS: [char; 5] = ['g', 'i', 'm', 'u', 'y'];
+
+/// Atoms of regular expressions, valid with or without the `u` flag.
+const REGEXP_ATOMS: [&'static str; 11] = ["a", "b", "z", "0", "9", ".", "\\d", "\\w", "\\s", "[a-z]", "[^0-9]"];
+
+/// Assertions of regular expressions. These may not be quantified.
+const REGEXP_ASSERTIONS: [&'static str; 3] = ["^", "$", "\\b"];
+
+const REGEXP_QUANTIFIERS: [&'static str; 6] = ["*", "+", "?", "{1,3}", "*?", "+?"];
+
+/// Maximal nesting of groups in regular expressions.
+const MAX_REGEXP_DEPTH: usize = 2;
+
+/// Smaller than for `Picker`, to keep the source code readable.
+const MAX_ARRAY_LEN: usize = 8;
+
+const MAX_STRING_LEN: usize = 10;
+
+const MAX_TEMPLATE_EXPRESSIONS: usize = 3;
+
+/// Generate random `Script`s that are valid JavaScript, with scope annotations.
+pub struct ValidPicker;
+impl Pick for ValidPicker {
+    /// Generate a random `Script`.
+    ///
+    /// `depth_limit` is used as *hint* to control the depth of the tree.
+    fn random<T: rand::Rng>(&self, syntax: &Spec, rng: &mut T, depth_limit: isize) -> JSON {
+        let script = syntax.get_node_name("Script")
+            .and_then(|name| syntax.get_interface_by_name(name))
+            .expect("Interface `Script` doesn't exist");
+        let mut generator = Generator {
+            spec: syntax,
+            rng,
+            context: Context::default(),
+            fresh: 0,
+            bound: vec![],
+        };
+        let mut ast = generator.interface(script, depth_limit);
+        AnnotationVisitor::new()
+            .annotate(&mut ast);
+        ast
+    }
+}
+
+/// What is allowed at the current position in the AST.
+#[derive(Clone, Default)]
+struct Context {
+    /// `return` is allowed.
+    in_function: bool,
+
+    /// `new.target` is allowed.
+    in_non_arrow_function: bool,
+
+    /// `yield` is allowed.
+    in_generator: bool,
+
+    /// `await` is allowed.
+    in_async: bool,
+
+    /// `continue` is allowed.
+    in_loop: bool,
+
+    /// `break` without a label is allowed if `in_loop` or `in_switch`.
+    in_switch: bool,
+
+    /// We are in strict code, i.e. in a class.
+    strict: bool,
+
+    /// The labels of the enclosing statements, within the current function.
+    labels: Vec<String>,
+
+    /// The kind of the enclosing `VariableDeclaration`, if any.
+    declaration_kind: Option<String>,
+
+    /// We are generating the body of e.g. an `if` or a loop, which may not be a declaration.
+    single_statement: bool,
+}
+
+struct Generator<'a, T: 'a + rand::Rng> {
+    spec: &'a Spec,
+    rng: &'a mut T,
+    context: Context,
+
+    /// The number of fresh names generated so far.
+    fresh: usize,
+
+    /// All the names bound so far, in any scope.
+    bound: Vec<String>,
+}
+impl<'a, T: rand::Rng> Generator<'a, T> {
+    fn type_(&mut self, type_: &Type, depth: isize) -> JSON {
+        if type_.is_optional() {
+            // 10% chance of returning the default value
+            if depth <= 0 || self.rng.gen_range(0, 10) > 0 {
+                return JSON::Null
+            }
+        }
+        self.type_spec(type_.spec(), depth)
+    }
+
+    fn type_spec(&mut self, spec: &TypeSpec, depth: isize) -> JSON {
+        match *spec {
+            TypeSpec::Array { supports_empty, contents: ref type_ } => {
+                if supports_empty && depth <= 0 {
+                    return array![]
+                }
+                let min = if supports_empty { 0 } else { 1 };
+                let len = self.rng.gen_range(min, MAX_ARRAY_LEN);
+                let mut buf = Vec::with_capacity(len);
+                for _ in 0..len {
+                    buf.push(self.type_(type_, depth - 1));
+                }
+                JSON::Array(buf)
+            }
+            TypeSpec::NamedType(ref name) =>
+                self.named_type(name, depth),
+            TypeSpec::TypeSum(ref sum) => {
+                // Sort the candidates, as the order of a `HashSet` would make the output
+                // depend on more than the rng.
+                let mut candidates : Vec<_> = sum.interfaces()
+                    .iter()
+                    .filter(|name| self.is_allowed(name))
+                    .cloned()
+                    .collect();
+                candidates.sort();
+                assert!(candidates.len() > 0, "No valid choice in {:?}", sum.types());
+                let name = pick(&mut *self.rng, &candidates).clone();
+                self.named_type(&name, depth)
+            }
+            TypeSpec::Boolean =>
+                JSON::Boolean(self.rng.gen()),
+            TypeSpec::String => {
+                let len = self.rng.gen_range(0, MAX_STRING_LEN);
+                let string : String = self.rng.gen_ascii_chars().take(len).collect();
+                json::from(string)
+            }
+            TypeSpec::Number =>
+                json::from(self.rng.next_f64()),
+            TypeSpec::Void =>
+                JSON::Null,
+            TypeSpec::Offset =>
+                json::from(self.rng.gen_range(0, u32::max_value()))
+        }
+    }
+
+    fn named_type(&mut self, name: &NodeName, depth: isize) -> JSON {
+        match name.to_str() {
+            "Identifier" => return json::from(self.reference()),
+            "IdentifierName" => return json::from(*pick(&mut *self.rng, &PROPERTY_NAMES)),
+            "Label" => return json::from(self.fresh("l")),
+            _ => {}
+        }
+        match self.spec.get_type_by_name(name) {
+            Some(NamedType::Interface(ref interface)) =>
+                self.interface(interface, depth),
+            Some(NamedType::Typedef(ref type_)) =>
+                self.type_(type_, depth),
+            Some(NamedType::StringEnum(ref string_enum)) => {
+                // `delete identifier` is an error in strict code.
+                let strict = self.context.strict;
+                let strings : Vec<_> = string_enum.strings()
+                    .iter()
+                    .filter(|string| !(strict && *string == "delete"))
+                    .collect();
+                json::from(pick(&mut *self.rng, &strings).as_str())
+            }
+            None => panic!("Could not find named type {:?}", name)
+        }
+    }
+
+    /// Check whether an interface may appear in the current context.
+    fn is_allowed(&self, name: &NodeName) -> bool {
+        let context = &self.context;
+        match name.to_str() {
+            "ReturnStatement" => context.in_function,
+            "BreakStatement" => context.in_loop || context.in_switch || context.labels.len() > 0,
+            "ContinueStatement" => context.in_loop,
+            "YieldExpression" | "YieldStarExpression" => context.in_generator,
+            "AwaitExpression" => context.in_async,
+            "NewTargetExpression" => context.in_non_arrow_function,
+            "WithStatement" => !context.strict,
+            "VariableDeclaration" | "ClassDeclaration" | "EagerFunctionDeclaration" => !context.single_statement,
+            "Super" | "Module" => false,
+            name => !name.starts_with("Skippable")
+        }
+    }
+
+    fn interface(&mut self, interface: &Interface, depth: isize) -> JSON {
+        let name = interface.name().to_str();
+        let mut obj = Object::with_capacity(interface.contents().fields().len() + 1);
+        obj.insert("type", json::from(name));
+        for field in interface.contents().fields() {
+            let field_name = field.name().to_str();
+            let saved = self.enter_field(name, field_name, field.type_(), &obj);
+            let value = self.field(name, field_name, field.type_(), &obj, depth - 1);
+            self.context = saved;
+            obj.insert(field_name, value);
+        }
+        JSON::Object(obj)
+    }
+
+    /// Update the context before generating a field, returning the previous context.
+    ///
+    /// `obj` contains the fields of the interface generated so far.
+    fn enter_field(&mut self, interface: &str, field: &str, type_: &Type, obj: &Object) -> Context {
+        let saved = self.context.clone();
+        let context = &mut self.context;
+
+        context.single_statement = match *type_.spec() {
+            TypeSpec::NamedType(ref name) => name.to_str() == "Statement",
+            _ => false
+        };
+
+        let is_arrow = interface == "EagerArrowExpression";
+        let is_function = is_arrow || match interface {
+            "EagerFunctionDeclaration" | "EagerFunctionExpression" | "EagerMethod" | "EagerGetter" | "EagerSetter" => true,
+            _ => false
+        };
+        if is_function && (field == "params" || field == "param" || field == "body") {
+            context.in_function = true;
+            context.in_loop = false;
+            context.in_switch = false;
+            context.labels.clear();
+            if !is_arrow {
+                context.in_non_arrow_function = true;
+            }
+            // `yield` and `await` are not allowed in parameters.
+            let is_body = field == "body";
+            context.in_generator = is_body && obj["isGenerator"].as_bool().unwrap_or(false);
+            context.in_async = is_body && obj["isAsync"].as_bool().unwrap_or(false);
+        }
+
+        match (interface, field) {
+            ("ClassDeclaration", _) | ("ClassExpression", _) =>
+                context.strict = true,
+            ("DoWhileStatement", "body") | ("ForInStatement", "body") | ("ForOfStatement", "body")
+            | ("ForStatement", "body") | ("WhileStatement", "body") =>
+                context.in_loop = true,
+            ("SwitchStatement", "cases") | ("SwitchStatementWithDefault", "preDefaultCases")
+            | ("SwitchStatementWithDefault", "defaultCase") | ("SwitchStatementWithDefault", "postDefaultCases") =>
+                context.in_switch = true,
+            ("LabelledStatement", "body") => {
+                if let Some(label) = obj["label"].as_str() {
+                    context.labels.push(label.to_string())
+                }
+            }
+            ("VariableDeclaration", "declarators") =>
+                context.declaration_kind = obj["kind"].as_str().map(str::to_string),
+            _ => {}
+        }
+        saved
+    }
+
+    fn field(&mut self, interface: &str, field: &str, type_: &Type, obj: &Object, depth: isize) -> JSON {
+        match (interface, field) {
+            (_, "scope") | (_, "parameterScope") | (_, "bodyScope") => {
+                // Computed once the AST is complete.
+                JSON::Null
+            }
+            (_, "directives") => {
+                // A directive could make the code strict.
+                array![]
+            }
+            ("BindingIdentifier", "name") => {
+                let name = self.fresh("v");
+                self.bound.push(name.clone());
+                json::from(name)
+            }
+            ("BreakStatement", "label") => {
+                let needs_label = !self.context.in_loop && !self.context.in_switch;
+                if self.context.labels.len() > 0 && (needs_label || self.rng.gen()) {
+                    json::from(pick(&mut *self.rng, &self.context.labels).as_str())
+                } else {
+                    JSON::Null
+                }
+            }
+            ("ContinueStatement", "label") => {
+                // A labelled `continue` would need to know whether the label is that of a loop.
+                JSON::Null
+            }
+            ("LiteralRegExpExpression", "pattern") => {
+                let mut pattern = String::new();
+                self.regexp_disjunction(&mut pattern, MAX_REGEXP_DEPTH);
+                json::from(pattern)
+            }
+            ("LiteralRegExpExpression", "flags") => {
+                let mut flags = String::new();
+                for flag in &REGEXP_FLAGS {
+                    if self.rng.gen_range(0, 4) == 0 {
+                        flags.push(*flag);
+                    }
+                }
+                json::from(flags)
+            }
+            ("TemplateExpression", "elements") =>
+                self.template_elements(depth),
+            ("VariableDeclarator", "init") => {
+                // Constants and destructuring declarations need an initializer.
+                let is_const = self.context.declaration_kind.as_ref()
+                    .map(|kind| kind == "const")
+                    .unwrap_or(false);
+                let is_pattern = obj["binding"]["type"].as_str() != Some("BindingIdentifier");
+                if is_const || is_pattern {
+                    self.type_spec(type_.spec(), depth)
+                } else {
+                    self.type_(type_, depth)
+                }
+            }
+            _ => self.type_(type_, depth)
+        }
+    }
+
+    /// Alternate `TemplateElement`s and `Expression`s, starting and ending with
+    /// a `TemplateElement`.
+    fn template_elements(&mut self, depth: isize) -> JSON {
+        let spec = self.spec;
+        let element = spec.get_node_name("TemplateElement")
+            .expect("Interface `TemplateElement` doesn't exist")
+            .clone();
+        let expression = spec.get_node_name("Expression")
+            .expect("Type `Expression` doesn't exist")
+            .clone();
+        let len = if depth <= 0 { 0 } else { self.rng.gen_range(0, MAX_TEMPLATE_EXPRESSIONS) };
+        let mut buf = Vec::with_capacity(2 * len + 1);
+        buf.push(self.named_type(&element, depth - 1));
+        for _ in 0..len {
+            buf.push(self.named_type(&expression, depth - 1));
+            buf.push(self.named_type(&element, depth - 1));
+        }
+        JSON::Array(buf)
+    }
+
+    fn regexp_disjunction(&mut self, buf: &mut String, depth: usize) {
+        let alternatives = self.rng.gen_range(1, 3);
+        for i in 0..alternatives {
+            if i > 0 {
+                buf.push('|');
+            }
+            // Never empty, as `//` is not a regular expression.
+            let terms = self.rng.gen_range(1, 4);
+            for _ in 0..terms {
+                self.regexp_term(buf, depth);
+            }
+        }
+    }
+
+    fn regexp_term(&mut self, buf: &mut String, depth: usize) {
+        let choices = if depth > 0 { 8 } else { 6 };
+        match self.rng.gen_range(0, choices) {
+            0 => {
+                buf.push_str(pick(&mut *self.rng, &REGEXP_ASSERTIONS));
+                return
+            }
+            6 => {
+                buf.push('(');
+                self.regexp_disjunction(buf, depth - 1);
+                buf.push(')');
+            }
+            7 => {
+                buf.push_str("(?:");
+                self.regexp_disjunction(buf, depth - 1);
+                buf.push(')');
+            }
+            _ => buf.push_str(pick(&mut *self.rng, &REGEXP_ATOMS))
+        }
+        if self.rng.gen_range(0, 3) == 0 {
+            buf.push_str(pick(&mut *self.rng, &REGEXP_QUANTIFIERS));
+        }
+    }
+
+    /// A name that has not been used yet.
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        format!("{}{}", prefix, self.fresh)
+    }
+
+    /// A name to reference, preferably one that is bound somewhere in the AST.
+    fn reference(&mut self) -> String {
+        if self.bound.len() > 0 && self.rng.gen_range(0, 4) > 0 {
+            pick(&mut *self.rng, &self.bound).clone()
+        } else {
+            pick(&mut *self.rng, &GLOBAL_NAMES).to_string()
+        }
+    }
+}
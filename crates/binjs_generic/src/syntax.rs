@@ -21,6 +21,352 @@ impl Comparator {
     pub fn compare(syntax: &Spec, left: &JSON, right: &JSON) -> Result<bool, ASTError> {
         syntax.get_root().compare(syntax, left, right)
     }
+
+    /// Compare two ASTs, returning the list of all mismatches rather than a `bool`.
+    ///
+    /// Differences accepted by `tolerances` are not reported.
+    pub fn mismatches(syntax: &Spec, left: &JSON, right: &JSON, tolerances: &Tolerances) -> Result<Vec<Mismatch>, ASTError> {
+        let collector = ComparisonWalker::new(syntax, MismatchCollector { mismatches: vec![] })
+            .with_tolerances(tolerances.clone())
+            .compare(left, right)?;
+        Ok(collector.mismatches)
+    }
+}
+
+/// Differences that `Comparator::mismatches` should not report.
+#[derive(Clone, Debug, Default)]
+pub struct Tolerances {
+    /// If `true`, ignore scope annotations (`AssertedBlockScope`,
+    /// `AssertedVarScope`, `AssertedParameterScope`).
+    pub ignore_scopes: bool,
+}
+
+/// The interfaces representing scope annotations.
+const SCOPE_INTERFACES: [&'static str; 3] = ["AssertedBlockScope", "AssertedVarScope", "AssertedParameterScope"];
+
+/// A difference between two ASTs.
+#[derive(Debug)]
+pub struct Mismatch {
+    /// The path to the value in the JSON, e.g. `.statements[3].expression.callee`.
+    pub json_path: String,
+
+    /// The innermost interface and field containing the value, if any.
+    pub interface: Option<NodeName>,
+    pub field: Option<FieldName>,
+
+    /// The values, as found in the left and right ASTs.
+    pub left: JSON,
+    pub right: JSON,
+}
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{path} ({interface}.{field}): {left} != {right}",
+            path = if self.json_path.len() == 0 { "<root>" } else { &self.json_path },
+            interface = self.interface.as_ref().map(|x| x.to_str()).unwrap_or("?"),
+            field = self.field.as_ref().map(|x| x.to_str()).unwrap_or("?"),
+            left = self.left.dump(),
+            right = self.right.dump())
+    }
+}
+
+/// Implementation of `Comparator::mismatches`.
+struct MismatchCollector {
+    mismatches: Vec<Mismatch>,
+}
+impl ComparisonVisitor for MismatchCollector {
+    fn update(&mut self, path: &WalkPath, json_path: &[String], left: &JSON, right: &JSON) {
+        let (interface, field) = match path.get(0) {
+            Some(item) => (Some(item.interface.clone()), Some(item.field.clone())),
+            None => (None, None)
+        };
+        self.mismatches.push(Mismatch {
+            json_path: json_path.concat(),
+            interface,
+            field,
+            left: left.clone(),
+            right: right.clone(),
+        })
+    }
+}
+
+/// Beyond this number of pairs of items, we do not attempt to align
+/// the middle of two lists, as this would take too much time/memory.
+/// Items are then simply compared position by position.
+const MAX_ALIGNMENT_CELLS: usize = 16_000_000;
+
+/// Callbacks used by a `ComparisonWalker` to report the differences between two ASTs.
+///
+/// `path` is the list of interfaces and fields containing the values, `json_path`
+/// the components of their path in the JSON, e.g. `.statements`, `[3]`.
+pub trait ComparisonVisitor {
+    /// The values `left` and `right` differ.
+    fn update(&mut self, path: &WalkPath, json_path: &[String], left: &JSON, right: &JSON);
+
+    /// With list alignment, item `index` of a list of the left AST has no
+    /// counterpart in the right AST.
+    fn delete(&mut self, _path: &WalkPath, _json_path: &[String], _index: usize, _value: &JSON) {
+        // Do nothing
+    }
+
+    /// With list alignment, item `index` of a list of the right AST has no
+    /// counterpart in the left AST.
+    fn insert(&mut self, _path: &WalkPath, _json_path: &[String], _index: usize, _value: &JSON) {
+        // Do nothing
+    }
+}
+
+/// Walk two ASTs side by side, restricting comparison to the items
+/// that appear in the grammar, and report their differences to a
+/// `ComparisonVisitor`.
+pub struct ComparisonWalker<'a, V> where V: ComparisonVisitor {
+    syntax: &'a Spec,
+    visitor: V,
+    tolerances: Tolerances,
+
+    /// If `true`, lists are aligned, so that inserting or removing an item is
+    /// reported as such rather than as a change to every subsequent item.
+    /// Otherwise, lists of distinct lengths are reported as a single update.
+    align_lists: bool,
+
+    path: WalkPath,
+    json_path: Vec<String>,
+}
+impl<'a, V> ComparisonWalker<'a, V> where V: ComparisonVisitor {
+    pub fn new(syntax: &'a Spec, visitor: V) -> Self {
+        ComparisonWalker {
+            syntax,
+            visitor,
+            tolerances: Tolerances::default(),
+            align_lists: false,
+            path: WalkPath::new(),
+            json_path: vec![],
+        }
+    }
+
+    /// Do not report the differences accepted by `tolerances`.
+    pub fn with_tolerances(self, tolerances: Tolerances) -> Self {
+        ComparisonWalker {
+            tolerances,
+            ..self
+        }
+    }
+
+    /// Align lists, reporting insertions and deletions of items.
+    ///
+    /// Items are aligned if they are equal, regardless of tolerances.
+    pub fn with_list_alignment(self) -> Self {
+        ComparisonWalker {
+            align_lists: true,
+            ..self
+        }
+    }
+
+    /// Compare two ASTs and return the visitor.
+    ///
+    /// This method assumes that both items are full ASTs.
+    pub fn compare(mut self, left: &JSON, right: &JSON) -> Result<V, ASTError> {
+        let root = self.syntax.get_root();
+        self.named_type(&root, left, right)?;
+        assert_eq!(self.path.len(), 0);
+        Ok(self.visitor)
+    }
+
+    fn update(&mut self, left: &JSON, right: &JSON) {
+        self.visitor.update(&self.path, &self.json_path, left, right)
+    }
+
+    fn named_type(&mut self, named: &NamedType, left: &JSON, right: &JSON) -> Result<(), ASTError> {
+        match *named {
+            NamedType::Interface(ref interface) =>
+                self.interface(interface, left, right),
+            NamedType::Typedef(ref type_) =>
+                self.type_(type_, left, right),
+            NamedType::StringEnum(_) => {
+                if left.as_str().is_none() || right.as_str().is_none() {
+                    return Err(ASTError::InvalidValue {
+                        got: format!("{} =?= {}", left.dump(), right.dump()),
+                        expected: format!("{:?}", named)
+                    })
+                }
+                if left.as_str() != right.as_str() {
+                    self.update(left, right);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn type_(&mut self, type_: &Type, left: &JSON, right: &JSON) -> Result<(), ASTError> {
+        if self.tolerances.ignore_scopes {
+            if let TypeSpec::NamedType(ref name) = *type_.spec() {
+                if SCOPE_INTERFACES.iter().find(|x| **x == name.to_str()).is_some() {
+                    return Ok(())
+                }
+            }
+        }
+        if type_.is_optional() {
+            match (left.is_null(), right.is_null()) {
+                (true, true) => return Ok(()),
+                (false, false) => {},
+                _ => {
+                    self.update(left, right);
+                    return Ok(())
+                }
+            }
+        }
+        self.type_spec(type_.spec(), left, right)
+    }
+
+    fn type_spec(&mut self, spec: &TypeSpec, left: &JSON, right: &JSON) -> Result<(), ASTError> {
+        use json::JsonValue::*;
+        match (spec, left, right) {
+            (&TypeSpec::Number, &Number(ref a), &Number(ref b)) => {
+                if a != b {
+                    self.update(left, right);
+                }
+                Ok(())
+            }
+            (&TypeSpec::Array { contents: ref type_, .. }, &Array(_), &Array(_)) => {
+                if self.align_lists {
+                    return self.aligned_list(type_, left, right)
+                }
+                if left.len() != right.len() {
+                    self.update(left, right);
+                    return Ok(())
+                }
+                for i in 0..left.len() {
+                    self.item(type_, left, i, right, i)?;
+                }
+                Ok(())
+            }
+            (&TypeSpec::NamedType(ref name), _, _) => {
+                let named = self.syntax.get_type_by_name(name)
+                    .ok_or_else(|| ASTError::InvalidType(name.to_str().to_string()))?;
+                self.named_type(&named, left, right)
+            }
+            (&TypeSpec::TypeSum(ref sum), _, _) => {
+                if left["type"].as_str() != right["type"].as_str() {
+                    self.update(left, right);
+                    return Ok(())
+                }
+                let kind = left["type"].as_str()
+                    .ok_or_else(|| ASTError::invalid_value(left, "object with a type"))?;
+                let interface = self.syntax.get_node_name(kind)
+                    .and_then(|name| sum.get_interface(self.syntax, name))
+                    .ok_or_else(|| ASTError::InvalidType(kind.to_string()))?;
+                self.interface(&interface, left, right)
+            }
+            _ => {
+                if !spec.compare(self.syntax, left, right)? {
+                    self.update(left, right);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn interface(&mut self, interface: &Interface, left: &JSON, right: &JSON) -> Result<(), ASTError> {
+        if left["type"].as_str() != right["type"].as_str() {
+            self.update(left, right);
+            return Ok(())
+        }
+        self.path.enter_interface(interface.name().clone());
+        for field in interface.contents().fields() {
+            let index = field.name().to_str();
+            self.path.enter_field(field.name().clone());
+            self.json_path.push(format!(".{}", index));
+            self.type_(field.type_(), &left[index], &right[index])?;
+            self.json_path.pop();
+            self.path.exit_field(field.name().clone());
+        }
+        self.path.exit_interface(interface.name().clone());
+        Ok(())
+    }
+
+    /// Compare item `i` of the list `left` with item `j` of the list `right`.
+    fn item(&mut self, contents: &Type, left: &JSON, i: usize, right: &JSON, j: usize) -> Result<(), ASTError> {
+        self.json_path.push(format!("[{}]", i));
+        let result = self.type_(contents, &left[i], &right[j]);
+        self.json_path.pop();
+        result
+    }
+
+    fn aligned_list(&mut self, contents: &Type, left: &JSON, right: &JSON) -> Result<(), ASTError> {
+        let (left_len, right_len) = (left.len(), right.len());
+
+        // Skip the common prefix and suffix, which are by far the most common case.
+        let mut prefix = 0;
+        while prefix < left_len && prefix < right_len && contents.compare(self.syntax, &left[prefix], &right[prefix])? {
+            prefix += 1;
+        }
+        let mut suffix = 0;
+        while suffix < left_len - prefix && suffix < right_len - prefix
+            && contents.compare(self.syntax, &left[left_len - suffix - 1], &right[right_len - suffix - 1])?
+        {
+            suffix += 1;
+        }
+        let left_middle : Vec<usize> = (prefix .. left_len - suffix).collect();
+        let right_middle : Vec<usize> = (prefix .. right_len - suffix).collect();
+
+        // Align the middle, using the longest common subsequence.
+        let mut matches = vec![];
+        if left_middle.len() * right_middle.len() <= MAX_ALIGNMENT_CELLS {
+            let (n, m) = (left_middle.len(), right_middle.len());
+            let mut equal = vec![vec![false; m]; n];
+            for i in 0..n {
+                for j in 0..m {
+                    equal[i][j] = contents.compare(self.syntax, &left[left_middle[i]], &right[right_middle[j]])?;
+                }
+            }
+            // `lcs[i][j]` is the length of the longest common subsequence of `left_middle[i..]` and `right_middle[j..]`.
+            let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+            for i in (0..n).rev() {
+                for j in (0..m).rev() {
+                    lcs[i][j] =
+                        if equal[i][j] {
+                            lcs[i + 1][j + 1] + 1
+                        } else {
+                            std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+                        };
+                }
+            }
+            let (mut i, mut j) = (0, 0);
+            while i < n && j < m {
+                if equal[i][j] {
+                    matches.push((left_middle[i], right_middle[j]));
+                    i += 1;
+                    j += 1;
+                } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                    i += 1;
+                } else {
+                    j += 1;
+                }
+            }
+        }
+        matches.push((left_len - suffix, right_len - suffix));
+
+        // Between two matches, items that take the same place are compared
+        // recursively, the others are insertions or deletions.
+        let (mut i, mut j) = (prefix, prefix);
+        for (next_i, next_j) in matches {
+            while i < next_i && j < next_j {
+                self.item(contents, left, i, right, j)?;
+                i += 1;
+                j += 1;
+            }
+            while i < next_i {
+                self.visitor.delete(&self.path, &self.json_path, i, &left[i]);
+                i += 1;
+            }
+            while j < next_j {
+                self.visitor.insert(&self.path, &self.json_path, j, &right[j]);
+                j += 1;
+            }
+            i += 1;
+            j += 1;
+        }
+        Ok(())
+    }
 }
 
 impl Compare for TypeSpec {
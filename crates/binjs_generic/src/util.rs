@@ -1,6 +1,8 @@
 use json::JsonValue as JSON;
 use rand;
 
+use std;
+
 /// Return a string describing a JSON value
 /// without dumping the entire AST.
 /// 
@@ -66,3 +68,23 @@ pub fn pick<'a, T: rand::Rng, U,>(rng: &mut T, slice: &'a [U]) -> &'a U {
     let index = rng.gen_range(0, slice.len());
     &slice[index]
 }
+
+/// Drop a JSON value without recursing.
+///
+/// Dropping a JSON value normally recurses once per level of nesting, which
+/// overflows the stack for very deep ASTs, such as those produced by
+/// `io::Decoder` for long chains of binary operators.
+pub fn drop_json(value: JSON) {
+    let mut stack = vec![value];
+    while let Some(mut value) = stack.pop() {
+        match value {
+            JSON::Array(ref mut items) => stack.extend(items.drain(..)),
+            JSON::Object(ref mut object) => {
+                for (_, field) in object.iter_mut() {
+                    stack.push(std::mem::replace(field, JSON::Null));
+                }
+            }
+            _ => {}
+        }
+    }
+}
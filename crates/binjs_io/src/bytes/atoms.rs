@@ -99,7 +99,12 @@ impl<T> AtomsTable<T> where T: Eq + Hash + ToBytes {
         Ok(bytes)
     }
 
-    pub fn read_index<U>(src: &mut U) -> Result<(usize, Self), Error> where U: Read, T: FromBytes + Debug + ToBytes + Clone {
+    /// Read a table written by `write_index`.
+    ///
+    /// Lengths come from the file, so they are not trusted: entries longer
+    /// than `max_entry_bytes` are rejected, and buffers are only allocated
+    /// as data arrives.
+    pub fn read_index<U>(src: &mut U, max_entry_bytes: usize) -> Result<(usize, Self), Error> where U: Read, T: FromBytes + Debug + ToBytes + Clone {
         let mut bytes = 0;
 
         // Read number of entries.
@@ -108,22 +113,27 @@ impl<T> AtomsTable<T> where T: Eq + Hash + ToBytes {
         let number_of_entries = number_of_entries as usize;
 
         // Read length of entries.
-        let mut byte_lengths = Vec::with_capacity(number_of_entries as usize);
+        let mut byte_lengths = Vec::new();
         for _ in 0..number_of_entries {
             let mut length_of_entry = 0;
             bytes += src.read_varnum(&mut length_of_entry)?;
             let length_of_entry = length_of_entry as usize;
+            if length_of_entry > max_entry_bytes {
+                return Err(Error::new(ErrorKind::InvalidData, "Entry too long"));
+            }
             byte_lengths.push(length_of_entry);
         }
 
         // Read actual entries.
-        let mut from_key : HashMap<u32, T> = HashMap::with_capacity(number_of_entries);
-        let mut to_key : HashMap<T, u32> = HashMap::with_capacity(number_of_entries);
+        let mut from_key : HashMap<u32, T> = HashMap::new();
+        let mut to_key : HashMap<T, u32> = HashMap::new();
         for i in 0..number_of_entries {
             let length_of_entry = byte_lengths[i];
-            let mut buf = Vec::with_capacity(length_of_entry);
-            unsafe { buf.set_len(length_of_entry); }
-            src.read_exact(&mut buf)?;
+            let mut buf = Vec::new();
+            src.by_ref().take(length_of_entry as u64).read_to_end(&mut buf)?;
+            if buf.len() != length_of_entry {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated entry"));
+            }
             bytes += length_of_entry;
 
             let data = T::from_bytes(&buf)?;
@@ -191,9 +201,12 @@ fn test_write_read_index() {
     assert_eq!(bytes, vec.len());
 
     println!("Read back atoms table.");
-    let (bytes2, table2) = AtomsTable::<String>::read_index(&mut Cursor::new(vec)).expect("Reading the atoms table.");
+    let (bytes2, table2) = AtomsTable::<String>::read_index(&mut Cursor::new(vec.clone()), 1024).expect("Reading the atoms table.");
     assert_eq!(bytes2, bytes);
 
+    println!("Entries longer than the limit are rejected.");
+    assert!(AtomsTable::<String>::read_index(&mut Cursor::new(vec), 2).is_err());
+
     drop(table); // Make sure that all further tests are performed with `table2`.
 
     println!("Ensure that are all entries are present.");
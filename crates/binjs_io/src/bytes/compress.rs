@@ -2,6 +2,7 @@
 
 use bytes::serialize::*;
 use bytes::varnum::*;
+use ::TokenReaderError;
 
 use rand;
 use rand::{ Rand, Rng };
@@ -176,7 +177,22 @@ impl Compression {
         })
     }
 
-    pub fn decompress<R: Read, T>(inp: &mut R, deserializer: &T) -> Result<T::Target, std::io::Error> where T: Deserializer {
+    /// Skip a compressed block, without decompressing it.
+    pub fn skip<R: Read>(inp: &mut R) -> Result<(), std::io::Error> {
+        Self::read_prefix(inp)?;
+
+        let mut byte_len = 0;
+        inp.read_varnum(&mut byte_len)?;
+
+        let skipped = std::io::copy(&mut inp.by_ref().take(byte_len as u64), &mut std::io::sink())?;
+        if skipped != byte_len as u64 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated compressed block"))
+        }
+        Ok(())
+    }
+
+    /// Read the prefix identifying the compression format, e.g. `"gzip;"`.
+    pub fn read_prefix<R: Read>(inp: &mut R) -> Result<Compression, std::io::Error> {
         const MAX_LENGTH: usize = 32;
         let mut header = Vec::with_capacity(MAX_LENGTH);
         let mut found = false;
@@ -211,49 +227,155 @@ impl Compression {
             } else {
                 return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid compression header"))
             };
+        Ok(compression)
+    }
+
+    /// Decompress a block and deserialize its contents.
+    ///
+    /// Fails with `TokenReaderError::DecompressedTooLarge` if the block
+    /// exceeds `max_bytes` once decompressed, e.g. `ReadLimits::max_decompressed_bytes`.
+    pub fn decompress<R: Read, T>(inp: &mut R, deserializer: &T, max_bytes: usize) -> Result<T::Target, std::io::Error> where T: Deserializer {
+        let (value, _) = Self::decompress_with_result(inp, deserializer, max_bytes)?;
+        Ok(value)
+    }
+
+    /// As `decompress`, but also return the compression format used
+    /// and the number of bytes before and after decompression.
+    ///
+    /// In the `CompressionResult`, `before_bytes` is the number of
+    /// decompressed bytes and `after_bytes` the number of compressed bytes,
+    /// as in the result of `compress`.
+    pub fn decompress_with_result<R: Read, T>(inp: &mut R, deserializer: &T, max_bytes: usize) -> Result<(T::Target, CompressionResult), std::io::Error> where T: Deserializer {
+        let compression = Self::read_prefix(inp)?;
 
         let mut byte_len = 0;
         inp.read_varnum(&mut byte_len)?;
 
-        let mut compressed_bytes = Vec::with_capacity(byte_len as usize);
-        unsafe { compressed_bytes.set_len(byte_len as usize )};
-        inp.read_exact(&mut compressed_bytes)?;
+        let compressed_bytes = read_exactly(inp, byte_len as usize)?;
+        let decompressed_bytes = compression.decompress_bytes(&compressed_bytes, max_bytes)?;
 
-        let decompressed_bytes = match compression {
-            Compression::Identity => compressed_bytes,
+        let result = CompressionResult {
+            before_bytes: decompressed_bytes.len(),
+            after_bytes: byte_len as usize,
+            algorithms: [compression].iter().cloned().collect()
+        };
+        let value = deserializer.read(&mut Cursor::new(decompressed_bytes))?;
+        Ok((value, result))
+    }
+
+    /// Decompress a buffer.
+    ///
+    /// Fails with `TokenReaderError::DecompressedTooLarge` if the data
+    /// exceeds `max_bytes` once decompressed. Decompression stops after
+    /// `max_bytes + 1` bytes, so the data is never decompressed entirely.
+    pub fn decompress_bytes(&self, compressed_bytes: &[u8], max_bytes: usize) -> Result<Vec<u8>, std::io::Error> {
+        match *self {
+            Compression::Identity => {
+                if compressed_bytes.len() > max_bytes {
+                    return Err(too_large(max_bytes))
+                }
+                Ok(compressed_bytes.to_vec())
+            }
             Compression::Gzip => {
                 use flate2;
-                let mut decoder = flate2::read::GzDecoder::new(Cursor::new(&compressed_bytes));
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
-                buf
+                read_limited(flate2::read::GzDecoder::new(Cursor::new(compressed_bytes)), max_bytes)
             }
             Compression::Deflate => {
                 use flate2;
-                let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(&compressed_bytes));
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
-                buf
+                read_limited(flate2::read::ZlibDecoder::new(Cursor::new(compressed_bytes)), max_bytes)
             }
             Compression::Brotli => {
                 use brotli;
-                let mut decoder = brotli::Decompressor::new(Cursor::new(&compressed_bytes), BROTLI_BUFFER_SIZE);
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
-                buf
+                read_limited(brotli::Decompressor::new(Cursor::new(compressed_bytes), BROTLI_BUFFER_SIZE), max_bytes)
             }
             Compression::Lzw => {
                 use lzw;
                 let reader = lzw::LsbReader::new();
                 let mut decoder = lzw::Decoder::new(reader, LZW_MIN_CODE_SIZE);
-                let (_, data) = decoder.decode_bytes(&compressed_bytes)?;
-                let mut buf = Vec::with_capacity(data.len());
-                buf.extend_from_slice(data);
-                buf
+                let mut buf = Vec::with_capacity(1024);
+                let mut remaining = compressed_bytes;
+                // Each call decodes a single code, i.e. at most a few kb.
+                while remaining.len() > 0 {
+                    let (consumed, data) = decoder.decode_bytes(remaining)?;
+                    if buf.len() + data.len() > max_bytes {
+                        return Err(too_large(max_bytes))
+                    }
+                    buf.extend_from_slice(data);
+                    if consumed == 0 {
+                        break;
+                    }
+                    remaining = &remaining[consumed..];
+                }
+                Ok(buf)
             }
-        };
+        }
+    }
+}
 
-        let value = deserializer.read(&mut Cursor::new(decompressed_bytes))?;
-        Ok(value)
+fn too_large(max_bytes: usize) -> std::io::Error {
+    TokenReaderError::DecompressedTooLarge {
+        max: max_bytes
+    }.into()
+}
+
+/// Read at most `max_bytes` from a decoder, failing with
+/// `TokenReaderError::DecompressedTooLarge` if there are more.
+fn read_limited<R: Read>(decoder: R, max_bytes: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut buf = Vec::with_capacity(1024);
+    decoder.take((max_bytes as u64).saturating_add(1)).read_to_end(&mut buf)?;
+    if buf.len() > max_bytes {
+        return Err(too_large(max_bytes))
+    }
+    Ok(buf)
+}
+
+/// Read exactly `byte_len` bytes.
+///
+/// By opposition to `Read::read_exact`, the buffer is allocated as data arrives,
+/// so a bogus `byte_len` read from a file cannot cause a large allocation.
+pub fn read_exactly<R: Read>(inp: &mut R, byte_len: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut buf = Vec::new();
+    inp.by_ref().take(byte_len as u64).read_to_end(&mut buf)?;
+    if buf.len() != byte_len {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated data"))
     }
-}
\ No newline at end of file
+    Ok(buf)
+}
+
+#[test]
+fn test_decompress_limit() {
+    use std::io::{ Cursor, Seek };
+
+    struct BytesDeserializer;
+    impl Deserializer for BytesDeserializer {
+        type Target = Vec<u8>;
+        fn read<R: Read + Seek>(&self, reader: &mut R) -> Result<Self::Target, std::io::Error> {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+    }
+
+    let data : Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+    for compression in &[Compression::Identity, Compression::Gzip, Compression::Deflate, Compression::Brotli, Compression::Lzw] {
+        let mut compressed = vec![];
+        compression.compress(&data, &mut compressed)
+            .expect("Could not compress");
+
+        let mut inp = Cursor::new(&compressed);
+        let decompressed = Compression::decompress(&mut inp, &BytesDeserializer, data.len())
+            .unwrap_or_else(|err| panic!("Could not decompress with {:?}: {:?}", compression, err));
+        assert_eq!(decompressed, data, "Decompressing with {:?}", compression);
+
+        let mut inp = Cursor::new(&compressed);
+        let err = Compression::decompress(&mut inp, &BytesDeserializer, data.len() - 1)
+            .expect_err("The limit should be exceeded");
+        let inner = err.into_inner()
+            .and_then(|inner| inner.downcast::<TokenReaderError>().ok())
+            .map(|inner| *inner);
+        match inner {
+            Some(TokenReaderError::DecompressedTooLarge { max }) => assert_eq!(max, data.len() - 1),
+            other => panic!("Unexpected error with {:?}: {:?}", compression, other)
+        }
+    }
+}
@@ -1,8 +1,44 @@
+use bytes::varnum::*;
+
 use std;
+use std::io::{ Read, Write };
+
+use rand::{ Rand, Rng };
 
 /// The representation of "no float", used for `float | null`.
 const NONE_FLOAT_REPR: u64 = 0x7FF0000000000001;
 
+/// Tags of the compact representation of `float | null`.
+const TAG_NULL: u8 = 0;
+const TAG_VARNUM: u8 = 1;
+const TAG_INT32: u8 = 2;
+const TAG_FLOAT32: u8 = 3;
+const TAG_FLOAT64: u8 = 4;
+
+/// The representation of numbers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NumberEncoding {
+    /// Always 8 bytes, see `bytes_of_float`.
+    Float64,
+
+    /// 1 to 9 bytes, see `write_compact_float`.
+    Compact,
+}
+impl Default for NumberEncoding {
+    fn default() -> Self {
+        NumberEncoding::Float64
+    }
+}
+impl Rand for NumberEncoding {
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        if rng.gen() {
+            NumberEncoding::Float64
+        } else {
+            NumberEncoding::Compact
+        }
+    }
+}
+
 /// Encode a f64 | null, little-endian
 pub fn bytes_of_float(value: Option<f64>) -> [u8; 8] {
     let mut as_u64 : u64 = match value {
@@ -36,6 +72,84 @@ pub fn float_of_bytes(buf: &[u8; 8]) -> Option<f64> {
     }
 }
 
+/// Encode a f64 | null compactly, as a tag byte followed by:
+///
+/// - `TAG_NULL`: nothing;
+/// - `TAG_VARNUM`: a `varnum`, for integers in [0, 2^32[ (but not -0);
+/// - `TAG_INT32`: a little-endian i32, for other integers in [-2^31, 2^31[ (but not -0);
+/// - `TAG_FLOAT32`: a little-endian f32, for values that are exactly representable as f32,
+///    including their NaN payload;
+/// - `TAG_FLOAT64`: a little-endian f64, for all other values.
+///
+/// Returns the number of bytes written.
+pub fn write_compact_float<W: Write>(out: &mut W, value: Option<f64>) -> Result<usize, std::io::Error> {
+    let value = match value {
+        None => {
+            out.write_all(&[TAG_NULL])?;
+            return Ok(1)
+        }
+        Some(value) => value
+    };
+    let bits = unsafe { std::mem::transmute::<f64, u64>(value) };
+    let is_integer = value.trunc() == value && !(value == 0. && value.is_sign_negative());
+    if is_integer && value >= 0. && value <= u32::max_value() as f64 {
+        out.write_all(&[TAG_VARNUM])?;
+        return Ok(1 + out.write_varnum(value as u32)?)
+    }
+    if is_integer && value >= i32::min_value() as f64 && value <= i32::max_value() as f64 {
+        let as_u32 = value as i32 as u32;
+        out.write_all(&[TAG_INT32])?;
+        out.write_all(&bytes_of_u32(as_u32))?;
+        return Ok(5)
+    }
+    let as_f32 = value as f32;
+    if unsafe { std::mem::transmute::<f64, u64>(as_f32 as f64) } == bits {
+        let as_u32 = unsafe { std::mem::transmute::<f32, u32>(as_f32) };
+        out.write_all(&[TAG_FLOAT32])?;
+        out.write_all(&bytes_of_u32(as_u32))?;
+        return Ok(5)
+    }
+    out.write_all(&[TAG_FLOAT64])?;
+    out.write_all(&bytes_of_float(Some(value)))?;
+    Ok(9)
+}
+
+/// Decode a f64 | null written by `write_compact_float`.
+pub fn read_compact_float<R: Read>(reader: &mut R) -> Result<Option<f64>, std::io::Error> {
+    let mut tag = [0];
+    reader.read_exact(&mut tag)?;
+    match tag[0] {
+        TAG_NULL => Ok(None),
+        TAG_VARNUM => Ok(Some(reader.read_varnum_2()? as f64)),
+        TAG_INT32 => {
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(Some(u32_of_bytes(&buf) as i32 as f64))
+        }
+        TAG_FLOAT32 => {
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf)?;
+            let as_f32 = unsafe { std::mem::transmute::<u32, f32>(u32_of_bytes(&buf)) };
+            Ok(Some(as_f32 as f64))
+        }
+        TAG_FLOAT64 => {
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            let as_u64 = (0..8).fold(0, |acc, i| acc | ((buf[i] as u64) << (8 * i)));
+            Ok(Some(unsafe { std::mem::transmute::<u64, f64>(as_u64) }))
+        }
+        tag => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid number tag {}", tag)))
+    }
+}
+
+fn bytes_of_u32(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+fn u32_of_bytes(buf: &[u8; 4]) -> u32 {
+    (buf[0] as u32) | ((buf[1] as u32) << 8) | ((buf[2] as u32) << 16) | ((buf[3] as u32) << 24)
+}
+
 #[test]
 fn test_floats() {
     use std::f64::*;
@@ -43,9 +157,53 @@ fn test_floats() {
         let value = Some(*x);
         let encoded = bytes_of_float(value);
         let decoded = float_of_bytes(&encoded);
-        println!("Encoded {:?} as {:?}, decoded as {:?}", x, encoded, decoded);
-        assert_eq!(decoded, value);
+        assert_eq!(decoded, value, "Encoded {:?} as {:?}", x, encoded);
     }
 
     assert_eq!(float_of_bytes(&bytes_of_float(None)), None);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_compact_floats() {
+    use std::f64::*;
+    use std::io::Cursor;
+
+    let to_bits = |value: f64| unsafe { std::mem::transmute::<f64, u64>(value) };
+    let of_bits = |bits: u64| unsafe { std::mem::transmute::<u64, f64>(bits) };
+    let cases = [
+        (Some(0.), 2),
+        (Some(1.), 2),
+        (Some(127.), 2),
+        (Some(128.), 3),
+        (Some(4294967295.), 6),
+        (Some(-0.), 5),
+        (Some(-1.), 5),
+        (Some(-2147483648.), 5),
+        (Some(0.5), 5),
+        (Some(-1.5), 5),
+        (Some(INFINITY), 5),
+        (Some(NEG_INFINITY), 5),
+        (Some(NAN), 5),
+        (Some(0.1), 9),
+        (Some(4294967296.), 5),
+        (Some(MAX), 9),
+        (Some(MIN_POSITIVE), 9),
+        // A NaN whose payload is lost when converted to f32.
+        (Some(of_bits(0x7FF0000000000001)), 9),
+        (Some(of_bits(0xFFF8000000000123)), 9),
+        (None, 1),
+    ];
+    for &(value, expected_len) in &cases {
+        let mut buf = vec![];
+        let len = write_compact_float(&mut buf, value)
+            .expect("Could not write number");
+        assert_eq!(len, buf.len());
+        assert_eq!(len, expected_len, "Unexpected length for {:?}", value);
+        let decoded = read_compact_float(&mut Cursor::new(&buf))
+            .expect("Could not read number");
+        assert_eq!(decoded.map(to_bits), value.map(to_bits), "Encoded {:?} as {:?}, decoded as {:?}", value, buf, decoded);
+    }
+
+    assert!(read_compact_float(&mut Cursor::new(&[5])).is_err());
+    assert!(read_compact_float(&mut Cursor::new(&[TAG_FLOAT64, 0, 0])).is_err());
+}
@@ -1,6 +1,264 @@
 use std;
 use std::io::{Read, Write};
 
+use rand::{ Rand, Rng };
+
+/// An encoding of unsigned integers as a variable number of bytes.
+///
+/// Each integer has exactly one encoding: readers reject over-long encodings,
+/// i.e. encodings that use more bytes than needed.
+pub trait IntegerCodec {
+    /// Write `value`, return the number of bytes written.
+    fn write_u64<W: Write>(&self, out: &mut W, value: u64) -> Result<usize, std::io::Error>;
+
+    /// Read a value.
+    fn read_u64<R: Read>(&self, inp: &mut R) -> Result<u64, std::io::Error>;
+
+    /// The number of bytes used to write `value`.
+    fn byte_len(&self, value: u64) -> usize;
+
+    /// Write `value`, return the number of bytes written.
+    fn write_u32<W: Write>(&self, out: &mut W, value: u32) -> Result<usize, std::io::Error> {
+        self.write_u64(out, value as u64)
+    }
+
+    /// Read a value, rejecting values that do not fit in 32 bits.
+    fn read_u32<R: Read>(&self, inp: &mut R) -> Result<u32, std::io::Error> {
+        let value = self.read_u64(inp)?;
+        if value > std::u32::MAX as u64 {
+            return Err(invalid("Integer does not fit in 32 bits"));
+        }
+        Ok(value as u32)
+    }
+}
+
+fn invalid(message: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Read 7 bits per byte, least significant bits first, until a byte that has
+/// its continuation bit cleared. `payload` extracts the 7 bits and the continuation
+/// bit of a byte.
+fn read_continued<R: Read, F: Fn(u8) -> (u64, bool)>(inp: &mut R, payload: F) -> Result<u64, std::io::Error> {
+    let mut result : u64 = 0;
+    let mut shift : u32 = 0;
+    let mut buf : [u8; 1] = [0];
+    loop {
+        inp.read_exact(&mut buf)?;
+        let (bits, continued) = payload(buf[0]);
+        if shift == 63 && (bits > 1 || continued) {
+            // Malformed input: a `u64` never needs more than 10 bytes.
+            return Err(invalid("Integer does not fit in 64 bits"));
+        }
+        result |= bits << shift;
+        if !continued {
+            if bits == 0 && shift > 0 {
+                return Err(invalid("Over-long integer"));
+            }
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+/// Write 7 bits per byte, least significant bits first. `byte` builds a byte
+/// from 7 bits and a continuation bit.
+fn write_continued<W: Write, F: Fn(u64, bool) -> u8>(out: &mut W, mut value: u64, byte: F) -> Result<usize, std::io::Error> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    loop {
+        let bits = value & 0x7F;
+        value >>= 7;
+        bytes[len] = byte(bits, value != 0);
+        len += 1;
+        if value == 0 {
+            break
+        }
+    }
+    out.write_all(&bytes[..len])?;
+    Ok(len)
+}
+
+/// The number of bytes used by `write_continued`.
+fn continued_byte_len(value: u64) -> usize {
+    let mut len = 1;
+    while len < 10 && value >> (7 * len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+/// The historical encoding of BinAST: 7 bits per byte, least significant bits
+/// first, with the continuation bit as the *least* significant bit of each byte.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VarNum;
+impl IntegerCodec for VarNum {
+    fn write_u64<W: Write>(&self, out: &mut W, value: u64) -> Result<usize, std::io::Error> {
+        write_continued(out, value, |bits, continued| ((bits as u8) << 1) | continued as u8)
+    }
+    fn read_u64<R: Read>(&self, inp: &mut R) -> Result<u64, std::io::Error> {
+        read_continued(inp, |byte| ((byte >> 1) as u64, byte & 1 != 0))
+    }
+    fn byte_len(&self, value: u64) -> usize {
+        continued_byte_len(value)
+    }
+}
+
+/// Unsigned LEB128, as used by DWARF, WebAssembly, protobuf, etc.: 7 bits per
+/// byte, least significant bits first, with the continuation bit as the *most*
+/// significant bit of each byte.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Leb128;
+impl IntegerCodec for Leb128 {
+    fn write_u64<W: Write>(&self, out: &mut W, value: u64) -> Result<usize, std::io::Error> {
+        write_continued(out, value, |bits, continued| bits as u8 | ((continued as u8) << 7))
+    }
+    fn read_u64<R: Read>(&self, inp: &mut R) -> Result<u64, std::io::Error> {
+        read_continued(inp, |byte| ((byte & 0x7F) as u64, byte & 0x80 != 0))
+    }
+    fn byte_len(&self, value: u64) -> usize {
+        continued_byte_len(value)
+    }
+}
+
+/// A prefix varint: the number of bytes is determined by the first byte, so
+/// decoding needs a single test rather than one per byte.
+///
+/// If the first byte has `n < 8` trailing zero bits, the integer takes `n + 1`
+/// bytes, which form a little-endian integer `(value << (n + 1)) | (1 << n)`,
+/// i.e. `7 * (n + 1)` bits of value. If the first byte is `0`, it is followed
+/// by the value as a little-endian 64-bit integer (8 bytes).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrefixVarint;
+impl IntegerCodec for PrefixVarint {
+    fn write_u64<W: Write>(&self, out: &mut W, value: u64) -> Result<usize, std::io::Error> {
+        let len = self.byte_len(value);
+        let mut bytes = [0; 9];
+        let encoded = if len == 9 {
+            bytes[0] = 0;
+            value
+        } else {
+            (value << len) | (1 << (len - 1))
+        };
+        let start = if len == 9 { 1 } else { 0 };
+        for i in 0..std::cmp::min(len, 8) {
+            bytes[start + i] = (encoded >> (8 * i)) as u8;
+        }
+        out.write_all(&bytes[..len])?;
+        Ok(len)
+    }
+    fn read_u64<R: Read>(&self, inp: &mut R) -> Result<u64, std::io::Error> {
+        let mut bytes = [0; 8];
+        inp.read_exact(&mut bytes[..1])?;
+        let len = bytes[0].trailing_zeros() as usize + 1;
+        if len == 9 {
+            inp.read_exact(&mut bytes)?;
+        } else {
+            inp.read_exact(&mut bytes[1..len])?;
+        }
+        let encoded = bytes.iter()
+            .rev()
+            .fold(0, |acc, &byte| (acc << 8) | byte as u64);
+        let value = if len == 9 {
+            encoded
+        } else {
+            encoded >> len
+        };
+        if self.byte_len(value) != len {
+            return Err(invalid("Over-long integer"));
+        }
+        Ok(value)
+    }
+    fn byte_len(&self, value: u64) -> usize {
+        let mut len = 1;
+        while len < 9 && value >> (7 * len) != 0 {
+            len += 1;
+        }
+        len
+    }
+}
+
+/// The encodings of integers supported by the multipart format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntegerEncoding {
+    /// See `VarNum`.
+    VarNum,
+
+    /// See `Leb128`.
+    Leb128,
+
+    /// See `PrefixVarint`.
+    PrefixVarint,
+}
+impl IntegerEncoding {
+    pub const ALL: [IntegerEncoding; 3] = [IntegerEncoding::VarNum, IntegerEncoding::Leb128, IntegerEncoding::PrefixVarint];
+
+    /// The code identifying this encoding in a file.
+    pub fn code(self) -> u32 {
+        match self {
+            IntegerEncoding::VarNum => 0,
+            IntegerEncoding::Leb128 => 1,
+            IntegerEncoding::PrefixVarint => 2,
+        }
+    }
+
+    /// The encoding identified by `code` in a file, if any.
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.iter()
+            .cloned()
+            .find(|encoding| encoding.code() == code)
+    }
+
+    /// A human-readable name, as accepted by `parse`.
+    pub fn name(self) -> &'static str {
+        match self {
+            IntegerEncoding::VarNum => "varnum",
+            IntegerEncoding::Leb128 => "leb128",
+            IntegerEncoding::PrefixVarint => "prefix-varint",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter()
+            .cloned()
+            .find(|encoding| encoding.name() == name)
+    }
+}
+impl Default for IntegerEncoding {
+    fn default() -> Self {
+        IntegerEncoding::VarNum
+    }
+}
+impl Rand for IntegerEncoding {
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        *rng.choose(&Self::ALL)
+            .unwrap() // The array is not empty.
+    }
+}
+impl IntegerCodec for IntegerEncoding {
+    fn write_u64<W: Write>(&self, out: &mut W, value: u64) -> Result<usize, std::io::Error> {
+        match *self {
+            IntegerEncoding::VarNum => VarNum.write_u64(out, value),
+            IntegerEncoding::Leb128 => Leb128.write_u64(out, value),
+            IntegerEncoding::PrefixVarint => PrefixVarint.write_u64(out, value),
+        }
+    }
+    fn read_u64<R: Read>(&self, inp: &mut R) -> Result<u64, std::io::Error> {
+        match *self {
+            IntegerEncoding::VarNum => VarNum.read_u64(inp),
+            IntegerEncoding::Leb128 => Leb128.read_u64(inp),
+            IntegerEncoding::PrefixVarint => PrefixVarint.read_u64(inp),
+        }
+    }
+    fn byte_len(&self, value: u64) -> usize {
+        match *self {
+            IntegerEncoding::VarNum => VarNum.byte_len(value),
+            IntegerEncoding::Leb128 => Leb128.byte_len(value),
+            IntegerEncoding::PrefixVarint => PrefixVarint.byte_len(value),
+        }
+    }
+}
+
 pub trait WriteVarNum {
     fn write_varnum(&mut self, num: u32) -> Result<usize, std::io::Error>;
 }
@@ -11,47 +269,19 @@ pub trait ReadVarNum {
 }
 
 impl<T> WriteVarNum for T where T: Write {
-    fn write_varnum(&mut self, mut value: u32) -> Result<usize, std::io::Error> {
-        let mut bytes = Vec::with_capacity(4);
-        loop {
-            let mut byte = ((value & 0x7F) << 1) as u8;
-            if value > 0x7F {
-                byte |= 1;
-            }
-            bytes.push(byte);
-            value >>= 7;
-            if value == 0 {
-                break
-            }
-        }
-        self.write(&bytes)
+    fn write_varnum(&mut self, value: u32) -> Result<usize, std::io::Error> {
+        VarNum.write_u32(self, value)
     }
 }
 
 impl<T> ReadVarNum for T where T: Read {
     fn read_varnum_2(&mut self) -> Result<u32, std::io::Error> {
-        let mut result = 0;
-        self.read_varnum(&mut result)?;
-        Ok(result)
+        VarNum.read_u32(self)
     }
 
     fn read_varnum(&mut self, num: &mut u32) -> Result<usize, std::io::Error> {
-        let mut bytes = 0;
-        let mut result : u32 = 0;
-        let mut shift : u32 = 0;
-        let mut buf : [u8;1] = [0];
-        loop {
-            debug_assert!(shift < 32);
-            bytes += self.read(&mut buf)?;
-
-            let byte = buf[0];
-            result |= (byte as u32 >> 1) << shift;
-            shift += 7;
-            if byte & 1 == 0 {
-                *num = result;
-                return Ok(bytes);
-            }
-        }
+        *num = VarNum.read_u32(self)?;
+        Ok(VarNum.byte_len(*num as u64))
     }
 }
 
@@ -77,4 +307,66 @@ fn test_varnum() {
             assert_eq!(encoded_bytes, decoded_bytes);
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_integer_encodings() {
+    use std::io::Cursor;
+    let mut values = vec![0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, std::u32::MAX as u64, 1 << 56, std::u64::MAX];
+    for shift in 0..64 {
+        values.push(1 << shift);
+        values.push((1 << shift) - 1);
+    }
+    for encoding in &IntegerEncoding::ALL {
+        for &value in &values {
+            let mut encoded = vec![];
+            let len = encoding.write_u64(&mut encoded, value).unwrap();
+            assert_eq!(len, encoded.len());
+            assert_eq!(len, encoding.byte_len(value));
+
+            let mut reader = Cursor::new(&encoded);
+            assert_eq!(encoding.read_u64(&mut reader).unwrap(), value, "Round trip of {} with {:?}", value, encoding);
+            assert_eq!(reader.position() as usize, len);
+
+            // A value that doesn't fit in 32 bits is rejected by `read_u32`.
+            let result = encoding.read_u32(&mut Cursor::new(&encoded));
+            assert_eq!(result.is_ok(), value <= std::u32::MAX as u64);
+
+            // A truncated value is rejected.
+            assert!(encoding.read_u64(&mut Cursor::new(&encoded[..len - 1])).is_err());
+        }
+    }
+
+    // Sizes.
+    assert_eq!(VarNum.byte_len(0x7F), 1);
+    assert_eq!(Leb128.byte_len(0x80), 2);
+    assert_eq!(VarNum.byte_len(std::u64::MAX), 10);
+    assert_eq!(PrefixVarint.byte_len((1 << 56) - 1), 8);
+    assert_eq!(PrefixVarint.byte_len(1 << 56), 9);
+
+    // Known encodings.
+    let mut encoded = vec![];
+    Leb128.write_u64(&mut encoded, 624485).unwrap();
+    assert_eq!(encoded, vec![0xE5, 0x8E, 0x26]);
+    encoded.clear();
+    PrefixVarint.write_u64(&mut encoded, 300).unwrap();
+    assert_eq!(encoded, vec![(300 << 2 | 0b10) as u8, (300 >> 6) as u8]);
+}
+
+#[test]
+fn test_integer_encodings_overlong() {
+    use std::io::Cursor;
+    // `0` written with two bytes.
+    assert!(VarNum.read_u64(&mut Cursor::new(&[1u8, 0][..])).is_err());
+    assert!(Leb128.read_u64(&mut Cursor::new(&[0x80u8, 0][..])).is_err());
+    assert!(PrefixVarint.read_u64(&mut Cursor::new(&[0b10u8, 0][..])).is_err());
+    assert!(PrefixVarint.read_u64(&mut Cursor::new(&[0u8, 1, 0, 0, 0, 0, 0, 0, 0][..])).is_err());
+
+    // More than 64 bits.
+    assert!(VarNum.read_u64(&mut Cursor::new(&[0xFFu8; 11][..])).is_err());
+    assert!(Leb128.read_u64(&mut Cursor::new(&[0xFFu8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02][..])).is_err());
+
+    // More than 32 bits, through `ReadVarNum`.
+    let mut value = 0;
+    assert!(Cursor::new(&[0xFFu8, 0xFF, 0xFF, 0xFF, 0x20][..]).read_varnum(&mut value).is_err());
+}
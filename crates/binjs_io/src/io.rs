@@ -12,6 +12,57 @@ use std::marker::PhantomData;
 use std::ops::Add;
 use std::rc::Rc;
 
+/// The category of a string, as determined by its type in the grammar.
+///
+/// Strings of distinct categories tend to have very distinct distributions,
+/// so a `TokenWriter` may store them separately. A string MUST be read with
+/// the category with which it was written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StringCategory {
+    /// Names of variables and labels (`Identifier`, `Label`).
+    Identifier = 0,
+
+    /// Names of properties, e.g. in member expressions, and names declared
+    /// in scopes (`IdentifierName`).
+    IdentifierName = 1,
+
+    /// Keys of literal properties (`PropertyKey`).
+    PropertyKey = 2,
+
+    /// Any other string, e.g. string literals, directives or module specifiers.
+    Literal = 3,
+}
+impl StringCategory {
+    pub const ALL: [StringCategory; 4] = [StringCategory::Identifier, StringCategory::IdentifierName, StringCategory::PropertyKey, StringCategory::Literal];
+
+    /// The category of strings whose type in the grammar is named `name`.
+    pub fn of_type(name: &str) -> Self {
+        match name {
+            "Identifier" | "Label" => StringCategory::Identifier,
+            "IdentifierName" => StringCategory::IdentifierName,
+            "PropertyKey" => StringCategory::PropertyKey,
+            _ => StringCategory::Literal,
+        }
+    }
+}
+
+/// The kind of a tagged tuple, as returned by `TokenReader::tagged_tuple_kind`.
+#[derive(Clone, Debug)]
+pub struct TupleKind {
+    /// A number identifying both the name and the order of fields of this
+    /// kind within the stream, e.g. its index in a grammar table, if the
+    /// reader has one.
+    ///
+    /// Consumers may use it to cache whatever they derive from `name`.
+    pub id: Option<u32>,
+
+    /// The tag name, shared with the reader.
+    pub name: Rc<str>,
+
+    /// The ordered array of fields in which the contents must be read.
+    pub fields: Option<Rc<Box<[String]>>>,
+}
+
 /// An API for reading tokens.
 ///
 /// Note that a `TokenReader` by itself *cannot* determine the nature of the
@@ -51,11 +102,56 @@ pub trait TokenReader where Self::Error: Debug + From<::TokenReaderError>,
     /// Poison the reader, ensuring that it will never be used for reading again.
     fn poison(&mut self);
 
+    /// The current position in the stream, in bytes, if the reader knows it.
+    ///
+    /// Used for debugging, e.g. by `trace::TracingReader`.
+    fn position(&self) -> Option<usize> {
+        None
+    }
+
     /// Read a single UTF-8 string.
     ///
     /// The returned string MUST be valid UTF-8.
     fn string(&mut self) -> Result<Option<String>, Self::Error>;
 
+    /// Read a single UTF-8 string, written with `TokenWriter::categorized_string`.
+    ///
+    /// By default, categories are ignored.
+    fn categorized_string(&mut self, _category: StringCategory) -> Result<Option<String>, Self::Error> {
+        self.string()
+    }
+
+    /// Read a single UTF-8 string, as `string`, without copying it if
+    /// the reader already holds it, e.g. in a strings table.
+    ///
+    /// By default, this calls `string`.
+    fn string_atom(&mut self) -> Result<Option<Rc<str>>, Self::Error> {
+        Ok(self.string()?
+            .map(Rc::from))
+    }
+
+    /// Read a single UTF-8 string, as `categorized_string`, without copying it
+    /// if the reader already holds it.
+    ///
+    /// By default, this calls `categorized_string`.
+    fn categorized_string_atom(&mut self, category: StringCategory) -> Result<Option<Rc<str>>, Self::Error> {
+        Ok(self.categorized_string(category)?
+            .map(Rc::from))
+    }
+
+    /// Read a value of a string enum, written with `TokenWriter::string_enum`,
+    /// and return its index in `strings`.
+    ///
+    /// By default, the value is read as a string, which MUST be one of `strings`.
+    fn string_enum(&mut self, strings: &[&str]) -> Result<usize, Self::Error> {
+        match self.string()? {
+            None => Err(From::from(::TokenReaderError::EmptyVariant)),
+            Some(string) => strings.iter()
+                .position(|candidate| *candidate == string)
+                .ok_or_else(|| From::from(::TokenReaderError::InvalidValue))
+        }
+    }
+
     /// Read a single `f64`. Note that all numbers are `f64`.
     fn float(&mut self) -> Result<Option<f64>, Self::Error>;
 
@@ -86,6 +182,20 @@ pub trait TokenReader where Self::Error: Debug + From<::TokenReaderError>,
     /// implementations, failure to do so will raise an assertion.
     fn tagged_tuple(&mut self) -> Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error>;
 
+    /// Start reading a tagged tuple, as `tagged_tuple`, without copying
+    /// the tag name, and with an id for the kind if the reader has one.
+    ///
+    /// By default, this calls `tagged_tuple`, and the kind has no id.
+    fn tagged_tuple_kind(&mut self) -> Result<(TupleKind, Self::TaggedGuard), Self::Error> {
+        let (name, fields, guard) = self.tagged_tuple()?;
+        let kind = TupleKind {
+            id: None,
+            name: Rc::from(name),
+            fields,
+        };
+        Ok((kind, guard))
+    }
+
     /// Start reading an untagged tuple.
     ///
     /// Once the tuple is read entirely, callers MUST
@@ -144,6 +254,21 @@ pub trait TokenWriter where Self::Error: Debug, Self::Statistics: Display + Size
     /// If specified, the string MUST be UTF-8.
     fn string(&mut self, Option<&str>) -> Result<Self::Tree, Self::Error>;
 
+    /// Write a single UTF-8 string, whose type in the grammar belongs to `category`.
+    ///
+    /// By default, categories are ignored. Strings written with `string` are `Literal`.
+    fn categorized_string(&mut self, _category: StringCategory, value: Option<&str>) -> Result<Self::Tree, Self::Error> {
+        self.string(value)
+    }
+
+    /// Write the value of a string enum, as its index in `strings`, the
+    /// values of the enum in the order of the grammar.
+    ///
+    /// By default, the value is written as a string.
+    fn string_enum(&mut self, strings: &[&str], index: usize) -> Result<Self::Tree, Self::Error> {
+        self.string(Some(strings[index]))
+    }
+
     /// Write a single number.
     fn float(&mut self, Option<f64>) -> Result<Self::Tree, Self::Error>;
 
@@ -214,14 +339,35 @@ impl<Error> Drop for TrivialGuard<Error> {
 pub trait Serialization<W, T> where W: TokenWriter, T: Sized {
     fn serialize(&mut self, data: T) -> Result<W::Tree, W::Error>;
 }
+/// Serialization of strings, knowing the category of their type in the grammar.
+pub trait CategorizedSerialization<W, T> where W: TokenWriter, T: Sized {
+    fn serialize_categorized(&mut self, category: StringCategory, data: T) -> Result<W::Tree, W::Error>;
+}
 pub trait TokenSerializer<W> where W: TokenWriter {
     fn done(self) -> Result<(W::Data, W::Statistics), W::Error>;
 }
 
 
+/// The default maximal number of nested nodes accepted when decoding an AST
+/// into a typed AST.
+///
+/// Typed deserializers recurse once per node, except along chains of binary
+/// operators, so this bounds their use of the stack. The generic decoder doesn't
+/// recurse, and is only bounded by the limits of its `TokenReader`, e.g.
+/// `multipart::ReadLimits`.
+pub const DEFAULT_MAX_DECODE_DEPTH: usize = 1_000;
+
 pub trait Deserialization<R, T> where R: TokenReader, T: Sized {
     fn deserialize(&mut self) -> Result<T, R::Error>;
 }
+/// Deserialization of strings, knowing the category of their type in the grammar.
+pub trait CategorizedDeserialization<R, T> where R: TokenReader, T: Sized {
+    fn deserialize_categorized(&mut self, category: StringCategory) -> Result<T, R::Error>;
+}
 pub trait InnerDeserialization<R, T> where R: TokenReader, T: Sized {
-    fn deserialize_inner(&mut self) -> Result<T, R::Error>;
+    /// Deserialize the fields of a tagged tuple, once its kind has been read.
+    ///
+    /// `fields` is the order in which the fields were written, as returned by
+    /// `TokenReader::tagged_tuple`, or `None` if they follow the order of the grammar.
+    fn deserialize_inner(&mut self, fields: Option<&[String]>) -> Result<T, R::Error>;
 }
\ No newline at end of file
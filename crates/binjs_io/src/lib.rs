@@ -6,6 +6,10 @@ extern crate log;
 extern crate rand;
 extern crate vec_map;
 
+#[cfg(test)]
+#[macro_use]
+extern crate assert_matches;
+
 #[derive(Debug)]
 pub enum TokenWriterError {
     InvalidOffsetField,
@@ -17,6 +21,17 @@ pub enum TokenReaderError {
     ReadError(std::io::Error),
     BadLength { expected: usize, got: usize },
     BadHeader,
+
+    /// The container was written with a version of the format that this reader does not know.
+    UnsupportedVersion(u32),
+
+    /// The container requires mandatory features that this reader does not support.
+    /// The value contains the unsupported flags.
+    UnsupportedFeatures(u32),
+
+    /// The container uses an encoding of integers that this reader does not know.
+    /// The value contains the code of the encoding.
+    UnsupportedIntegerEncoding(u32),
     BadCompression(std::io::Error),
     EndOffsetError {
         start: u64,
@@ -30,10 +45,59 @@ pub enum TokenReaderError {
     Encoding(std::string::FromUtf8Error),
     EmptyNodeName,
     EmptyFieldName,
+
+    /// A tagged tuple lists a field that its kind doesn't have, or lists it twice.
+    BadField { kind: String, field: String },
+
+    /// A tagged tuple doesn't list a mandatory field of its kind.
+    MissingField { kind: String, field: String },
     EmptyVariant,
     EmptyBool,
     EmptyString,
     BadEnumVariant,
+
+    /// The index of a value of a string enum is not smaller than the number of values.
+    BadEnumIndex { index: u32, len: usize },
+
+    /// A section is larger than permitted by the `ReadLimits`.
+    SectionTooLarge { max: usize, got: usize },
+
+    /// A section, once decompressed, is larger than permitted by the `ReadLimits`.
+    DecompressedTooLarge { max: usize },
+
+    /// A string is longer than permitted by the `ReadLimits`.
+    StringTooLong { max: usize, got: usize },
+
+    /// A list is longer than permitted by the `ReadLimits`.
+    ListTooLong { max: u32, got: u32 },
+
+    /// Nodes are nested deeper than permitted by the `ReadLimits`.
+    TooDeep { max: usize },
+
+    /// A back-reference refers to a tagged tuple that hasn't been read,
+    /// or that is still being read.
+    BadBackReference(u32),
+
+    /// More back-references were followed than permitted by the `ReadLimits`.
+    TooManyBackReferences { max: usize },
+
+    /// With feature `SCOPE_NAMES`, a reference to a name refers to more names
+    /// than are declared by the enclosing scope annotations.
+    BadNameReference { reference: u32, visible: usize },
+
+    /// A text-encoded file is malformed or doesn't contain the expected token.
+    /// `line` starts at 1.
+    Syntax { line: usize, reason: String },
+}
+impl std::fmt::Display for TokenReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+impl std::error::Error for TokenReaderError {
+    fn description(&self) -> &str {
+        "Could not read token"
+    }
 }
 
 
@@ -49,6 +113,15 @@ pub use io::*;
 /// of grammar encoders/decoders.
 pub mod simple;
 
+/// A human-readable implementation of TokenReader/TokenWriter,
+/// designed to help debug grammar encoders/decoders and
+/// to write test fixtures by hand.
+pub mod text;
+
+/// Adapters recording the tokens read by a TokenReader or written
+/// by a TokenWriter, to debug mismatches between them.
+pub mod trace;
+
 /// An optimization of TokenReader/TokenWriter,
 /// designed to minimize the size of the file.
 pub mod multipart;
@@ -12,7 +12,8 @@
 //! The sections are:
 //!
 //! 1. the grammar table;
-//! 2. the strings table (which contains both strings and identifiers);
+//! 2. the strings table (which contains both strings and identifiers), or with feature `STRING_TABLES`,
+//!    one strings table per category of strings;
 //! 3. the representation of the tree.
 //!
 //! The grammar table lists the AST nodes used in the file. Its primary role is to serve as a lightweight
@@ -30,19 +31,51 @@
 //! Its secondary role is compression.
 //!
 //! In the current version, the tree is a sequence of tokens. All these tokens are ambiguous and a stream may
-//! only be tokenized by a client that knows both the grammar and the grammar table. Specific tokens (lists)
-//! contain their byte length, so as to allow skipping them for purposes of lazy parsing and/or concurrent
-//! parsing.
+//! only be tokenized by a client that knows both the grammar and the grammar table. Skippable subtrees
+//! (i.e. tagged tuples whose first field is an offset) contain their byte length, so as to allow skipping
+//! them for purposes of lazy parsing and/or concurrent parsing. Other tokens, including lists, do not, as
+//! they never need to be skipped.
 //!
 //! # Format
 //!
 //! The entire file is formatted as:
 //!
 //! - the characters `"BINJS"`;
-//! - a container version number (`varnum`, currently `0`);
+//! - a container version number (`varnum`, currently `1`, see `FORMAT_VERSION`);
+//! - since version `1`, feature flags (`varnum`, see `FeatureFlags`);
+//! - with feature `INTEGERS`, the encoding of integers in the tree (`varnum`, see below);
 //! - the compressed grammar table (see below);
-//! - the compressed strings table (see below);
-//! - the compressed tree (see below).
+//! - the compressed strings table (see below), or with feature `STRING_TABLES`, the compressed
+//!   strings table of each category;
+//! - the compressed tree (see below), or with feature `COLUMNS`, the compressed columns of the tree.
+//!
+//! ## Versioning
+//!
+//! The container version number changes only when the structure of the container itself
+//! changes in a manner that older readers cannot detect. Everything else is negotiated through
+//! feature flags:
+//!
+//! - bits 0-15 are *mandatory* features: a reader that doesn't support one of them MUST reject the file;
+//! - bits 16-31 are *optional* features: a reader that doesn't support one of them MAY ignore it.
+//!
+//! The mandatory features currently defined are:
+//!
+//! - bit 0, `COMPACT_NUMBERS`: floats in the tree use the compact representation (see below);
+//! - bit 1, `BITMAPS`: the file contains a layout section and tagged tuples may start with a bitmap (see below);
+//! - bit 2, `COLUMNS`: the tree is split into one section per type of token (see below);
+//! - bit 3, `DEDUP`: tagged tuples may be replaced by back-references (see below);
+//! - bit 4, `STRING_TABLES`: strings are split into one table per category (see below);
+//! - bit 5, `ENUM_INDICES`: values of string enums are written as indices in the enum (see below);
+//! - bit 6, `FIELD_ORDER`: the grammar table lists the fields of each entry, in the order in which they
+//!   are written (see below);
+//! - bit 7, `INTEGERS`: integers in the tree use the encoding recorded in the header (see below);
+//! - bit 8, `SCOPE_NAMES`: identifiers may be written as references to the names declared by the scope
+//!   annotations (see below).
+//!
+//! Any section may be preceded by additional sections, with the same structure (a header `"[NAME]"`,
+//! a compression `prefix`, a number of compressed bytes and the compressed data). Readers MUST skip
+//! sections that they do not know. A writer that needs readers to understand a new section MUST
+//! also set a mandatory feature flag.
 //!
 //! ## Grammar table
 //!
@@ -54,13 +87,19 @@
 //! - compressed in the format identified by `prefix`:
 //!    - the number of entries (`varnum`);
 //!    - for each entry,
-//!      - byte length of entry (`varnum`);
-//!    - for each entry,
 //!      - byte length of node name (`varnum`);
-//!      - number of fields (`varnum`);
-//!      - for each field
-//!        - byte length of field name (`varnum`);
-//!        - field name (utf-8 encoded string).
+//!      - node name (utf-8 encoded string);
+//!      - with feature `FIELD_ORDER`,
+//!        - number of fields (`varnum`);
+//!        - for each field
+//!          - byte length of field name (`varnum`);
+//!          - field name (utf-8 encoded string).
+//!
+//! Without feature `FIELD_ORDER`, the fields of a tagged tuple are written in the order of the grammar.
+//! With feature `FIELD_ORDER`, they are written in the order listed by its entry, which lets writers
+//! pick the order that compresses best, e.g. writing scope annotations after the code they describe.
+//! Fields of the grammar that are not listed are omitted; readers replace optional omitted fields by
+//! `null` and MAY reject files that omit other fields.
 //!
 //! ## Strings table
 //!
@@ -77,6 +116,41 @@
 //!        - the invalid strings [255, 0] (representing the null string, only valid if byte length is 2);
 //!        - a utf-8 encoded string (utf-8 encoded, `bytelen` bytes, no terminator).
 //!
+//! ### String tables
+//!
+//! With feature `STRING_TABLES`, the strings table section is replaced by four sections, in this
+//! order, each with the same structure as the strings table section:
+//!
+//! - `"[STRINGS.IDENTIFIERS]"`: strings whose type in the grammar is `Identifier` or `Label`;
+//! - `"[STRINGS.IDENTIFIER_NAMES]"`: strings whose type in the grammar is `IdentifierName`;
+//! - `"[STRINGS.PROPERTY_KEYS]"`: strings whose type in the grammar is `PropertyKey`;
+//! - `"[STRINGS.LITERALS]"`: all other strings, including string enums without feature `ENUM_INDICES`.
+//!
+//! These categories (see `StringCategory`) have very distinct distributions, so each table may be
+//! compressed with the format that suits it best. Each table is indexed independently.
+//!
+//! ## Layout table
+//!
+//! With feature `BITMAPS`, the strings table is followed by a layout table, which describes
+//! the fields of tagged tuples that are packed into a bitmap rather than written as tokens.
+//!
+//! - the characters `"[LAYOUT]"`;
+//! - a `prefix` identifying the compression format used for the layouts (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!    - the number of entries (`varnum`);
+//!    - for each entry,
+//!      - an entry in the grammar table (`varnum`);
+//!      - number of fields (`varnum`), including the offset, if any;
+//!      - for each field, one of (`varnum`)
+//!        - `0`: the field is written as a token;
+//!        - `1`: the field is a non-null boolean, represented by one bit;
+//!        - `2 + kind`, where `kind` is an entry in the grammar table of a tagged tuple without fields:
+//!          the field is a tagged tuple whose presence is represented by one bit. If the bit is `0`,
+//!          the field is a tuple of kind `kind` and nothing else is written. Otherwise, the field is
+//!          written as a token. This is designed for optional fields, in which case `kind` is the
+//!          null interface.
+//!
 //! ## The tree
 //!
 //! This contains the actual tree for a specific grammar. The file does not contain all the information
@@ -88,6 +162,43 @@
 //! - compressed in the format identified by `prefix`:
 //!   - one tree token.
 //!
+//! ### Columns
+//!
+//! With feature `COLUMNS`, the tree section is replaced by five sections, in this order, each with
+//! the same structure as the tree section:
+//!
+//! - `"[TREE.KINDS]"`: the grammar table entries of tagged tuples and, with feature `ENUM_INDICES`,
+//!   the indices of string enums;
+//! - `"[TREE.STRINGS]"`: the strings table entries of strings;
+//! - `"[TREE.NUMBERS]"`: floats;
+//! - `"[TREE.BOOLS]"`: booleans and bitmaps;
+//! - `"[TREE.LENGTHS]"`: the number of items of lists and offsets.
+//!
+//! Each column contains the representation of its tokens, in the order in which they appear in the
+//! tree. Tuples have no representation of their own, so the tree is read by picking the next token
+//! from the column determined by the grammar. Values with a similar distribution are thus stored
+//! together, and each column may be compressed with the format that suits it best.
+//!
+//! Offsets keep the same value as without feature `COLUMNS`, but as the fields of a tagged tuple
+//! are spread across columns, this value does not let a reader skip them.
+//!
+//! ### Integers
+//!
+//! Integers in the tree, noted `integer` below, are written as `varnum`s, i.e. 7 bits per byte, least
+//! significant bits first, with the least significant bit of each byte set if more bytes follow. With
+//! feature `INTEGERS`, they are written in the encoding whose code follows the feature flags:
+//!
+//! - `0`: `varnum`;
+//! - `1`: unsigned LEB128, i.e. as `varnum`, but with the most significant bit of each byte set if more
+//!   bytes follow;
+//! - `2`: prefix varint, i.e. if the first byte has `n < 8` trailing zero bits, `n + 1` bytes forming the
+//!   low-endian integer `(value << (n + 1)) | (1 << n)`, otherwise a `0` byte followed by the value as a
+//!   low-endian 64-bit integer (8 bytes).
+//!
+//! Readers MUST reject unknown codes, as well as integers written with more bytes than needed. Integers
+//! outside of the tree, e.g. in the header of sections, in tables or in compact floats, remain `varnum`s.
+//! See `bytes::varnum::IntegerEncoding`.
+//!
 //! ### Tree token
 //!
 //!  A tree token is defined as one of
@@ -96,25 +207,81 @@
 //!     - a low-endian IEEE764 64-bit floating point value signalling NaN (8 bytes),
 //!   - a non-null float, represented as:
 //!     - a low-endian IEEE764 64-bit floating point value non-signalling NaN (8 bytes),
+//!   - with feature `COMPACT_NUMBERS`, a float or null float is instead represented as:
+//!     - a tag (one byte);
+//!     - depending on the tag
+//!       - `0`: nothing, for the null float;
+//!       - `1`: a `varnum`, for integers in [0, 2^32[, except -0;
+//!       - `2`: a low-endian 32-bit integer (4 bytes), for other integers in [-2^31, 2^31[, except -0;
+//!       - `3`: a low-endian IEEE764 32-bit floating point value (4 bytes), for values that convert exactly
+//!         to and from 32 bits, including their NaN payload;
+//!       - `4`: a low-endian IEEE764 64-bit floating point value (8 bytes), for everything else,
 //!   - a null boolean, represented as:
 //!     -  a single byte with value `2` (one byte);
 //!   - a non-null boolean, represented as:
 //!     -  a single byte with value `0` (false) or `1` (true) (one byte);
 //!   - a string, representing as
-//!     - an entry in the table of strings (`varnum`);
+//!     - an entry in the table of strings (`integer`), or with feature `STRING_TABLES`, in the table of
+//!       the category of the string;
+//!   - with feature `SCOPE_NAMES`, a string whose type in the grammar is `Identifier` or `Label` is
+//!     instead represented as one of
+//!     - `0` (`integer`), followed by the string, as above;
+//!     - `k + 1` (`integer`), for the `k`-th name declared by the enclosing scope annotations, counting
+//!       from the latest one (see below);
+//!   - a value of a string enum, represented as
+//!     - a string, as above;
+//!   - with feature `ENUM_INDICES`, a value of a string enum is instead represented as
+//!     - its index in the values of the enum, in the order in which the grammar declares them (`integer`).
+//!       Readers MUST reject indices that are not smaller than the number of values;
 //!   - a list, represented as
-//!     - byte length (`varnum`);
-//!     - `bytelen` bytes representing:
-//!       - number of items (`varnum`);
-//!       - for each item
-//!          - the token;
+//!     - number of items (`integer`);
+//!     - for each item
+//!        - the token;
+//!   - an offset, only as the first field of a tagged tuple, represented as
+//!     - the byte length of the other fields of the tagged tuple (`integer`);
 //!   - a untagged tuple, represented as
 //!     - for each item
 //!       - the token;
 //!   - a tagged tuple, represented as
-//!     - an entry in the grammar table (`varnum`);
-//!     - for each field
+//!     - an entry in the grammar table (`integer`);
+//!     - with feature `BITMAPS`, if the entry has a layout with `n` packed fields, the bits of the packed
+//!       fields, in the order of fields, least significant bit first (`ceil(n / 8)` bytes);
+//!     - for each field that is not packed
 //!       - the token
+//!   - with feature `DEDUP`, in place of a tagged tuple, a back-reference, represented as
+//!     - the number of entries in the grammar table (`integer`), which is not a valid entry;
+//!     - the number of tagged tuples between the referenced tagged tuple and the back-reference (`integer`).
+//!
+//! ### Back-references
+//!
+//! With feature `DEDUP`, tagged tuples are numbered from `0`, in the order in which their entry in the
+//! grammar table is written. Neither back-references nor tagged tuples that are packed as absent
+//! fields are numbered. A back-reference with value `d`, found when the next number is `n`, stands
+//! for the tokens of tagged tuple `n - 1 - d`. These tokens are read again, in place of the
+//! back-reference, and the tagged tuples they contain take the numbers they had when first read.
+//! Back-references may only refer to tagged tuples that have been read entirely.
+//!
+//! With feature `COLUMNS`, the first part of a back-reference is written in `"[TREE.KINDS]"` and the
+//! second part in `"[TREE.LENGTHS]"`.
+//!
+//! ### Scope names
+//!
+//! With feature `SCOPE_NAMES`, the names of local variables are written as references to the names
+//! declared by the scope annotations, i.e. the tagged tuples whose kind is one of `SCOPE_ANNOTATIONS`,
+//! so that the strings table only contains free names, and references, which are small numbers, repeat
+//! across functions.
+//!
+//! Each string whose type in the grammar is `IdentifierName`, read within a scope annotation (but not
+//! within a tagged tuple nested in it), is declared. Declared names remain visible until the end of the
+//! tagged tuple that contains the scope annotation. Names are counted in the order in which they are
+//! read, so reference `1` stands for the latest visible name. When the same name is declared several
+//! times, writers refer to its latest declaration.
+//!
+//! This only relies on the order of tokens: with feature `FIELD_ORDER`, names that are read before the
+//! scope annotation that declares them are written as strings. With feature `DEDUP`, the tokens of a
+//! tagged tuple read again through a back-reference declare and refer to names as if they were written
+//! in place of the back-reference. With feature `COLUMNS`, both representations are written in
+//! `"[TREE.STRINGS]"`.
 
 /// Implementation of the token reader.
 mod read;
@@ -122,15 +289,175 @@ mod read;
 /// Implementation of the token writer.
 mod write;
 
+use io::StringCategory;
+
+/// The version of the container format written by `TreeTokenWriter`.
+///
+/// - version `0` has no feature flags;
+/// - version `1` adds feature flags after the version number.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Optional capabilities of a container, written in its header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FeatureFlags(pub u32);
+impl FeatureFlags {
+    /// No feature at all. This is the case of all files with version `0`.
+    pub const NONE: FeatureFlags = FeatureFlags(0);
+
+    /// Mandatory: floats are written with `bytes::float::write_compact_float`
+    /// rather than as 8 bytes.
+    pub const COMPACT_NUMBERS: FeatureFlags = FeatureFlags(1 << 0);
+
+    /// Mandatory: booleans and the presence of optional fields of tagged
+    /// tuples are packed into per-node bitmaps, as described by a `[LAYOUT]`
+    /// section.
+    pub const BITMAPS: FeatureFlags = FeatureFlags(1 << 1);
+
+    /// Mandatory: the tree is split into columns, one per type of token.
+    pub const COLUMNS: FeatureFlags = FeatureFlags(1 << 2);
+
+    /// Mandatory: tagged tuples may be replaced by back-references to
+    /// identical tagged tuples written earlier.
+    pub const DEDUP: FeatureFlags = FeatureFlags(1 << 3);
+
+    /// Mandatory: strings are split into one table per `StringCategory`.
+    pub const STRING_TABLES: FeatureFlags = FeatureFlags(1 << 4);
+
+    /// Mandatory: values of string enums are written as their index in the
+    /// enum rather than through the strings table.
+    pub const ENUM_INDICES: FeatureFlags = FeatureFlags(1 << 5);
+
+    /// Mandatory: the grammar table lists the fields of each kind, in the
+    /// order in which they are written.
+    pub const FIELD_ORDER: FeatureFlags = FeatureFlags(1 << 6);
+
+    /// Mandatory: integers in the tree are written in the `IntegerEncoding`
+    /// recorded in the header rather than as `varnum`s.
+    pub const INTEGERS: FeatureFlags = FeatureFlags(1 << 7);
+
+    /// Mandatory: identifiers may be written as references to the names
+    /// declared by the enclosing scope annotations.
+    pub const SCOPE_NAMES: FeatureFlags = FeatureFlags(1 << 8);
+
+    /// The bits reserved to mandatory features.
+    pub const MANDATORY_MASK: u32 = 0x0000_ffff;
+
+    /// `true` if all the features of `other` are also in `self`.
+    pub fn contains(self, other: FeatureFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features that are either in `self` or in `other`.
+    pub fn union(self, other: FeatureFlags) -> FeatureFlags {
+        FeatureFlags(self.0 | other.0)
+    }
+
+    /// The features that are in `self` but not in `other`.
+    pub fn difference(self, other: FeatureFlags) -> FeatureFlags {
+        FeatureFlags(self.0 & !other.0)
+    }
+
+    /// The mandatory features of `self`.
+    pub fn mandatory(self) -> FeatureFlags {
+        FeatureFlags(self.0 & Self::MANDATORY_MASK)
+    }
+
+    /// The optional features of `self`.
+    pub fn optional(self) -> FeatureFlags {
+        FeatureFlags(self.0 & !Self::MANDATORY_MASK)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// With feature `SCOPE_NAMES`, the kinds of the tagged tuples whose strings
+/// of category `IdentifierName` are declared names.
+pub const SCOPE_ANNOTATIONS: [&str; 3] = ["AssertedBlockScope", "AssertedParameterScope", "AssertedVarScope"];
+
 /// The header of the strings table section.
 const HEADER_STRINGS_TABLE : &str = "[STRINGS]";
 
+/// With feature `STRING_TABLES`, the header of the strings table of `category`.
+fn strings_table_header(category: StringCategory) -> &'static str {
+    match category {
+        StringCategory::Identifier => "[STRINGS.IDENTIFIERS]",
+        StringCategory::IdentifierName => "[STRINGS.IDENTIFIER_NAMES]",
+        StringCategory::PropertyKey => "[STRINGS.PROPERTY_KEYS]",
+        StringCategory::Literal => "[STRINGS.LITERALS]",
+    }
+}
+
 /// The header of the grammars table section.
 const HEADER_GRAMMAR_TABLE: &str = "[GRAMMAR]";
 
 /// The header of the tree section.
 const HEADER_TREE: &str = "[TREE]";
 
+/// The header of the layout section, with feature `BITMAPS`.
+const HEADER_LAYOUT: &str = "[LAYOUT]";
+
+/// With feature `COLUMNS`, the sections replacing the tree section, in the
+/// order in which they appear in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Column {
+    Kinds = 0,
+    Strings = 1,
+    Numbers = 2,
+    Bools = 3,
+    Lengths = 4,
+}
+impl Column {
+    const ALL: [Column; 5] = [Column::Kinds, Column::Strings, Column::Numbers, Column::Bools, Column::Lengths];
+
+    fn header(self) -> &'static str {
+        match self {
+            Column::Kinds => "[TREE.KINDS]",
+            Column::Strings => "[TREE.STRINGS]",
+            Column::Numbers => "[TREE.NUMBERS]",
+            Column::Bools => "[TREE.BOOLS]",
+            Column::Lengths => "[TREE.LENGTHS]",
+        }
+    }
+}
+
+/// The representation of a field of a tagged tuple in a `[LAYOUT]` section.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Slot {
+    /// The field is written as a token.
+    Token,
+
+    /// The field is a non-null boolean, written as one bit of the bitmap.
+    Bool,
+
+    /// The presence of the field is written as one bit of the bitmap. If
+    /// absent, the field is a tagged tuple without fields, with the given
+    /// index in the grammar table.
+    Optional(u32),
+}
+impl Slot {
+    fn code(self) -> u32 {
+        match self {
+            Slot::Token => 0,
+            Slot::Bool => 1,
+            Slot::Optional(kind) => 2 + kind,
+        }
+    }
+    fn from_code(code: u32) -> Self {
+        match code {
+            0 => Slot::Token,
+            1 => Slot::Bool,
+            kind => Slot::Optional(kind - 2),
+        }
+    }
+
+    /// `true` if the field takes one bit of the bitmap.
+    fn is_packed(self) -> bool {
+        self != Slot::Token
+    }
+}
+
 /// A trait specifying whether a piece of data needs the addition of a length index.
 trait FormatInTable {
     const HAS_LENGTH_INDEX : bool;
@@ -141,8 +468,14 @@ impl FormatInTable for Option<String> {
     const HAS_LENGTH_INDEX : bool = false;
 }
 
-pub use self::read::TreeTokenReader;
-pub use self::write::{ TreeTokenWriter, Statistics, WriteOptions };
+impl FormatInTable for Option<std::rc::Rc<str>> {
+    const HAS_LENGTH_INDEX : bool = false;
+}
+
+pub use self::read::{ NodeDescription, ReadLimits, SectionInfo, Table, TreeTokenReader };
+pub use self::write::{ Columns, FieldOrder, StringTables, TreeTokenWriter, Statistics, WriteOptions };
+pub use bytes::float::NumberEncoding;
+pub use bytes::varnum::IntegerEncoding;
 
 
 #[test]
@@ -165,11 +498,21 @@ fn test_multipart_io() {
         for grammar_table in &compressions {
             for strings_table in &compressions {
                 for tree in &compressions {
-                    vec.push(WriteOptions {
-                        grammar_table: grammar_table.clone(),
-                        strings_table: strings_table.clone(),
-                        tree: tree.clone(),
-                    });
+                    for numbers in &[NumberEncoding::Float64, NumberEncoding::Compact] {
+                        for columns in &[false, true] {
+                            for string_tables in &[false, true] {
+                                vec.push(WriteOptions {
+                                    grammar_table: grammar_table.clone(),
+                                    strings_table: strings_table.clone(),
+                                    tree: tree.clone(),
+                                    numbers: numbers.clone(),
+                                    columns: if *columns { Some(Columns::uniform(tree.clone())) } else { None },
+                                    string_tables: if *string_tables { Some(StringTables::uniform(strings_table.clone())) } else { None },
+                                    .. WriteOptions::default()
+                                });
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -178,7 +521,7 @@ fn test_multipart_io() {
 
     for options in all_options {
         println!("Options {:?}", options);
-        let suffix = format!("{:?}-{:?}-{:?}", options.grammar_table, options.strings_table, options.tree);
+        let suffix = format!("{:?}-{:?}-{:?}-{:?}-{}-{}", options.grammar_table, options.strings_table, options.tree, options.numbers, options.columns.is_some(), options.string_tables.is_some());
 
 
         {
@@ -403,3 +746,1013 @@ fn test_multipart_io() {
     }
 }
 
+#[test]
+fn test_multipart_versioning() {
+    use bytes::varnum::*;
+    use io::{ TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(WriteOptions::default());
+    writer.string(Some("simple string"))
+        .expect("Writing simple string");
+    let (output, _) = writer.done()
+        .expect("Finalizing data");
+
+    // "BINJS", version (1 byte), features (1 byte).
+    assert_eq!(&output[0..5], b"BINJS");
+    assert_eq!(output[5], (FORMAT_VERSION as u8) << 1);
+    assert_eq!(output[6], 0);
+    let body = &output[7..];
+
+    // Build a container with a given version, features and extra sections.
+    let container = |version: u32, features: Option<u32>, extra: &[u8]| {
+        let mut data = b"BINJS".to_vec();
+        data.write_varnum(version).unwrap();
+        if let Some(features) = features {
+            data.write_varnum(features).unwrap();
+        }
+        data.extend_from_slice(extra);
+        data.extend_from_slice(body);
+        data
+    };
+    let read = |data: Vec<u8>| {
+        let mut reader = TreeTokenReader::new(Cursor::new(data))?;
+        reader.string()
+    };
+
+    // Version 0, without features.
+    assert_eq!(read(container(0, None, b"")).unwrap(), Some("simple string".to_string()));
+
+    // Unknown optional features are ignored.
+    assert_eq!(read(container(FORMAT_VERSION, Some(1 << 20), b"")).unwrap(), Some("simple string".to_string()));
+
+    // Unknown mandatory features are rejected.
+    match read(container(FORMAT_VERSION, Some(1 << 15), b"")) {
+        Err(TokenReaderError::UnsupportedFeatures(flags)) => assert_eq!(flags, 1 << 15),
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    // Unknown encodings of integers are rejected.
+    let mut code = vec![];
+    code.write_varnum(7).unwrap();
+    match read(container(FORMAT_VERSION, Some(FeatureFlags::INTEGERS.0), &code)) {
+        Err(TokenReaderError::UnsupportedIntegerEncoding(code)) => assert_eq!(code, 7),
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    // Unknown versions are rejected.
+    match read(container(FORMAT_VERSION + 1, Some(0), b"")) {
+        Err(TokenReaderError::UnsupportedVersion(version)) => assert_eq!(version, FORMAT_VERSION + 1),
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    // Unknown sections are skipped.
+    let mut extra = b"[EXTENSION]identity;".to_vec();
+    extra.write_varnum(3).unwrap();
+    extra.extend_from_slice(b"abc");
+    assert_eq!(read(container(FORMAT_VERSION, Some(0), &extra)).unwrap(), Some("simple string".to_string()));
+}
+
+#[test]
+fn test_multipart_limits() {
+    use bytes::compress::Compression;
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let options = WriteOptions {
+        tree: Compression::Gzip,
+        .. WriteOptions::default()
+    };
+
+    // Strings.
+    let long_string = "0123456789".repeat(10);
+    let mut writer = TreeTokenWriter::new(options.clone());
+    writer.string(Some(&long_string))
+        .expect("Writing long string");
+    let (output, _) = writer.done()
+        .expect("Finalizing data");
+
+    let limits = ReadLimits {
+        max_string_bytes: 10,
+        .. ReadLimits::default()
+    };
+    match TreeTokenReader::with_limits(Cursor::new(&output), limits) {
+        Err(TokenReaderError::StringTooLong { max: 10, got: 100 }) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Long string should have been rejected")
+    }
+
+    // Sections.
+    let limits = ReadLimits {
+        max_section_bytes: 4,
+        .. ReadLimits::default()
+    };
+    match TreeTokenReader::with_limits(Cursor::new(&output), limits) {
+        Err(TokenReaderError::SectionTooLarge { max: 4, .. }) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Large section should have been rejected")
+    }
+    let limits = ReadLimits {
+        max_decompressed_bytes: 4,
+        .. ReadLimits::default()
+    };
+    match TreeTokenReader::with_limits(Cursor::new(&output), limits) {
+        Err(TokenReaderError::DecompressedTooLarge { max: 4 }) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Large section should have been rejected")
+    }
+
+    // Truncated or malformed files are rejected without panicking.
+    for len in 0..output.len() {
+        assert!(TreeTokenReader::new(Cursor::new(&output[0..len])).is_err());
+    }
+    let mut garbage = b"BINJS".to_vec();
+    garbage.extend_from_slice(&[255; 16]);
+    assert!(TreeTokenReader::new(Cursor::new(&garbage)).is_err());
+
+    // Nesting and lists.
+    let mut writer = TreeTokenWriter::new(options.clone());
+    let item_0 = writer.string(Some("foo")).unwrap();
+    let item_1 = writer.string(Some("bar")).unwrap();
+    let list = writer.list(vec![item_0, item_1]).unwrap();
+    writer.untagged_tuple(&[list])
+        .expect("Writing nested list");
+    let (output, _) = writer.done()
+        .expect("Finalizing data");
+
+    let limits = ReadLimits {
+        max_depth: 1,
+        .. ReadLimits::default()
+    };
+    let mut reader = TreeTokenReader::with_limits(Cursor::new(&output), limits)
+        .expect("Creating reader");
+    let guard = reader.untagged_tuple()
+        .expect("Reading untagged tuple");
+    match reader.list() {
+        Err(TokenReaderError::TooDeep { max: 1 }) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Nested list should have been rejected")
+    }
+    drop(guard);
+
+    let limits = ReadLimits {
+        max_list_length: 1,
+        .. ReadLimits::default()
+    };
+    let mut reader = TreeTokenReader::with_limits(Cursor::new(&output), limits)
+        .expect("Creating reader");
+    let guard = reader.untagged_tuple()
+        .expect("Reading untagged tuple");
+    match reader.list() {
+        Err(TokenReaderError::ListTooLong { max: 1, got: 2 }) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Long list should have been rejected")
+    }
+    drop(guard);
+
+    // With default limits, the same file is fine.
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let guard = reader.untagged_tuple()
+        .expect("Reading untagged tuple");
+    let (len, list_guard) = reader.list()
+        .expect("Reading list");
+    assert_eq!(len, 2);
+    for _ in 0..len {
+        reader.string().expect("Reading string");
+    }
+    list_guard.done().expect("Finalizing list");
+    guard.done().expect("Finalizing tuple");
+}
+
+#[test]
+fn test_multipart_compact_numbers() {
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+
+    use std;
+    use std::io::Cursor;
+
+    let values = [
+        None,
+        Some(0.),
+        Some(-0.),
+        Some(42.),
+        Some(-1.),
+        Some(1.5),
+        Some(0.1),
+        Some(std::f64::INFINITY),
+        Some(unsafe { std::mem::transmute::<u64, f64>(0x7ff8_0000_0000_0123) }),
+    ];
+    let write = |numbers: NumberEncoding| {
+        let mut writer = TreeTokenWriter::new(WriteOptions {
+            numbers,
+            .. WriteOptions::default()
+        });
+        let items = values.iter()
+            .map(|value| writer.float(*value).expect("Writing float"))
+            .collect();
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let (float64, float64_stats) = write(NumberEncoding::Float64);
+    let (compact, compact_stats) = write(NumberEncoding::Compact);
+    assert_eq!(float64_stats.float.entries, values.len());
+    assert_eq!(compact_stats.float.entries, values.len());
+    assert_eq!(float64_stats.float.own_bytes, 8 * values.len());
+    assert!(compact_stats.float.own_bytes < float64_stats.float.own_bytes);
+
+    for output in &[float64, compact] {
+        let mut reader = TreeTokenReader::new(Cursor::new(output))
+            .expect("Creating reader");
+        let (len, guard) = reader.list()
+            .expect("Reading list");
+        assert_eq!(len as usize, values.len());
+        for value in &values {
+            let read = reader.float()
+                .expect("Reading float");
+            // Compare bit patterns, to distinguish -0 from 0 and NaN payloads.
+            let bits = |value: Option<f64>| value.map(|value| value.to_bits());
+            assert_eq!(bits(read), bits(*value));
+        }
+        guard.done()
+            .expect("Finalizing list");
+    }
+}
+
+#[test]
+fn test_multipart_bitmaps() {
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    // A list of `Node { flag: bool, child: Node | null }`.
+    let write = |bitmaps: bool| {
+        let mut writer = TreeTokenWriter::new(WriteOptions {
+            bitmaps,
+            .. WriteOptions::default()
+        });
+        let mut items = vec![];
+        for i in 0..10 {
+            let flag = writer.bool(Some(i % 3 == 0)).unwrap();
+            let child = if i % 2 == 0 {
+                writer.tagged_tuple("", &[]).unwrap()
+            } else {
+                let flag = writer.bool(Some(true)).unwrap();
+                let child = writer.tagged_tuple("", &[]).unwrap();
+                writer.tagged_tuple("Node", &[("flag", flag), ("child", child)]).unwrap()
+            };
+            items.push(writer.tagged_tuple("Node", &[("flag", flag), ("child", child)]).unwrap());
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let (plain, plain_stats) = write(false);
+    let (packed, packed_stats) = write(true);
+    assert_eq!(plain_stats.bitmap.entries, 0);
+    assert_eq!(packed_stats.bitmap.entries, 15);
+    assert_eq!(packed_stats.bool.entries, plain_stats.bool.entries);
+    assert!(packed_stats.tree.compression.before_bytes < plain_stats.tree.compression.before_bytes);
+
+    for output in &[plain, packed] {
+        let mut reader = TreeTokenReader::new(Cursor::new(output))
+            .expect("Creating reader");
+        let (len, guard) = reader.list()
+            .expect("Reading list");
+        assert_eq!(len, 10);
+        for i in 0..10 {
+            let (name, _, node_guard) = reader.tagged_tuple()
+                .expect("Reading node");
+            assert_eq!(name, "Node");
+            assert_eq!(reader.bool().expect("Reading flag"), Some(i % 3 == 0));
+            let (name, _, child_guard) = reader.tagged_tuple()
+                .expect("Reading child");
+            if i % 2 == 0 {
+                assert_eq!(name, "");
+            } else {
+                assert_eq!(name, "Node");
+                assert_eq!(reader.bool().expect("Reading inner flag"), Some(true));
+                let (name, _, inner_guard) = reader.tagged_tuple()
+                    .expect("Reading inner child");
+                assert_eq!(name, "");
+                inner_guard.done()
+                    .expect("Finalizing inner child");
+            }
+            child_guard.done()
+                .expect("Finalizing child");
+            node_guard.done()
+                .expect("Finalizing node");
+        }
+        guard.done()
+            .expect("Finalizing list");
+    }
+}
+
+#[test]
+fn test_multipart_list_lengths() {
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(WriteOptions::default());
+    let item_0 = writer.string(Some("foo")).unwrap();
+    let item_1 = writer.string(Some("bar")).unwrap();
+    let list = writer.list(vec![item_0, item_1]).unwrap();
+    let after = writer.string(Some("baz")).unwrap();
+    writer.untagged_tuple(&[list, after])
+        .expect("Writing tuple");
+    let (output, stats) = writer.done()
+        .expect("Finalizing data");
+
+    // Only the number of items is written.
+    assert_eq!(stats.list_header.entries, 1);
+    assert_eq!(stats.list_header.own_bytes, 1);
+    assert_eq!(stats.omitted_list_bytes, 1);
+
+    // Reading fewer items than announced.
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let guard = reader.untagged_tuple()
+        .expect("Reading tuple");
+    let (len, list_guard) = reader.list()
+        .expect("Reading list");
+    assert_eq!(len, 2);
+    reader.string().expect("Reading string");
+    match list_guard.done() {
+        Err(TokenReaderError::BadLength { expected: 2, got: 1 }) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+    drop(guard);
+
+    // Reading more items than announced.
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let guard = reader.untagged_tuple()
+        .expect("Reading tuple");
+    let (_, list_guard) = reader.list()
+        .expect("Reading list");
+    reader.string().expect("Reading string");
+    reader.string().expect("Reading string");
+    match reader.string() {
+        Err(TokenReaderError::BadLength { expected: 2, got: 3 }) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+    drop(list_guard);
+    drop(guard);
+
+    // Reading exactly the announced items.
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let guard = reader.untagged_tuple()
+        .expect("Reading tuple");
+    let (_, list_guard) = reader.list()
+        .expect("Reading list");
+    reader.string().expect("Reading string");
+    reader.string().expect("Reading string");
+    list_guard.done()
+        .expect("Finalizing list");
+    assert_eq!(reader.string().expect("Reading string"), Some("baz".to_string()));
+    guard.done()
+        .expect("Finalizing tuple");
+}
+
+#[test]
+fn test_multipart_columns() {
+    use bytes::compress::Compression;
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    // A list of `Skippable { offset, value: float, flag: bool, name: string }`.
+    let write = |columns: Option<Columns>| {
+        let mut writer = TreeTokenWriter::new(WriteOptions {
+            columns,
+            .. WriteOptions::default()
+        });
+        let mut items = vec![];
+        for i in 0..10 {
+            let offset = writer.offset().unwrap();
+            let value = writer.float(Some(i as f64)).unwrap();
+            let flag = writer.bool(Some(i % 2 == 0)).unwrap();
+            let name = writer.string(Some(if i % 3 == 0 { "foo" } else { "bar" })).unwrap();
+            items.push(writer.tagged_tuple("Skippable", &[("_offset", offset), ("value", value), ("flag", flag), ("name", name)]).unwrap());
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let (interleaved, interleaved_stats) = write(None);
+    let (columnar, columnar_stats) = write(Some(Columns::uniform(Compression::Identity)));
+    assert_eq!(columnar_stats.tree.compression.before_bytes, interleaved_stats.tree.compression.before_bytes);
+
+    let mut offsets = vec![];
+    for output in &[interleaved, columnar] {
+        let mut reader = TreeTokenReader::new(Cursor::new(output))
+            .expect("Creating reader");
+        let (len, guard) = reader.list()
+            .expect("Reading list");
+        assert_eq!(len, 10);
+        for i in 0..10 {
+            let (name, _, tuple_guard) = reader.tagged_tuple()
+                .expect("Reading tuple");
+            assert_eq!(name, "Skippable");
+            offsets.push(reader.offset().expect("Reading offset"));
+            assert_eq!(reader.float().expect("Reading float"), Some(i as f64));
+            assert_eq!(reader.bool().expect("Reading bool"), Some(i % 2 == 0));
+            assert_eq!(reader.string().expect("Reading string"), Some(if i % 3 == 0 { "foo" } else { "bar" }.to_string()));
+            tuple_guard.done()
+                .expect("Finalizing tuple");
+        }
+        guard.done()
+            .expect("Finalizing list");
+    }
+
+    // Offsets have the same value in both layouts.
+    assert_eq!(offsets[..10], offsets[10..]);
+}
+
+#[test]
+fn test_multipart_dedup() {
+    use bytes::compress::Compression;
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    // A list of 3 identical `Pair { left: Node, right: Node }`, where `Node`
+    // is `Node { name: "foo", value: 1.0, leaf: Leaf { name: "bar" } }`.
+    let write = |options: WriteOptions| {
+        let mut writer = TreeTokenWriter::new(options);
+        let mut items = vec![];
+        for _ in 0..3 {
+            let mut nodes = vec![];
+            for _ in 0..2 {
+                let name = writer.string(Some("foo")).unwrap();
+                let value = writer.float(Some(1.)).unwrap();
+                let leaf_name = writer.string(Some("bar")).unwrap();
+                let leaf = writer.tagged_tuple("Leaf", &[("name", leaf_name)]).unwrap();
+                nodes.push(writer.tagged_tuple("Node", &[("name", name), ("value", value), ("leaf", leaf)]).unwrap());
+            }
+            let right = nodes.pop().unwrap();
+            let left = nodes.pop().unwrap();
+            items.push(writer.tagged_tuple("Pair", &[("left", left), ("right", right)]).unwrap());
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+    let read = |output: &[u8], limits: ReadLimits| -> Result<(), TokenReaderError> {
+        let mut reader = TreeTokenReader::with_limits(Cursor::new(output), limits)?;
+        let (len, guard) = reader.list()?;
+        assert_eq!(len, 3);
+        for _ in 0..3 {
+            let (name, _, pair_guard) = reader.tagged_tuple()?;
+            assert_eq!(name, "Pair");
+            for _ in 0..2 {
+                let (name, _, node_guard) = reader.tagged_tuple()?;
+                assert_eq!(name, "Node");
+                assert_eq!(reader.string()?, Some("foo".to_string()));
+                assert_eq!(reader.float()?, Some(1.));
+                let (name, _, leaf_guard) = reader.tagged_tuple()?;
+                assert_eq!(name, "Leaf");
+                assert_eq!(reader.string()?, Some("bar".to_string()));
+                leaf_guard.done()?;
+                node_guard.done()?;
+            }
+            pair_guard.done()?;
+        }
+        guard.done()
+    };
+
+    let (_, plain_stats) = write(WriteOptions::default());
+    assert_eq!(plain_stats.back_reference.entries, 0);
+
+    for options in &[
+        WriteOptions {
+            dedup: true,
+            .. WriteOptions::default()
+        },
+        WriteOptions {
+            dedup: true,
+            bitmaps: true,
+            columns: Some(Columns::uniform(Compression::Identity)),
+            .. WriteOptions::default()
+        },
+    ] {
+        let (output, stats) = write(options.clone());
+
+        // The second `Node` of the first `Pair`, then the next two `Pair`s.
+        // `Leaf` is not larger than a back-reference.
+        assert_eq!(stats.back_reference.entries, 3);
+        assert!(stats.deduplicated_bytes > stats.back_reference.own_bytes);
+        assert!(stats.tree.compression.before_bytes < plain_stats.tree.compression.before_bytes);
+
+        read(&output, ReadLimits::default())
+            .expect("Reading deduplicated tree");
+
+        // Each `Pair` read through a back-reference contains another back-reference.
+        let limits = ReadLimits {
+            max_back_references: 4,
+            .. ReadLimits::default()
+        };
+        match read(&output, limits) {
+            Err(TokenReaderError::TooManyBackReferences { max: 4 }) => {},
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+}
+
+#[test]
+fn test_multipart_dedup_cycle() {
+    use bytes::varnum::WriteVarNum;
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    // `Outer { inner: Inner, again: Inner }`, where `again` is a back-reference to `inner`.
+    let mut writer = TreeTokenWriter::new(WriteOptions {
+        dedup: true,
+        .. WriteOptions::default()
+    });
+    let mut inners = vec![];
+    for _ in 0..2 {
+        let name = writer.string(Some("foo")).unwrap();
+        let value = writer.float(Some(1.5)).unwrap();
+        inners.push(writer.tagged_tuple("Inner", &[("name", name), ("value", value)]).unwrap());
+    }
+    let again = inners.pop().unwrap();
+    let inner = inners.pop().unwrap();
+    writer.tagged_tuple("Outer", &[("inner", inner), ("again", again)])
+        .expect("Writing tagged tuple");
+    let (output, stats) = writer.done()
+        .expect("Finalizing data");
+    assert_eq!(stats.back_reference.entries, 1);
+
+    // Make `again` refer to `Outer`, which is still being read.
+    let mut output = output.to_vec();
+    assert_eq!(output.pop(), Some(0), "The tree should end with a back-reference to the previous tagged tuple");
+    output.write_varnum(1).unwrap();
+
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let (kind, _, _outer_guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert_eq!(kind, "Outer");
+    let (kind, _, inner_guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert_eq!(kind, "Inner");
+    assert_eq!(reader.string().unwrap(), Some("foo".to_string()));
+    assert_eq!(reader.float().unwrap(), Some(1.5));
+    inner_guard.done()
+        .expect("Finalizing tagged tuple");
+    match reader.tagged_tuple() {
+        Err(TokenReaderError::BadBackReference(1)) => {},
+        other => panic!("Unexpected result {:?}", other.map(|(kind, _, _)| kind))
+    }
+}
+
+#[test]
+fn test_multipart_string_tables() {
+    use bytes::compress::Compression;
+    use io::{ Guard, StringCategory, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let strings = [
+        (StringCategory::Identifier, "foo"),
+        (StringCategory::Literal, "foo"),
+        (StringCategory::PropertyKey, "bar"),
+        (StringCategory::Identifier, "foo"),
+    ];
+    let write = |string_tables: Option<StringTables>| {
+        let mut writer = TreeTokenWriter::new(WriteOptions {
+            string_tables,
+            .. WriteOptions::default()
+        });
+        let mut items = vec![];
+        for &(category, string) in &strings {
+            items.push(writer.categorized_string(category, Some(string)).unwrap());
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let (single, single_stats) = write(None);
+    let (split, split_stats) = write(Some(StringTables::uniform(Compression::Identity)));
+    assert_eq!(single_stats.strings_table.entries, 2);
+    assert_eq!(split_stats.strings_table.entries, 3);
+
+    for output in &[&single, &split] {
+        let mut reader = TreeTokenReader::new(Cursor::new(output))
+            .expect("Creating reader");
+        let (len, guard) = reader.list()
+            .expect("Reading list");
+        assert_eq!(len as usize, strings.len());
+        for &(category, string) in &strings {
+            assert_eq!(reader.categorized_string(category).expect("Reading string"), Some(string.to_string()));
+        }
+        guard.done()
+            .expect("Finalizing list");
+    }
+
+    // Each category has its own table.
+    let mut reader = TreeTokenReader::new(Cursor::new(&split))
+        .expect("Creating reader");
+    let headers: Vec<_> = reader.sections()
+        .iter()
+        .map(|section| section.header)
+        .collect();
+    assert_eq!(headers, vec!["[GRAMMAR]", "[STRINGS.IDENTIFIERS]", "[STRINGS.IDENTIFIER_NAMES]", "[STRINGS.PROPERTY_KEYS]", "[STRINGS.LITERALS]", "[TREE]"]);
+    let tables: Vec<Vec<_>> = reader.with_tables(|_, tables| tables.iter()
+        .map(|table| table.iter()
+            .map(|(_, string)| string.as_ref().map(|string| string.to_string()))
+            .collect())
+        .collect());
+    assert_eq!(tables, vec![
+        vec![Some("foo".to_string())],
+        vec![],
+        vec![Some("bar".to_string())],
+        vec![Some("foo".to_string())],
+    ]);
+
+    // A string must be read with its category. The reader is poisoned, so the guard may be dropped.
+    let (_, _guard) = reader.list()
+        .expect("Reading list");
+    match reader.categorized_string(StringCategory::IdentifierName) {
+        Err(TokenReaderError::BadStringIndex(0)) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+}
+
+#[test]
+fn test_multipart_enum_indices() {
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let operators = ["+", "-", "*", "/"];
+    let values = [2, 0, 2, 3, 1];
+    let write = |enum_indices: bool| {
+        let mut writer = TreeTokenWriter::new(WriteOptions {
+            enum_indices,
+            .. WriteOptions::default()
+        });
+        let mut items = vec![];
+        for &index in &values {
+            items.push(writer.string_enum(&operators, index).unwrap());
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let (strings, strings_stats) = write(false);
+    let (indices, indices_stats) = write(true);
+    assert_eq!(strings_stats.strings_table.entries, 4);
+    assert_eq!(indices_stats.strings_table.entries, 0);
+    assert_eq!(indices_stats.string_enum.entries, values.len());
+    assert!(indices.len() < strings.len());
+
+    for output in &[strings, indices] {
+        let mut reader = TreeTokenReader::new(Cursor::new(output))
+            .expect("Creating reader");
+        let (len, guard) = reader.list()
+            .expect("Reading list");
+        assert_eq!(len as usize, values.len());
+        for &index in &values {
+            assert_eq!(reader.string_enum(&operators).expect("Reading string enum"), index);
+        }
+        guard.done()
+            .expect("Finalizing list");
+    }
+
+    // Indices beyond the values of the enum are rejected. The reader is poisoned, so the guard may be dropped.
+    let mut writer = TreeTokenWriter::new(WriteOptions {
+        enum_indices: true,
+        .. WriteOptions::default()
+    });
+    let item = writer.string_enum(&operators, 3).unwrap();
+    writer.list(vec![item])
+        .expect("Writing list");
+    let (output, _) = writer.done()
+        .expect("Finalizing data");
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let (_, _guard) = reader.list()
+        .expect("Reading list");
+    match reader.string_enum(&operators[..3]) {
+        Err(TokenReaderError::BadEnumIndex { index: 3, len: 3 }) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+}
+
+#[test]
+fn test_multipart_field_order() {
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let write = |field_order: Option<FieldOrder>| {
+        let mut writer = TreeTokenWriter::new(WriteOptions {
+            field_order,
+            .. WriteOptions::default()
+        });
+        let a = writer.string(Some("a")).unwrap();
+        let b = writer.float(Some(1.)).unwrap();
+        let c = writer.bool(Some(true)).unwrap();
+        let inner = writer.tagged_tuple("Inner", &[("a", a), ("b", b), ("c", c)]).unwrap();
+        let offset = writer.offset().unwrap();
+        let d = writer.string(Some("d")).unwrap();
+        writer.tagged_tuple("Outer", &[("offset", offset), ("inner", inner), ("d", d)])
+            .expect("Writing tagged tuple");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    // Without `FIELD_ORDER`, fields are written in the order of the grammar.
+    let (output, _) = write(None);
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let (_, fields, guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert!(fields.is_none());
+    reader.offset()
+        .expect("Reading offset");
+    let (_, fields, inner_guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert!(fields.is_none());
+    assert_eq!(reader.string().unwrap(), Some("a".to_string()));
+    assert_eq!(reader.float().unwrap(), Some(1.));
+    assert_eq!(reader.bool().unwrap(), Some(true));
+    inner_guard.done()
+        .expect("Finalizing tagged tuple");
+    assert_eq!(reader.string().unwrap(), Some("d".to_string()));
+    guard.done()
+        .expect("Finalizing tagged tuple");
+
+    // With `FIELD_ORDER`, the listed fields come first or last, and offsets remain first.
+    let order = FieldOrder::last(&["inner", "a"])
+        .with_kind("Inner", &["c"]);
+    let (output, _) = write(Some(order));
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let grammar: Vec<_> = reader.with_tables(|grammar, _| grammar.iter()
+        .map(|(_, description)| (description.kind().to_string(), description.fields().map(|fields| fields.to_vec())))
+        .collect());
+    assert_eq!(grammar, vec![
+        ("Inner".to_string(), Some(vec!["c".to_string(), "b".to_string(), "a".to_string()])),
+        ("Outer".to_string(), Some(vec!["offset".to_string(), "d".to_string(), "inner".to_string()])),
+    ]);
+
+    let (kind, fields, guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert_eq!(kind, "Outer");
+    assert_eq!(fields.unwrap().to_vec(), vec!["offset", "d", "inner"]);
+    reader.offset()
+        .expect("Reading offset");
+    assert_eq!(reader.string().unwrap(), Some("d".to_string()));
+    let (kind, fields, inner_guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert_eq!(kind, "Inner");
+    assert_eq!(fields.unwrap().to_vec(), vec!["c", "b", "a"]);
+    assert_eq!(reader.bool().unwrap(), Some(true));
+    assert_eq!(reader.float().unwrap(), Some(1.));
+    assert_eq!(reader.string().unwrap(), Some("a".to_string()));
+    inner_guard.done()
+        .expect("Finalizing tagged tuple");
+    guard.done()
+        .expect("Finalizing tagged tuple");
+
+    // With a shuffled order, the fields are permuted, and offsets remain first.
+    let mut orders = std::collections::HashSet::new();
+    for seed in 0..16 {
+        let (output, _) = write(Some(FieldOrder::shuffle(seed)));
+        let mut reader = TreeTokenReader::new(Cursor::new(&output))
+            .expect("Creating reader");
+        let grammar: Vec<_> = reader.with_tables(|grammar, _| grammar.iter()
+            .map(|(_, description)| description.fields().map(|fields| fields.to_vec()).expect("Missing fields"))
+            .collect());
+        let mut inner = grammar[0].clone();
+        assert_eq!(grammar[1][0], "offset");
+        orders.insert(inner.clone());
+        inner.sort();
+        assert_eq!(inner, vec!["a", "b", "c"]);
+    }
+    assert!(orders.len() > 1, "Shuffling should change the order of fields");
+}
+
+#[test]
+fn test_multipart_atoms() {
+    use io::{ Guard, TokenReader, TokenWriter };
+    use multipart::*;
+
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    let mut writer = TreeTokenWriter::new(WriteOptions::default());
+    let mut items = vec![];
+    for &(kind, string) in &[("A", "foo"), ("B", "bar"), ("A", "foo")] {
+        let item = writer.string(Some(string)).unwrap();
+        items.push(writer.tagged_tuple(kind, &[("x", item)]).unwrap());
+    }
+    writer.list(items)
+        .expect("Writing list");
+    let (output, _) = writer.done()
+        .expect("Finalizing data");
+
+    // Kinds and strings read twice are shared with the tables, and kinds have the id of their table entry.
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let (len, guard) = reader.list()
+        .expect("Reading list");
+    assert_eq!(len, 3);
+    let mut read = vec![];
+    for _ in 0..len {
+        let (kind, tuple_guard) = reader.tagged_tuple_kind()
+            .expect("Reading tagged tuple");
+        let string = reader.string_atom()
+            .expect("Reading string")
+            .expect("Non-null string");
+        tuple_guard.done()
+            .expect("Finalizing tagged tuple");
+        read.push((kind, string));
+    }
+    guard.done()
+        .expect("Finalizing list");
+
+    assert_eq!(&*read[0].0.name, "A");
+    assert_eq!(&*read[1].0.name, "B");
+    assert_eq!(&*read[0].1, "foo");
+    assert_eq!(&*read[1].1, "bar");
+    assert!(read[0].0.id.is_some());
+    assert_eq!(read[0].0.id, read[2].0.id);
+    assert_ne!(read[0].0.id, read[1].0.id);
+    assert!(Rc::ptr_eq(&read[0].0.name, &read[2].0.name));
+    assert!(Rc::ptr_eq(&read[0].1, &read[2].1));
+}
+
+#[test]
+fn test_multipart_scope_names() {
+    use bytes::varnum::WriteVarNum;
+    use io::{ Guard, StringCategory, TokenReader, TokenWriter };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    // `Script { scope: AssertedVarScope { names: ["a", "b"] }, body: [b, a, c, f, f, a] }`,
+    // where `f` is `Function { scope: AssertedVarScope { names: ["a"] }, body: [a, b] }`.
+    let write = |options: WriteOptions| {
+        let mut writer = TreeTokenWriter::new(options);
+        {
+            let scope = |writer: &mut TreeTokenWriter, names: &[&str]| {
+                let names = names.iter()
+                    .map(|name| writer.categorized_string(StringCategory::IdentifierName, Some(name)).unwrap())
+                    .collect();
+                let names = writer.list(names).unwrap();
+                writer.tagged_tuple("AssertedVarScope", &[("names", names)]).unwrap()
+            };
+            let body = |writer: &mut TreeTokenWriter, names: &[&str]| {
+                let names = names.iter()
+                    .map(|name| writer.categorized_string(StringCategory::Identifier, Some(name)).unwrap())
+                    .collect();
+                writer.list(names).unwrap()
+            };
+            let mut items = vec![];
+            for name in &["b", "a", "c"] {
+                items.push(writer.categorized_string(StringCategory::Identifier, Some(name)).unwrap());
+            }
+            for _ in 0..2 {
+                let function_scope = scope(&mut writer, &["a"]);
+                let function_body = body(&mut writer, &["a", "b"]);
+                items.push(writer.tagged_tuple("Function", &[("scope", function_scope), ("body", function_body)]).unwrap());
+            }
+            items.push(writer.categorized_string(StringCategory::Identifier, Some("a")).unwrap());
+            let script_scope = scope(&mut writer, &["a", "b"]);
+            let script_body = writer.list(items).unwrap();
+            writer.tagged_tuple("Script", &[("scope", script_scope), ("body", script_body)]).unwrap();
+        }
+        writer.done()
+            .expect("Finalizing data")
+    };
+    let read = |output: &[u8]| -> Result<(), TokenReaderError> {
+        let mut reader = TreeTokenReader::new(Cursor::new(output))?;
+        let mut fields = vec![];
+        let (name, script_fields, script_guard) = reader.tagged_tuple()?;
+        assert_eq!(name, "Script");
+        fields.push(script_fields);
+        let read_scope = |reader: &mut TreeTokenReader, names: &[&str]| -> Result<(), TokenReaderError> {
+            let (name, _, guard) = reader.tagged_tuple()?;
+            assert_eq!(name, "AssertedVarScope");
+            let (len, list_guard) = reader.list()?;
+            assert_eq!(len as usize, names.len());
+            for name in names {
+                assert_eq!(reader.categorized_string(StringCategory::IdentifierName)?, Some(name.to_string()));
+            }
+            list_guard.done()?;
+            guard.done()
+        };
+        // Fields are read in the order in which they were written.
+        let scope_first = fields[0].as_ref().map(|fields| &*fields[0] == "scope").unwrap_or(true);
+        if scope_first {
+            read_scope(&mut reader, &["a", "b"])?;
+        }
+        let (len, body_guard) = reader.list()?;
+        assert_eq!(len, 6);
+        for name in &["b", "a", "c"] {
+            assert_eq!(reader.categorized_string(StringCategory::Identifier)?, Some(name.to_string()));
+        }
+        for _ in 0..2 {
+            let (name, _, function_guard) = reader.tagged_tuple()?;
+            assert_eq!(name, "Function");
+            if scope_first {
+                read_scope(&mut reader, &["a"])?;
+            }
+            let (len, list_guard) = reader.list()?;
+            assert_eq!(len, 2);
+            for name in &["a", "b"] {
+                assert_eq!(reader.categorized_string(StringCategory::Identifier)?, Some(name.to_string()));
+            }
+            list_guard.done()?;
+            if !scope_first {
+                read_scope(&mut reader, &["a"])?;
+            }
+            function_guard.done()?;
+        }
+        assert_eq!(reader.categorized_string(StringCategory::Identifier)?, Some("a".to_string()));
+        body_guard.done()?;
+        if !scope_first {
+            read_scope(&mut reader, &["a", "b"])?;
+        }
+        script_guard.done()
+    };
+
+    let options = WriteOptions {
+        scope_names: true,
+        .. WriteOptions::default()
+    };
+    let (_, plain_stats) = write(WriteOptions::default());
+    let (output, stats) = write(options.clone());
+    assert_eq!(plain_stats.name.entries, 0);
+    // Each identifier, whether it is a reference or a free name.
+    assert_eq!(stats.name.entries, 8);
+    // Only `c` and the declarations.
+    assert_eq!(stats.string.entries, 1 + 4);
+    read(&output)
+        .expect("Reading scope names");
+
+    for options in &[
+        WriteOptions {
+            dedup: true,
+            .. options.clone()
+        },
+        WriteOptions {
+            field_order: Some(FieldOrder::last(&["scope"])),
+            .. options.clone()
+        },
+    ] {
+        let (output, _) = write(options.clone());
+        read(&output)
+            .expect("Reading scope names");
+    }
+
+    // The last token is a reference to `a`, one of the two names of `Script`.
+    let mut bad = output.to_vec();
+    bad.pop();
+    bad.write_varnum(5)
+        .expect("Writing reference");
+    match read(&bad) {
+        Err(TokenReaderError::BadNameReference { reference: 5, visible: 2 }) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+}
@@ -3,6 +3,7 @@ use std::cell::RefCell;
 use std::io::{ Cursor, Read, Seek };
 use std::rc::Rc;
 
+use vec_map;
 use vec_map::VecMap;
 
 use bytes;
@@ -11,43 +12,109 @@ use bytes::varnum::*;
 use bytes::serialize::*;
 use ::TokenReaderError;
 use io::*;
-use multipart::{ FormatInTable, HEADER_GRAMMAR_TABLE, HEADER_STRINGS_TABLE, HEADER_TREE };
+use multipart::{ strings_table_header, Column, FeatureFlags, FormatInTable, Slot, FORMAT_VERSION, HEADER_GRAMMAR_TABLE, HEADER_LAYOUT, HEADER_STRINGS_TABLE, HEADER_TREE, SCOPE_ANNOTATIONS };
 use util::{ PoisonLock, Pos, ReadConst };
 
 impl Into<std::io::Error> for TokenReaderError {
     fn into(self) -> std::io::Error {
-        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", self))
+        std::io::Error::new(std::io::ErrorKind::InvalidData, self)
     }
 }
 
+/// Extract the `TokenReaderError` wrapped in an `std::io::Error`, if any,
+/// otherwise wrap the `std::io::Error` with `default`.
+fn unwrap_io_error<F>(err: std::io::Error, default: F) -> TokenReaderError where F: FnOnce(std::io::Error) -> TokenReaderError {
+    let is_token_reader_error = err.get_ref()
+        .map(|inner| inner.is::<TokenReaderError>())
+        .unwrap_or(false);
+    if !is_token_reader_error {
+        return default(err)
+    }
+    let inner = err.into_inner()
+        .unwrap(); // We have just checked that there is an inner error.
+    *inner.downcast::<TokenReaderError>()
+        .unwrap() // We have just checked the type of the inner error.
+}
+
+/// Limits on the resources used while reading a file.
+///
+/// Files may come from untrusted sources, so a reader MUST NOT trust
+/// the lengths it finds in the file. Any file that exceeds these limits
+/// is rejected with a dedicated `TokenReaderError`.
+#[derive(Clone, Debug)]
+pub struct ReadLimits {
+    /// Maximal number of bytes in a section, as stored in the file.
+    pub max_section_bytes: usize,
+
+    /// Maximal number of bytes in a section, once decompressed.
+    pub max_decompressed_bytes: usize,
+
+    /// Maximal number of bytes in a string (including node names).
+    pub max_string_bytes: usize,
+
+    /// Maximal number of items in a list.
+    pub max_list_length: u32,
+
+    /// Maximal number of nested lists and tuples.
+    pub max_depth: usize,
+
+    /// Maximal number of back-references followed while reading the tree,
+    /// including back-references within tagged tuples read through a
+    /// back-reference.
+    pub max_back_references: usize,
+}
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits {
+            max_section_bytes: 256 * 1024 * 1024,
+            max_decompressed_bytes: 1024 * 1024 * 1024,
+            max_string_bytes: 16 * 1024 * 1024,
+            max_list_length: 16 * 1024 * 1024,
+            max_depth: 100_000,
+            max_back_references: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// The features supported by this reader.
+const SUPPORTED_FEATURES: FeatureFlags = FeatureFlags(FeatureFlags::COMPACT_NUMBERS.0 | FeatureFlags::BITMAPS.0 | FeatureFlags::COLUMNS.0 | FeatureFlags::DEDUP.0 | FeatureFlags::STRING_TABLES.0 | FeatureFlags::ENUM_INDICES.0 | FeatureFlags::FIELD_ORDER.0 | FeatureFlags::INTEGERS.0 | FeatureFlags::SCOPE_NAMES.0);
+
 /// Deserialize a bunch of bytes into itself.
 struct BufDeserializer;
 impl Deserializer for BufDeserializer {
     type Target = Vec<u8>;
     fn read<R: Read + Seek>(&self, reader: &mut R) -> Result<Self::Target, std::io::Error> {
-        let size = reader.size();
-        let mut buf = Vec::with_capacity(size);
-        unsafe { buf.set_len(size); }
-        reader.read_exact(&mut buf)?;
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
         Ok(buf)
     }
 }
 
 /// Deserialize a String|null
-impl Deserializer for Option<String> {
-    type Target = Self;
-    fn read<R: Read>(&self, inp: &mut R) -> Result<Self, std::io::Error> {
+///
+/// Strings are shared, so that reading them from a table does not copy them.
+struct StringDeserializer {
+    /// The maximal number of bytes in a string.
+    max_bytes: usize,
+}
+impl Deserializer for StringDeserializer {
+    type Target = Option<Rc<str>>;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
         let mut byte_len = 0;
         inp.read_varnum(&mut byte_len)?;
-        let mut bytes = Vec::with_capacity(byte_len as usize);
-        unsafe { bytes.set_len(byte_len as usize); }
-        inp.read_exact(&mut bytes)?;
+        if byte_len as usize > self.max_bytes {
+            return Err(TokenReaderError::StringTooLong {
+                max: self.max_bytes,
+                got: byte_len as usize,
+            }.into())
+        }
+        let bytes = read_exactly(inp, byte_len as usize)?;
         if &bytes == &[255, 0] {
             Ok(None)
         } else {
             String::from_utf8(bytes)
                 .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
-                .map(Some)
+                .map(|string| Some(Rc::from(string)))
         }
     }
 }
@@ -60,6 +127,16 @@ impl<Value> Table<Value> {
     fn get(&self, key: u32) -> Option<&Value> {
         self.map.get(key as usize)
     }
+
+    /// The number of entries in the table.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Iterate through the entries of the table, in the order of their indices.
+    pub fn iter(&self) -> vec_map::Iter<Value> {
+        self.map.iter()
+    }
 }
 
 /// Deserialize a `Table`.
@@ -75,6 +152,16 @@ impl<'a, D> Deserializer for TableDeserializer<D> where D: Deserializer, D::Targ
         let mut number_of_entries = 0;
         inp.read_varnum(&mut number_of_entries)?;
 
+        // Each entry takes at least one byte, so don't trust `number_of_entries`
+        // blindly before allocating.
+        let remaining = inp.size() - inp.pos();
+        if number_of_entries as usize > remaining {
+            return Err(TokenReaderError::BadLength {
+                expected: number_of_entries as usize,
+                got: remaining
+            }.into());
+        }
+
         let mut map = VecMap::with_capacity(number_of_entries as usize);
 
         if D::Target::HAS_LENGTH_INDEX {
@@ -114,14 +201,37 @@ impl<'a, D> Deserializer for TableDeserializer<D> where D: Deserializer, D::Targ
 /// Description of a node in the table.
 #[derive(Debug)]
 pub struct NodeDescription {
-    kind: String,
+    kind: Rc<str>,
+
+    /// With feature `FIELD_ORDER`, the fields, in the order in which they are written.
+    fields: Option<Rc<Box<[String]>>>,
+}
+impl NodeDescription {
+    /// The name of the node, as specified in the grammar.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// With feature `FIELD_ORDER`, the fields of the node, in the order in
+    /// which they are written. Otherwise, fields are written in the order of
+    /// the grammar.
+    pub fn fields(&self) -> Option<&[String]> {
+        self.fields.as_ref()
+            .map(|fields| &***fields)
+    }
 }
 
 impl<'a> FormatInTable for NodeDescription {
     const HAS_LENGTH_INDEX : bool = false;
 }
 
-struct NodeDescriptionDeserializer;
+struct NodeDescriptionDeserializer {
+    /// The deserializer used for node names and field names.
+    strings: StringDeserializer,
+
+    /// `true` if entries list their fields, with feature `FIELD_ORDER`.
+    fields: bool,
+}
 
 /// Deserialize a `NodeDescription`.
 ///
@@ -130,87 +240,636 @@ impl Deserializer for NodeDescriptionDeserializer {
     type Target = NodeDescription;
     fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
         // Extract kind
-        let strings_deserializer : Option<String> = None;
-        let name = match strings_deserializer.read(inp)? {
+        let name = match self.strings.read(inp)? {
             None => return Err(TokenReaderError::EmptyNodeName.into()),
             Some(x) => x
         };
 
+        let fields = if self.fields {
+            let len = inp.read_varnum_2()?;
+            // Don't trust `len` blindly before allocating.
+            let mut fields = Vec::new();
+            for _ in 0..len {
+                match self.strings.read(inp)? {
+                    None => return Err(TokenReaderError::EmptyFieldName.into()),
+                    Some(field) => fields.push(field.to_string())
+                }
+            }
+            Some(Rc::new(fields.into_boxed_slice()))
+        } else {
+            None
+        };
+
         Ok(NodeDescription {
             kind: name,
+            fields,
         })
     }
 }
 
+/// Deserialize the layouts of a `[LAYOUT]` section, indexed by kind.
+struct LayoutsDeserializer;
+impl Deserializer for LayoutsDeserializer {
+    type Target = VecMap<Rc<Vec<Slot>>>;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        let number_of_entries = inp.read_varnum_2()?;
+
+        let mut layouts = VecMap::new();
+        for _ in 0..number_of_entries {
+            let kind = inp.read_varnum_2()?;
+            let number_of_fields = inp.read_varnum_2()? as usize;
+
+            // Each slot takes at least one byte, so don't trust `number_of_fields`
+            // blindly before allocating.
+            let remaining = inp.size() - inp.pos();
+            if number_of_fields > remaining {
+                return Err(TokenReaderError::BadLength {
+                    expected: number_of_fields,
+                    got: remaining
+                }.into());
+            }
+            let mut slots = Vec::with_capacity(number_of_fields);
+            for _ in 0..number_of_fields {
+                slots.push(Slot::from_code(inp.read_varnum_2()?));
+            }
+            layouts.insert(kind as usize, Rc::new(slots));
+        }
+        Ok(layouts)
+    }
+}
+
+/// The field of a tagged tuple, as determined by its layout and bitmap.
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    /// The field is written as a token.
+    Token,
+
+    /// A packed boolean.
+    Bool(bool),
+
+    /// An absent field, i.e. a tagged tuple without fields, with the given
+    /// index in the grammar table.
+    Absent(u32),
+}
+
+/// With feature `SCOPE_NAMES`, the names that a tagged tuple declares.
+#[derive(Clone, Copy, Debug)]
+enum Declarations {
+    /// A scope annotation, whose names are declared in the enclosing tagged tuple.
+    Annotation,
+
+    /// Any other tagged tuple, with the number of names visible when it started.
+    /// The names declared within it are visible until its end.
+    Scope(usize),
+}
+
+/// A list or tuple being read.
+struct Frame {
+    /// For packed tagged tuples, the fields, in order. Empty otherwise.
+    fields: Vec<Field>,
+
+    /// For lists, the number of items.
+    items: Option<u32>,
+
+    /// The number of fields or items already read.
+    next: usize,
+
+    /// For a tagged tuple read through a back-reference, the positions in
+    /// the tree and the number of tagged tuples at which to resume reading.
+    resume: Option<(Vec<u64>, u32)>,
+
+    /// With feature `DEDUP`, for a tagged tuple, its number.
+    ordinal: Option<u32>,
+
+    /// With feature `SCOPE_NAMES`, for a tagged tuple, the names it declares.
+    declarations: Option<Declarations>,
+}
+impl Frame {
+    fn list(items: u32) -> Self {
+        Frame {
+            fields: vec![],
+            items: Some(items),
+            next: 0,
+            resume: None,
+            ordinal: None,
+            declarations: None,
+        }
+    }
+
+    fn tuple(fields: Vec<Field>) -> Self {
+        Frame {
+            fields,
+            items: None,
+            next: 0,
+            resume: None,
+            ordinal: None,
+            declarations: None,
+        }
+    }
+}
+
 /// The state of the `TreeTokenReader`.
 ///
 /// Use a `PoisonLock` to access this state.
 pub struct ReaderState {
+    /// The tree, unless the container has feature `COLUMNS`.
     reader: Cursor<Vec<u8>>,
-    pub strings_table: Table<Option<String>>,
+
+    /// With feature `COLUMNS`, the columns of the tree, indexed by `Column`.
+    columns: Vec<Cursor<Vec<u8>>>,
+
+    /// The strings tables, indexed by `StringCategory` with feature
+    /// `STRING_TABLES`, a single table otherwise.
+    pub strings_tables: Vec<Table<Option<Rc<str>>>>,
     pub grammar_table: Table<NodeDescription>,
+
+    /// The features of the container, which determine the representation of tokens.
+    features: FeatureFlags,
+
+    /// The representation of integers in the tree, `VarNum` unless the container has feature `INTEGERS`.
+    integers: IntegerEncoding,
+
+    /// With feature `BITMAPS`, the layout of packed kinds, by index in the grammar table.
+    layouts: VecMap<Rc<Vec<Slot>>>,
+
+    /// The lists and tuples currently being read, innermost last.
+    frames: Vec<Frame>,
+
+    /// With feature `DEDUP`, the number of the next tagged tuple.
+    tuples: u32,
+
+    /// With feature `DEDUP`, the positions in the tree at which each tagged
+    /// tuple starts, one per stream, by number.
+    starts: Vec<u64>,
+
+    /// The number of back-references followed so far.
+    back_references: usize,
+
+    /// With feature `SCOPE_NAMES`, the names declared by the scope annotations
+    /// read so far that are still visible, in the order in which they were read.
+    names: Vec<Rc<str>>,
+    limits: ReadLimits,
+}
+impl ReaderState {
+    /// The stream from which tokens of `column` are read.
+    fn stream(&mut self, column: Column) -> &mut Cursor<Vec<u8>> {
+        if self.columns.is_empty() {
+            &mut self.reader
+        } else {
+            &mut self.columns[column as usize]
+        }
+    }
+
+    /// Read an integer of the tree from the stream of `column`.
+    fn read_integer(&mut self, column: Column) -> Result<u32, TokenReaderError> {
+        let integers = self.integers;
+        integers.read_u32(self.stream(column))
+            .map_err(TokenReaderError::ReadError)
+    }
+
+    /// The positions in each stream of the tree.
+    fn positions(&self) -> Vec<u64> {
+        if self.columns.is_empty() {
+            vec![self.reader.position()]
+        } else {
+            self.columns.iter()
+                .map(|column| column.position())
+                .collect()
+        }
+    }
+
+    fn seek(&mut self, positions: &[u64]) {
+        if self.columns.is_empty() {
+            self.reader.set_position(positions[0]);
+        } else {
+            for (column, position) in self.columns.iter_mut().zip(positions) {
+                column.set_position(*position);
+            }
+        }
+    }
+
+    /// Follow a back-reference, whose marker has just been read, to the
+    /// start of the tagged tuple it refers to.
+    ///
+    /// Returns the positions and number of tagged tuples at which reading
+    /// must resume once this tagged tuple has been read.
+    fn follow_back_reference(&mut self) -> Result<(Vec<u64>, u32), TokenReaderError> {
+        if self.back_references >= self.limits.max_back_references {
+            return Err(TokenReaderError::TooManyBackReferences {
+                max: self.limits.max_back_references
+            })
+        }
+        self.back_references += 1;
+        let distance = self.read_integer(Column::Lengths)?;
+        if distance >= self.tuples {
+            return Err(TokenReaderError::BadBackReference(distance))
+        }
+        let target = self.tuples - 1 - distance;
+        // A tagged tuple that is still being read would contain itself.
+        if self.frames.iter().any(|frame| frame.ordinal == Some(target)) {
+            return Err(TokenReaderError::BadBackReference(distance))
+        }
+        let resume = (self.positions(), self.tuples);
+        let stride = resume.0.len();
+        let start = self.starts[target as usize * stride..(target as usize + 1) * stride].to_vec();
+        self.seek(&start);
+        self.tuples = target;
+        Ok(resume)
+    }
+
+    /// Enter a list or a tuple.
+    fn enter(&mut self, frame: Frame) -> Result<(), TokenReaderError> {
+        if self.frames.len() >= self.limits.max_depth {
+            return Err(TokenReaderError::TooDeep {
+                max: self.limits.max_depth
+            })
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// Leave the innermost list or tuple, resuming after the back-reference
+    /// through which it was read, if any.
+    fn exit(&mut self) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return
+        };
+        if let Some(Declarations::Scope(visible)) = frame.declarations {
+            self.names.truncate(visible);
+        }
+        if let Some((positions, tuples)) = frame.resume {
+            self.seek(&positions);
+            self.tuples = tuples;
+        }
+    }
+
+    /// With feature `SCOPE_NAMES`, `true` if the innermost tagged tuple is a scope annotation.
+    fn in_annotation(&self) -> bool {
+        match self.frames.iter().rev().filter_map(|frame| frame.declarations).next() {
+            Some(Declarations::Annotation) => true,
+            _ => false
+        }
+    }
+
+    /// With feature `SCOPE_NAMES`, read an identifier, which may be a reference
+    /// to a declared name rather than an entry in the strings table.
+    fn read_name(&mut self) -> Result<Option<Option<Rc<str>>>, TokenReaderError> {
+        let reference = self.read_integer(Column::Strings)?;
+        if reference == 0 {
+            return Ok(None)
+        }
+        let visible = self.names.len();
+        if reference as usize > visible {
+            return Err(TokenReaderError::BadNameReference {
+                reference,
+                visible
+            })
+        }
+        let name = self.names[visible - reference as usize].clone();
+        debug!(target: "multipart", "Reading name reference {} => {:?}", reference, name);
+        Ok(Some(Some(name)))
+    }
+
+    /// Advance to the next token of the innermost list or tuple, returning
+    /// the field it represents.
+    ///
+    /// Lists are not prefixed by their byte length, so reading past the end
+    /// of a list is detected by counting items.
+    fn next_field(&mut self) -> Result<Field, TokenReaderError> {
+        match self.frames.last_mut() {
+            Some(frame) => {
+                if let Some(items) = frame.items {
+                    if frame.next >= items as usize {
+                        return Err(TokenReaderError::BadLength {
+                            expected: items as usize,
+                            got: frame.next + 1,
+                        })
+                    }
+                }
+                let field = frame.fields.get(frame.next)
+                    .cloned()
+                    .unwrap_or(Field::Token);
+                frame.next += 1;
+                Ok(field)
+            }
+            None => Ok(Field::Token)
+        }
+    }
+
+    /// Advance to the next token, which MUST be written as a token.
+    fn next_token(&mut self) -> Result<(), TokenReaderError> {
+        match self.next_field()? {
+            Field::Token => Ok(()),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    /// Check that all the items of the innermost list have been read.
+    fn check_list_complete(&self) -> Result<(), TokenReaderError> {
+        match self.frames.last() {
+            Some(&Frame { items: Some(items), next, .. }) if next != items as usize => {
+                Err(TokenReaderError::BadLength {
+                    expected: items as usize,
+                    got: next,
+                })
+            }
+            _ => Ok(())
+        }
+    }
+
+    /// Read the bitmap of a tagged tuple with layout `slots`.
+    fn read_fields(&mut self, slots: &[Slot]) -> Result<Vec<Field>, TokenReaderError> {
+        let packed = slots.iter()
+            .filter(|slot| slot.is_packed())
+            .count();
+        let mut bitmap = vec![0; (packed + 7) / 8];
+        self.stream(Column::Bools).read_exact(&mut bitmap)
+            .map_err(TokenReaderError::ReadError)?;
+        let mut bits = (0..packed)
+            .map(|i| bitmap[i / 8] & (1 << (i % 8)) != 0);
+        let fields = slots.iter()
+            .map(|slot| {
+                match *slot {
+                    Slot::Token => Field::Token,
+                    Slot::Bool => Field::Bool(bits.next().unwrap()), // We have read one bit per packed slot.
+                    Slot::Optional(kind) => {
+                        if bits.next().unwrap() {
+                            Field::Token
+                        } else {
+                            Field::Absent(kind)
+                        }
+                    }
+                }
+            })
+            .collect();
+        Ok(fields)
+    }
+}
+
+/// Information on one section of the container.
+///
+/// Used to inspect the structure of a file, e.g. for debugging purposes.
+#[derive(Clone, Debug)]
+pub struct SectionInfo {
+    /// The header of the section, e.g. `[TREE]`.
+    pub header: &'static str,
+
+    /// The position of the header in the container, in bytes.
+    pub offset: usize,
+
+    /// The compression format used for the section.
+    pub compression: Compression,
+
+    /// The number of bytes of the section, as stored in the container.
+    pub compressed_bytes: usize,
+
+    /// The number of bytes of the section, once decompressed.
+    pub decompressed_bytes: usize,
 }
 
 pub struct TreeTokenReader {
     // Shared with all children.
     owner: Rc<RefCell<PoisonLock<ReaderState>>>,
+
+    /// The container version number.
+    version: u32,
+
+    /// The features used by the container.
+    features: FeatureFlags,
+
+    /// The sections of the container, in the order in which they appear.
+    sections: Vec<SectionInfo>,
 }
 
 
 impl TreeTokenReader {
-    pub fn new<R: Read + Seek>(mut reader: R) -> Result<Self, TokenReaderError> {
+    pub fn new<R: Read + Seek>(reader: R) -> Result<Self, TokenReaderError> {
+        Self::with_limits(reader, ReadLimits::default())
+    }
+
+    /// Create a reader, rejecting any file that exceeds `limits`.
+    pub fn with_limits<R: Read + Seek>(mut reader: R, limits: ReadLimits) -> Result<Self, TokenReaderError> {
         // Check magic headers.
         const MAGIC_HEADER: &'static [u8; 5] = b"BINJS";
-        const FORMAT_VERSION: u32 = 0;
 
         reader.read_const(MAGIC_HEADER)
-            .map_err(TokenReaderError::ReadError)?;
+            .map_err(|_| TokenReaderError::BadHeader)?;
 
         let mut version = 0;
         reader.read_varnum(&mut version)
             .map_err(TokenReaderError::ReadError)?;
 
-        if version != FORMAT_VERSION {
-            return Err(TokenReaderError::BadHeader)
+        let features = match version {
+            0 => FeatureFlags::NONE, // Version 0 doesn't have feature flags.
+            FORMAT_VERSION => {
+                let flags = reader.read_varnum_2()
+                    .map_err(TokenReaderError::ReadError)?;
+                FeatureFlags(flags)
+            }
+            _ => return Err(TokenReaderError::UnsupportedVersion(version))
+        };
+
+        // Reject mandatory features we don't know about, ignore optional features.
+        let unsupported = features.mandatory()
+            .difference(Self::supported_features());
+        if !unsupported.is_empty() {
+            return Err(TokenReaderError::UnsupportedFeatures(unsupported.0))
         }
+        let integers = if features.contains(FeatureFlags::INTEGERS) {
+            let code = reader.read_varnum_2()
+                .map_err(TokenReaderError::ReadError)?;
+            IntegerEncoding::from_code(code)
+                .ok_or(TokenReaderError::UnsupportedIntegerEncoding(code))?
+        } else {
+            IntegerEncoding::VarNum
+        };
+        debug!(target: "multipart", "Container version {}, features {:?}, integers {:?}", version, features, integers);
 
-        // At this stage, we could start parallelizing reads between grammar table and strings table, possibly even the tree.
-        reader.read_const(HEADER_GRAMMAR_TABLE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
+        let mut sections = Vec::with_capacity(3);
 
+        // At this stage, we could start parallelizing reads between grammar table and strings table, possibly even the tree.
         // Read grammar table
         let grammar_deserializer = TableDeserializer {
-            deserializer: NodeDescriptionDeserializer
+            deserializer: NodeDescriptionDeserializer {
+                strings: StringDeserializer {
+                    max_bytes: limits.max_string_bytes
+                },
+                fields: features.contains(FeatureFlags::FIELD_ORDER),
+            }
         };
-        let grammar_table = Compression::decompress(&mut reader, &grammar_deserializer)
-            .map_err(TokenReaderError::BadCompression)?;
+        let grammar_table = Self::read_section(&mut reader, HEADER_GRAMMAR_TABLE, &grammar_deserializer, &limits, &mut sections)?;
         debug!(target: "multipart", "Grammar table: {:?}",
             grammar_table.map);
 
         // Read strings table
-        reader.read_const(HEADER_STRINGS_TABLE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
         let strings_deserializer = TableDeserializer {
-            deserializer: None /* Option<String> */
+            deserializer: StringDeserializer {
+                max_bytes: limits.max_string_bytes
+            }
+        };
+        let strings_tables = if features.contains(FeatureFlags::STRING_TABLES) {
+            let mut tables = Vec::with_capacity(StringCategory::ALL.len());
+            for category in &StringCategory::ALL {
+                tables.push(Self::read_section(&mut reader, strings_table_header(*category), &strings_deserializer, &limits, &mut sections)?);
+            }
+            tables
+        } else {
+            vec![Self::read_section(&mut reader, HEADER_STRINGS_TABLE, &strings_deserializer, &limits, &mut sections)?]
         };
-        let strings_table = Compression::decompress(&mut reader, &strings_deserializer)
-            .map_err(TokenReaderError::BadCompression)?;
 
-        // Decompress tree section to memory (we could as well stream it)
-        reader.read_const(HEADER_TREE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
-        let decompressed_tree = Compression::decompress(&mut reader, &BufDeserializer)
-            .map_err(TokenReaderError::BadCompression)?;
+        // Read layout table
+        let layouts = if features.contains(FeatureFlags::BITMAPS) {
+            Self::read_section(&mut reader, HEADER_LAYOUT, &LayoutsDeserializer, &limits, &mut sections)?
+        } else {
+            VecMap::new()
+        };
+
+        // Decompress tree section(s) to memory (we could as well stream it)
+        let (decompressed_tree, columns) = if features.contains(FeatureFlags::COLUMNS) {
+            let mut columns = Vec::with_capacity(Column::ALL.len());
+            for column in &Column::ALL {
+                let decompressed = Self::read_section(&mut reader, column.header(), &BufDeserializer, &limits, &mut sections)?;
+                columns.push(Cursor::new(decompressed));
+            }
+            (Vec::new(), columns)
+        } else {
+            (Self::read_section(&mut reader, HEADER_TREE, &BufDeserializer, &limits, &mut sections)?, Vec::new())
+        };
         let implem = ReaderState {
-            strings_table,
+            strings_tables,
             grammar_table,
-            reader: Cursor::new(decompressed_tree)
+            reader: Cursor::new(decompressed_tree),
+            columns,
+            features,
+            integers,
+            layouts,
+            frames: Vec::new(),
+            tuples: 0,
+            starts: Vec::new(),
+            back_references: 0,
+            names: Vec::new(),
+            limits,
         };
 
         Ok(TreeTokenReader {
-            owner: Rc::new(RefCell::new(PoisonLock::new(implem)))
+            owner: Rc::new(RefCell::new(PoisonLock::new(implem))),
+            version,
+            features,
+            sections,
         })
     }
+
+    /// The features that this reader knows how to decode.
+    pub fn supported_features() -> FeatureFlags {
+        SUPPORTED_FEATURES
+    }
+
+    /// The features used by this container.
+    pub fn features(&self) -> FeatureFlags {
+        self.features
+    }
+
+    /// Read the header of a section, then decompress and deserialize its contents.
+    ///
+    /// Any unknown section found before the expected section is skipped.
+    fn read_section<R: Read + Seek, D: Deserializer>(reader: &mut R, header: &'static str, deserializer: &D, limits: &ReadLimits, sections: &mut Vec<SectionInfo>) -> Result<D::Target, TokenReaderError> {
+        let mut offset = reader.pos();
+        loop {
+            let found = Self::read_section_header(reader)?;
+            if found == header {
+                break;
+            }
+            if found == HEADER_GRAMMAR_TABLE || found == HEADER_STRINGS_TABLE || found == HEADER_LAYOUT || found == HEADER_TREE
+                || StringCategory::ALL.iter().any(|category| found == strings_table_header(*category))
+                || Column::ALL.iter().any(|column| found == column.header()) {
+                // A known section, but not at the expected position.
+                debug!(target: "multipart", "Found section {} while expecting {}", found, header);
+                return Err(TokenReaderError::BadHeader)
+            }
+            debug!(target: "multipart", "Skipping unknown section {}", found);
+            Compression::skip(reader)
+                .map_err(TokenReaderError::BadCompression)?;
+            offset = reader.pos();
+        }
+        let compression = Compression::read_prefix(reader)
+            .map_err(TokenReaderError::BadCompression)?;
+        let compressed_bytes = reader.read_varnum_2()
+            .map_err(TokenReaderError::ReadError)? as usize;
+        if compressed_bytes > limits.max_section_bytes {
+            return Err(TokenReaderError::SectionTooLarge {
+                max: limits.max_section_bytes,
+                got: compressed_bytes
+            })
+        }
+        let compressed = read_exactly(reader, compressed_bytes)
+            .map_err(TokenReaderError::ReadError)?;
+        let decompressed = compression.decompress_bytes(&compressed, limits.max_decompressed_bytes)
+            .map_err(|err| unwrap_io_error(err, TokenReaderError::BadCompression))?;
+        let decompressed_bytes = decompressed.len();
+        let value = deserializer.read(&mut Cursor::new(decompressed))
+            .map_err(|err| unwrap_io_error(err, TokenReaderError::BadCompression))?;
+        sections.push(SectionInfo {
+            header,
+            offset,
+            compression,
+            compressed_bytes,
+            decompressed_bytes,
+        });
+        Ok(value)
+    }
+
+    /// Read a section header, i.e. `[NAME]`.
+    fn read_section_header<R: Read>(reader: &mut R) -> Result<String, TokenReaderError> {
+        const MAX_LENGTH: usize = 32;
+        let mut header = Vec::with_capacity(MAX_LENGTH);
+        let mut buf = [0];
+        for _ in 0..MAX_LENGTH {
+            reader.read_exact(&mut buf)
+                .map_err(TokenReaderError::ReadError)?;
+            header.push(buf[0]);
+            if header[0] != b'[' {
+                return Err(TokenReaderError::BadHeader)
+            }
+            if buf[0] == b']' {
+                return String::from_utf8(header)
+                    .map_err(TokenReaderError::Encoding)
+            }
+        }
+        Err(TokenReaderError::BadHeader)
+    }
+
+    /// The container version number.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The sections of the container, in the order in which they appear.
+    pub fn sections(&self) -> &[SectionInfo] {
+        &self.sections
+    }
+
+    /// Inspect the grammar table and the strings tables.
+    ///
+    /// With feature `STRING_TABLES`, there is one strings table per
+    /// `StringCategory`, in this order. Otherwise, there is a single table.
+    pub fn with_tables<F, T>(&self, f: F) -> T where F: FnOnce(&Table<NodeDescription>, &[Table<Option<Rc<str>>>]) -> T {
+        let owner = self.owner.borrow();
+        let state = owner.state();
+        f(&state.grammar_table, &state.strings_tables)
+    }
+
+    /// The current position in the decompressed `[TREE]` section, in bytes.
+    ///
+    /// With feature `COLUMNS`, the number of bytes read from all the columns.
+    pub fn tree_position(&self) -> usize {
+        let owner = self.owner.borrow();
+        let state = owner.state();
+        let position = state.reader.position() + state.columns.iter()
+            .map(|column| column.position())
+            .sum::<u64>();
+        position as usize
+    }
 }
 
 pub struct SimpleGuard {
@@ -224,11 +883,25 @@ impl SimpleGuard {
             owner
         }
     }
+
+    /// Mark the list or tuple as read entirely.
+    fn exit(&mut self) {
+        self.parent.finalized = true;
+
+        let mut owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return
+        }
+        let _ : Result<(), TokenReaderError> = owner.try(|state| {
+            state.exit();
+            Ok(())
+        });
+    }
 }
 impl Guard for SimpleGuard {
     type Error = TokenReaderError;
     fn done(mut self) -> Result<(), Self::Error> {
-        self.parent.finalized = true;
+        self.exit();
         Ok(())
     }
 }
@@ -257,13 +930,17 @@ impl ListGuard {
 impl Guard for ListGuard {
     type Error = TokenReaderError;
     fn done(mut self) -> Result<(), Self::Error> {
-        self.parent.parent.finalized = true;
-
-        let owner = self.parent.owner.borrow_mut();
-        if owner.is_poisoned() {
-            return Ok(())
-        }
-        Ok(())
+        let result = {
+            let mut owner = self.parent.owner.borrow_mut();
+            if owner.is_poisoned() {
+                // The error has already been reported.
+                Ok(())
+            } else {
+                owner.try(|state| state.check_list_complete())
+            }
+        };
+        self.parent.exit();
+        result
     }
 }
 impl Drop for ListGuard {
@@ -283,17 +960,73 @@ impl TokenReader for TreeTokenReader {
         self.owner.borrow_mut().poison();
     }
 
+    fn position(&self) -> Option<usize> {
+        Some(self.tree_position())
+    }
+
     fn string(&mut self) -> Result<Option<String>, Self::Error> {
+        self.categorized_string(StringCategory::Literal)
+    }
+
+    fn categorized_string(&mut self, category: StringCategory) -> Result<Option<String>, Self::Error> {
+        Ok(self.categorized_string_atom(category)?
+            .map(|string| string.to_string()))
+    }
+
+    fn string_atom(&mut self) -> Result<Option<Rc<str>>, Self::Error> {
+        self.categorized_string_atom(StringCategory::Literal)
+    }
+
+    fn categorized_string_atom(&mut self, category: StringCategory) -> Result<Option<Rc<str>>, Self::Error> {
         self.owner.borrow_mut().try(|state| {
-            let index = state.reader.read_varnum_2()
-                .map_err(TokenReaderError::ReadError)?;
-            match state.strings_table.get(index) {
-                Some(result) => {
-                    debug!(target: "multipart", "Reading string {:?} => {:?}", index, result);
-                    Ok(result.clone())
+            state.next_token()?;
+            let scope_names = state.features.contains(FeatureFlags::SCOPE_NAMES);
+            if scope_names && category == StringCategory::Identifier {
+                if let Some(name) = state.read_name()? {
+                    return Ok(name)
+                }
+            }
+            let index = state.read_integer(Column::Strings)?;
+            let table = if state.features.contains(FeatureFlags::STRING_TABLES) {
+                category as usize
+            } else {
+                0
+            };
+            let result = match state.strings_tables[table].get(index) {
+                Some(result) => result.clone(),
+                None => return Err(TokenReaderError::BadStringIndex(index))
+            };
+            debug!(target: "multipart", "Reading string {:?} ({:?}) => {:?}", index, category, result);
+            if scope_names && category == StringCategory::IdentifierName && state.in_annotation() {
+                if let Some(ref name) = result {
+                    state.names.push(name.clone());
                 }
-                None => Err(TokenReaderError::BadStringIndex(index))
             }
+            Ok(result)
+        })
+    }
+
+    fn string_enum(&mut self, strings: &[&str]) -> Result<usize, Self::Error> {
+        if !self.owner.borrow().state().features.contains(FeatureFlags::ENUM_INDICES) {
+            let string = self.string_atom()?;
+            return self.owner.borrow_mut().try(|_| match string {
+                None => Err(TokenReaderError::EmptyVariant),
+                Some(string) => strings.iter()
+                    .position(|candidate| *candidate == &*string)
+                    .ok_or(TokenReaderError::InvalidValue)
+            })
+        }
+        self.owner.borrow_mut().try(|state| {
+            state.next_token()?;
+            let index = state.read_integer(Column::Kinds)?;
+            if index as usize >= strings.len() {
+                return Err(TokenReaderError::BadEnumIndex {
+                    index,
+                    len: strings.len()
+                })
+            }
+            debug!(target: "multipart", "Reading string enum {} => {:?}", index, strings[index as usize]);
+            Ok(index as usize)
         })
     }
 
@@ -301,8 +1034,15 @@ impl TokenReader for TreeTokenReader {
     /// Read a single `f64`. Note that all numbers are `f64`.
     fn float(&mut self) -> Result<Option<f64>, Self::Error> {
         self.owner.borrow_mut().try(|state| {
-            let mut buf : [u8; 8] = unsafe { std::mem::uninitialized() };
-            state.reader.read(&mut buf)
+            state.next_token()?;
+            if state.features.contains(FeatureFlags::COMPACT_NUMBERS) {
+                let result = bytes::float::read_compact_float(state.stream(Column::Numbers))
+                    .map_err(TokenReaderError::ReadError)?;
+                debug!(target: "multipart", "Reading compact float => {:?}", result);
+                return Ok(result)
+            }
+            let mut buf = [0; 8];
+            state.stream(Column::Numbers).read_exact(&mut buf)
                 .map_err(TokenReaderError::ReadError)?;
             let result = bytes::float::float_of_bytes(&buf);
             debug!(target: "multipart", "Reading float {:?} => {:?}", buf, result);
@@ -313,8 +1053,16 @@ impl TokenReader for TreeTokenReader {
     /// Read a single `bool`.
     fn bool(&mut self) -> Result<Option<bool>, Self::Error> {
         self.owner.borrow_mut().try(|state| {
-            let mut buf : [u8; 1] = unsafe { std::mem::uninitialized() };
-            state.reader.read(&mut buf)
+            match state.next_field()? {
+                Field::Token => {},
+                Field::Bool(value) => {
+                    debug!(target: "multipart", "Reading packed bool => {:?}", value);
+                    return Ok(Some(value))
+                }
+                Field::Absent(_) => return Err(TokenReaderError::InvalidValue)
+            }
+            let mut buf = [0; 1];
+            state.stream(Column::Bools).read_exact(&mut buf)
                 .map_err(TokenReaderError::ReadError)?;
             let result = bytes::bool::bool_of_bytes(&buf)
                 .map_err(|_| TokenReaderError::InvalidValue);
@@ -326,8 +1074,8 @@ impl TokenReader for TreeTokenReader {
     /// Read a single `bool`.
     fn offset(&mut self) -> Result<u32, Self::Error> {
         self.owner.borrow_mut().try(|state| {
-            let byte_len = state.reader.read_varnum_2()
-                .map_err(TokenReaderError::ReadError)?;
+            state.next_token()?;
+            let byte_len = state.read_integer(Column::Lengths)?;
             Ok(byte_len)
         })
     }
@@ -340,10 +1088,19 @@ impl TokenReader for TreeTokenReader {
     fn list(&mut self) -> Result<(u32, Self::ListGuard), Self::Error> {
         let clone = self.owner.clone();
         self.owner.borrow_mut().try(move |state| {
-            let guard = ListGuard::new(clone);
-            let list_len = state.reader.read_varnum_2()
-                .map_err(TokenReaderError::ReadError)?;
+            state.next_token()?;
+            let list_len = state.read_integer(Column::Lengths)?;
+            if list_len > state.limits.max_list_length {
+                return Err(TokenReaderError::ListTooLong {
+                    max: state.limits.max_list_length,
+                    got: list_len
+                })
+            }
+            state.enter(Frame::list(list_len))?;
             debug!(target: "multipart", "Reading list with {} items", list_len);
+            // Create the guard only once we know that we have succeeded,
+            // as dropping it requires access to the state.
+            let guard = ListGuard::new(clone);
             Ok((list_len, guard))
         })
     }
@@ -354,18 +1111,71 @@ impl TokenReader for TreeTokenReader {
     /// sub-extractor dedicated
     /// to that tuple. The sub-extractor MUST be consumed entirely.
     fn tagged_tuple(&mut self) -> Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error> {
+        let (kind, guard) = self.tagged_tuple_kind()?;
+        Ok((kind.name.to_string(), kind.fields, guard))
+    }
+
+    /// As `tagged_tuple`, with the index of the kind in the grammar table
+    /// as its id.
+    fn tagged_tuple_kind(&mut self) -> Result<(TupleKind, Self::TaggedGuard), Self::Error> {
         let clone = self.owner.clone();
         self.owner.borrow_mut().try(|state| {
-            let index = state.reader.read_varnum_2()
-                .map_err(TokenReaderError::ReadError)?;
-            let description = state.grammar_table.get(index)
-                .ok_or(TokenReaderError::BadKindIndex(index))?;
+            let mut resume = None;
+            let mut ordinal = None;
+            let (index, fields) = match state.next_field()? {
+                Field::Token => {
+                    let dedup = state.features.contains(FeatureFlags::DEDUP);
+                    let mut start = if dedup { state.positions() } else { vec![] };
+                    let mut index = state.read_integer(Column::Kinds)?;
+                    if dedup && index as usize == state.grammar_table.len() {
+                        resume = Some(state.follow_back_reference()?);
+                        debug!(target: "multipart", "Following back-reference to tagged tuple {}", state.tuples);
+                        start = state.positions();
+                        index = state.read_integer(Column::Kinds)?;
+                    }
+                    if dedup {
+                        // Tagged tuples read again through a back-reference
+                        // have already been recorded.
+                        if state.starts.len() == state.tuples as usize * start.len() {
+                            state.starts.extend(start);
+                        }
+                        ordinal = Some(state.tuples);
+                        state.tuples += 1;
+                    }
+                    let fields = match state.layouts.get(index as usize).cloned() {
+                        Some(slots) => state.read_fields(&slots)?,
+                        None => vec![]
+                    };
+                    (index, fields)
+                }
+                Field::Absent(index) => (index, vec![]),
+                Field::Bool(_) => return Err(TokenReaderError::InvalidValue)
+            };
+            let kind = {
+                let description = state.grammar_table.get(index)
+                    .ok_or(TokenReaderError::BadKindIndex(index))?;
+                TupleKind {
+                    id: Some(index),
+                    name: description.kind.clone(),
+                    fields: description.fields.clone(),
+                }
+            };
+            let mut frame = Frame::tuple(fields);
+            frame.resume = resume;
+            frame.ordinal = ordinal;
+            if state.features.contains(FeatureFlags::SCOPE_NAMES) {
+                frame.declarations = Some(if SCOPE_ANNOTATIONS.contains(&&*kind.name) {
+                    Declarations::Annotation
+                } else {
+                    Declarations::Scope(state.names.len())
+                });
+            }
+            state.enter(frame)?;
 
-            let tag = description.kind.clone();
             let guard = SimpleGuard::new(clone);
             debug!(target: "multipart", "Reading tagged tuple with kind \"{}\"",
-                tag);
-            Ok((tag, None, guard))
+                kind.name);
+            Ok((kind, guard))
         })
     }
 
@@ -374,6 +1184,10 @@ impl TokenReader for TreeTokenReader {
     fn untagged_tuple(&mut self) -> Result<Self::UntaggedGuard, Self::Error> {
         let clone = self.owner.clone();
         debug!(target: "multipart", "Reading untagged tuple");
+        self.owner.borrow_mut().try(|state| {
+            state.next_token()?;
+            state.enter(Frame::tuple(vec![]))
+        })?;
         Ok(SimpleGuard::new(clone))
     }
 }
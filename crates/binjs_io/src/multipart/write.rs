@@ -1,5 +1,6 @@
 use bytes;
 use bytes::compress::*;
+use bytes::float::NumberEncoding;
 use bytes::varnum::*;
 use io::*;
 use ::TokenWriterError;
@@ -7,9 +8,10 @@ use multipart::*;
 
 use std;
 use std::collections::{ HashMap, HashSet };
+use std::collections::hash_map::DefaultHasher;
 use std::cell::RefCell;
 use std::fmt::{ Debug, Display, Formatter };
-use std::hash::Hash;
+use std::hash::{ Hash, Hasher };
 use std::io::Write;
 use std::ops::{ Add, AddAssign };
 use std::rc::Rc;
@@ -24,6 +26,150 @@ pub struct WriteOptions {
     pub grammar_table: Compression,
     pub strings_table: Compression,
     pub tree: Compression,
+
+    /// The representation of numbers in the tree.
+    pub numbers: NumberEncoding,
+
+    /// If `true`, pack booleans and the presence of optional fields of
+    /// tagged tuples into per-node bitmaps.
+    pub bitmaps: bool,
+
+    /// If specified, split the tree into one section per type of token, each
+    /// compressed independently. Otherwise, the tree is a single section,
+    /// compressed with `tree`.
+    pub columns: Option<Columns>,
+
+    /// If `true`, replace tagged tuples that have already been written by
+    /// back-references, whenever this saves bytes.
+    pub dedup: bool,
+
+    /// If specified, split strings into one table per `StringCategory`, each
+    /// compressed independently. Otherwise, all strings are in a single table,
+    /// compressed with `strings_table`.
+    pub string_tables: Option<StringTables>,
+
+    /// If `true`, write values of string enums as their index in the enum
+    /// rather than as entries in the strings table.
+    pub enum_indices: bool,
+
+    /// If specified, write the fields of tagged tuples in this order, and
+    /// record the order of the fields of each kind in the grammar table.
+    /// Otherwise, fields are written in the order of the grammar.
+    pub field_order: Option<FieldOrder>,
+
+    /// The representation of integers in the tree.
+    pub integers: IntegerEncoding,
+
+    /// If `true`, write identifiers that have been declared by an enclosing
+    /// scope annotation as references to their declaration rather than as
+    /// entries in the strings table.
+    pub scope_names: bool,
+}
+impl WriteOptions {
+    /// The optional features required to read a file written with these options.
+    pub fn features(&self) -> FeatureFlags {
+        let mut features = FeatureFlags::NONE;
+        if self.numbers == NumberEncoding::Compact {
+            features = features.union(FeatureFlags::COMPACT_NUMBERS);
+        }
+        if self.bitmaps {
+            features = features.union(FeatureFlags::BITMAPS);
+        }
+        if self.columns.is_some() {
+            features = features.union(FeatureFlags::COLUMNS);
+        }
+        if self.dedup {
+            features = features.union(FeatureFlags::DEDUP);
+        }
+        if self.string_tables.is_some() {
+            features = features.union(FeatureFlags::STRING_TABLES);
+        }
+        if self.enum_indices {
+            features = features.union(FeatureFlags::ENUM_INDICES);
+        }
+        if self.field_order.is_some() {
+            features = features.union(FeatureFlags::FIELD_ORDER);
+        }
+        if self.integers != IntegerEncoding::VarNum {
+            features = features.union(FeatureFlags::INTEGERS);
+        }
+        if self.scope_names {
+            features = features.union(FeatureFlags::SCOPE_NAMES);
+        }
+        features
+    }
+
+    /// One variant of these options for each optional feature, named after
+    /// this feature.
+    ///
+    /// Columns are compressed with the same format as the tree, string tables
+    /// with the same format as the strings table. The only field order writes
+    /// the scope annotations last.
+    pub fn variants(&self) -> Vec<(&'static str, WriteOptions)> {
+        vec![
+            ("compact numbers", WriteOptions {
+                numbers: NumberEncoding::Compact,
+                .. self.clone()
+            }),
+            ("bitmaps", WriteOptions {
+                bitmaps: true,
+                .. self.clone()
+            }),
+            ("columns", WriteOptions {
+                columns: Some(Columns::uniform(self.tree.clone())),
+                .. self.clone()
+            }),
+            ("string tables", WriteOptions {
+                string_tables: Some(StringTables::uniform(self.strings_table.clone())),
+                .. self.clone()
+            }),
+            ("dedup", WriteOptions {
+                dedup: true,
+                .. self.clone()
+            }),
+            ("enum indices", WriteOptions {
+                enum_indices: true,
+                .. self.clone()
+            }),
+            ("scopes last", WriteOptions {
+                field_order: Some(FieldOrder::last(&["scope", "parameterScope", "bodyScope"])),
+                .. self.clone()
+            }),
+            ("leb128", WriteOptions {
+                integers: IntegerEncoding::Leb128,
+                .. self.clone()
+            }),
+            ("prefix varint", WriteOptions {
+                integers: IntegerEncoding::PrefixVarint,
+                .. self.clone()
+            }),
+            ("scope names", WriteOptions {
+                scope_names: true,
+                .. self.clone()
+            }),
+        ]
+    }
+}
+
+/// No compression, and the same representation of values as earlier versions
+/// of the format.
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            grammar_table: Compression::Identity,
+            strings_table: Compression::Identity,
+            tree: Compression::Identity,
+            numbers: NumberEncoding::default(),
+            bitmaps: false,
+            columns: None,
+            dedup: false,
+            string_tables: None,
+            enum_indices: false,
+            field_order: None,
+            integers: IntegerEncoding::default(),
+            scope_names: false,
+        }
+    }
 }
 
 impl Rand for WriteOptions {
@@ -32,10 +178,200 @@ impl Rand for WriteOptions {
             grammar_table: Compression::rand(rng),
             strings_table: Compression::rand(rng),
             tree: Compression::rand(rng),
+            numbers: NumberEncoding::rand(rng),
+            bitmaps: rng.gen(),
+            columns: if rng.gen() { Some(Columns::rand(rng)) } else { None },
+            dedup: rng.gen(),
+            string_tables: if rng.gen() { Some(StringTables::rand(rng)) } else { None },
+            enum_indices: rng.gen(),
+            field_order: if rng.gen() { Some(FieldOrder::rand(rng)) } else { None },
+            integers: IntegerEncoding::rand(rng),
+            scope_names: rng.gen(),
         }
     }
 }
 
+/// The compression of each column of the tree, see `WriteOptions::columns`.
+#[derive(Clone, Debug)]
+pub struct Columns {
+    /// The entries of tagged tuples in the grammar table.
+    pub kinds: Compression,
+
+    /// The entries of strings in the strings table.
+    pub strings: Compression,
+
+    /// Floats.
+    pub numbers: Compression,
+
+    /// Booleans and bitmaps.
+    pub bools: Compression,
+
+    /// Numbers of items of lists and offsets.
+    pub lengths: Compression,
+}
+impl Columns {
+    /// Compress all the columns with the same format.
+    pub fn uniform(compression: Compression) -> Self {
+        Columns {
+            kinds: compression.clone(),
+            strings: compression.clone(),
+            numbers: compression.clone(),
+            bools: compression.clone(),
+            lengths: compression,
+        }
+    }
+
+    fn compression(&self, column: Column) -> &Compression {
+        match column {
+            Column::Kinds => &self.kinds,
+            Column::Strings => &self.strings,
+            Column::Numbers => &self.numbers,
+            Column::Bools => &self.bools,
+            Column::Lengths => &self.lengths,
+        }
+    }
+}
+
+impl Rand for Columns {
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        Columns {
+            kinds: Compression::rand(rng),
+            strings: Compression::rand(rng),
+            numbers: Compression::rand(rng),
+            bools: Compression::rand(rng),
+            lengths: Compression::rand(rng),
+        }
+    }
+}
+
+/// The compression of the strings table of each category, see `WriteOptions::string_tables`.
+#[derive(Clone, Debug)]
+pub struct StringTables {
+    /// Names of variables and labels.
+    pub identifiers: Compression,
+
+    /// Names of properties and names declared in scopes.
+    pub identifier_names: Compression,
+
+    /// Keys of literal properties.
+    pub property_keys: Compression,
+
+    /// All other strings.
+    pub literals: Compression,
+}
+impl StringTables {
+    /// Compress all the tables with the same format.
+    pub fn uniform(compression: Compression) -> Self {
+        StringTables {
+            identifiers: compression.clone(),
+            identifier_names: compression.clone(),
+            property_keys: compression.clone(),
+            literals: compression,
+        }
+    }
+
+    fn compression(&self, category: StringCategory) -> &Compression {
+        match category {
+            StringCategory::Identifier => &self.identifiers,
+            StringCategory::IdentifierName => &self.identifier_names,
+            StringCategory::PropertyKey => &self.property_keys,
+            StringCategory::Literal => &self.literals,
+        }
+    }
+}
+
+impl Rand for StringTables {
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        StringTables {
+            identifiers: Compression::rand(rng),
+            identifier_names: Compression::rand(rng),
+            property_keys: Compression::rand(rng),
+            literals: Compression::rand(rng),
+        }
+    }
+}
+
+/// The order in which the fields of tagged tuples are written, see `WriteOptions::field_order`.
+///
+/// The fields of a tagged tuple are written in this order:
+///
+/// 1. the fields listed for its kind in `kinds`, in the order of the list;
+/// 2. the fields that are listed neither in `kinds` nor in `last`, in the order of the grammar,
+///    or in a pseudo-random order if `shuffle` is specified;
+/// 3. the fields listed in `last`, in the order of the list.
+///
+/// An offset always remains the first field.
+#[derive(Clone, Debug, Default)]
+pub struct FieldOrder {
+    /// For some kinds, the fields to write first.
+    pub kinds: HashMap<String, Vec<String>>,
+
+    /// For all kinds, the fields to write last, e.g. scope annotations.
+    pub last: Vec<String>,
+
+    /// If specified, the seed of a pseudo-random permutation of the fields
+    /// of each kind. Mostly useful to test readers.
+    pub shuffle: Option<u64>,
+}
+impl FieldOrder {
+    /// For all kinds, write the fields named in `last` after all other fields.
+    pub fn last(last: &[&str]) -> Self {
+        FieldOrder {
+            kinds: HashMap::new(),
+            last: last.iter()
+                .map(|field| field.to_string())
+                .collect(),
+            shuffle: None,
+        }
+    }
+
+    /// For all kinds, write the fields in a pseudo-random order derived from `seed`.
+    pub fn shuffle(seed: u64) -> Self {
+        FieldOrder {
+            shuffle: Some(seed),
+            .. FieldOrder::default()
+        }
+    }
+
+    /// Write the fields of `kind` named in `first` before all other fields.
+    pub fn with_kind(mut self, kind: &str, first: &[&str]) -> Self {
+        self.kinds.insert(kind.to_string(), first.iter()
+            .map(|field| field.to_string())
+            .collect());
+        self
+    }
+
+    /// The indices of `fields`, the fields of `kind` in the order of the
+    /// grammar, in the order in which they should be written.
+    fn permutation(&self, kind: &str, fields: &[&str]) -> Vec<usize> {
+        let first = self.kinds.get(kind);
+        let rank = |field: &str| {
+            if let Some(position) = first.and_then(|first| first.iter().position(|candidate| candidate == field)) {
+                (0, position as u64)
+            } else if let Some(position) = self.last.iter().position(|candidate| candidate == field) {
+                (2, position as u64)
+            } else if let Some(seed) = self.shuffle {
+                let mut hasher = DefaultHasher::new();
+                (seed, kind, field).hash(&mut hasher);
+                (1, hasher.finish())
+            } else {
+                (1, 0)
+            }
+        };
+        let mut permutation: Vec<_> = (0..fields.len()).collect();
+        // The sort is stable, so other fields remain in the order of the grammar.
+        permutation.sort_by_key(|&i| rank(fields[i]));
+        permutation
+    }
+}
+
+/// A pseudo-random permutation of the fields of each kind.
+impl Rand for FieldOrder {
+    fn rand<R: Rng>(rng: &mut R) -> Self {
+        FieldOrder::shuffle(rng.gen())
+    }
+}
+
 
 /// A value that may be serialized to bytes, optionally compressed.
 trait Serializable {
@@ -108,14 +444,19 @@ struct TableEntry<T> where T: Clone + std::fmt::Debug { // We shouldn't need the
     data: T,
 
     /// The index, actually computed in `write()`.
-    index: TableIndex<T>
+    index: TableIndex<T>,
+
+    /// The number of entries inserted before this one. Used to break ties
+    /// in `write()`, so that the output doesn't depend on the order of the `HashMap`.
+    rank: usize,
 }
 impl<T> TableEntry<T> where T: Clone + std::fmt::Debug {
-    fn new(data: T) -> Self {
+    fn new(data: T, rank: usize) -> Self {
         TableEntry {
             instances: RefCell::new(1),
             index: TableIndex::new(&format!("{:?}", data)),
             data,
+            rank,
         }
     }
 }
@@ -153,13 +494,14 @@ impl<Entry> WriterTable<Entry> where Entry: Eq + Hash + Clone + Serializable + F
     /// If the entry is already present, increment its number instances of 1.
     fn insert(&mut self, entry: Entry) -> TableIndex<Entry> {
         use std::collections::hash_map::Entry::*;
+        let rank = self.map.len();
         match self.map.entry(entry) {
             Occupied(slot) => {
                 *slot.get().instances.borrow_mut() += 1;
                 slot.get().index.clone()
             }
             Vacant(slot) => {
-                let entry = TableEntry::new(slot.key().clone());
+                let entry = TableEntry::new(slot.key().clone(), rank);
                 let index = entry.index.clone();
                 slot.insert(entry);
                 index
@@ -180,9 +522,10 @@ impl<Entry> Serializable for WriterTable<Entry> where Entry: Eq + Hash + Clone +
     fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
         let mut total = 0;
 
-        // Sort entries by number of uses.
+        // Sort entries by number of uses, then by order of insertion.
         let mut contents : Vec<_> = self.map.values().collect();
-        contents.sort_unstable_by(|a, b| u32::cmp(&*b.instances.borrow(), &*a.instances.borrow()));
+        contents.sort_unstable_by(|a, b| u32::cmp(&*b.instances.borrow(), &*a.instances.borrow())
+            .then(usize::cmp(&a.rank, &b.rank)));
 
         // Assign TableIndex
         for i in 0..contents.len() {
@@ -227,18 +570,28 @@ impl<Entry> Serializable for WriterTable<Entry> where Entry: Eq + Hash + Clone +
 #[derive(PartialEq, Eq, Clone, Hash, Debug)] // FIXME: Clone shouldn't be necessary. Sigh.
 pub struct NodeDescription {
     kind: String,
+
+    /// With `WriteOptions::field_order`, the fields, in the order in which they are written.
+    fields: Option<Vec<String>>,
 }
 
 /// Format:
-/// - kind name (see Option<String>);
-/// - number of fields (varnum);
-/// - for each field
-///    - field name (see Option<String>)
+/// - kind name (see String);
+/// - with feature `FIELD_ORDER`
+///    - number of fields (varnum);
+///    - for each field
+///       - field name (see String)
 impl Serializable for NodeDescription {
     fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
         let mut total = 0;
 
         total += self.kind.to_string().write(out)?;
+        if let Some(ref fields) = self.fields {
+            total += out.write_varnum(fields.len() as u32)?;
+            for field in fields {
+                total += field.write(out)?;
+            }
+        }
         Ok(total)
     }
 }
@@ -247,6 +600,134 @@ impl FormatInTable for NodeDescription {
     const HAS_LENGTH_INDEX : bool = false;
 }
 
+/// A table of layouts, indexed by kind, is serialized as
+///
+/// - number of entries (varnum);
+/// - for each entry,
+///    - index of the kind in the grammar table (varnum);
+///    - number of fields (varnum);
+///    - for each field, the code of its slot (varnum).
+impl Serializable for VecMap<Vec<Slot>> {
+    fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        let mut total = 0;
+        total += out.write_varnum(self.len() as u32)?;
+        for (kind, slots) in self.iter() {
+            total += out.write_varnum(kind as u32)?;
+            total += out.write_varnum(slots.len() as u32)?;
+            for slot in slots {
+                total += out.write_varnum(slot.code())?;
+            }
+        }
+        Ok(total)
+    }
+}
+
+/// The way a field of a kind may be packed, learnt from all the tagged tuples
+/// of that kind.
+#[derive(Clone, Debug)]
+enum FieldShape {
+    /// So far, only non-null booleans.
+    Bool,
+
+    /// So far, only tagged tuples. The kind of the first tagged tuple without
+    /// fields, if any.
+    TaggedTuple(Option<TableIndex<NodeDescription>>),
+
+    /// Anything else.
+    Token,
+}
+impl FieldShape {
+    fn of(tree: &UnresolvedTree) -> Self {
+        match (&tree.nature, &tree.data) {
+            (&Nature::Bool, &UnresolvedTreeNode::Encoded(ref encoded))
+                if encoded[..] != bytes::bool::bytes_of_bool(None)[..] => FieldShape::Bool,
+            (&Nature::TaggedTuple(_), _) => FieldShape::TaggedTuple(tree.empty_kind().cloned()),
+            _ => FieldShape::Token,
+        }
+    }
+
+    fn merge(self, other: FieldShape) -> Self {
+        match (self, other) {
+            (FieldShape::Bool, FieldShape::Bool) => FieldShape::Bool,
+            (FieldShape::TaggedTuple(None), FieldShape::TaggedTuple(kind)) => FieldShape::TaggedTuple(kind),
+            (FieldShape::TaggedTuple(Some(kind)), FieldShape::TaggedTuple(_)) => FieldShape::TaggedTuple(Some(kind)),
+            _ => FieldShape::Token,
+        }
+    }
+
+    /// The slot of the field, once the grammar table has been indexed.
+    fn slot(&self) -> Slot {
+        match *self {
+            FieldShape::Bool => Slot::Bool,
+            FieldShape::TaggedTuple(Some(ref kind)) =>
+                Slot::Optional(kind.index().expect("TableIndex hasn't been resolved")),
+            _ => Slot::Token,
+        }
+    }
+}
+
+/// The representation of a subtree, abstracted from its tokens. Two subtrees
+/// with the same structure are written as the same bytes.
+#[derive(PartialEq, Eq, Hash)]
+enum Structure {
+    /// An index into the table of strings or the table of nodes.
+    Index(Column, u32),
+    Encoded(Column, Vec<u8>),
+    Offset(u32),
+    Tuple(Vec<u32>),
+}
+
+/// The state of the deduplication of tagged tuples, see `WriteOptions::dedup`.
+struct Dedup {
+    /// The structure of each tagged tuple, by address, until it is resolved.
+    structures: HashMap<*const UnresolvedTree, u32>,
+
+    /// For each structure already written, the number of tagged tuples written
+    /// before its first occurrence and the byte length of that occurrence.
+    written: HashMap<u32, (u32, u32)>,
+
+    /// The number of tagged tuples written so far, not including back-references.
+    count: u32,
+
+    /// The index that marks a back-reference in place of an entry of the grammar table.
+    marker: u32,
+}
+impl Dedup {
+    fn new(marker: u32) -> Self {
+        Dedup {
+            structures: HashMap::new(),
+            written: HashMap::new(),
+            count: 0,
+            marker,
+        }
+    }
+
+    /// A back-reference to the first occurrence of `structure`, if it has
+    /// already been written and is larger than the back-reference.
+    fn back_reference(&self, structure: u32, stats: &mut Statistics, integers: IntegerEncoding) -> Option<(u32, ResolvedTree)> {
+        let (ordinal, byte_len) = match self.written.get(&structure) {
+            Some(&written) => written,
+            None => return None
+        };
+        let mut marker = Vec::with_capacity(4);
+        let marker_len = integers.write_u32(&mut marker, self.marker).unwrap(); // This operation can't fail.
+        let mut distance = Vec::with_capacity(4);
+        let distance_len = integers.write_u32(&mut distance, self.count - 1 - ordinal).unwrap(); // This operation can't fail.
+        let own = (marker_len + distance_len) as u32;
+        if own >= byte_len {
+            return None
+        }
+        stats.back_reference.entries += 1;
+        stats.back_reference.own_bytes += own as usize;
+        stats.back_reference.total_bytes += own as usize;
+        stats.deduplicated_bytes += byte_len as usize;
+        Some((own, ResolvedTree::Tuple(vec![
+            ResolvedTree::Encoded(Column::Kinds, marker),
+            ResolvedTree::Encoded(Column::Lengths, distance),
+        ])))
+    }
+}
+
 /// The tree, as it is being built.
 enum UnresolvedTreeNode {
     /// An index into the table of strings.
@@ -257,6 +738,10 @@ enum UnresolvedTreeNode {
 
     /// A subtree, preceded by the number of bytes it takes.
     UnresolvedOffset(Option<Box<UnresolvedTree>>),
+
+    /// With `WriteOptions::scope_names`, a string of category `Identifier` or
+    /// `IdentifierName`, until the names declared around it are known.
+    UnresolvedName(StringCategory, Option<String>),
     Tuple(Vec<Rc<UnresolvedTree>>),
     Encoded(Vec<u8>),
 }
@@ -264,16 +749,57 @@ struct UnresolvedTree {
     nature: Nature,
     data: UnresolvedTreeNode
 }
+impl UnresolvedTree {
+    /// If this is a tagged tuple without fields, its kind.
+    fn empty_kind(&self) -> Option<&TableIndex<NodeDescription>> {
+        if let (&Nature::TaggedTuple(ref index), &UnresolvedTreeNode::Tuple(ref items)) = (&self.nature, &self.data) {
+            if let Some(body) = items.get(1) {
+                if let UnresolvedTreeNode::Tuple(ref fields) = body.data {
+                    if fields.is_empty() {
+                        return Some(index)
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Compute the structure of this subtree, recording the structure of
+    /// each tagged tuple in `structures`.
+    fn intern(&self, interner: &mut HashMap<Structure, u32>, structures: &mut HashMap<*const UnresolvedTree, u32>) -> u32 {
+        use self::UnresolvedTreeNode::*;
+        let structure = match self.data {
+            UnresolvedStringIndex(ref index) => Structure::Index(Column::Strings, index.index()
+                .expect("String index should have been resolved by now.")),
+            UnresolvedNodeIndex(ref index) => Structure::Index(Column::Kinds, index.index()
+                .expect("Node index should have been resolved by now.")),
+            UnresolvedOffset(None) => panic!("UnresolvedOffset should have children"),
+            UnresolvedOffset(Some(ref child)) => Structure::Offset(child.intern(interner, structures)),
+            UnresolvedName(..) => panic!("Names should have been resolved by now."),
+            Tuple(ref items) => Structure::Tuple(items.iter()
+                .map(|item| item.intern(interner, structures))
+                .collect()),
+            Encoded(ref vec) => Structure::Encoded(self.nature.column(), vec.clone()),
+        };
+        let next = interner.len() as u32;
+        let id = *interner.entry(structure)
+            .or_insert(next);
+        if let Nature::TaggedTuple(_) = self.nature {
+            structures.insert(self as *const UnresolvedTree, id);
+        }
+        id
+    }
+}
 
 enum ResolvedTree {
     Tuple(Vec<ResolvedTree>),
-    Encoded(Vec<u8>),
+    Encoded(Column, Vec<u8>),
 }
 
 impl ResolvedTree {
     fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
         match *self {
-            ResolvedTree::Encoded(ref buf) => {
+            ResolvedTree::Encoded(_, ref buf) => {
                 out.write_all(&*buf)?;
                 Ok(buf.len())
             }
@@ -286,19 +812,150 @@ impl ResolvedTree {
             }
         }
     }
+
+    /// Write the tokens to their respective columns, indexed by `Column`.
+    fn write_columns(&self, columns: &mut [Vec<u8>]) {
+        match *self {
+            ResolvedTree::Encoded(column, ref buf) => {
+                columns[column as usize].extend_from_slice(buf);
+            }
+            ResolvedTree::Tuple(ref items) => {
+                for item in items {
+                    item.write_columns(columns);
+                }
+            }
+        }
+    }
 }
 
 impl UnresolvedTree {
-    fn resolve(self, stats: &mut Statistics) -> (u32, ResolvedTree) {
+    /// Move the fields of a tagged tuple that are packed according to `slots`
+    /// into a bitmap, placed after the header.
+    fn pack(data: UnresolvedTreeNode, slots: &[Slot], stats: &mut Statistics) -> UnresolvedTreeNode {
+        let mut items = match data {
+            UnresolvedTreeNode::Tuple(items) => items,
+            _ => panic!("A tagged tuple should be a tuple")
+        };
+        // `items` is `[header, body]`, where the fields of `body` may be wrapped in an offset.
+        let body = items.pop()
+            .expect("A tagged tuple should have a body");
+        let mut body = std::rc::Rc::try_unwrap(body)
+            .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
+        let bits = match body.data {
+            UnresolvedTreeNode::UnresolvedOffset(Some(ref mut inner)) => {
+                match inner.data {
+                    // The offset is the first field.
+                    UnresolvedTreeNode::Tuple(ref mut fields) => Self::pack_fields(fields, &slots[1..], stats),
+                    _ => panic!("The fields of a tagged tuple should be a tuple")
+                }
+            }
+            UnresolvedTreeNode::Tuple(ref mut fields) => Self::pack_fields(fields, slots, stats),
+            _ => panic!("The fields of a tagged tuple should be a tuple")
+        };
+
+        let mut bitmap = vec![0; (bits.len() + 7) / 8];
+        for (i, bit) in bits.iter().enumerate() {
+            if *bit {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        items.push(Rc::new(UnresolvedTree {
+            nature: Nature::Bitmap,
+            data: UnresolvedTreeNode::Encoded(bitmap),
+        }));
+        items.push(Rc::new(body));
+        UnresolvedTreeNode::Tuple(items)
+    }
+
+    /// Remove the packed fields from `fields`, returning their bits.
+    fn pack_fields(fields: &mut Vec<Rc<UnresolvedTree>>, slots: &[Slot], stats: &mut Statistics) -> Vec<bool> {
+        assert_eq!(fields.len(), slots.len(), "All tagged tuples of a kind should have the same number of fields");
+        let mut bits = Vec::with_capacity(slots.len());
+        let mut kept = Vec::with_capacity(fields.len());
+        for (field, slot) in fields.drain(..).zip(slots) {
+            match *slot {
+                Slot::Bool => {
+                    let value = match field.data {
+                        UnresolvedTreeNode::Encoded(ref encoded) => encoded[..] == bytes::bool::bytes_of_bool(Some(true))[..],
+                        _ => panic!("A packed field should be a boolean")
+                    };
+                    bits.push(value);
+                    stats.bool.entries += 1;
+                    continue;
+                }
+                Slot::Optional(kind) => {
+                    let is_absent = field.empty_kind()
+                        .and_then(|index| index.index()) == Some(kind);
+                    bits.push(!is_absent);
+                    if is_absent {
+                        stats.tagged_tuple.entries += 1;
+                        match stats.per_kind_index.entry(kind as usize) {
+                            vec_map::Entry::Occupied(mut entry) => {
+                                entry.get_mut().entries += 1;
+                            }
+                            vec_map::Entry::Vacant(entry) => {
+                                entry.insert(NodeStatistics {
+                                    entries: 1,
+                                    max_entries: 1,
+                                    .. NodeStatistics::default()
+                                });
+                            }
+                        }
+                        continue;
+                    }
+                }
+                Slot::Token => {}
+            }
+            kept.push(field);
+        }
+        *fields = kept;
+        bits
+    }
+
+    /// Resolve `tree`, an item of a tuple, replacing it with a back-reference
+    /// if it is a tagged tuple that has already been written.
+    fn resolve_child(tree: Rc<UnresolvedTree>, stats: &mut Statistics, layouts: &VecMap<Vec<Slot>>, dedup: &mut Dedup, integers: IntegerEncoding) -> (u32, ResolvedTree) {
+        // Only tagged tuples are looked up, as other nodes may have been
+        // allocated after `structures` was computed.
+        let structure = match tree.nature {
+            Nature::TaggedTuple(_) => dedup.structures.remove(&(&*tree as *const UnresolvedTree)),
+            _ => None
+        };
+        if let Some(structure) = structure {
+            if let Some(reference) = dedup.back_reference(structure, stats, integers) {
+                return reference
+            }
+        }
+        let ordinal = dedup.count;
+        let tree = std::rc::Rc::try_unwrap(tree)
+            .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
+        let (byte_len, resolved) = tree.resolve(stats, layouts, dedup, integers);
+        if let Some(structure) = structure {
+            dedup.written.entry(structure)
+                .or_insert((ordinal, byte_len));
+        }
+        (byte_len, resolved)
+    }
+
+    fn resolve(mut self, stats: &mut Statistics, layouts: &VecMap<Vec<Slot>>, dedup: &mut Dedup, integers: IntegerEncoding) -> (u32, ResolvedTree) {
         use self::UnresolvedTreeNode::*;
+        if let Nature::TaggedTuple(ref index) = self.nature {
+            dedup.count += 1;
+            let key = index.index()
+                .expect("TableIndex hasn't been resolved");
+            if let Some(slots) = layouts.get(key as usize) {
+                let data = std::mem::replace(&mut self.data, Tuple(vec![]));
+                self.data = Self::pack(data, slots, stats);
+            }
+        }
         let (total_bytes, own_bytes, tree) = match self.data {
             UnresolvedStringIndex(index) => {
                 let index = index.index()
                     .expect("String index should have been resolved by now.");
                 let mut buf = Vec::with_capacity(4);
-                let byte_len : usize = buf.write_varnum(index).unwrap(); // This operation can't fail.
+                let byte_len : usize = integers.write_u32(&mut buf, index).unwrap(); // This operation can't fail.
 
-                (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
+                (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(self.nature.column(), buf))
             }
             UnresolvedNodeIndex(index) => {
                 debug!(target: "multipart", "Rewriting node '{}'", index.description);
@@ -306,19 +963,22 @@ impl UnresolvedTree {
                 let index = index.index()
                     .expect("Node index should have been resolved by now.");
                 let mut buf = Vec::with_capacity(4);
-                let byte_len : usize = buf.write_varnum(index).unwrap(); // This operation can't fail.
+                let byte_len : usize = integers.write_u32(&mut buf, index).unwrap(); // This operation can't fail.
 
 
-                (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
+                (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(self.nature.column(), buf))
             }
             UnresolvedOffset(None) => {
                 panic!("UnresolvedOffset should have children");
             }
+            UnresolvedName(..) => {
+                panic!("Names should have been resolved by now.");
+            }
             UnresolvedOffset(Some(child)) => {
-                let (sub_byte_len, sub_resolved) = child.resolve(stats);
+                let (sub_byte_len, sub_resolved) = child.resolve(stats, layouts, dedup, integers);
                 let mut buf = Vec::with_capacity(4);
-                let offset_byte_len = buf.write_varnum(sub_byte_len).unwrap(); // This operation can't fail.
-                let offset_resolved = ResolvedTree::Encoded(buf);
+                let offset_byte_len = integers.write_u32(&mut buf, sub_byte_len).unwrap(); // This operation can't fail.
+                let offset_resolved = ResolvedTree::Encoded(Column::Lengths, buf);
 
                 (sub_byte_len + offset_byte_len as u32, offset_byte_len as u32, ResolvedTree::Tuple(vec![offset_resolved, ResolvedTree::Tuple(vec![sub_resolved])]))
             }
@@ -326,9 +986,7 @@ impl UnresolvedTree {
                 let mut byte_len = 0;
                 let mut resolved = Vec::with_capacity(subtrees.len());
                 for tree in subtrees.drain(..) {
-                    let tree = std::rc::Rc::try_unwrap(tree)
-                        .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
-                    let (sub_byte_len, sub_resolved) = tree.resolve(stats);
+                    let (sub_byte_len, sub_resolved) = Self::resolve_child(tree, stats, layouts, dedup, integers);
                     byte_len += sub_byte_len;
                     resolved.push(sub_resolved);
                 }
@@ -338,7 +996,7 @@ impl UnresolvedTree {
             Encoded(vec) => {
                 let byte_len = vec.len() as u32;
 
-                (byte_len, byte_len, ResolvedTree::Encoded(vec))
+                (byte_len, byte_len, ResolvedTree::Encoded(self.nature.column(), vec))
             }
         };
 
@@ -410,9 +1068,16 @@ impl UnresolvedTree {
             Nature::List => {
                 stats.list.entries += 1;
                 stats.list.total_bytes += total;
+
+                // Lists are not prefixed by their byte length, measure how much this saves.
+                stats.omitted_list_bytes += integers.byte_len(total_bytes as u64);
             }
             Nature::ListHeader => {
                 stats.list.own_bytes += total;
+                stats.list_header.entries += 1;
+                stats.list_header.own_bytes += own;
+                stats.list_header.total_bytes += total;
+                stats.list_header.shallow_bytes += own;
             }
             Nature::Bool => {
                 stats.bool.entries += 1;
@@ -420,6 +1085,24 @@ impl UnresolvedTree {
                 stats.bool.total_bytes += total;
                 stats.bool.shallow_bytes += own;
             }
+            Nature::Name => {
+                stats.name.entries += 1;
+                stats.name.own_bytes += own;
+                stats.name.total_bytes += total;
+                stats.name.shallow_bytes += own;
+            }
+            Nature::StringEnum => {
+                stats.string_enum.entries += 1;
+                stats.string_enum.own_bytes += own;
+                stats.string_enum.total_bytes += total;
+                stats.string_enum.shallow_bytes += own;
+            }
+            Nature::Bitmap => {
+                stats.bitmap.entries += 1;
+                stats.bitmap.own_bytes += own;
+                stats.bitmap.total_bytes += total;
+                stats.bitmap.shallow_bytes += own;
+            }
             Nature::Float => {
                 stats.float.entries += 1;
                 stats.float.own_bytes += own;
@@ -449,10 +1132,134 @@ enum Nature {
     TaggedTupleHeader(TableIndex<NodeDescription>),
     Float,
     Bool,
+    /// The packed fields of a tagged tuple.
+    Bitmap,
     String(TableIndex<Option<String>>),
+    /// A reference to a declared name, or the marker of a free name, with
+    /// feature `SCOPE_NAMES`.
+    Name,
+    /// The index of a value in its string enum, with feature `ENUM_INDICES`.
+    StringEnum,
     /// Internal data representing a number of bytes.
     Offset,
 }
+impl Nature {
+    /// The column of a token with this nature, with feature `COLUMNS`.
+    fn column(&self) -> Column {
+        match *self {
+            Nature::TaggedTupleHeader(_) | Nature::StringEnum => Column::Kinds,
+            Nature::String(_) | Nature::Name => Column::Strings,
+            Nature::Float => Column::Numbers,
+            Nature::Bool | Nature::Bitmap => Column::Bools,
+            Nature::ListHeader | Nature::Offset => Column::Lengths,
+            Nature::List | Nature::UntaggedTuple | Nature::TaggedTuple(_) => {
+                panic!("A tuple is not a token")
+            }
+        }
+    }
+}
+
+/// The names declared by the scope annotations, see `WriteOptions::scope_names`.
+struct Names<'a> {
+    /// The indices in the grammar table of the scope annotations.
+    annotations: HashSet<u32>,
+
+    /// The names declared so far that are still visible, in the order in
+    /// which they are written.
+    visible: Vec<String>,
+
+    strings_tables: &'a mut [WriterTable<Option<String>>],
+    integers: IntegerEncoding,
+}
+impl<'a> Names<'a> {
+    /// Replace the names of `tree` by entries in the strings table or by
+    /// references to their declaration, in the order in which they are written.
+    ///
+    /// `declaring` is `true` if the innermost tagged tuple is a scope annotation.
+    fn resolve(&mut self, tree: UnresolvedTree, declaring: bool) -> UnresolvedTree {
+        use self::UnresolvedTreeNode::*;
+        let UnresolvedTree { nature, data } = tree;
+        let data = match data {
+            UnresolvedName(category, key) => return self.name(category, key, declaring),
+            UnresolvedOffset(Some(child)) => UnresolvedOffset(Some(Box::new(self.resolve(*child, declaring)))),
+            Tuple(items) => {
+                let scope = match nature {
+                    Nature::TaggedTuple(ref index) => {
+                        let index = index.index()
+                            .expect("TableIndex hasn't been resolved");
+                        Some((self.annotations.contains(&index), self.visible.len()))
+                    }
+                    _ => None
+                };
+                let declaring = match scope {
+                    Some((is_annotation, _)) => is_annotation,
+                    None => declaring
+                };
+                let items = items.into_iter()
+                    .map(|item| {
+                        let item = std::rc::Rc::try_unwrap(item)
+                            .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
+                        Rc::new(self.resolve(item, declaring))
+                    })
+                    .collect();
+                // The names declared by a scope annotation remain visible in the enclosing tagged tuple.
+                if let Some((false, start)) = scope {
+                    self.visible.truncate(start);
+                }
+                Tuple(items)
+            }
+            data => data
+        };
+        UnresolvedTree {
+            nature,
+            data
+        }
+    }
+
+    fn name(&mut self, category: StringCategory, key: Option<String>, declaring: bool) -> UnresolvedTree {
+        if category == StringCategory::Identifier {
+            let position = self.visible.iter()
+                .rposition(|name| Some(name) == key.as_ref());
+            let reference = match position {
+                Some(position) => (self.visible.len() - position) as u32,
+                None => 0
+            };
+            let mut buf = Vec::with_capacity(1);
+            self.integers.write_u32(&mut buf, reference).unwrap(); // This operation can't fail.
+            debug!(target: "multipart", "writing name {:?} => {}", key, reference);
+            let marker = UnresolvedTree {
+                nature: Nature::Name,
+                data: UnresolvedTreeNode::Encoded(buf),
+            };
+            if reference != 0 {
+                return marker
+            }
+            return UnresolvedTree {
+                nature: Nature::UntaggedTuple,
+                data: UnresolvedTreeNode::Tuple(vec![Rc::new(marker), Rc::new(self.string(category, key))]),
+            }
+        }
+        if declaring {
+            if let Some(ref name) = key {
+                self.visible.push(name.clone());
+            }
+        }
+        self.string(category, key)
+    }
+
+    fn string(&mut self, category: StringCategory, key: Option<String>) -> UnresolvedTree {
+        let table = if self.strings_tables.len() > 1 {
+            category as usize
+        } else {
+            0
+        };
+        let index = self.strings_tables[table].insert(key);
+        UnresolvedTree {
+            data: UnresolvedTreeNode::UnresolvedStringIndex(index.clone()),
+            nature: Nature::String(index)
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Tree(Rc<UnresolvedTree>);
@@ -502,10 +1309,18 @@ impl<T> Serializable for TableIndex<T> {
 
 impl TreeTokenWriter {
     pub fn new(options: WriteOptions) -> Self {
+        let strings_tables = if options.string_tables.is_some() {
+            StringCategory::ALL.len()
+        } else {
+            1
+        };
         TreeTokenWriter {
             grammar_table: WriterTable::new(),
-            strings_table: WriterTable::new(),
+            strings_tables: (0..strings_tables)
+                .map(|_| WriterTable::new())
+                .collect(),
             root: None,
+            shapes: HashMap::new(),
             data: Vec::with_capacity(1024),
             options,
             statistics: Statistics::default()
@@ -525,10 +1340,16 @@ impl TreeTokenWriter {
             .map_err(TokenWriterError::WriteError)?;
         self.statistics.uncompressed_bytes += MAGIC_HEADER.len();
 
-        const FORMAT_VERSION : u32 = 0;
-        self.data.write_varnum(FORMAT_VERSION)
+        self.statistics.uncompressed_bytes += self.data.write_varnum(FORMAT_VERSION)
             .map_err(TokenWriterError::WriteError)?;
-        self.statistics.uncompressed_bytes += std::mem::size_of_val(&FORMAT_VERSION);
+
+        let features = self.options.features();
+        self.statistics.uncompressed_bytes += self.data.write_varnum(features.0)
+            .map_err(TokenWriterError::WriteError)?;
+        if features.contains(FeatureFlags::INTEGERS) {
+            self.statistics.uncompressed_bytes += self.data.write_varnum(self.options.integers.code())
+                .map_err(TokenWriterError::WriteError)?;
+        }
 
         // Write grammar table to byte stream.
         self.data.write_all(HEADER_GRAMMAR_TABLE.as_bytes())
@@ -540,17 +1361,71 @@ impl TreeTokenWriter {
         self.statistics.grammar_table.max_entries = self.grammar_table.map.len();
         self.statistics.grammar_table.compression = compression;
 
-        // Write strings table to byte stream.
-        self.data.write_all(HEADER_STRINGS_TABLE.as_bytes())
-            .map_err(TokenWriterError::WriteError)?;
-        let compression = self.strings_table.write_with_compression(&mut self.data, &self.options.strings_table)
-            .map_err(TokenWriterError::WriteError)?;
-        self.statistics.strings_table.entries = self.strings_table.map.len();
-        self.statistics.strings_table.max_entries = self.strings_table.map.len();
-        self.statistics.strings_table.compression = compression;
+        // Now that the grammar table is indexed, resolve the names declared by the scope annotations.
+        if self.options.scope_names {
+            if let Some(root) = self.root.take() {
+                let annotations = self.grammar_table.map.iter()
+                    .filter(|&(description, _)| SCOPE_ANNOTATIONS.contains(&&*description.kind))
+                    .map(|(_, entry)| entry.index.index()
+                        .expect("TableIndex hasn't been resolved"))
+                    .collect();
+                let mut names = Names {
+                    annotations,
+                    visible: vec![],
+                    strings_tables: &mut self.strings_tables,
+                    integers: self.options.integers,
+                };
+                let root = std::rc::Rc::try_unwrap(root.0)
+                    .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
+                self.root = Some(Tree(Rc::new(names.resolve(root, false))));
+            }
+        }
+
+        // Now that the grammar table is indexed, determine the layout of each kind.
+        let mut layouts = VecMap::new();
+        for (description, shapes) in self.shapes.drain() {
+            let slots : Vec<_> = match shapes {
+                None => continue, // Inconsistent number of fields, don't pack this kind.
+                Some(ref shapes) => shapes.iter()
+                    .map(FieldShape::slot)
+                    .collect()
+            };
+            if !slots.iter().any(|slot| slot.is_packed()) {
+                continue;
+            }
+            let index = self.grammar_table.map.get(&description)
+                .and_then(|entry| entry.index.index())
+                .expect("Kind should have been indexed");
+            layouts.insert(index as usize, slots);
+        }
+
+        // Write strings table(s) to byte stream.
+        match self.options.string_tables {
+            None => {
+                self.data.write_all(HEADER_STRINGS_TABLE.as_bytes())
+                    .map_err(TokenWriterError::WriteError)?;
+                let compression = self.strings_tables[0].write_with_compression(&mut self.data, &self.options.strings_table)
+                    .map_err(TokenWriterError::WriteError)?;
+                self.statistics.strings_table.compression = compression;
+            }
+            Some(ref tables) => {
+                for category in &StringCategory::ALL {
+                    self.data.write_all(strings_table_header(*category).as_bytes())
+                        .map_err(TokenWriterError::WriteError)?;
+                    let compression = self.strings_tables[*category as usize].write_with_compression(&mut self.data, tables.compression(*category))
+                        .map_err(TokenWriterError::WriteError)?;
+                    self.statistics.strings_table.compression += compression;
+                }
+            }
+        }
+        let entries: usize = self.strings_tables.iter()
+            .map(|table| table.map.len())
+            .sum();
+        self.statistics.strings_table.entries = entries;
+        self.statistics.strings_table.max_entries = entries;
 
         // Compute more statistics on strings.
-        for (key, value) in &self.strings_table.map {
+        for (key, value) in self.strings_tables.iter().flat_map(|table| table.map.iter()) {
             let len = match *key {
                 None => 0,
                 Some(ref s) => s.len()
@@ -577,21 +1452,62 @@ impl TreeTokenWriter {
         }
 
 
-        // Write tree itself to byte stream.
-        self.data.write_all(HEADER_TREE.as_bytes())
-            .map_err(TokenWriterError::WriteError)?;
-        if let Some(root) = self.root {
-            let mut buf = Vec::with_capacity(2048);
-            let root = std::rc::Rc::try_unwrap(root.0)
-                .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
-            let (_, resolved) = root.resolve(&mut self.statistics);
-            resolved.write(&mut buf)
+        // Write layout table to byte stream.
+        if self.options.bitmaps {
+            self.data.write_all(HEADER_LAYOUT.as_bytes())
                 .map_err(TokenWriterError::WriteError)?;
-            let compression = buf.write_with_compression(&mut self.data, &self.options.tree)
+            self.statistics.uncompressed_bytes += HEADER_LAYOUT.len();
+            let compression = layouts.write_with_compression(&mut self.data, &self.options.grammar_table)
                 .map_err(TokenWriterError::WriteError)?;
-            self.statistics.tree.entries = 1;
-            self.statistics.tree.max_entries = 1;
-            self.statistics.tree.compression = compression;
+            self.statistics.uncompressed_bytes += compression.before_bytes;
+        }
+
+        // Write tree itself to byte stream.
+        let resolved = match self.root {
+            Some(root) => {
+                let mut dedup = Dedup::new(self.grammar_table.map.len() as u32);
+                if self.options.dedup {
+                    root.0.intern(&mut HashMap::new(), &mut dedup.structures);
+                    // The root cannot be a back-reference.
+                    dedup.structures.remove(&(&*root.0 as *const UnresolvedTree));
+                }
+                let root = std::rc::Rc::try_unwrap(root.0)
+                    .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
+                let (_, resolved) = root.resolve(&mut self.statistics, &layouts, &mut dedup, self.options.integers);
+                Some(resolved)
+            }
+            None => None
+        };
+        match self.options.columns {
+            None => {
+                self.data.write_all(HEADER_TREE.as_bytes())
+                    .map_err(TokenWriterError::WriteError)?;
+                if let Some(resolved) = resolved {
+                    let mut buf = Vec::with_capacity(2048);
+                    resolved.write(&mut buf)
+                        .map_err(TokenWriterError::WriteError)?;
+                    let compression = buf.write_with_compression(&mut self.data, &self.options.tree)
+                        .map_err(TokenWriterError::WriteError)?;
+                    self.statistics.tree.entries = 1;
+                    self.statistics.tree.max_entries = 1;
+                    self.statistics.tree.compression = compression;
+                }
+            }
+            Some(ref columns) => {
+                let mut bufs = vec![Vec::with_capacity(2048); Column::ALL.len()];
+                if let Some(resolved) = resolved {
+                    resolved.write_columns(&mut bufs);
+                }
+                for column in &Column::ALL {
+                    self.data.write_all(column.header().as_bytes())
+                        .map_err(TokenWriterError::WriteError)?;
+                    let compression = bufs[*column as usize].write_with_compression(&mut self.data, columns.compression(*column))
+                        .map_err(TokenWriterError::WriteError)?;
+                    self.statistics.tree.compression += compression;
+                }
+                self.statistics.tree.entries = 1;
+                self.statistics.tree.max_entries = 1;
+            }
         }
 
         // Compute more statistics on nodes.
@@ -631,7 +1547,15 @@ impl TokenWriter for TreeTokenWriter {
     }
 
     fn float(&mut self, value: Option<f64>) -> Result<Self::Tree, Self::Error> {
-        let bytes : Vec<_> = bytes::float::bytes_of_float(value).iter().cloned().collect();
+        let bytes : Vec<_> = match self.options.numbers {
+            NumberEncoding::Float64 => bytes::float::bytes_of_float(value).iter().cloned().collect(),
+            NumberEncoding::Compact => {
+                let mut bytes = Vec::with_capacity(9);
+                bytes::float::write_compact_float(&mut bytes, value)
+                    .map_err(TokenWriterError::WriteError)?;
+                bytes
+            }
+        };
         debug!(target: "multipart", "writing float {:?} => {:?}", value, bytes);
         Ok(self.register(UnresolvedTree {
             nature: Nature::Float,
@@ -656,8 +1580,25 @@ impl TokenWriter for TreeTokenWriter {
     }
 
     fn string(&mut self, data: Option<&str>) -> Result<Self::Tree, Self::Error> {
+        self.categorized_string(StringCategory::Literal, data)
+    }
+    fn categorized_string(&mut self, category: StringCategory, data: Option<&str>) -> Result<Self::Tree, Self::Error> {
+        if self.options.scope_names {
+            if let StringCategory::Identifier | StringCategory::IdentifierName = category {
+                // Whether the name is declared is only known once the tree is complete.
+                return Ok(self.register(UnresolvedTree {
+                    data: UnresolvedTreeNode::UnresolvedName(category, data.map(str::to_string)),
+                    nature: Nature::Name
+                }))
+            }
+        }
+        let table = if self.options.string_tables.is_some() {
+            category as usize
+        } else {
+            0
+        };
         let key = data.map(str::to_string);
-        let index = self.strings_table
+        let index = self.strings_tables[table]
             .get(&key)
             .map(|entry| entry.index.clone());
 
@@ -667,18 +1608,31 @@ impl TokenWriter for TreeTokenWriter {
                 nature: Nature::String(index)
             }));
         }
-        let index = self.strings_table.insert(key);
-        debug!(target: "multipart", "writing string {:?} => {:?}", data, index);
+        let index = self.strings_tables[table].insert(key);
+        debug!(target: "multipart", "writing string {:?} ({:?}) => {:?}", data, category, index);
         Ok(self.register(UnresolvedTree {
             data: UnresolvedTreeNode::UnresolvedStringIndex(index.clone()),
             nature: Nature::String(index)
         }))
     }
+    fn string_enum(&mut self, strings: &[&str], index: usize) -> Result<Self::Tree, Self::Error> {
+        if !self.options.enum_indices {
+            return self.string(Some(strings[index]))
+        }
+        let mut bytes = Vec::with_capacity(1);
+        self.options.integers.write_u32(&mut bytes, index as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        debug!(target: "multipart", "writing string enum {:?} => {}", strings[index], index);
+        Ok(self.register(UnresolvedTree {
+            nature: Nature::StringEnum,
+            data: UnresolvedTreeNode::Encoded(bytes),
+        }))
+    }
     fn list(&mut self, mut children: Vec<Self::Tree>) -> Result<Self::Tree, Self::Error> {
         let mut items = Vec::with_capacity(children.len() + 1);
         // First child is the number of children.
         let mut encoded_number_of_items = Vec::with_capacity(4);
-        encoded_number_of_items.write_varnum(children.len() as u32)
+        self.options.integers.write_u32(&mut encoded_number_of_items, children.len() as u32)
             .map_err(TokenWriterError::WriteError)?;
         items.push(Rc::new(UnresolvedTree {
             data: UnresolvedTreeNode::Encoded(encoded_number_of_items),
@@ -714,19 +1668,68 @@ impl TokenWriter for TreeTokenWriter {
     // All tagged tuples with the same `tag` are written with the fields in the same order.
     //
     // - index in the grammar table (varnum);
-    // - for each item, in the order specified
+    // - for each item, in the order specified by `WriteOptions::field_order`
     //    - the item (see item)
     fn tagged_tuple(&mut self, name: &str, children: &[(&str, Self::Tree)]) -> Result<Self::Tree, Self::Error> {
         let data;
+        let reordered : Vec<_>;
+        let children = match self.options.field_order {
+            None => children,
+            Some(ref order) => {
+                // An offset, if any, remains the first field.
+                let start = match children.get(0) {
+                    Some(&(_, Tree(ref tree))) => if let Nature::Offset = tree.nature { 1 } else { 0 },
+                    None => 0
+                };
+                let names : Vec<_> = children[start..].iter()
+                    .map(|&(field, _)| field)
+                    .collect();
+                reordered = children[..start].iter()
+                    .cloned()
+                    .chain(order.permutation(name, &names)
+                        .into_iter()
+                        .map(|i| children[start + i].clone()))
+                    .collect();
+                &reordered[..]
+            }
+        };
         let description = NodeDescription {
             kind: name.to_string(),
+            fields: self.options.field_order.as_ref()
+                .map(|_| children.iter()
+                    .map(|&(field, _)| field.to_string())
+                    .collect()),
         };
         debug!(target: "multipart", "writing tagged tuple {} with {} children as {:?}",
             name,
             children.len(),
             description,
         );
-        let index : TableIndex<_> = self.grammar_table.insert(description);
+        let index : TableIndex<_> = self.grammar_table.insert(description.clone());
+        if self.options.bitmaps {
+            use std::collections::hash_map::Entry::*;
+            let shapes : Vec<_> = children.iter()
+                .map(|&(_, ref tree)| FieldShape::of(&tree.0))
+                .collect();
+            match self.shapes.entry(description) {
+                Vacant(entry) => {
+                    entry.insert(Some(shapes));
+                }
+                Occupied(mut entry) => {
+                    let merged = entry.get_mut().take().and_then(|known| {
+                        if known.len() == shapes.len() {
+                            Some(known.into_iter()
+                                .zip(shapes)
+                                .map(|(a, b)| a.merge(b))
+                                .collect())
+                        } else {
+                            None
+                        }
+                    });
+                    *entry.get_mut() = merged;
+                }
+            }
+        }
         {
             // Now, we determine if we should add an Offset
             let inject_in_offset =
@@ -797,11 +1800,16 @@ pub struct TreeTokenWriter {
     /// and how they are laid out in the binary.
     grammar_table: WriterTable<NodeDescription>,
 
-    /// The strings used in the binary.
-    strings_table: WriterTable<Option<String>>,
+    /// The strings used in the binary, one table per `StringCategory` with
+    /// `WriteOptions::string_tables`, a single table otherwise.
+    strings_tables: Vec<WriterTable<Option<String>>>,
 
     root: Option<Tree>,
 
+    /// With `WriteOptions::bitmaps`, the shape of the fields of each kind,
+    /// or `None` if tagged tuples of that kind have different numbers of fields.
+    shapes: HashMap<NodeDescription, Option<Vec<FieldShape>>>,
+
     data: Vec<u8>,
 
     options: WriteOptions,
@@ -907,14 +1915,36 @@ pub struct Statistics {
     pub string_usage: VecMap<usize>,
 
     pub bool: NodeStatistics,
+
+    /// Bitmaps of packed fields, see `WriteOptions::bitmaps`.
+    pub bitmap: NodeStatistics,
     pub float: NodeStatistics,
     pub string: NodeStatistics,
+
+    /// References to declared names, and markers of free names, see
+    /// `WriteOptions::scope_names`.
+    pub name: NodeStatistics,
+
+    /// Indices of values of string enums, see `WriteOptions::enum_indices`.
+    pub string_enum: NodeStatistics,
     pub list: NodeStatistics,
     pub offset: NodeStatistics,
     pub list_header: NodeStatistics,
+
+    /// The number of bytes that the headers of lists would take if lists
+    /// were prefixed by their byte length. Only skippable subtrees need a
+    /// byte length, which is written as an offset.
+    pub omitted_list_bytes: usize,
     pub tagged_header: NodeStatistics,
     pub tagged_tuple: NodeStatistics,
 
+    /// Back-references to tagged tuples, see `WriteOptions::dedup`.
+    pub back_reference: NodeStatistics,
+
+    /// The number of bytes that the tagged tuples replaced by back-references
+    /// would have taken.
+    pub deduplicated_bytes: usize,
+
     pub number_of_files: usize,
     pub uncompressed_bytes: usize,
     pub compressed_bytes: usize,
@@ -1001,12 +2031,18 @@ impl Add for Statistics {
         }
 
         self.bool += rhs.bool;
+        self.bitmap += rhs.bitmap;
         self.float += rhs.float;
         self.string += rhs.string;
+        self.name += rhs.name;
+        self.string_enum += rhs.string_enum;
         self.list += rhs.list;
         self.list_header += rhs.list_header;
+        self.omitted_list_bytes += rhs.omitted_list_bytes;
         self.tagged_header += rhs.tagged_header;
         self.tagged_tuple += rhs.tagged_tuple;
+        self.back_reference += rhs.back_reference;
+        self.deduplicated_bytes += rhs.deduplicated_bytes;
 
         self.number_of_files += rhs.number_of_files;
         self.compressed_bytes += rhs.compressed_bytes;
@@ -1158,6 +2194,8 @@ impl Display for Statistics {
         let total_number_of_tokens = self.bool.entries
             + self.float.entries
             + self.string.entries
+            + self.name.entries
+            + self.string_enum.entries
             + self.list.entries
             + self.tagged_tuple.entries;
         write!(f, "
@@ -1179,11 +2217,17 @@ Statistics
 {collapsed_nodes}
 \tTokens:
 {token_bool}
+{token_bitmap}
 {token_float}
 {token_offset}
 {token_string}
-{token_list}
+{token_name}
+{token_string_enum}
+{token_list}\t\t\tOmitted byte lengths: {omitted_list_bytes}
+
 {token_tagged_tuple}
+{token_back_reference}\t\t\tDeduplicated bytes: {deduplicated_bytes}
+
 \tLists per size:
 {lists_per_size}
 \tStrings per size:
@@ -1192,6 +2236,8 @@ Statistics
 {strings_per_usage}
 ",
         number_of_files = self.number_of_files,
+        omitted_list_bytes = self.omitted_list_bytes,
+        deduplicated_bytes = self.deduplicated_bytes,
         total_source_bytes = match self.source_bytes {
             None => "<not available>".to_string(),
             Some(ref bytes) => format!("{}", bytes)
@@ -1231,6 +2277,13 @@ Statistics
             total_uncompressed_bytes: self.uncompressed_bytes,
             header_bytes: 0,
         },
+        token_bitmap = NodeAndStatistics {
+            name: "Bitmap",
+            stats: &self.bitmap,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
         token_float = NodeAndStatistics {
             name: "Float",
             stats: &self.float,
@@ -1252,6 +2305,20 @@ Statistics
             total_uncompressed_bytes: self.uncompressed_bytes,
             header_bytes: 0,
         },
+        token_name = NodeAndStatistics {
+            name: "Names",
+            stats: &self.name,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
+        token_string_enum = NodeAndStatistics {
+            name: "String enum indices",
+            stats: &self.string_enum,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
         token_list = NodeAndStatistics {
             name: "List",
             stats: &self.list,
@@ -1266,6 +2333,13 @@ Statistics
             total_uncompressed_bytes: self.uncompressed_bytes,
             header_bytes: self.tagged_header.own_bytes,
         },
+        token_back_reference = NodeAndStatistics {
+            name: "Back-reference",
+            stats: &self.back_reference,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
         )
     }
 }
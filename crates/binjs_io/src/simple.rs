@@ -3,6 +3,7 @@
 //! included in release builds.
 
 use bytes;
+use bytes::compress::read_exactly;
 use io::*;
 use ::{ TokenReaderError, TokenWriterError };
 use util::{ PoisonLock, Pos, ReadConst };
@@ -22,7 +23,7 @@ impl<R> ReaderState<R> where R: Read + Seek {
     pub fn read_u32(&mut self) -> Result<u32, TokenReaderError> {
         let mut buf : [u8; 4] = [0, 0, 0, 0];
         debug_assert!(std::mem::size_of::<u32>() == std::mem::size_of_val(&buf));
-        self.reader.read(&mut buf)
+        self.reader.read_exact(&mut buf)
             .map_err(TokenReaderError::ReadError)?;
 
         let result =
@@ -37,7 +38,7 @@ impl<R> ReaderState<R> where R: Read + Seek {
         let mut bytes = Vec::new();
         let mut buf: [u8;1] = [0];
         loop {
-            self.reader.read(&mut buf)
+            self.reader.read_exact(&mut buf)
                 .map_err(TokenReaderError::ReadError)?;
             if buf[0] == 0 {
                 return String::from_utf8(bytes)
@@ -206,12 +207,20 @@ impl<R> TokenReader for TreeTokenReader<R> where R: Read + Seek {
         self.owner.borrow_mut().poison();
     }
 
+    fn position(&self) -> Option<usize> {
+        let mut owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return None
+        }
+        Some(owner.pos())
+    }
+
     fn bool(&mut self) -> Result<Option<bool>, Self::Error> {
         debug!(target: "simple_reader", "bool");
         let mut buf : [u8; 1] = [0];
         let mut owner = self.owner.borrow_mut();
         owner.try(|state| {
-            state.reader.read(&mut buf)
+            state.reader.read_exact(&mut buf)
                 .map_err(TokenReaderError::ReadError)?;
             match bytes::bool::bool_of_bytes(&buf) {
                 Ok(x) => Ok(x),
@@ -233,7 +242,7 @@ impl<R> TokenReader for TreeTokenReader<R> where R: Read + Seek {
         let mut owner = self.owner.borrow_mut();
         owner.try(|state| {
             let mut buf : [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
-            state.reader.read(&mut buf)
+            state.reader.read_exact(&mut buf)
                 .map_err(TokenReaderError::ReadError)?;
             Ok(bytes::float::float_of_bytes(&buf))
         })
@@ -247,8 +256,8 @@ impl<R> TokenReader for TreeTokenReader<R> where R: Read + Seek {
                 .map_err(TokenReaderError::ReadError)?;
             let byte_len = state.read_u32()?;
 
-            let mut bytes : Vec<u8> = vec![0 as u8; byte_len as usize];
-            state.reader.read(&mut bytes)
+            // Don't trust `byte_len` before allocating.
+            let bytes = read_exactly(&mut state.reader, byte_len as usize)
                 .map_err(TokenReaderError::ReadError)?;
 
             state.reader.read_const(b"</string>")
@@ -271,8 +280,10 @@ impl<R> TokenReader for TreeTokenReader<R> where R: Read + Seek {
             state.reader.read_const(b"<list>")
                 .map_err(TokenReaderError::ReadError)?;
 
-            let guard = ListGuard::new(clone);
             let list_len = state.read_u32()?;
+            // Create the guard only once we know that we have succeeded,
+            // as dropping it requires access to the state.
+            let guard = ListGuard::new(clone);
             debug!(target: "simple_writer", "TreeTokenReader: list has {} items", list_len);
             Ok((list_len, guard))
         })
@@ -293,7 +304,7 @@ impl<R> TokenReader for TreeTokenReader<R> where R: Read + Seek {
 
             // Read the field names
             let len = state.read_u32()?;
-            let mut fields = Vec::with_capacity(len as usize);
+            let mut fields = Vec::new(); // Don't trust `len` before allocating.
             for _ in 0..len {
                 let name = state.read_string()?;
                 fields.push(name);
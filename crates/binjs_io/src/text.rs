@@ -0,0 +1,840 @@
+//! A human-readable encoding, designed to help debug grammar encoders/decoders
+//! and to write test fixtures by hand. Not meant to be included in release builds.
+//!
+//! Each token is written as an S-expression:
+//!
+//! - a string is written between double quotes, e.g. `"foo"`, with escapes
+//!   `\"`, `\\`, `\n`, `\r`, `\t` and `\u{7f}`;
+//! - a float is written in decimal, e.g. `3.1415`, or as `NaN`, `inf`, `-inf`;
+//! - a bool is written `true` or `false`;
+//! - a null string, float or bool is written `null`;
+//! - a list is written `(list item_0 item_1 ...)`;
+//! - an untagged tuple is written `(tuple item_0 item_1 ...)`;
+//! - a tagged tuple is written `(tagged Kind (field_0 value_0) (field_1 value_1) ...)`.
+//!   A kind or field name that is not made only of alphanumeric characters,
+//!   `_` and `$` is quoted, e.g. `(tagged "")`;
+//! - an offset is written `(offset N)`, where `N` is the number of bytes written
+//!   between the offset (or the field containing it) and the closing parenthesis
+//!   of the tuple.
+//!
+//! The writer indents each item of a list or tuple on its own line, e.g.
+//!
+//! ```text
+//! (tagged BinaryExpression
+//!   (operator "+")
+//!   (left (tagged IdentifierExpression
+//!     (name "x")))
+//!   (right (tagged LiteralNumericExpression
+//!     (value 1.0))))
+//! ```
+//!
+//! The reader ignores whitespace and comments, which start with `;` and extend
+//! to the end of the line. It returns offsets as written, without checking them,
+//! so fixtures may be edited without updating offsets.
+
+use io::*;
+use ::{ TokenReaderError, TokenWriterError };
+use util::PoisonLock;
+
+use std;
+use std::cell::RefCell;
+use std::io::Read;
+use std::rc::Rc;
+
+/// Write `name` as a kind or field name, quoting it if necessary.
+pub(crate) fn write_name(name: &str, out: &mut String) {
+    let is_symbol = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_symbol {
+        out.push_str(name);
+    } else {
+        write_string(name, out);
+    }
+}
+
+/// Write `data` between double quotes, escaping as needed.
+pub(crate) fn write_string(data: &str, out: &mut String) {
+    out.push('"');
+    for c in data.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            _ => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+fn write_indent(indent: usize, out: &mut String) {
+    out.push('\n');
+    for _ in 0..indent {
+        out.push(' ');
+    }
+}
+
+#[derive(Clone)]
+enum TreeItem {
+    String(Option<String>),
+    Float(Option<f64>),
+    Bool(Option<bool>),
+    Offset,
+    List(Vec<AbstractTree>),
+    TaggedTuple(String, Vec<(String, AbstractTree)>),
+    UntaggedTuple(Vec<AbstractTree>),
+}
+impl TreeItem {
+    /// Write this item, indenting its children by `indent + 2`.
+    fn write(&self, indent: usize, out: &mut String) -> Result<(), TokenWriterError> {
+        match *self {
+            TreeItem::String(None) | TreeItem::Float(None) | TreeItem::Bool(None) =>
+                out.push_str("null"),
+            TreeItem::String(Some(ref data)) =>
+                write_string(data, out),
+            TreeItem::Float(Some(data)) =>
+                out.push_str(&format!("{:?}", data)),
+            TreeItem::Bool(Some(data)) =>
+                out.push_str(if data { "true" } else { "false" }),
+            TreeItem::Offset =>
+                // An offset field only makes sense as the item of a tuple.
+                return Err(TokenWriterError::InvalidOffsetField),
+            TreeItem::List(ref items) => {
+                out.push_str("(list");
+                let items : Vec<_> = items.iter()
+                    .map(|item| (None, &*item.0))
+                    .collect();
+                Self::write_items(&items, indent + 2, out)?;
+                out.push(')');
+            }
+            TreeItem::TaggedTuple(ref tag, ref fields) => {
+                out.push_str("(tagged ");
+                write_name(tag, out);
+                let items : Vec<_> = fields.iter()
+                    .map(|&(ref name, ref item)| (Some(name.as_str()), &*item.0))
+                    .collect();
+                Self::write_items(&items, indent + 2, out)?;
+                out.push(')');
+            }
+            TreeItem::UntaggedTuple(ref items) => {
+                out.push_str("(tuple");
+                let items : Vec<_> = items.iter()
+                    .map(|item| (None, &*item.0))
+                    .collect();
+                Self::write_items(&items, indent + 2, out)?;
+                out.push(')');
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the items of a list or tuple, one per line.
+    ///
+    /// If an item is an `Offset`, the items that follow it are written first
+    /// to a separate buffer, to determine the value of the offset.
+    fn write_items(items: &[(Option<&str>, &TreeItem)], indent: usize, out: &mut String) -> Result<(), TokenWriterError> {
+        for (i, &(name, item)) in items.iter().enumerate() {
+            write_indent(indent, out);
+            if let Some(name) = name {
+                out.push('(');
+                write_name(name, out);
+                out.push(' ');
+            }
+            if let TreeItem::Offset = *item {
+                let mut rest = String::new();
+                Self::write_items(&items[i + 1..], indent, &mut rest)?;
+                out.push_str(&format!("(offset {})", rest.len()));
+                if name.is_some() {
+                    out.push(')');
+                }
+                out.push_str(&rest);
+                return Ok(());
+            }
+            item.write(indent, out)?;
+            if name.is_some() {
+                out.push(')');
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Abstract type for the contents of the tree.
+#[derive(Clone)]
+pub struct AbstractTree(Rc<TreeItem>);
+
+/// A tree writer producing indented text.
+pub struct TreeTokenWriter {
+    root: Option<AbstractTree>,
+}
+impl TreeTokenWriter {
+    pub fn new() -> Self {
+        TreeTokenWriter {
+            root: None
+        }
+    }
+
+    fn register(&mut self, item: TreeItem) -> AbstractTree {
+        let result = AbstractTree(Rc::new(item));
+        self.root = Some(result.clone());
+        result
+    }
+}
+
+#[derive(Default)]
+/// This encoder doesn't produce useful statistics.
+pub struct Statistics;
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "No statistics available for this encoder")
+    }
+}
+
+impl std::ops::Add for Statistics {
+    type Output = Self;
+    fn add(self, _: Self) -> Self::Output {
+        Statistics
+    }
+}
+impl std::ops::AddAssign for Statistics {
+    fn add_assign(&mut self, _: Self) {
+        // Nothing to do.
+    }
+}
+
+impl TokenWriter for TreeTokenWriter {
+    type Tree = AbstractTree;
+    type Error = TokenWriterError;
+    type Data = Vec<u8>;
+    type Statistics = Statistics;
+
+    fn done(self) -> Result<(Self::Data, Self::Statistics), Self::Error> {
+        let mut result = String::new();
+        if let Some(root) = self.root {
+            root.0.write(0, &mut result)?;
+            result.push('\n');
+        }
+        Ok((result.into_bytes(), Statistics))
+    }
+
+    fn float(&mut self, data: Option<f64>) -> Result<Self::Tree, Self::Error> {
+        Ok(self.register(TreeItem::Float(data)))
+    }
+
+    fn bool(&mut self, data: Option<bool>) -> Result<Self::Tree, Self::Error> {
+        Ok(self.register(TreeItem::Bool(data)))
+    }
+
+    fn offset(&mut self) -> Result<Self::Tree, Self::Error> {
+        Ok(self.register(TreeItem::Offset))
+    }
+
+    fn string(&mut self, data: Option<&str>) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "text_writer", "TreeTokenWriter: string {:?}", data);
+        Ok(self.register(TreeItem::String(data.map(str::to_string))))
+    }
+
+    fn list(&mut self, items: Vec<Self::Tree>) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "text_writer", "TreeTokenWriter: list has {} items", items.len());
+        for item in &items {
+            if let TreeItem::Offset = *item.0 {
+                // An offset field makes no sense in a list.
+                return Err(TokenWriterError::InvalidOffsetField);
+            }
+        }
+        Ok(self.register(TreeItem::List(items)))
+    }
+
+    fn tagged_tuple(&mut self, tag: &str, children: &[(&str, Self::Tree)]) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "text_writer", "TreeTokenWriter: tagged_tuple {}", tag);
+        let children = children.iter()
+            .map(|&(name, ref child)| (name.to_string(), child.clone()))
+            .collect();
+        Ok(self.register(TreeItem::TaggedTuple(tag.to_string(), children)))
+    }
+
+    fn untagged_tuple(&mut self, children: &[Self::Tree]) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "text_writer", "TreeTokenWriter: untagged_tuple");
+        Ok(self.register(TreeItem::UntaggedTuple(children.to_vec())))
+    }
+}
+
+
+/// An S-expression, as parsed by the `TreeTokenReader`.
+#[derive(Debug)]
+pub(crate) enum Value {
+    /// A bare word, e.g. `list`, `true`, `3.1415`, `BinaryExpression`.
+    Atom(String),
+    /// A quoted string, unescaped.
+    String(String),
+    /// A parenthesized sequence.
+    List(Vec<Expr>),
+}
+
+#[derive(Debug)]
+pub(crate) struct Expr {
+    /// The line at which the expression starts, starting at 1.
+    pub line: usize,
+    pub value: Value,
+}
+
+pub(crate) fn syntax_error<T>(line: usize, reason: String) -> Result<T, TokenReaderError> {
+    Err(TokenReaderError::Syntax { line, reason })
+}
+
+/// Parse a sequence of S-expressions.
+///
+/// This parser doesn't recurse, so it accepts arbitrarily nested expressions.
+pub(crate) fn parse(source: &str) -> Result<Vec<Expr>, TokenReaderError> {
+    // The lists being parsed, each with the line at which it starts.
+    let mut stack : Vec<(usize, Vec<Expr>)> = vec![(1, vec![])];
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let start = line;
+        let value = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            _ if c.is_whitespace() => continue,
+            ';' => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+                continue;
+            }
+            '(' => {
+                stack.push((line, vec![]));
+                continue;
+            }
+            ')' => {
+                if stack.len() == 1 {
+                    return syntax_error(line, "Unexpected `)`".to_string());
+                }
+                let (start, items) = stack.pop()
+                    .unwrap(); // We just checked that the stack contains at least 2 items.
+                stack.last_mut()
+                    .unwrap() // We just checked that the stack contains at least 2 items.
+                    .1.push(Expr {
+                        line: start,
+                        value: Value::List(items)
+                    });
+                continue;
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        None => return syntax_error(start, "Unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.next() {
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('n') => '\n',
+                                Some('r') => '\r',
+                                Some('t') => '\t',
+                                Some('u') => {
+                                    if chars.next() != Some('{') {
+                                        return syntax_error(line, "Expected `{` after `\\u`".to_string());
+                                    }
+                                    let mut code = String::new();
+                                    loop {
+                                        match chars.next() {
+                                            Some('}') => break,
+                                            Some(c) if c.is_digit(16) && code.len() < 6 => code.push(c),
+                                            _ => return syntax_error(line, "Invalid unicode escape".to_string())
+                                        }
+                                    }
+                                    match u32::from_str_radix(&code, 16).ok().and_then(std::char::from_u32) {
+                                        Some(c) => c,
+                                        None => return syntax_error(line, format!("Invalid code point {:?}", code))
+                                    }
+                                }
+                                other => return syntax_error(line, format!("Invalid escape {:?}", other))
+                            };
+                            string.push(escaped);
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            string.push(c)
+                        }
+                    }
+                }
+                Value::String(string)
+            }
+            _ => {
+                let mut atom = String::new();
+                atom.push(c);
+                loop {
+                    match chars.peek() {
+                        Some(&c) if !c.is_whitespace() && c != '(' && c != ')' && c != '"' && c != ';' => atom.push(c),
+                        _ => break
+                    }
+                    chars.next();
+                }
+                Value::Atom(atom)
+            }
+        };
+        stack.last_mut()
+            .unwrap() // The stack is never empty.
+            .1.push(Expr {
+                line: start,
+                value
+            });
+    }
+    if stack.len() != 1 {
+        let (start, _) = stack.pop()
+            .unwrap(); // We just checked that the stack contains at least 2 items.
+        return syntax_error(start, "Unterminated `(`".to_string());
+    }
+    Ok(stack.pop()
+        .unwrap() // We just checked that the stack contains 1 item.
+        .1)
+}
+
+/// A list or tuple being read.
+struct Frame {
+    /// The items that haven't been read yet.
+    items: std::vec::IntoIter<Expr>,
+
+    /// If `true`, each item is a field `(name value)`.
+    is_tagged: bool,
+
+    /// The line at which the list or tuple starts.
+    line: usize,
+}
+
+/// The state of the `TreeTokenReader`.
+///
+/// Use a `PoisonLock` to access this state.
+struct ReaderState {
+    /// The lists and tuples being read. The first frame contains the
+    /// toplevel expressions and is never popped.
+    stack: Vec<Frame>,
+}
+impl ReaderState {
+    /// Read the next value, unwrapping it from its field if necessary.
+    fn next(&mut self, expected: &str) -> Result<Expr, TokenReaderError> {
+        let frame = self.stack.last_mut()
+            .unwrap(); // The stack is never empty.
+        let expr = match frame.items.next() {
+            Some(expr) => expr,
+            None => return syntax_error(frame.line, format!("Expected {}, found the end of the list or tuple", expected))
+        };
+        if !frame.is_tagged {
+            return Ok(expr)
+        }
+        // We have checked the shape of fields in `tagged_tuple`.
+        match expr.value {
+            Value::List(mut field) => Ok(field.pop().unwrap()),
+            _ => panic!("Invalid field")
+        }
+    }
+
+    /// Read the next value, which must be `(keyword ...)`, and return its
+    /// line and contents, without `keyword`.
+    fn next_compound(&mut self, keyword: &str) -> Result<(usize, Vec<Expr>), TokenReaderError> {
+        let expr = self.next(keyword)?;
+        if let Value::List(mut items) = expr.value {
+            let is_keyword = match items.first() {
+                Some(&Expr { value: Value::Atom(ref atom), .. }) => atom == keyword,
+                _ => false
+            };
+            if is_keyword {
+                items.remove(0);
+                return Ok((expr.line, items))
+            }
+        }
+        syntax_error(expr.line, format!("Expected ({} ...)", keyword))
+    }
+
+    fn push(&mut self, line: usize, items: Vec<Expr>, is_tagged: bool) {
+        self.stack.push(Frame {
+            items: items.into_iter(),
+            is_tagged,
+            line
+        })
+    }
+}
+
+/// A guard for a list, a tagged tuple or an untagged tuple.
+///
+/// Calling `done()` checks that all the items have been read.
+pub struct ContainerGuard {
+    finalized: bool,
+    owner: Rc<RefCell<PoisonLock<ReaderState>>>,
+}
+
+impl ContainerGuard {
+    fn new(owner: Rc<RefCell<PoisonLock<ReaderState>>>) -> Self {
+        ContainerGuard {
+            finalized: false,
+            owner
+        }
+    }
+}
+impl Guard for ContainerGuard {
+    type Error = TokenReaderError;
+    fn done(mut self) -> Result<(), Self::Error> {
+        self.finalized = true;
+        let mut owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return Ok(())
+        }
+
+        owner.try(|state| {
+            let frame = state.stack.pop()
+                .unwrap(); // Guards always have a frame.
+            if let Some(expr) = frame.items.as_slice().first() {
+                return syntax_error(expr.line, "Expected the end of the list or tuple".to_string())
+            }
+            Ok(())
+        })
+    }
+}
+impl Drop for ContainerGuard {
+    fn drop(&mut self) {
+        let owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return;
+        }
+        assert!(self.finalized)
+    }
+}
+
+/// A reader for the text produced by the `TreeTokenWriter`.
+pub struct TreeTokenReader {
+    owner: Rc<RefCell<PoisonLock<ReaderState>>>,
+}
+
+impl TreeTokenReader {
+    /// Read and parse the entire text.
+    ///
+    /// Fails if the text is not a well-formed sequence of S-expressions.
+    pub fn new<R: Read>(mut reader: R) -> Result<Self, TokenReaderError> {
+        let mut source = String::new();
+        reader.read_to_string(&mut source)
+            .map_err(TokenReaderError::ReadError)?;
+        let toplevel = parse(&source)?;
+        let state = ReaderState {
+            stack: vec![Frame {
+                items: toplevel.into_iter(),
+                is_tagged: false,
+                line: 1,
+            }]
+        };
+        Ok(TreeTokenReader {
+            owner: Rc::new(RefCell::new(PoisonLock::new(state)))
+        })
+    }
+}
+
+impl TokenReader for TreeTokenReader {
+    type ListGuard = ContainerGuard;
+    type TaggedGuard = ContainerGuard;
+    type UntaggedGuard = ContainerGuard;
+    type Error = TokenReaderError;
+
+    fn poison(&mut self) {
+        self.owner.borrow_mut().poison();
+    }
+
+    fn bool(&mut self) -> Result<Option<bool>, Self::Error> {
+        debug!(target: "text_reader", "bool");
+        self.owner.borrow_mut().try(|state| {
+            let expr = state.next("a bool")?;
+            if let Value::Atom(ref atom) = expr.value {
+                match atom.as_str() {
+                    "true" => return Ok(Some(true)),
+                    "false" => return Ok(Some(false)),
+                    "null" => return Ok(None),
+                    _ => {}
+                }
+            }
+            syntax_error(expr.line, format!("Expected a bool, found {:?}", expr.value))
+        })
+    }
+
+    fn offset(&mut self) -> Result<u32, Self::Error> {
+        debug!(target: "text_reader", "offset");
+        self.owner.borrow_mut().try(|state| {
+            let (line, items) = state.next_compound("offset")?;
+            if items.len() == 1 {
+                if let Value::Atom(ref atom) = items[0].value {
+                    if let Ok(offset) = atom.parse() {
+                        return Ok(offset)
+                    }
+                }
+            }
+            syntax_error(line, "Expected (offset N)".to_string())
+        })
+    }
+
+    fn float(&mut self) -> Result<Option<f64>, Self::Error> {
+        debug!(target: "text_reader", "float");
+        self.owner.borrow_mut().try(|state| {
+            let expr = state.next("a float")?;
+            if let Value::Atom(ref atom) = expr.value {
+                if atom == "null" {
+                    return Ok(None)
+                }
+                if let Ok(value) = atom.parse() {
+                    return Ok(Some(value))
+                }
+            }
+            syntax_error(expr.line, format!("Expected a float, found {:?}", expr.value))
+        })
+    }
+
+    fn string(&mut self) -> Result<Option<String>, Self::Error> {
+        debug!(target: "text_reader", "string");
+        self.owner.borrow_mut().try(|state| {
+            let expr = state.next("a string")?;
+            match expr.value {
+                Value::String(string) => Ok(Some(string)),
+                Value::Atom(ref atom) if atom == "null" => Ok(None),
+                other => syntax_error(expr.line, format!("Expected a string, found {:?}", other))
+            }
+        })
+    }
+
+    fn list(&mut self) -> Result<(u32, Self::ListGuard), Self::Error> {
+        debug!(target: "text_reader", "list");
+        let clone = self.owner.clone();
+        self.owner.borrow_mut().try(|state| {
+            let (line, items) = state.next_compound("list")?;
+            let len = items.len() as u32;
+            state.push(line, items, false);
+            Ok((len, ContainerGuard::new(clone)))
+        })
+    }
+
+    fn tagged_tuple(&mut self) -> Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error> {
+        debug!(target: "text_reader", "tagged tuple");
+        let clone = self.owner.clone();
+        self.owner.borrow_mut().try(|state| {
+            let (line, mut items) = state.next_compound("tagged")?;
+            if items.is_empty() {
+                return syntax_error(line, "Expected (tagged Kind ...)".to_string())
+            }
+            let kind = match items.remove(0) {
+                Expr { value: Value::Atom(name), .. } | Expr { value: Value::String(name), .. } => name,
+                Expr { line, .. } => return syntax_error(line, "Expected a kind".to_string())
+            };
+            let mut fields = Vec::with_capacity(items.len());
+            for item in &items {
+                let name = match item.value {
+                    Value::List(ref field) if field.len() == 2 => {
+                        match field[0].value {
+                            Value::Atom(ref name) | Value::String(ref name) => Some(name.clone()),
+                            _ => None
+                        }
+                    }
+                    _ => None
+                };
+                match name {
+                    Some(name) => fields.push(name),
+                    None => return syntax_error(item.line, "Expected a field (name value)".to_string())
+                }
+            }
+            debug!(target: "text_reader", "TreeTokenReader: tagged_tuple has name {:?}, fields {:?}", kind, fields);
+            state.push(line, items, true);
+            Ok((kind, Some(Rc::new(fields.into_boxed_slice())), ContainerGuard::new(clone)))
+        })
+    }
+
+    fn untagged_tuple(&mut self) -> Result<Self::UntaggedGuard, Self::Error> {
+        debug!(target: "text_reader", "untagged tuple");
+        let clone = self.owner.clone();
+        self.owner.borrow_mut().try(|state| {
+            let (line, items) = state.next_compound("tuple")?;
+            state.push(line, items, false);
+            Ok(ContainerGuard::new(clone))
+        })
+    }
+}
+
+#[test]
+fn test_text_io() {
+    use std::io::Cursor;
+
+    eprintln!("Testing tagged tuple I/O");
+
+    {
+        let mut writer = TreeTokenWriter::new();
+        let operator = writer.string(Some("+")).unwrap();
+        let name = writer.string(Some("x")).unwrap();
+        let left = writer.tagged_tuple("IdentifierExpression", &[("name", name)]).unwrap();
+        let value = writer.float(Some(1.)).unwrap();
+        let right = writer.tagged_tuple("LiteralNumericExpression", &[("value", value)]).unwrap();
+        writer.tagged_tuple("BinaryExpression", &[("operator", operator), ("left", left), ("right", right)])
+            .expect("Writing tagged tuple");
+        let (data, _) = writer.done()
+            .expect("Finalizing tagged tuple");
+        assert_eq!(std::str::from_utf8(&data).unwrap(),
+"(tagged BinaryExpression
+  (operator \"+\")
+  (left (tagged IdentifierExpression
+    (name \"x\")))
+  (right (tagged LiteralNumericExpression
+    (value 1.0))))
+");
+
+        let mut reader = TreeTokenReader::new(Cursor::new(data))
+            .expect("Parsing tagged tuple");
+        let (name, fields, guard) = reader.tagged_tuple()
+            .expect("Reading tagged tuple");
+        assert_eq!(name, "BinaryExpression");
+        assert_eq!(fields.expect("Missing fields").to_vec(), vec!["operator", "left", "right"]);
+        assert_eq!(reader.string().unwrap(), Some("+".to_string()));
+
+        let (name, _, left_guard) = reader.tagged_tuple()
+            .expect("Reading left");
+        assert_eq!(name, "IdentifierExpression");
+        assert_eq!(reader.string().unwrap(), Some("x".to_string()));
+        left_guard.done()
+            .expect("Left read properly");
+
+        let (name, _, right_guard) = reader.tagged_tuple()
+            .expect("Reading right");
+        assert_eq!(name, "LiteralNumericExpression");
+        assert_eq!(reader.float().unwrap(), Some(1.));
+        right_guard.done()
+            .expect("Right read properly");
+
+        guard.done()
+            .expect("Tagged tuple read properly");
+    }
+
+    eprintln!("Testing primitive values I/O");
+
+    {
+        let data = "string with escapes \"\\\n\t\r \u{0}\u{1}\u{0} and unicode 🦀";
+        let mut writer = TreeTokenWriter::new();
+        let items = vec![
+            writer.string(Some(data)).unwrap(),
+            writer.string(None).unwrap(),
+            writer.string(Some("")).unwrap(),
+            writer.float(Some(-0.1)).unwrap(),
+            writer.float(Some(1e300)).unwrap(),
+            writer.float(Some(std::f64::INFINITY)).unwrap(),
+            writer.float(Some(std::f64::NAN)).unwrap(),
+            writer.float(None).unwrap(),
+            writer.bool(Some(true)).unwrap(),
+            writer.bool(Some(false)).unwrap(),
+            writer.bool(None).unwrap(),
+        ];
+        writer.untagged_tuple(&items)
+            .expect("Writing primitive values");
+        let (data_written, _) = writer.done()
+            .expect("Finalizing primitive values");
+
+        let mut reader = TreeTokenReader::new(Cursor::new(data_written))
+            .expect("Parsing primitive values");
+        let guard = reader.untagged_tuple()
+            .expect("Reading tuple");
+        assert_eq!(reader.string().unwrap(), Some(data.to_string()));
+        assert_eq!(reader.string().unwrap(), None);
+        assert_eq!(reader.string().unwrap(), Some("".to_string()));
+        assert_eq!(reader.float().unwrap(), Some(-0.1));
+        assert_eq!(reader.float().unwrap(), Some(1e300));
+        assert_eq!(reader.float().unwrap(), Some(std::f64::INFINITY));
+        assert!(reader.float().unwrap().unwrap().is_nan());
+        assert_eq!(reader.float().unwrap(), None);
+        assert_eq!(reader.bool().unwrap(), Some(true));
+        assert_eq!(reader.bool().unwrap(), Some(false));
+        assert_eq!(reader.bool().unwrap(), None);
+        guard.done()
+            .expect("Tuple read properly");
+    }
+
+    eprintln!("Testing list and offset I/O");
+
+    {
+        let mut writer = TreeTokenWriter::new();
+        let offset = writer.offset().unwrap();
+        let item_0 = writer.string(Some("foo")).unwrap();
+        let item_1 = writer.string(Some("bar")).unwrap();
+        let list = writer.list(vec![item_0, item_1]).unwrap();
+        let empty = writer.list(vec![]).unwrap();
+        writer.tagged_tuple("", &[("skip", offset), ("items", list), ("empty", empty)])
+            .expect("Writing tuple with offset");
+        let (data, _) = writer.done()
+            .expect("Finalizing tuple with offset");
+        let rest = "\n  (items (list\n    \"foo\"\n    \"bar\"))\n  (empty (list))";
+        assert_eq!(std::str::from_utf8(&data).unwrap(),
+            format!("(tagged \"\"\n  (skip (offset {}))", rest.len()) + rest + ")\n");
+
+        let mut reader = TreeTokenReader::new(Cursor::new(data))
+            .expect("Parsing tuple with offset");
+        let (name, _, guard) = reader.tagged_tuple()
+            .expect("Reading tuple with offset");
+        assert_eq!(name, "");
+        assert_eq!(reader.offset().unwrap() as usize, rest.len());
+        let (len, list_guard) = reader.list()
+            .expect("Reading list");
+        assert_eq!(len, 2);
+        assert_eq!(reader.string().unwrap(), Some("foo".to_string()));
+        assert_eq!(reader.string().unwrap(), Some("bar".to_string()));
+        list_guard.done()
+            .expect("List read properly");
+        let (len, list_guard) = reader.list()
+            .expect("Reading empty list");
+        assert_eq!(len, 0);
+        list_guard.done()
+            .expect("Empty list read properly");
+        guard.done()
+            .expect("Tuple with offset read properly");
+    }
+
+    {
+        let mut writer = TreeTokenWriter::new();
+        let offset = writer.offset().unwrap();
+        assert_matches!(writer.list(vec![offset]).err(), Some(TokenWriterError::InvalidOffsetField));
+    }
+}
+
+#[test]
+fn test_text_handwritten() {
+    use std::io::Cursor;
+
+    let source = "
+        ; A fixture written by hand.
+        (tagged IdentifierExpression ; Comments may appear anywhere.
+            (name \"x\\u{1f980}\"))
+        (tuple 1 2)
+    ";
+    let mut reader = TreeTokenReader::new(Cursor::new(source))
+        .expect("Parsing fixture");
+    let (name, _, guard) = reader.tagged_tuple()
+        .expect("Reading tagged tuple");
+    assert_eq!(name, "IdentifierExpression");
+    assert_eq!(reader.string().unwrap(), Some("x🦀".to_string()));
+    guard.done()
+        .expect("Tagged tuple read properly");
+
+    // Reading too few items.
+    let guard = reader.untagged_tuple()
+        .expect("Reading untagged tuple");
+    assert_eq!(reader.float().unwrap(), Some(1.));
+    assert_matches!(guard.done(), Err(TokenReaderError::Syntax { line: 5, .. }));
+
+    // Syntax errors.
+    assert_matches!(TreeTokenReader::new(Cursor::new("(list\n(list)")).err(), Some(TokenReaderError::Syntax { line: 1, .. }));
+    assert_matches!(TreeTokenReader::new(Cursor::new("\n)")).err(), Some(TokenReaderError::Syntax { line: 2, .. }));
+    assert_matches!(TreeTokenReader::new(Cursor::new("\"foo")).err(), Some(TokenReaderError::Syntax { line: 1, .. }));
+
+    // Type errors.
+    let mut reader = TreeTokenReader::new(Cursor::new("(list) \"foo\""))
+        .expect("Parsing fixture");
+    assert_matches!(reader.string(), Err(TokenReaderError::Syntax { line: 1, .. }));
+}
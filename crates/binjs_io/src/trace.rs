@@ -0,0 +1,813 @@
+//! Adapters that record the tokens read by a `TokenReader` or written by a
+//! `TokenWriter`, to debug mismatches between encoders and decoders.
+//!
+//! Both `TracingReader` and `TracingWriter` log each token with its depth
+//! (target `trace_reader`, resp. `trace_writer`) and record it as a `Trace`,
+//! in the order in which it is read. Traces may be saved to a file, loaded
+//! back, replayed with a `TraceReader` or compared with `Trace::divergence`.
+//!
+//! A trace file contains one token per line, as an S-expression, indented by depth:
+//!
+//! ```text
+//! (tagged BinaryExpression)
+//!   (string "+")
+//!   (tagged IdentifierExpression)
+//!     (string "x")
+//!   (done)
+//!   ...
+//! (done)
+//! ```
+
+use io::*;
+use text::{ self, Expr, Value };
+use ::TokenReaderError;
+use util::PoisonLock;
+
+use std;
+use std::cell::RefCell;
+use std::io::{ Read, Write };
+use std::rc::Rc;
+
+/// A single token, in the order in which it is read.
+#[derive(Clone, Debug)]
+pub enum Token {
+    /// The start of a tagged tuple, with its kind.
+    TaggedTuple(String),
+
+    /// The start of an untagged tuple.
+    UntaggedTuple,
+
+    /// The start of a list, with its number of items.
+    List(u32),
+
+    String(Option<String>),
+    Float(Option<f64>),
+    Bool(Option<bool>),
+
+    /// An offset. Its value is not recorded, as a writer doesn't know it.
+    Offset,
+
+    /// The end of a tagged tuple, untagged tuple or list.
+    Done,
+}
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        use self::Token::*;
+        match (self, other) {
+            (&TaggedTuple(ref a), &TaggedTuple(ref b)) => a == b,
+            (&UntaggedTuple, &UntaggedTuple) => true,
+            (&List(a), &List(b)) => a == b,
+            (&String(ref a), &String(ref b)) => a == b,
+            // Compare floats bitwise, so that `NaN` is equal to itself.
+            (&Float(a), &Float(b)) => a.map(f64::to_bits) == b.map(f64::to_bits),
+            (&Bool(a), &Bool(b)) => a == b,
+            (&Offset, &Offset) => true,
+            (&Done, &Done) => true,
+            _ => false
+        }
+    }
+}
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let mut out = std::string::String::new();
+        match *self {
+            Token::TaggedTuple(ref kind) => {
+                out.push_str("(tagged ");
+                text::write_name(kind, &mut out);
+                out.push(')');
+            }
+            Token::UntaggedTuple => out.push_str("(untagged)"),
+            Token::List(len) => out.push_str(&format!("(list {})", len)),
+            Token::String(None) => out.push_str("(string null)"),
+            Token::String(Some(ref value)) => {
+                out.push_str("(string ");
+                text::write_string(value, &mut out);
+                out.push(')');
+            }
+            Token::Float(None) => out.push_str("(float null)"),
+            Token::Float(Some(value)) => out.push_str(&format!("(float {:?})", value)),
+            Token::Bool(None) => out.push_str("(bool null)"),
+            Token::Bool(Some(value)) => out.push_str(&format!("(bool {})", value)),
+            Token::Offset => out.push_str("(offset)"),
+            Token::Done => out.push_str("(done)"),
+        }
+        write!(f, "{}", out)
+    }
+}
+impl Token {
+    /// Parse a token from its `Display` form.
+    fn parse(expr: Expr) -> Result<Self, TokenReaderError> {
+        let line = expr.line;
+        let invalid = || text::syntax_error(line, "Invalid token".to_string());
+        let items = match expr.value {
+            Value::List(items) => items,
+            _ => return invalid()
+        };
+        let mut items = items.into_iter().map(|item| item.value);
+        let head = match items.next() {
+            Some(Value::Atom(head)) => head,
+            _ => return invalid()
+        };
+        let arg = items.next();
+        if items.next().is_some() {
+            return invalid()
+        }
+        let token = match (head.as_str(), arg) {
+            ("tagged", Some(Value::Atom(kind))) | ("tagged", Some(Value::String(kind))) => Token::TaggedTuple(kind),
+            ("untagged", None) => Token::UntaggedTuple,
+            ("offset", None) => Token::Offset,
+            ("done", None) => Token::Done,
+            ("string", Some(Value::String(value))) => Token::String(Some(value)),
+            (kind, Some(Value::Atom(value))) => {
+                match (kind, value.as_str()) {
+                    ("string", "null") => Token::String(None),
+                    ("float", "null") => Token::Float(None),
+                    ("bool", "null") => Token::Bool(None),
+                    ("bool", "true") => Token::Bool(Some(true)),
+                    ("bool", "false") => Token::Bool(Some(false)),
+                    ("float", _) => match value.parse() {
+                        Ok(value) => Token::Float(Some(value)),
+                        Err(_) => return invalid()
+                    },
+                    ("list", _) => match value.parse() {
+                        Ok(len) => Token::List(len),
+                        Err(_) => return invalid()
+                    },
+                    _ => return invalid()
+                }
+            }
+            _ => return invalid()
+        };
+        Ok(token)
+    }
+}
+
+/// The first difference between two traces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The index of the first token that differs.
+    pub index: usize,
+
+    /// The containers of this token, as `kind[index of the item]`,
+    /// e.g. `Script[2] > list[0] > ExpressionStatement[0]`.
+    pub path: String,
+
+    /// The token in the first trace, or `None` if it ended earlier.
+    pub expected: Option<Token>,
+
+    /// The token in the second trace, or `None` if it ended earlier.
+    pub found: Option<Token>,
+}
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let show = |token: &Option<Token>| match *token {
+            None => "the end of the trace".to_string(),
+            Some(ref token) => format!("{}", token)
+        };
+        write!(f, "At token {} ({}): expected {}, found {}", self.index, self.path, show(&self.expected), show(&self.found))
+    }
+}
+
+/// A sequence of tokens, in the order in which they are read.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub tokens: Vec<Token>,
+}
+impl Trace {
+    /// Write the trace, one token per line.
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<(), std::io::Error> {
+        let mut depth = 0;
+        for token in &self.tokens {
+            if let Token::Done = *token {
+                // Don't panic on traces edited by hand.
+                depth = std::cmp::max(depth, 1) - 1;
+            }
+            writeln!(out, "{:indent$}{}", "", token, indent = 2 * depth)?;
+            match *token {
+                Token::TaggedTuple(_) | Token::UntaggedTuple | Token::List(_) => depth += 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a trace written by `write`.
+    pub fn read<R: Read>(mut inp: R) -> Result<Self, TokenReaderError> {
+        let mut source = String::new();
+        inp.read_to_string(&mut source)
+            .map_err(TokenReaderError::ReadError)?;
+        let mut tokens = vec![];
+        for expr in text::parse(&source)? {
+            tokens.push(Token::parse(expr)?);
+        }
+        Ok(Trace {
+            tokens
+        })
+    }
+
+    /// Find the first token that differs between `self` and `other`,
+    /// or `None` if the traces are identical.
+    pub fn divergence(&self, other: &Trace) -> Option<Divergence> {
+        // The containers of the current token, with the number of items already seen.
+        let mut path : Vec<(String, usize)> = vec![];
+        for index in 0.. {
+            let expected = self.tokens.get(index);
+            let found = other.tokens.get(index);
+            if expected != found {
+                let path = path.iter()
+                    .map(|&(ref label, count)| format!("{}[{}]", label, count))
+                    .collect::<Vec<_>>()
+                    .join(" > ");
+                return Some(Divergence {
+                    index,
+                    path,
+                    expected: expected.cloned(),
+                    found: found.cloned(),
+                })
+            }
+            match expected {
+                None => return None,
+                Some(&Token::TaggedTuple(ref kind)) => path.push((kind.clone(), 0)),
+                Some(&Token::UntaggedTuple) => path.push(("tuple".to_string(), 0)),
+                Some(&Token::List(_)) => path.push(("list".to_string(), 0)),
+                Some(&Token::Done) => {
+                    path.pop();
+                    if let Some(&mut (_, ref mut count)) = path.last_mut() {
+                        *count += 1;
+                    }
+                }
+                Some(_) => {
+                    if let Some(&mut (_, ref mut count)) = path.last_mut() {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// The tokens recorded by a `TracingReader` or a `TracingWriter`.
+///
+/// Keep a clone of the `Recorder` to access the trace once the reader or
+/// writer has been moved, e.g. into a deserializer.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    state: Rc<RefCell<RecorderState>>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    trace: Trace,
+    depth: usize,
+    unbalanced: Vec<Divergence>,
+}
+
+impl Recorder {
+    /// A copy of the tokens recorded so far.
+    pub fn trace(&self) -> Trace {
+        self.state.borrow().trace.clone()
+    }
+
+    /// The `Token::Done` recorded while no list or tuple was open, e.g.
+    /// because a guard was closed twice. Each is reported at the root,
+    /// where the end of the trace was expected.
+    pub fn unbalanced(&self) -> Vec<Divergence> {
+        self.state.borrow().unbalanced.clone()
+    }
+
+    fn record(&self, target: &str, token: Token, position: Option<usize>, details: Option<&str>) {
+        let mut state = self.state.borrow_mut();
+        if let Token::Done = token {
+            match state.depth.checked_sub(1) {
+                Some(depth) => state.depth = depth,
+                None => {
+                    warn!(target: target, "{} without a matching list or tuple, position: {:?}", token, position);
+                    let index = state.trace.tokens.len();
+                    state.unbalanced.push(Divergence {
+                        index,
+                        path: String::new(),
+                        expected: None,
+                        found: Some(token.clone()),
+                    });
+                }
+            }
+        }
+        debug!(target: target, "{:indent$}{} depth: {}, position: {:?}{}", "", token, state.depth, position,
+            details.unwrap_or(""),
+            indent = 2 * state.depth);
+        match token {
+            Token::TaggedTuple(_) | Token::UntaggedTuple | Token::List(_) => state.depth += 1,
+            _ => {}
+        }
+        state.trace.tokens.push(token);
+    }
+}
+
+/// A `TokenReader` that records and logs the tokens read by another `TokenReader`.
+///
+/// Tokens are recorded once they have been read successfully.
+pub struct TracingReader<R> where R: TokenReader {
+    reader: R,
+    recorder: Recorder,
+}
+impl<R> TracingReader<R> where R: TokenReader {
+    pub fn new(reader: R) -> Self {
+        TracingReader {
+            reader,
+            recorder: Recorder::default()
+        }
+    }
+
+    pub fn recorder(&self) -> Recorder {
+        self.recorder.clone()
+    }
+
+    fn record(&self, token: Token, details: Option<&str>) {
+        self.recorder.record("trace_reader", token, self.reader.position(), details)
+    }
+}
+
+/// A guard that records `Token::Done` once the guard is done.
+pub struct TracingGuard<G> where G: Guard {
+    guard: G,
+    recorder: Recorder,
+}
+impl<G> Guard for TracingGuard<G> where G: Guard {
+    type Error = G::Error;
+    fn done(self) -> Result<(), Self::Error> {
+        self.guard.done()?;
+        self.recorder.record("trace_reader", Token::Done, None, None);
+        Ok(())
+    }
+}
+
+impl<R> TokenReader for TracingReader<R> where R: TokenReader {
+    type Error = R::Error;
+    type ListGuard = TracingGuard<R::ListGuard>;
+    type TaggedGuard = TracingGuard<R::TaggedGuard>;
+    type UntaggedGuard = TracingGuard<R::UntaggedGuard>;
+
+    fn poison(&mut self) {
+        self.reader.poison()
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.reader.position()
+    }
+
+    fn string(&mut self) -> Result<Option<String>, Self::Error> {
+        let result = self.reader.string()?;
+        self.record(Token::String(result.clone()), None);
+        Ok(result)
+    }
+
+    fn categorized_string(&mut self, category: StringCategory) -> Result<Option<String>, Self::Error> {
+        let result = self.reader.categorized_string(category)?;
+        self.record(Token::String(result.clone()), None);
+        Ok(result)
+    }
+
+    fn string_atom(&mut self) -> Result<Option<Rc<str>>, Self::Error> {
+        let result = self.reader.string_atom()?;
+        self.record(Token::String(result.as_ref().map(|s| s.to_string())), None);
+        Ok(result)
+    }
+
+    fn categorized_string_atom(&mut self, category: StringCategory) -> Result<Option<Rc<str>>, Self::Error> {
+        let result = self.reader.categorized_string_atom(category)?;
+        self.record(Token::String(result.as_ref().map(|s| s.to_string())), None);
+        Ok(result)
+    }
+
+    fn string_enum(&mut self, strings: &[&str]) -> Result<usize, Self::Error> {
+        let result = self.reader.string_enum(strings)?;
+        self.record(Token::String(strings.get(result).map(|s| s.to_string())), None);
+        Ok(result)
+    }
+
+    fn float(&mut self) -> Result<Option<f64>, Self::Error> {
+        let result = self.reader.float()?;
+        self.record(Token::Float(result), None);
+        Ok(result)
+    }
+
+    fn bool(&mut self) -> Result<Option<bool>, Self::Error> {
+        let result = self.reader.bool()?;
+        self.record(Token::Bool(result), None);
+        Ok(result)
+    }
+
+    fn offset(&mut self) -> Result<u32, Self::Error> {
+        let result = self.reader.offset()?;
+        self.record(Token::Offset, Some(&format!(", value: {}", result)));
+        Ok(result)
+    }
+
+    fn list(&mut self) -> Result<(u32, Self::ListGuard), Self::Error> {
+        let (len, guard) = self.reader.list()?;
+        self.record(Token::List(len), None);
+        Ok((len, TracingGuard {
+            guard,
+            recorder: self.recorder.clone()
+        }))
+    }
+
+    fn tagged_tuple(&mut self) -> Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error> {
+        let (kind, fields, guard) = self.reader.tagged_tuple()?;
+        self.record(Token::TaggedTuple(kind.clone()), Some(&format!(", fields: {:?}", fields)));
+        Ok((kind, fields, TracingGuard {
+            guard,
+            recorder: self.recorder.clone()
+        }))
+    }
+
+    fn tagged_tuple_kind(&mut self) -> Result<(TupleKind, Self::TaggedGuard), Self::Error> {
+        let (kind, guard) = self.reader.tagged_tuple_kind()?;
+        self.record(Token::TaggedTuple(kind.name.to_string()), Some(&format!(", fields: {:?}", kind.fields)));
+        Ok((kind, TracingGuard {
+            guard,
+            recorder: self.recorder.clone()
+        }))
+    }
+
+    fn untagged_tuple(&mut self) -> Result<Self::UntaggedGuard, Self::Error> {
+        let guard = self.reader.untagged_tuple()?;
+        self.record(Token::UntaggedTuple, None);
+        Ok(TracingGuard {
+            guard,
+            recorder: self.recorder.clone()
+        })
+    }
+}
+
+/// The tokens of a subtree, in reading order.
+enum TraceNode {
+    Leaf(Token),
+
+    /// A list or tuple, followed by its items and `Token::Done`.
+    Container(Token, Vec<Rc<TraceNode>>),
+}
+
+/// A tree of the underlying `TokenWriter`, with the tokens it contains.
+#[derive(Clone)]
+pub struct TracedTree<T> {
+    tree: T,
+    trace: Rc<TraceNode>,
+}
+
+/// A `TokenWriter` that records and logs the tokens written by another `TokenWriter`.
+///
+/// As trees are written bottom-up, calls are logged in the order in which
+/// they are made, while tokens are recorded in reading order by `done()`,
+/// starting from the last tree written. Writers don't know byte positions
+/// until they are done, so none are logged.
+pub struct TracingWriter<W> where W: TokenWriter {
+    writer: W,
+    recorder: Recorder,
+    root: Option<Rc<TraceNode>>,
+}
+impl<W> TracingWriter<W> where W: TokenWriter {
+    pub fn new(writer: W) -> Self {
+        TracingWriter {
+            writer,
+            recorder: Recorder::default(),
+            root: None,
+        }
+    }
+
+    pub fn recorder(&self) -> Recorder {
+        self.recorder.clone()
+    }
+
+    fn register(&mut self, tree: W::Tree, trace: TraceNode) -> TracedTree<W::Tree> {
+        let trace = Rc::new(trace);
+        self.root = Some(trace.clone());
+        TracedTree {
+            tree,
+            trace
+        }
+    }
+
+    fn leaf(&mut self, tree: W::Tree, token: Token) -> TracedTree<W::Tree> {
+        debug!(target: "trace_writer", "{}", token);
+        self.register(tree, TraceNode::Leaf(token))
+    }
+}
+
+impl<W> TokenWriter for TracingWriter<W> where W: TokenWriter, W::Tree: Clone {
+    type Tree = TracedTree<W::Tree>;
+    type Statistics = W::Statistics;
+    type Data = W::Data;
+    type Error = W::Error;
+
+    fn done(self) -> Result<(Self::Data, Self::Statistics), Self::Error> {
+        let result = self.writer.done()?;
+
+        // Record the tokens in reading order. Don't recurse, as trees may be deep.
+        let mut stack = vec![];
+        stack.extend(self.root);
+        while let Some(node) = stack.pop() {
+            match *node {
+                TraceNode::Leaf(ref token) =>
+                    self.recorder.record("trace_writer", token.clone(), None, None),
+                TraceNode::Container(ref token, ref items) => {
+                    self.recorder.record("trace_writer", token.clone(), None, None);
+                    stack.push(Rc::new(TraceNode::Leaf(Token::Done)));
+                    stack.extend(items.iter().rev().cloned());
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn tagged_tuple(&mut self, tag: &str, children: &[(&str, Self::Tree)]) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "trace_writer", "tagged_tuple {} with {} fields", tag, children.len());
+        let trees : Vec<_> = children.iter()
+            .map(|&(name, ref child)| (name, child.tree.clone()))
+            .collect();
+        let tree = self.writer.tagged_tuple(tag, &trees)?;
+        let items = children.iter()
+            .map(|&(_, ref child)| child.trace.clone())
+            .collect();
+        Ok(self.register(tree, TraceNode::Container(Token::TaggedTuple(tag.to_string()), items)))
+    }
+
+    fn untagged_tuple(&mut self, children: &[Self::Tree]) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "trace_writer", "untagged_tuple with {} items", children.len());
+        let trees : Vec<_> = children.iter()
+            .map(|child| child.tree.clone())
+            .collect();
+        let tree = self.writer.untagged_tuple(&trees)?;
+        let items = children.iter()
+            .map(|child| child.trace.clone())
+            .collect();
+        Ok(self.register(tree, TraceNode::Container(Token::UntaggedTuple, items)))
+    }
+
+    fn list(&mut self, children: Vec<Self::Tree>) -> Result<Self::Tree, Self::Error> {
+        debug!(target: "trace_writer", "list with {} items", children.len());
+        let len = children.len() as u32;
+        let (trees, items) = children.into_iter()
+            .map(|child| (child.tree, child.trace))
+            .unzip();
+        let tree = self.writer.list(trees)?;
+        Ok(self.register(tree, TraceNode::Container(Token::List(len), items)))
+    }
+
+    fn string(&mut self, data: Option<&str>) -> Result<Self::Tree, Self::Error> {
+        let tree = self.writer.string(data)?;
+        Ok(self.leaf(tree, Token::String(data.map(str::to_string))))
+    }
+
+    fn categorized_string(&mut self, category: StringCategory, data: Option<&str>) -> Result<Self::Tree, Self::Error> {
+        let tree = self.writer.categorized_string(category, data)?;
+        Ok(self.leaf(tree, Token::String(data.map(str::to_string))))
+    }
+
+    fn string_enum(&mut self, strings: &[&str], index: usize) -> Result<Self::Tree, Self::Error> {
+        let tree = self.writer.string_enum(strings, index)?;
+        Ok(self.leaf(tree, Token::String(Some(strings[index].to_string()))))
+    }
+
+    fn float(&mut self, data: Option<f64>) -> Result<Self::Tree, Self::Error> {
+        let tree = self.writer.float(data)?;
+        Ok(self.leaf(tree, Token::Float(data)))
+    }
+
+    fn bool(&mut self, data: Option<bool>) -> Result<Self::Tree, Self::Error> {
+        let tree = self.writer.bool(data)?;
+        Ok(self.leaf(tree, Token::Bool(data)))
+    }
+
+    fn offset(&mut self) -> Result<Self::Tree, Self::Error> {
+        let tree = self.writer.offset()?;
+        Ok(self.leaf(tree, Token::Offset))
+    }
+}
+
+/// The state of the `TraceReader`.
+///
+/// Use a `PoisonLock` to access this state.
+struct ReplayState {
+    tokens: std::iter::Enumerate<std::vec::IntoIter<Token>>,
+}
+impl ReplayState {
+    /// Read the next token, failing if `check` doesn't accept it.
+    ///
+    /// Errors are reported with the line of the token in a file written by `Trace::write`.
+    fn next<T, F>(&mut self, expected: &str, check: F) -> Result<T, TokenReaderError> where F: FnOnce(Token) -> Result<T, Token> {
+        match self.tokens.next() {
+            None => Err(TokenReaderError::Syntax {
+                line: 0,
+                reason: format!("Expected {}, found the end of the trace", expected)
+            }),
+            Some((index, token)) => check(token)
+                .map_err(|token| TokenReaderError::Syntax {
+                    line: index + 1,
+                    reason: format!("Expected {}, found {}", expected, token)
+                })
+        }
+    }
+}
+
+/// A `TokenReader` that replays a `Trace`.
+///
+/// Tagged tuples are replayed without field names. Offsets are replayed as 0.
+pub struct TraceReader {
+    owner: Rc<RefCell<PoisonLock<ReplayState>>>,
+}
+impl TraceReader {
+    pub fn new(trace: Trace) -> Self {
+        let state = ReplayState {
+            tokens: trace.tokens.into_iter().enumerate()
+        };
+        TraceReader {
+            owner: Rc::new(RefCell::new(PoisonLock::new(state)))
+        }
+    }
+}
+
+/// A guard that expects `Token::Done`.
+pub struct ReplayGuard {
+    finalized: bool,
+    owner: Rc<RefCell<PoisonLock<ReplayState>>>,
+}
+impl ReplayGuard {
+    fn new(owner: Rc<RefCell<PoisonLock<ReplayState>>>) -> Self {
+        ReplayGuard {
+            finalized: false,
+            owner
+        }
+    }
+}
+impl Guard for ReplayGuard {
+    type Error = TokenReaderError;
+    fn done(mut self) -> Result<(), Self::Error> {
+        self.finalized = true;
+        let mut owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return Ok(())
+        }
+        owner.try(|state| state.next("(done)", |token| match token {
+            Token::Done => Ok(()),
+            other => Err(other)
+        }))
+    }
+}
+impl Drop for ReplayGuard {
+    fn drop(&mut self) {
+        let owner = self.owner.borrow_mut();
+        if owner.is_poisoned() {
+            return;
+        }
+        assert!(self.finalized)
+    }
+}
+
+impl TokenReader for TraceReader {
+    type Error = TokenReaderError;
+    type ListGuard = ReplayGuard;
+    type TaggedGuard = ReplayGuard;
+    type UntaggedGuard = ReplayGuard;
+
+    fn poison(&mut self) {
+        self.owner.borrow_mut().poison();
+    }
+
+    fn string(&mut self) -> Result<Option<String>, Self::Error> {
+        self.owner.borrow_mut().try(|state| state.next("(string ...)", |token| match token {
+            Token::String(value) => Ok(value),
+            other => Err(other)
+        }))
+    }
+
+    fn float(&mut self) -> Result<Option<f64>, Self::Error> {
+        self.owner.borrow_mut().try(|state| state.next("(float ...)", |token| match token {
+            Token::Float(value) => Ok(value),
+            other => Err(other)
+        }))
+    }
+
+    fn bool(&mut self) -> Result<Option<bool>, Self::Error> {
+        self.owner.borrow_mut().try(|state| state.next("(bool ...)", |token| match token {
+            Token::Bool(value) => Ok(value),
+            other => Err(other)
+        }))
+    }
+
+    fn offset(&mut self) -> Result<u32, Self::Error> {
+        self.owner.borrow_mut().try(|state| state.next("(offset)", |token| match token {
+            Token::Offset => Ok(0),
+            other => Err(other)
+        }))
+    }
+
+    fn list(&mut self) -> Result<(u32, Self::ListGuard), Self::Error> {
+        let len = self.owner.borrow_mut().try(|state| state.next("(list ...)", |token| match token {
+            Token::List(len) => Ok(len),
+            other => Err(other)
+        }))?;
+        Ok((len, ReplayGuard::new(self.owner.clone())))
+    }
+
+    fn tagged_tuple(&mut self) -> Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error> {
+        let kind = self.owner.borrow_mut().try(|state| state.next("(tagged ...)", |token| match token {
+            Token::TaggedTuple(kind) => Ok(kind),
+            other => Err(other)
+        }))?;
+        Ok((kind, None, ReplayGuard::new(self.owner.clone())))
+    }
+
+    fn untagged_tuple(&mut self) -> Result<Self::UntaggedGuard, Self::Error> {
+        self.owner.borrow_mut().try(|state| state.next("(untagged)", |token| match token {
+            Token::UntaggedTuple => Ok(()),
+            other => Err(other)
+        }))?;
+        Ok(ReplayGuard::new(self.owner.clone()))
+    }
+}
+
+#[test]
+fn test_trace_io() {
+    use std::io::Cursor;
+
+    let trace = Trace {
+        tokens: vec![
+            Token::TaggedTuple("BinaryExpression".to_string()),
+            Token::String(Some("a \"string\"\n".to_string())),
+            Token::List(2),
+            Token::Float(Some(1.5)),
+            Token::Float(Some(std::f64::NAN)),
+            Token::Done,
+            Token::UntaggedTuple,
+            Token::Bool(Some(true)),
+            Token::Bool(None),
+            Token::Offset,
+            Token::Done,
+            Token::TaggedTuple("".to_string()),
+            Token::String(None),
+            Token::Float(None),
+            Token::Done,
+            Token::Done,
+        ]
+    };
+    let mut data = vec![];
+    trace.write(&mut data)
+        .expect("Writing trace");
+    assert!(String::from_utf8(data.clone()).unwrap().starts_with("(tagged BinaryExpression)\n  (string \"a \\\"string\\\"\\n\")\n  (list 2)\n    (float 1.5)\n"));
+
+    let read = Trace::read(Cursor::new(data))
+        .expect("Reading trace");
+    assert_eq!(read, trace);
+    assert_eq!(read.divergence(&trace), None);
+
+    let mut other = trace.clone();
+    other.tokens[8] = Token::Bool(Some(false));
+    let divergence = trace.divergence(&other)
+        .expect("Traces should differ");
+    assert_eq!(divergence.index, 8);
+    assert_eq!(divergence.path, "BinaryExpression[2] > tuple[1]");
+    assert_eq!(divergence.expected, Some(Token::Bool(None)));
+    assert_eq!(divergence.found, Some(Token::Bool(Some(false))));
+
+    other.tokens.truncate(3);
+    let divergence = trace.divergence(&other)
+        .expect("Traces should differ");
+    assert_eq!(divergence.index, 3);
+    assert_eq!(divergence.path, "BinaryExpression[1] > list[0]");
+    assert_eq!(divergence.found, None);
+
+    // Replay.
+    let mut reader = TraceReader::new(trace);
+    let (kind, _, guard) = reader.tagged_tuple().unwrap();
+    assert_eq!(kind, "BinaryExpression");
+    assert_eq!(reader.string().unwrap(), Some("a \"string\"\n".to_string()));
+    let (len, list_guard) = reader.list().unwrap();
+    assert_eq!(len, 2);
+    assert_eq!(reader.float().unwrap(), Some(1.5));
+    match reader.string() {
+        Err(TokenReaderError::Syntax { line: 5, .. }) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+    list_guard.done().unwrap();
+    guard.done().unwrap();
+}
+
+#[test]
+fn test_recorder_unbalanced() {
+    let recorder = Recorder::default();
+    recorder.record("trace_reader", Token::List(0), None, None);
+    recorder.record("trace_reader", Token::Done, None, None);
+    assert_eq!(recorder.unbalanced(), vec![]);
+
+    // A second `Done` doesn't match any list or tuple.
+    recorder.record("trace_reader", Token::Done, Some(3), None);
+    assert_eq!(recorder.unbalanced(), vec![Divergence {
+        index: 2,
+        path: String::new(),
+        expected: None,
+        found: Some(Token::Done),
+    }]);
+    assert_eq!(recorder.trace().tokens.len(), 3);
+}
@@ -34,10 +34,9 @@ pub trait ReadConst {
 }
 impl<T> ReadConst for T where T: std::io::Read {
     fn read_const(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
-        let mut buf = Vec::with_capacity(data.len());
-        unsafe { buf.set_len(data.len()); }
-        let bytes = self.read(&mut buf)?;
-        if bytes != data.len() || &buf as &[u8] != data {
+        let mut buf = vec![0; data.len()];
+        self.read_exact(&mut buf)?;
+        if &buf as &[u8] != data {
             debug!(target: "read_const", "Invalid data {:?}, expected {:?}",
                 String::from_utf8(buf.to_vec()),
                 String::from_utf8(data.to_vec())
@@ -85,6 +84,13 @@ impl<S> PoisonLock<S> {
         self.poisoned = true;
     }
 
+    /// Access the state for inspection, without altering it.
+    ///
+    /// Unlike `try`, this succeeds even if the state is poisoned.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
     pub fn is_poisoned(&self) -> bool {
         self.poisoned
     }
@@ -11,13 +11,26 @@ use std::rc::*;
 
 
 /// The name of an interface or enum.
-#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// Names are interned by the `SpecBuilder`, so names of the same spec
+/// are compared by pointer first.
+#[derive(Clone, Eq, PartialOrd, Ord)]
 pub struct NodeName(Rc<String>);
 impl NodeName {
     pub fn to_string(&self) -> &String {
         self.0.as_ref()
     }
 }
+impl PartialEq for NodeName {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+impl Hash for NodeName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
 impl Debug for NodeName {
     fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         Debug::fmt(self.to_str(), formatter)
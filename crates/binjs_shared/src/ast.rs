@@ -51,7 +51,7 @@ pub struct Path<I, F> where I: Debug + PartialEq, F: Debug + PartialEq {
     items: Vec<PathItem<I, F>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct PathItem<I, F> where I: Debug + PartialEq, F: Debug + PartialEq {
     pub interface: I,
     pub field: F,
@@ -71,6 +71,14 @@ impl<I, F> Debug for Path<I, F> where I: Debug + PartialEq, F: Debug + PartialEq
         )
     }
 }
+impl<I, F> Clone for Path<I, F> where I: Clone + Debug + PartialEq, F: Clone + Debug + PartialEq {
+    fn clone(&self) -> Self {
+        Self {
+            interface: self.interface.clone(),
+            items: self.items.clone(),
+        }
+    }
+}
 impl<I, F> Path<I, F> where I: Debug + PartialEq, F: Debug + PartialEq {
     /// Create an empty `Path`.
     pub fn new() -> Self {
@@ -81,7 +81,8 @@ fn main() {
         binjs::io::multipart::WriteOptions {
             strings_table: compression.clone(),
             grammar_table: compression.clone(),
-            tree: compression.clone()
+            tree: compression.clone(),
+            .. binjs::io::multipart::WriteOptions::default()
         }
     };
 
@@ -0,0 +1,147 @@
+//! Generate seed corpora for the fuzz targets of `fuzz/`, from the
+//! frameworks of `tests/data/frameworks`.
+//!
+//! Seeds are written to `<DEST>/<target>/<name>`.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate glob;
+
+use binjs::generic::FromJSON;
+use binjs::io::TokenSerializer;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::WriteOptions;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+
+use clap::*;
+
+use std::fs::*;
+use std::io::*;
+use std::path::*;
+
+/// A sequence of operations for `binjs::util::fuzz::read_tokens`, visiting each kind of token.
+const OPS: [u8; 64] = [
+    5, 5, 4, 2, 2, 7, 1, 0,
+    3, 6, 7, 7, 5, 4, 5, 2,
+    7, 7, 7, 4, 6, 1, 1, 7,
+    7, 5, 0, 0, 2, 3, 7, 5,
+    4, 2, 7, 7, 6, 6, 0, 7,
+    7, 5, 5, 5, 2, 1, 7, 7,
+    7, 4, 4, 4, 2, 7, 7, 7,
+    5, 3, 0, 1, 2, 6, 7, 7,
+];
+
+fn write_seed(dest: &Path, target: &str, name: &str, data: &[u8]) {
+    let dir = dest.join(target);
+    create_dir_all(&dir)
+        .expect("Could not create corpus directory");
+    File::create(dir.join(name))
+        .expect("Could not create seed")
+        .write_all(data)
+        .expect("Could not write seed");
+}
+
+/// Write seeds for a target using `binjs::util::fuzz::split_ops`: one that
+/// only reads the file header, one that also reads a few tokens.
+fn write_ops_seeds(dest: &Path, target: &str, name: &str, data: &[u8]) {
+    let mut header_only = vec![0];
+    header_only.extend_from_slice(data);
+    write_seed(dest, target, &format!("{}-header", name), &header_only);
+
+    let mut with_ops = vec![OPS.len() as u8];
+    with_ops.extend_from_slice(&OPS);
+    with_ops.extend_from_slice(data);
+    write_seed(dest, target, &format!("{}-tokens", name), &with_ops);
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS fuzz corpus generator")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Generate seed corpora for the fuzz targets from tests/data/frameworks.")
+        .args(&[
+            Arg::with_name("DEST")
+                .required(true)
+                .help("Directory in which to write the corpora, typically fuzz/corpus."),
+        ])
+        .get_matches();
+
+    let dest = PathBuf::from(matches.value_of("DEST")
+        .expect("Expected destination directory"));
+
+    let parser = Shift::new();
+
+    let path = format!("{}/tests/data/frameworks/*.js", env!("CARGO_MANIFEST_DIR"));
+    for entry in glob::glob(&path)
+        .expect("Invalid glob pattern")
+    {
+        let entry = entry.expect("Invalid entry");
+        let name = entry.file_stem()
+            .expect("Missing file name")
+            .to_string_lossy()
+            .into_owned();
+        println!("Generating seeds from {:?}", entry);
+
+        // `decompress`: the source text, in each compression format.
+        let mut source = vec![];
+        File::open(&entry)
+            .expect("Could not open source")
+            .read_to_end(&mut source)
+            .expect("Could not read source");
+        for compression in Compression::values().iter() {
+            let mut compressed = vec![];
+            compression.compress(&source, &mut compressed)
+                .expect("Could not compress source");
+            write_seed(&dest, "decompress", &format!("{}-{}", name, compression.code()), &compressed);
+        }
+
+        let json = parser.parse_file(entry.clone())
+            .expect("Could not parse source");
+        let mut ast = Script::import(&json)
+            .expect("Could not import AST");
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_script(&mut ast);
+
+        // `multipart_reader`, `es6_deserializer`, `varnum`: the multipart encoding.
+        // The uncompressed version is the most useful to mutate, but we also want
+        // to reach the decompressors.
+        let all_options = [
+            ("identity", Compression::Identity),
+            ("gzip", Compression::Gzip),
+            ("br", Compression::Brotli),
+        ];
+        for &(suffix, ref compression) in all_options.iter() {
+            let writer = binjs::io::multipart::TreeTokenWriter::new(WriteOptions {
+                grammar_table: compression.clone(),
+                strings_table: compression.clone(),
+                tree: compression.clone(),
+                .. WriteOptions::default()
+            });
+            let mut serializer = binjs::specialized::es6::io::Serializer::new(writer);
+            serializer.serialize(&ast)
+                .expect("Could not encode AST");
+            let (data, _) = serializer.done()
+                .expect("Could not finalize AST encoding");
+
+            let name = format!("{}-{}", name, suffix);
+            write_seed(&dest, "es6_deserializer", &name, &data);
+            write_ops_seeds(&dest, "multipart_reader", &name, &data);
+            if let Compression::Identity = *compression {
+                // Mostly varnums.
+                write_seed(&dest, "varnum", &name, &data);
+            }
+        }
+
+        // `simple_reader`: the simple encoding.
+        let writer = binjs::io::simple::TreeTokenWriter::new();
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(writer);
+        serializer.serialize(&ast)
+            .expect("Could not encode AST");
+        let (data, _) = serializer.done()
+            .expect("Could not finalize AST encoding");
+        write_ops_seeds(&dest, "simple_reader", &name, &data);
+    }
+}
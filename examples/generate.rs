@@ -1,6 +1,7 @@
 //! Generate a believed-to-be-correct JS file and its encoding.
 //!
-//! Note that the JS file is only correct insofar as the AST matches the grammar.
+//! Note that the JS file is only correct insofar as the AST matches the grammar,
+//! unless `--valid` is specified.
 
 
 extern crate binjs;
@@ -14,7 +15,7 @@ const DEFAULT_TREE_SIZE: isize = 5;
 
 use binjs::source::Shift;
 use binjs::generic::io::encode::Encode;
-use binjs::generic::pick::{ Pick, Picker };
+use binjs::generic::pick::{ Model, ModelPicker, Pick, Picker, ValidPicker };
 
 use clap::*;
 use rand::Rand;
@@ -50,7 +51,15 @@ Note that this tool does not attempt to make sure that the files are entirely co
                 .help("Expected file size (in AST depth). Default: 5."),
             Arg::with_name("random-ast-metadata")
                 .long("random-metadata")
-                .help("If specified, generate random ast metadata (declared variables, etc.).")
+                .help("If specified, generate random ast metadata (declared variables, etc.)."),
+            Arg::with_name("valid")
+                .long("valid")
+                .help("If specified, generate files that are valid JavaScript, with consistent ast metadata."),
+            Arg::with_name("model")
+                .long("model")
+                .takes_value(true)
+                .conflicts_with("valid")
+                .help("If specified, a model produced by binjs_model, used to generate files that resemble its corpus.")
         ])
         .get_matches();
     let is_multipart =
@@ -82,6 +91,14 @@ Note that this tool does not attempt to make sure that the files are entirely co
     });
 
     let random_metadata = matches.is_present("random-metadata");
+    let valid = matches.is_present("valid");
+    let model = matches.value_of("model")
+        .map(|path| {
+            let file = std::fs::File::open(path)
+                .expect("Could not open model");
+            Model::load(std::io::BufReader::new(file))
+                .expect("Could not load model")
+        });
 
     let mut rng = rand::thread_rng();
     let parser = Shift::new();
@@ -91,9 +108,16 @@ Note that this tool does not attempt to make sure that the files are entirely co
         if i >= number {
             break;
         }
-        let mut ast = Picker.random(&spec, &mut rng, size);
-
-        if !random_metadata {
+        let mut ast =
+            if valid {
+                ValidPicker.random(&spec, &mut rng, size)
+            } else if let Some(ref model) = model {
+                ModelPicker::new(model).random(&spec, &mut rng, size)
+            } else {
+                Picker.random(&spec, &mut rng, size)
+            };
+
+        if !random_metadata && !valid {
             // Overwrite random annotations.
             library.annotate(&mut ast);
         }
@@ -95,7 +95,8 @@ fn main() {
             Some(binjs::io::multipart::WriteOptions {
                 strings_table: strings,
                 grammar_table: grammar,
-                tree
+                tree,
+                .. binjs::io::multipart::WriteOptions::default()
             })
         } else {
             println!("Format: simple");
@@ -179,9 +180,12 @@ fn main() {
         };
 
         println!("Checking.");
-        let equal = binjs::generic::syntax::Comparator::compare(&spec, &ast, &decoded)
+        let mismatches = binjs::generic::syntax::Comparator::mismatches(&spec, &ast, &decoded, &Default::default())
             .expect("Could not compare ASTs");
-        assert!(equal);
+        for mismatch in &mismatches {
+            println!("Mismatch: {}", mismatch);
+        }
+        assert_eq!(mismatches.len(), 0);
 
         println!("Roundtrip success!");
     }
@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate binjs;
+
+fuzz_target!(|data: &[u8]| {
+    binjs::util::fuzz::es6_deserializer(data);
+});
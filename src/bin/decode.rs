@@ -51,6 +51,18 @@ fn main() {
         let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
         deserializer.deserialize()
             .expect("Could not decode")
+    } else if let Some(tree) = binjs::io::text::TreeTokenReader::new(File::open(source_path)
+        .expect("Could not open source"))
+        .ok()
+        .and_then(|reader| {
+            // The simple format may happen to parse as text, so also check that we can decode.
+            let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+            let result : Result<binjs::specialized::es6::ast::Script, _> = deserializer.deserialize();
+            result.ok()
+        })
+    {
+        println!("... decoded as text format.");
+        tree
     } else {
         println!("... falling back to simple format.");
 
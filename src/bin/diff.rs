@@ -0,0 +1,70 @@
+//! Compare two BinJS files at the AST level.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::io::Deserialization;
+use binjs::generic::ToJSON;
+use binjs::generic::diff::Diff;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+
+use std::fs::*;
+use std::io::*;
+
+use clap::*;
+
+fn decode(path: &str) -> binjs::specialized::es6::ast::Script {
+    let file = File::open(path)
+        .unwrap_or_else(|_| panic!("Could not open {}", path));
+    let stream = BufReader::new(file);
+    let reader = binjs::io::multipart::TreeTokenReader::new(stream)
+        .unwrap_or_else(|e| panic!("Could not read container {}: {:?}", path, e));
+    let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+    deserializer.deserialize()
+        .unwrap_or_else(|e| panic!("Could not decode {}: {:?}", path, e))
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS diff")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Compare two BinJS (multipart) files, printing the differences between their ASTs.")
+        .args(&[
+            Arg::with_name("LEFT")
+                .required(true)
+                .help("Original file. Must be a BinJS source file."),
+            Arg::with_name("RIGHT")
+                .required(true)
+                .help("Modified file. Must be a BinJS source file."),
+        ])
+    .get_matches();
+
+    let left_path = matches.value_of("LEFT")
+        .expect("Expected left file");
+    let right_path = matches.value_of("RIGHT")
+        .expect("Expected right file");
+
+    let left = decode(left_path).export();
+    let right = decode(right_path).export();
+
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    let changes = Diff::diff(&spec, &left, &right)
+        .expect("Could not compare ASTs");
+    for change in &changes {
+        println!("{}", change);
+    }
+    if changes.len() == 0 {
+        println!("No difference.");
+    } else {
+        println!("{} difference(s).", changes.len());
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Dump the structure of a multipart BinJS file, for debugging purposes.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::io::decode::Decoder;
+use binjs::io::{ Guard, StringCategory, TokenReader, TokenReaderError };
+use binjs::io::multipart::TreeTokenReader;
+use binjs::meta::spec::{ Spec, SpecBuilder, SpecOptions, ToStr };
+
+use std::cell::RefCell;
+use std::fs::*;
+use std::io::*;
+use std::rc::Rc;
+
+use clap::*;
+
+/// The node being read, used to label tokens.
+enum Frame {
+    /// A tagged tuple, with the names of its fields and the index of the next field.
+    Tuple(Vec<String>, usize),
+
+    /// A list and the index of the next item.
+    List(usize),
+}
+
+/// A `TokenReader` that prints each token read from the underlying
+/// `TreeTokenReader`, along with its offset in the `[TREE]` section.
+struct DumpReader<'a> {
+    reader: TreeTokenReader,
+    spec: &'a Spec,
+    frames: Rc<RefCell<Vec<Frame>>>,
+}
+impl<'a> DumpReader<'a> {
+    /// Read the next token, then print a line describing it.
+    fn trace<T, F, D>(&mut self, f: F, describe: D) -> std::result::Result<T, TokenReaderError>
+        where F: FnOnce(&mut TreeTokenReader) -> std::result::Result<T, TokenReaderError>,
+              D: FnOnce(&T) -> String
+    {
+        let offset = self.reader.tree_position();
+        let (depth, label) = {
+            let mut frames = self.frames.borrow_mut();
+            let depth = frames.len();
+            let label = match frames.last_mut() {
+                Some(&mut Frame::Tuple(ref fields, ref mut next)) => {
+                    let label = fields.get(*next)
+                        .cloned()
+                        .unwrap_or_else(|| "?".to_string());
+                    *next += 1;
+                    label
+                }
+                Some(&mut Frame::List(ref mut next)) => {
+                    let label = format!("[{}]", next);
+                    *next += 1;
+                    label
+                }
+                None => "<root>".to_string()
+            };
+            (depth, label)
+        };
+        let result = f(&mut self.reader)?;
+        println!("{offset:08x} {indent}{label}: {token}",
+            offset = offset,
+            indent = "  ".repeat(depth),
+            label = label,
+            token = describe(&result));
+        Ok(result)
+    }
+}
+
+/// A guard that closes the current `Frame` once the node is read.
+struct DumpGuard<G> where G: Guard<Error = TokenReaderError> {
+    guard: G,
+    frames: Rc<RefCell<Vec<Frame>>>,
+}
+impl<G> Guard for DumpGuard<G> where G: Guard<Error = TokenReaderError> {
+    type Error = TokenReaderError;
+    fn done(self) -> std::result::Result<(), Self::Error> {
+        self.frames.borrow_mut()
+            .pop()
+            .expect("Closing a node that was never opened");
+        self.guard.done()
+    }
+}
+
+impl<'a> TokenReader for DumpReader<'a> {
+    type Error = TokenReaderError;
+    type TaggedGuard = DumpGuard<<TreeTokenReader as TokenReader>::TaggedGuard>;
+    type UntaggedGuard = DumpGuard<<TreeTokenReader as TokenReader>::UntaggedGuard>;
+    type ListGuard = DumpGuard<<TreeTokenReader as TokenReader>::ListGuard>;
+
+    fn poison(&mut self) {
+        self.reader.poison()
+    }
+
+    fn string(&mut self) -> std::result::Result<Option<String>, Self::Error> {
+        self.trace(|reader| reader.string(), |result| format!("{:?}", result))
+    }
+
+    fn categorized_string(&mut self, category: StringCategory) -> std::result::Result<Option<String>, Self::Error> {
+        self.trace(|reader| reader.categorized_string(category), |result| format!("{:?}", result))
+    }
+
+    fn string_enum(&mut self, strings: &[&str]) -> std::result::Result<usize, Self::Error> {
+        self.trace(|reader| reader.string_enum(strings), |result| format!("{:?}", strings[*result]))
+    }
+
+    fn float(&mut self) -> std::result::Result<Option<f64>, Self::Error> {
+        self.trace(|reader| reader.float(), |result| format!("{:?}", result))
+    }
+
+    fn bool(&mut self) -> std::result::Result<Option<bool>, Self::Error> {
+        self.trace(|reader| reader.bool(), |result| format!("{:?}", result))
+    }
+
+    fn offset(&mut self) -> std::result::Result<u32, Self::Error> {
+        self.trace(|reader| reader.offset(), |result| format!("offset {}", result))
+    }
+
+    fn list(&mut self) -> std::result::Result<(u32, Self::ListGuard), Self::Error> {
+        let (len, guard) = self.trace(|reader| reader.list(),
+            |&(len, _)| format!("list ({} items)", len))?;
+        self.frames.borrow_mut()
+            .push(Frame::List(0));
+        Ok((len, DumpGuard {
+            guard,
+            frames: self.frames.clone()
+        }))
+    }
+
+    fn tagged_tuple(&mut self) -> std::result::Result<(String, Option<Rc<Box<[String]>>>, Self::TaggedGuard), Self::Error> {
+        let (kind, fields, guard) = self.trace(|reader| reader.tagged_tuple(),
+            |&(ref kind, _, _)| kind.clone())?;
+
+        // Fields are read in the order specified by the stream, if any, otherwise by the grammar.
+        let names = match fields {
+            Some(ref fields) => fields.iter()
+                .cloned()
+                .collect(),
+            None => self.spec.get_node_name(&kind)
+                .and_then(|name| self.spec.get_interface_by_name(name))
+                .map(|interface| interface.contents()
+                    .fields()
+                    .iter()
+                    .map(|field| field.name().to_str().to_string())
+                    .collect())
+                .unwrap_or_else(|| vec![])
+        };
+        self.frames.borrow_mut()
+            .push(Frame::Tuple(names, 0));
+        Ok((kind, fields, DumpGuard {
+            guard,
+            frames: self.frames.clone()
+        }))
+    }
+
+    fn untagged_tuple(&mut self) -> std::result::Result<Self::UntaggedGuard, Self::Error> {
+        let guard = self.reader.untagged_tuple()?;
+        self.frames.borrow_mut()
+            .push(Frame::Tuple(vec![], 0));
+        Ok(DumpGuard {
+            guard,
+            frames: self.frames.clone()
+        })
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS dump")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Print the structure of a BinJS (multipart) file: header, sections, tables and tokens.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinJS source file."),
+            Arg::with_name("no-tree")
+                .long("no-tree")
+                .takes_value(false)
+                .help("If specified, do not print the tokens of the [TREE] section."),
+        ])
+    .get_matches();
+
+    let source_path = matches.value_of("INPUT")
+        .expect("Expected input file");
+
+    let file = File::open(source_path)
+        .expect("Could not open source");
+    let reader = TreeTokenReader::new(BufReader::new(file))
+        .expect("Could not read container");
+
+    // Header
+    println!("Header: BINJS, container version {}, features {:#x} (supported: {:#x})",
+        reader.version(),
+        reader.features().0,
+        TreeTokenReader::supported_features().0);
+
+    // Sections
+    for section in reader.sections() {
+        println!("Section {header} at {offset:08x}: {prefix}; {compressed} bytes ({decompressed} bytes decompressed)",
+            header = section.header,
+            offset = section.offset,
+            prefix = section.compression.code(),
+            compressed = section.compressed_bytes,
+            decompressed = section.decompressed_bytes);
+    }
+
+    reader.with_tables(|grammar_table, strings_tables| {
+        // Grammar table, with the fields of each node if the file lists them,
+        // with feature `FIELD_ORDER`.
+        println!("[GRAMMAR] {} entries", grammar_table.len());
+        for (index, description) in grammar_table.iter() {
+            match description.fields() {
+                Some(fields) => println!("{:>6} {} {{ {} }}", index, description.kind(), fields.join(", ")),
+                None => println!("{:>6} {}", index, description.kind()),
+            }
+        }
+
+        // Strings table(s)
+        for (i, strings_table) in strings_tables.iter().enumerate() {
+            if strings_tables.len() == 1 {
+                println!("[STRINGS] {} entries", strings_table.len());
+            } else {
+                println!("[STRINGS] {:?}, {} entries", StringCategory::ALL[i], strings_table.len());
+            }
+            for (index, string) in strings_table.iter() {
+                match *string {
+                    Some(ref string) => println!("{:>6} {:?}", index, string),
+                    None => println!("{:>6} null", index),
+                }
+            }
+        }
+    });
+
+    if matches.is_present("no-tree") {
+        return;
+    }
+
+    // Tree
+    println!("[TREE]");
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+    let dump = DumpReader {
+        reader,
+        spec: &spec,
+        frames: Rc::new(RefCell::new(vec![])),
+    };
+    let mut decoder = Decoder::new(&spec, dump);
+    decoder.decode()
+        .expect("Could not decode tree");
+}
@@ -20,6 +20,8 @@ struct Options<'a> {
     multipart_stats: RefCell<binjs::io::multipart::Statistics>,
     simple_stats: RefCell<binjs::io::simple::Statistics>,
     compression: Option<binjs::io::multipart::WriteOptions>,
+    /// If `true` and `compression` is `None`, use the text format rather than the simple format.
+    text: bool,
     dest_dir: Option<PathBuf>
 }
 
@@ -93,6 +95,15 @@ fn handle_path<'a>(options: &Options<'a>,
     println!("Encoding.");
     let data: Box<AsRef<[u8]>> = {
         match options.compression {
+            None if options.text => {
+                let writer = binjs::io::text::TreeTokenWriter::new();
+                let mut serializer = binjs::specialized::es6::io::Serializer::new(writer);
+                serializer.serialize(&ast)
+                    .expect("Could not encode AST");
+                let (data, _) = serializer.done()
+                    .expect("Could not finalize AST encoding");
+                Box::new(data)
+            }
             None => {
                 let writer = binjs::io::simple::TreeTokenWriter::new();
                 let mut serializer = binjs::specialized::es6::io::Serializer::new(writer);
@@ -163,7 +174,7 @@ fn main() {
             Arg::with_name("format")
                 .long("format")
                 .takes_value(true)
-                .possible_values(&["simple", "multipart"])
+                .possible_values(&["simple", "multipart", "text"])
                 .help("Format to use for writing to OUTPUT. Defaults to `multipart`."),
             Arg::with_name("strings")
                 .long("strings")
@@ -185,6 +196,39 @@ fn main() {
                 .takes_value(true)
                 .possible_values(&["identity", "gzip", "deflate", "br", "lzw"])
                 .help("Compression format for the tree. Defaults to identity."),
+            Arg::with_name("numbers")
+                .long("numbers")
+                .takes_value(true)
+                .possible_values(&["float64", "compact"])
+                .help("Representation of numbers in the tree. Defaults to float64."),
+            Arg::with_name("bitmaps")
+                .long("bitmaps")
+                .help("Pack booleans and the presence of optional fields into bitmaps."),
+            Arg::with_name("columns")
+                .long("columns")
+                .help("Split the tree into one section per type of token, compressed like the tree."),
+            Arg::with_name("string-tables")
+                .long("string-tables")
+                .help("Split the strings table into one table per category of string, compressed like the strings table."),
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Replace repeated subtrees by back-references."),
+            Arg::with_name("enum-indices")
+                .long("enum-indices")
+                .help("Write values of string enums as their index in the enum rather than as strings."),
+            Arg::with_name("fields-last")
+                .long("fields-last")
+                .takes_value(true)
+                .use_delimiter(true)
+                .help("Comma-separated list of fields to write after the other fields of their node, e.g. `scope,parameterScope,bodyScope`."),
+            Arg::with_name("integers")
+                .long("integers")
+                .takes_value(true)
+                .possible_values(&["varnum", "leb128", "prefix-varint"])
+                .help("Representation of integers in the tree. Defaults to varnum."),
+            Arg::with_name("scope-names")
+                .long("scope-names")
+                .help("Write references to local variables relative to the scope annotations rather than as strings."),
             Arg::with_name("statistics")
                 .long("show-stats")
                 .help("Show statistics."),
@@ -213,20 +257,62 @@ fn main() {
                    || matches.value_of("strings").is_some()
                    || matches.value_of("grammar").is_some()
                    || matches.value_of("tree").is_some()
+                   || matches.value_of("numbers").is_some()
+                   || matches.is_present("bitmaps")
+                   || matches.is_present("columns")
+                   || matches.is_present("string-tables")
+                   || matches.is_present("dedup")
+                   || matches.is_present("enum-indices")
+                   || matches.values_of("fields-last").is_some()
+                   || matches.value_of("integers").is_some()
+                   || matches.is_present("scope-names")
                  => {
-                    println!("Error: Cannot specify `strings`, `grammar` or `tree` with this format.\n{}", matches.usage());
+                    println!("Error: Cannot specify `strings`, `grammar`, `tree`, `numbers`, `bitmaps`, `columns`, `string-tables`, `dedup`, `enum-indices`, `fields-last`, `integers` or `scope-names` with this format.\n{}", matches.usage());
                     std::process::exit(-1);
                  }
                 _ => false
             };
+        let numbers = match matches.value_of("numbers") {
+            Some("compact") => binjs::io::multipart::NumberEncoding::Compact,
+            _ => binjs::io::multipart::NumberEncoding::Float64,
+        };
+        let integers = matches.value_of("integers")
+            .map(|name| binjs::io::multipart::IntegerEncoding::parse(name)
+                .expect("Could not parse integer encoding"))
+            .unwrap_or_default();
+        let bitmaps = matches.is_present("bitmaps");
+        let dedup = matches.is_present("dedup");
+        let enum_indices = matches.is_present("enum-indices");
+        let scope_names = matches.is_present("scope-names");
+        let field_order = matches.values_of("fields-last")
+            .map(|fields| binjs::io::multipart::FieldOrder::last(&fields.collect::<Vec<_>>()));
+        let columns = |tree: &binjs::io::bytes::compress::Compression| if matches.is_present("columns") {
+            Some(binjs::io::multipart::Columns::uniform(tree.clone()))
+        } else {
+            None
+        };
+        let string_tables = |strings: &binjs::io::bytes::compress::Compression| if matches.is_present("string-tables") {
+            Some(binjs::io::multipart::StringTables::uniform(strings.clone()))
+        } else {
+            None
+        };
         if is_compressed {
             if let Some(ref compression) = matches.value_of("sections") {
                 let compression = binjs::io::bytes::compress::Compression::parse(Some(compression))
                     .expect("Could not parse sections compression format");
                 Some(binjs::io::multipart::WriteOptions {
+                    string_tables: string_tables(&compression),
                     strings_table: compression.clone(),
                     grammar_table: compression.clone(),
+                    columns: columns(&compression),
                     tree: compression,
+                    numbers,
+                    bitmaps,
+                    dedup,
+                    enum_indices,
+                    field_order: field_order.clone(),
+                    integers,
+                    scope_names,
                 })
             } else {
                 let strings = binjs::io::bytes::compress::Compression::parse(matches.value_of("strings"))
@@ -236,16 +322,26 @@ fn main() {
                 let tree = binjs::io::bytes::compress::Compression::parse(matches.value_of("tree"))
                     .expect("Could not parse tree compression format");
                 Some(binjs::io::multipart::WriteOptions {
+                    string_tables: string_tables(&strings),
                     strings_table: strings,
                     grammar_table: grammar,
-                    tree
+                    columns: columns(&tree),
+                    tree,
+                    numbers,
+                    bitmaps,
+                    dedup,
+                    enum_indices,
+                    field_order: field_order.clone(),
+                    integers,
+                    scope_names,
                 })
             }
         } else {
-            println!("Format: simple");
+            println!("Format: {}", matches.value_of("format").unwrap());
             None
         }
     };
+    let text = matches.value_of("format") == Some("text");
     let show_stats = matches.is_present("statistics");
 
     // Setup.
@@ -259,6 +355,7 @@ fn main() {
         multipart_stats: RefCell::new(multipart_stats),
         simple_stats: RefCell::new(simple_stats),
         compression,
+        text,
         dest_dir,
     };
     for source_path in sources {
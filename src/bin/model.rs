@@ -0,0 +1,90 @@
+//! Learn a probability model of ASTs from a corpus of JS files, for use
+//! with `ModelPicker` (see `examples/generate.rs`).
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::pick::Model;
+use binjs::source::{ Shift, SourceParser };
+
+use std::fs::*;
+use std::path::Path;
+
+use clap::*;
+
+fn handle_path(parser: &Shift, model: &mut Model, source_path: &Path) {
+    let is_dir = std::fs::metadata(source_path)
+        .unwrap()
+        .is_dir();
+    if is_dir {
+        for entry in std::fs::read_dir(source_path)
+            .expect("Could not open directory")
+            .map(|dir| dir.unwrap())
+        {
+            handle_path(parser, model, entry.path().as_path());
+        }
+        return;
+    }
+    if let Some(Some("js")) = source_path.extension().map(std::ffi::OsStr::to_str) {
+        // Proceed
+    } else {
+        println!("Skipping {:?}", source_path);
+        return;
+    }
+
+    println!("Learning from {:?}", source_path);
+    let mut ast = parser.parse_file(source_path)
+        .expect("Could not parse source");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate(&mut ast);
+    model.learn(&ast);
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS model learner")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Learn the distribution of nodes, list lengths and strings from a corpus of JS files, to generate realistic random ASTs.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .multiple(true)
+                .help("Input files or directories. Directories are visited recursively."),
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .required(true)
+                .takes_value(true)
+                .help("Output file for the model."),
+            Arg::with_name("max-strings")
+                .long("max-strings")
+                .takes_value(true)
+                .help("Maximal number of distinct strings kept for each field. Default: 100."),
+        ])
+        .get_matches();
+
+    let max_strings : usize = match matches.value_of("max-strings") {
+        None => 100,
+        Some(max) => max.parse()
+            .expect("Invalid max-strings")
+    };
+
+    let parser = Shift::new();
+    let mut model = Model::new();
+    for source_path in matches.values_of("INPUT")
+        .expect("Missing argument `INPUT`")
+    {
+        handle_path(&parser, &mut model, Path::new(source_path));
+    }
+    model.truncate_strings(max_strings);
+
+    let dest_path = matches.value_of("out")
+        .expect("Missing argument `out`");
+    let mut dest = File::create(dest_path)
+        .expect("Could not create model file");
+    model.save(&mut dest)
+        .expect("Could not write model file");
+    println!("Model written to {}", dest_path);
+}
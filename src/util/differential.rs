@@ -0,0 +1,280 @@
+//! Differential testing of the encoders and decoders.
+//!
+//! For each format, check that the generic (JSON-based) `Encoder` and the typed
+//! `Serializer` produce the same bytes, and that the generic `Decoder` and the
+//! typed `Deserializer` decode these bytes to the same AST.
+
+use binjs_es6::ast::Script;
+use binjs_es6::io::{ Deserializer, Serializer };
+use binjs_generic::io::decode::Decoder;
+use binjs_generic::io::encode::{ Encode, Encoder };
+use binjs_generic::syntax::{ Comparator, Mismatch, Tolerances };
+use binjs_io::{ Deserialization, TokenReader, TokenReaderError, TokenSerializer, TokenWriter };
+use binjs_io::bytes::compress::Compression;
+use binjs_io::multipart;
+use binjs_io::simple;
+use binjs_io::text;
+use binjs_io::trace::{ Divergence, TracingWriter };
+use binjs_meta::spec::Spec;
+use binjs_shared::{ FromJSON, ToJSON };
+
+use json::JsonValue as JSON;
+
+use std;
+use std::io::Cursor;
+
+/// A format checked by the `Harness`.
+#[derive(Clone, Debug)]
+pub enum Format {
+    Simple,
+    Text,
+    Multipart(multipart::WriteOptions),
+}
+impl Format {
+    /// The simple and text formats, and the multipart format both
+    /// uncompressed and compressed, and with each optional feature, see
+    /// `multipart::WriteOptions::variants`.
+    pub fn all() -> Vec<Format> {
+        let multipart = |compression: Compression| Format::Multipart(multipart::WriteOptions {
+            grammar_table: compression.clone(),
+            strings_table: compression.clone(),
+            tree: compression,
+            .. multipart::WriteOptions::default()
+        });
+        let mut formats = vec![
+            Format::Simple,
+            Format::Text,
+            multipart(Compression::Identity),
+            multipart(Compression::Brotli),
+        ];
+        formats.extend(multipart::WriteOptions::default()
+            .variants()
+            .into_iter()
+            .map(|(_, options)| Format::Multipart(options)));
+        formats
+    }
+}
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Format::Simple => write!(f, "simple"),
+            Format::Text => write!(f, "text"),
+            Format::Multipart(ref options) => write!(f, "multipart ({:?})", options),
+        }
+    }
+}
+
+/// A disagreement found by the `Harness`.
+#[derive(Debug)]
+pub enum Disagreement {
+    /// An encoder or a decoder failed. `stage` is one of "import",
+    /// "generic encoder", "typed encoder", "generic decoder", "typed decoder"
+    /// or "comparison".
+    Failure {
+        format: Option<String>,
+        stage: &'static str,
+        error: String,
+    },
+
+    /// The generic and typed encoders produced different bytes.
+    Bytes {
+        format: String,
+
+        /// The first byte that differs.
+        offset: usize,
+
+        /// The first token that differs, with `expected` written by the generic
+        /// encoder and `found` written by the typed encoder. `None` if both wrote
+        /// the same tokens, in which case the writer is not deterministic.
+        divergence: Option<Divergence>,
+    },
+
+    /// The generic and typed decoders produced different ASTs, with `left`
+    /// decoded by the generic decoder and `right` by the typed decoder.
+    Ast {
+        format: String,
+        mismatches: Vec<Mismatch>,
+    },
+}
+impl std::fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Disagreement::Failure { format: None, stage, ref error } =>
+                write!(f, "{} failed: {}", stage, error),
+            Disagreement::Failure { format: Some(ref format), stage, ref error } =>
+                write!(f, "[{}] {} failed: {}", format, stage, error),
+            Disagreement::Bytes { ref format, offset, divergence: Some(ref divergence) } =>
+                write!(f, "[{}] The encoders disagree at byte {}. {}", format, offset, divergence),
+            Disagreement::Bytes { ref format, offset, divergence: None } =>
+                write!(f, "[{}] The encoders disagree at byte {}, with the same tokens", format, offset),
+            Disagreement::Ast { ref format, ref mismatches } => {
+                write!(f, "[{}] The decoders disagree:", format)?;
+                for mismatch in mismatches {
+                    write!(f, "\n  {}", mismatch)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compare the generic and typed encoders and decoders.
+pub struct Harness<'a> {
+    spec: &'a Spec,
+    formats: Vec<Format>,
+}
+impl<'a> Harness<'a> {
+    /// A harness checking `Format::all()`.
+    ///
+    /// `spec` must be the specification of the es6 library.
+    pub fn new(spec: &'a Spec) -> Self {
+        Self::with_formats(spec, Format::all())
+    }
+
+    pub fn with_formats(spec: &'a Spec, formats: Vec<Format>) -> Self {
+        Harness {
+            spec,
+            formats,
+        }
+    }
+
+    /// Check an AST in every format, returning all the disagreements.
+    ///
+    /// The AST is expected to be annotated.
+    pub fn check(&self, ast: &JSON) -> Vec<Disagreement> {
+        let mut disagreements = vec![];
+        let script = match Script::import(ast) {
+            Ok(script) => script,
+            Err(err) => {
+                disagreements.push(Disagreement::Failure {
+                    format: None,
+                    stage: "import",
+                    error: format!("{:?}", err)
+                });
+                return disagreements
+            }
+        };
+        for format in &self.formats {
+            match *format {
+                Format::Simple =>
+                    self.check_format(format, ast, &script,
+                        simple::TreeTokenWriter::new,
+                        |data| Ok(simple::TreeTokenReader::new(Cursor::new(data))),
+                        &mut disagreements),
+                Format::Text =>
+                    self.check_format(format, ast, &script,
+                        text::TreeTokenWriter::new,
+                        |data| text::TreeTokenReader::new(Cursor::new(data)),
+                        &mut disagreements),
+                Format::Multipart(ref options) =>
+                    self.check_format(format, ast, &script,
+                        || multipart::TreeTokenWriter::new(options.clone()),
+                        |data| multipart::TreeTokenReader::new(Cursor::new(data)),
+                        &mut disagreements),
+            }
+        }
+        disagreements
+    }
+
+    fn check_format<W, R, FW, FR>(&self, format: &Format, ast: &JSON, script: &Script, new_writer: FW, new_reader: FR, disagreements: &mut Vec<Disagreement>)
+        where W: TokenWriter,
+              W::Tree: Clone,
+              R: TokenReader,
+              FW: Fn() -> W,
+              FR: Fn(Vec<u8>) -> Result<R, TokenReaderError>
+    {
+        let format = format.to_string();
+        let failure = |stage, error| Disagreement::Failure {
+            format: Some(format.clone()),
+            stage,
+            error,
+        };
+
+        // Encode with both encoders, recording the tokens.
+        let writer = TracingWriter::new(new_writer());
+        let generic_recorder = writer.recorder();
+        let encoder = Encoder::new(self.spec, writer);
+        let generic = encoder.encode(ast)
+            .map(|_| ())
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| encoder.done()
+                .map(|(data, _)| data.as_ref().to_vec())
+                .map_err(|err| format!("{:?}", err)));
+
+        let writer = TracingWriter::new(new_writer());
+        let typed_recorder = writer.recorder();
+        let mut serializer = Serializer::new(writer);
+        let typed = serializer.serialize(script)
+            .map(|_| ())
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|_| serializer.done()
+                .map(|(data, _)| data.as_ref().to_vec())
+                .map_err(|err| format!("{:?}", err)));
+
+        let data = match (generic, typed) {
+            (Ok(generic), Ok(typed)) => {
+                if generic != typed {
+                    let offset = generic.iter()
+                        .zip(typed.iter())
+                        .position(|(a, b)| a != b)
+                        .unwrap_or_else(|| std::cmp::min(generic.len(), typed.len()));
+                    disagreements.push(Disagreement::Bytes {
+                        format: format.clone(),
+                        offset,
+                        divergence: generic_recorder.trace().divergence(&typed_recorder.trace())
+                    });
+                }
+                typed
+            }
+            (Err(error), Ok(typed)) => {
+                disagreements.push(failure("generic encoder", error));
+                typed
+            }
+            (Ok(generic), Err(error)) => {
+                disagreements.push(failure("typed encoder", error));
+                generic
+            }
+            (Err(generic_error), Err(typed_error)) => {
+                disagreements.push(failure("generic encoder", generic_error));
+                disagreements.push(failure("typed encoder", typed_error));
+                return
+            }
+        };
+
+        // Decode the same bytes with both decoders.
+        let generic = new_reader(data.clone())
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|reader| Decoder::new(self.spec, reader)
+                .decode()
+                .map_err(|err| format!("{:?}", err)));
+        let typed = new_reader(data)
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|reader| {
+                let mut deserializer = Deserializer::new(reader);
+                let result : Result<Script, _> = deserializer.deserialize();
+                result.map(|script| script.export())
+                    .map_err(|err| format!("{:?}", err))
+            });
+
+        match (generic, typed) {
+            (Ok(generic), Ok(typed)) => {
+                match Comparator::mismatches(self.spec, &generic, &typed, &Tolerances::default()) {
+                    Ok(ref mismatches) if mismatches.len() == 0 => {},
+                    Ok(mismatches) => disagreements.push(Disagreement::Ast {
+                        format: format.clone(),
+                        mismatches
+                    }),
+                    Err(err) => disagreements.push(failure("comparison", format!("{:?}", err)))
+                }
+            }
+            (generic, typed) => {
+                if let Err(error) = generic {
+                    disagreements.push(failure("generic decoder", error));
+                }
+                if let Err(error) = typed {
+                    disagreements.push(failure("typed decoder", error));
+                }
+            }
+        }
+    }
+}
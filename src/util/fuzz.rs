@@ -0,0 +1,179 @@
+//! Harnesses for fuzzing the readers.
+//!
+//! Each harness accepts arbitrary bytes and must neither panic nor hang,
+//! whatever the input. Errors, on the other hand, are expected.
+//!
+//! The harnesses are used both by the `cargo fuzz` targets of `fuzz/`
+//! and by `tests/test_fuzz.rs`, which replays the inputs that used
+//! to crash.
+
+use binjs_es6;
+use binjs_io::{ Deserialization, Guard, TokenReader };
+use binjs_io::bytes::compress::{ read_exactly, Compression };
+use binjs_io::bytes::varnum::{ IntegerCodec, IntegerEncoding, ReadVarNum };
+use binjs_io::multipart;
+use binjs_io::simple;
+
+use std;
+use std::io::{ Cursor, Read };
+
+/// The limits used when fuzzing the multipart reader.
+///
+/// They are much lower than the defaults, so that short inputs cannot
+/// make the harness allocate gigabytes or overflow the stack.
+pub fn read_limits() -> multipart::ReadLimits {
+    multipart::ReadLimits {
+        max_section_bytes: 16 * 1024 * 1024,
+        max_decompressed_bytes: 64 * 1024 * 1024,
+        max_string_bytes: 1024 * 1024,
+        max_list_length: 1024 * 1024,
+        max_depth: 256,
+        max_back_references: 64 * 1024,
+    }
+}
+
+/// Split an input between a list of operations and the file to read.
+///
+/// The first byte is the number of operations, followed by the
+/// operations themselves, then by the file.
+pub fn split_ops(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.split_first() {
+        None => (&[], &[]),
+        Some((&len, rest)) => {
+            let len = std::cmp::min(len as usize, rest.len());
+            rest.split_at(len)
+        }
+    }
+}
+
+/// A node whose guard has not been consumed yet.
+enum Open<R> where R: TokenReader {
+    List(R::ListGuard),
+    Tagged(R::TaggedGuard),
+    Untagged(R::UntaggedGuard),
+}
+impl<R> Open<R> where R: TokenReader {
+    fn done(self) -> Result<(), R::Error> {
+        match self {
+            Open::List(guard) => guard.done(),
+            Open::Tagged(guard) => guard.done(),
+            Open::Untagged(guard) => guard.done(),
+        }
+    }
+}
+
+/// Read tokens from `reader`, regardless of any grammar.
+///
+/// Each byte of `ops` determines the next call to `reader`: reading a
+/// primitive value, entering a list or a tuple, or closing the innermost
+/// list or tuple. Anything still open once `ops` is exhausted is closed.
+pub fn read_tokens<R>(mut reader: R, ops: &[u8]) -> Result<(), R::Error> where R: TokenReader {
+    let mut open : Vec<Open<R>> = vec![];
+    for op in ops {
+        match op % 8 {
+            0 => { reader.bool()?; }
+            1 => { reader.float()?; }
+            2 => { reader.string()?; }
+            3 => { reader.offset()?; }
+            4 => {
+                let (_, guard) = reader.list()?;
+                open.push(Open::List(guard));
+            }
+            5 => {
+                let (_, _, guard) = reader.tagged_tuple()?;
+                open.push(Open::Tagged(guard));
+            }
+            6 => {
+                let guard = reader.untagged_tuple()?;
+                open.push(Open::Untagged(guard));
+            }
+            _ => {
+                if let Some(node) = open.pop() {
+                    node.done()?;
+                }
+            }
+        }
+    }
+    while let Some(node) = open.pop() {
+        node.done()?;
+    }
+    Ok(())
+}
+
+/// Fuzz `multipart::TreeTokenReader`: parse the container, then read tokens.
+///
+/// `data` is split with `split_ops`.
+pub fn multipart_reader(data: &[u8]) {
+    let (ops, file) = split_ops(data);
+    if let Ok(reader) = multipart::TreeTokenReader::with_limits(Cursor::new(file), read_limits()) {
+        let _ = read_tokens(reader, ops);
+    }
+}
+
+/// Fuzz `simple::TreeTokenReader` by reading tokens.
+///
+/// `data` is split with `split_ops`.
+pub fn simple_reader(data: &[u8]) {
+    let (ops, file) = split_ops(data);
+    let reader = simple::TreeTokenReader::new(Cursor::new(file));
+    let _ = read_tokens(reader, ops);
+}
+
+/// Fuzz `ReadVarNum`: read varnums until the first error.
+///
+/// Then, for each `IntegerEncoding`, read integers until the first error,
+/// checking that each integer is read from its only encoding.
+pub fn varnum(data: &[u8]) {
+    let mut reader = Cursor::new(data);
+    let mut value = 0;
+    while reader.read_varnum(&mut value).is_ok() {
+        // Each successful read consumes at least one byte, so this terminates.
+    }
+
+    for encoding in &IntegerEncoding::ALL {
+        let mut reader = Cursor::new(data);
+        let mut start = 0;
+        while let Ok(value) = encoding.read_u64(&mut reader) {
+            let end = reader.position() as usize;
+            let mut encoded = vec![];
+            encoding.write_u64(&mut encoded, value)
+                .expect("Could not write integer");
+            assert_eq!(&data[start..end], &encoded[..], "Integer {} was not read from its encoding with {:?}", value, encoding);
+            start = end;
+        }
+    }
+}
+
+/// Read a compressed block and decompress it, within the limits of `read_limits`.
+fn decompress_block<R: Read>(reader: &mut R) -> Result<Vec<u8>, std::io::Error> {
+    let compression = Compression::read_prefix(reader)?;
+    let mut byte_len = 0;
+    reader.read_varnum(&mut byte_len)?;
+    let compressed = read_exactly(reader, byte_len as usize)?;
+    compression.decompress_bytes(&compressed, read_limits().max_decompressed_bytes)
+}
+
+/// Fuzz `Compression::decompress_bytes`: decompress blocks until the first error.
+pub fn decompress(data: &[u8]) {
+    let mut reader = Cursor::new(data);
+    while decompress_block(&mut reader).is_ok() {
+        // Each successful read consumes at least the compression prefix, so this terminates.
+    }
+}
+
+/// Fuzz the typed `Deserializer` of ES6, reading a `Script` from a multipart container.
+pub fn es6_deserializer(data: &[u8]) {
+    if let Ok(reader) = multipart::TreeTokenReader::with_limits(Cursor::new(data), read_limits()) {
+        let mut deserializer = binjs_es6::io::Deserializer::new(reader);
+        let _ : Result<binjs_es6::ast::Script, _> = deserializer.deserialize();
+    }
+}
+
+/// All the harnesses, by name of fuzz target.
+pub const TARGETS: [(&'static str, fn(&[u8])); 5] = [
+    ("multipart_reader", multipart_reader),
+    ("simple_reader", simple_reader),
+    ("varnum", varnum),
+    ("decompress", decompress),
+    ("es6_deserializer", es6_deserializer),
+];
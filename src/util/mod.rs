@@ -1,3 +1,12 @@
+/// Harnesses for fuzzing the readers.
+pub mod fuzz;
+
+/// Differential testing of the generic and typed encoders/decoders.
+pub mod differential;
+
+/// Round-trip testing of random ASTs, with shrinking.
+pub mod roundtrip;
+
 use binjs_generic::syntax::ASTError;
 
 use rand;
@@ -0,0 +1,364 @@
+//! Property-based round-trip testing.
+//!
+//! Generate random ASTs with the `Picker`, from reproducible seeds, and check
+//! that they survive an encoding/decoding round trip through each of a list
+//! of `WriteOptions`. Failing ASTs are shrunk to a minimal failing case.
+
+use binjs_generic::io::decode::Decoder;
+use binjs_generic::io::encode::{ Encode, Encoder };
+use binjs_generic::pick::{ Pick, Picker };
+use binjs_generic::syntax::{ Comparator, Tolerances };
+use binjs_io::bytes::compress::Compression;
+use binjs_io::multipart::{ TreeTokenReader, TreeTokenWriter, WriteOptions };
+use binjs_meta::spec::{ Interface, NamedType, Spec, Type, TypeSpec };
+
+use json::JsonValue as JSON;
+use rand::{ Rng, SeedableRng, XorShiftRng };
+
+use std;
+use std::io::Cursor;
+
+/// Default depth hint passed to the `Picker`.
+pub const DEFAULT_DEPTH: isize = 4;
+
+/// Default maximal number of successful shrinking steps.
+pub const DEFAULT_MAX_SHRINK_STEPS: usize = 1000;
+
+/// The rng used to generate and shrink the sample with a given seed.
+pub fn rng(seed: u32) -> XorShiftRng {
+    // `XorShiftRng` rejects all-zero seeds, so only the last word varies.
+    XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, seed])
+}
+
+/// The `WriteOptions` checked by default: all combinations of compression
+/// formats for the sections of the multipart format, then each pair of other
+/// features, see `WriteOptions::variants`, as features interact with each other
+/// but not with the compression of other sections.
+pub fn write_options() -> Vec<WriteOptions> {
+    use self::Compression::*;
+    let compressions = [Identity, Gzip, Deflate, Brotli, /*Lzw doesn't work yet*/];
+    let mut vec = vec![];
+    for grammar_table in &compressions {
+        for strings_table in &compressions {
+            for tree in &compressions {
+                vec.push(WriteOptions {
+                    grammar_table: grammar_table.clone(),
+                    strings_table: strings_table.clone(),
+                    tree: tree.clone(),
+                    .. WriteOptions::default()
+                });
+            }
+        }
+    }
+    let variants = WriteOptions::default()
+        .variants();
+    for (i, &(_, ref first)) in variants.iter().enumerate() {
+        vec.push(first.clone());
+        for (_, second) in first.variants().into_iter().skip(i + 1) {
+            // Skip variants of the same feature, e.g. two representations of integers.
+            if second.features() != first.features() {
+                vec.push(second);
+            }
+        }
+    }
+    vec
+}
+
+/// The step of a round trip that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Encode,
+    Decode,
+    /// The decoded AST differs from the original.
+    Compare,
+}
+
+#[derive(Debug)]
+pub struct RoundtripError {
+    pub stage: Stage,
+    pub message: String,
+}
+impl std::fmt::Display for RoundtripError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{:?} failed: {}", self.stage, self.message)
+    }
+}
+
+/// Encode `ast` with the multipart format, decode it and compare the result
+/// with `ast`.
+pub fn roundtrip(spec: &Spec, ast: &JSON, options: &WriteOptions) -> Result<(), RoundtripError> {
+    let error = |stage, message| RoundtripError {
+        stage,
+        message
+    };
+    let encoder = Encoder::new(spec, TreeTokenWriter::new(options.clone()));
+    encoder.encode(ast)
+        .map_err(|err| error(Stage::Encode, format!("{:?}", err)))?;
+    let (data, _) = encoder.done()
+        .map_err(|err| error(Stage::Encode, format!("{:?}", err)))?;
+
+    let reader = TreeTokenReader::new(Cursor::new(data.as_ref().to_vec()))
+        .map_err(|err| error(Stage::Decode, format!("{:?}", err)))?;
+    let decoded = Decoder::new(spec, reader)
+        .decode()
+        .map_err(|err| error(Stage::Decode, format!("{:?}", err)))?;
+
+    let mismatches = Comparator::mismatches(spec, ast, &decoded, &Tolerances::default())
+        .map_err(|err| error(Stage::Compare, format!("{:?}", err)))?;
+    if let Some(mismatch) = mismatches.first() {
+        return Err(error(Stage::Compare, format!("{} ({} mismatches)", mismatch, mismatches.len())))
+    }
+    Ok(())
+}
+
+/// The number of JSON values in an AST, used to measure progress while shrinking.
+pub fn size(value: &JSON) -> usize {
+    match *value {
+        JSON::Array(ref items) => 1 + items.iter().map(size).sum::<usize>(),
+        JSON::Object(ref object) => 1 + object.iter().map(|(_, value)| size(value)).sum::<usize>(),
+        _ => 1
+    }
+}
+
+/// A minimal failing case.
+#[derive(Debug)]
+pub struct Failure {
+    /// The seed of the sample, to be passed to `Runner::check_seed` to reproduce the failure.
+    pub seed: u32,
+    pub options: WriteOptions,
+
+    /// The error caused by the shrunk AST.
+    pub error: RoundtripError,
+
+    /// The shrunk AST.
+    pub ast: JSON,
+
+    /// The size of the original AST, as measured by `size`.
+    pub original_size: usize,
+}
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "Round trip failed with seed {} and {:?}: {}\nMinimal AST ({} values, shrunk from {}):\n{}",
+            self.seed,
+            self.options,
+            self.error,
+            size(&self.ast),
+            self.original_size,
+            self.ast.pretty(2))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+/// A way to make an AST smaller.
+#[derive(Debug)]
+enum Site {
+    /// Remove item `index` from the list at `path`.
+    Remove(Vec<Step>, usize),
+
+    /// Replace the value at `path` by a smaller pick of type `Type`.
+    Replace(Vec<Step>, Type),
+}
+
+/// Generate random ASTs and round-trip them.
+pub struct Runner<'a> {
+    spec: &'a Spec,
+    options: Vec<WriteOptions>,
+    depth: isize,
+    max_shrink_steps: usize,
+}
+impl<'a> Runner<'a> {
+    /// A runner checking `write_options()`.
+    pub fn new(spec: &'a Spec) -> Self {
+        Runner {
+            spec,
+            options: write_options(),
+            depth: DEFAULT_DEPTH,
+            max_shrink_steps: DEFAULT_MAX_SHRINK_STEPS,
+        }
+    }
+
+    pub fn with_options(self, options: Vec<WriteOptions>) -> Self {
+        Runner {
+            options,
+            ..self
+        }
+    }
+
+    pub fn with_depth(self, depth: isize) -> Self {
+        Runner {
+            depth,
+            ..self
+        }
+    }
+
+    pub fn with_max_shrink_steps(self, max_shrink_steps: usize) -> Self {
+        Runner {
+            max_shrink_steps,
+            ..self
+        }
+    }
+
+    /// The AST generated for `seed`.
+    pub fn generate(&self, seed: u32) -> JSON {
+        Picker.random(self.spec, &mut rng(seed), self.depth)
+    }
+
+    /// Check the samples with seeds `first_seed..first_seed + samples`,
+    /// stopping at the first failure.
+    pub fn run(&self, first_seed: u32, samples: u32) -> Option<Failure> {
+        for seed in first_seed..first_seed.saturating_add(samples) {
+            debug!(target: "roundtrip", "Checking sample {}", seed);
+            if let Some(failure) = self.check_seed(seed) {
+                return Some(failure)
+            }
+        }
+        None
+    }
+
+    /// Check the sample with seed `seed` with every `WriteOptions`, shrinking
+    /// the first failure.
+    pub fn check_seed(&self, seed: u32) -> Option<Failure> {
+        let mut rng = rng(seed);
+        let ast = Picker.random(self.spec, &mut rng, self.depth);
+        for options in &self.options {
+            let stage = match roundtrip(self.spec, &ast, options) {
+                Ok(()) => continue,
+                Err(err) => err.stage
+            };
+            debug!(target: "roundtrip", "Sample {} fails with {:?}, shrinking", seed, options);
+
+            // Only accept candidates that fail in the same way, to avoid
+            // drifting towards an unrelated failure.
+            let (shrunk, _) = self.shrink(&ast, &mut rng, |candidate| {
+                match roundtrip(self.spec, candidate, options) {
+                    Err(ref err) if err.stage == stage => true,
+                    _ => false
+                }
+            });
+            let error = roundtrip(self.spec, &shrunk, options)
+                .expect_err("The shrunk AST should still fail");
+            return Some(Failure {
+                seed,
+                options: options.clone(),
+                error,
+                original_size: size(&ast),
+                ast: shrunk,
+            })
+        }
+        None
+    }
+
+    /// Shrink an AST for which `fails` holds, by removing list items and
+    /// replacing subtrees with smaller picks. Returns the smallest AST found
+    /// for which `fails` still holds, and the number of steps taken.
+    pub fn shrink<R, F>(&self, ast: &JSON, rng: &mut R, mut fails: F) -> (JSON, usize)
+        where R: Rng,
+              F: FnMut(&JSON) -> bool
+    {
+        let mut current = ast.clone();
+        let mut steps = 0;
+        'shrink: while steps < self.max_shrink_steps {
+            let mut sites = vec![];
+            self.collect_type_spec(&current, &TypeSpec::NamedType(self.spec.get_root_name().clone()), &mut vec![], &mut sites);
+
+            // Removing list items is the most predictable way to shrink, try it first.
+            let (removals, replacements): (Vec<_>, Vec<_>) = sites.into_iter()
+                .partition(|site| match *site {
+                    Site::Remove(..) => true,
+                    Site::Replace(..) => false
+                });
+            let current_size = size(&current);
+            for site in removals.into_iter().chain(replacements.into_iter()) {
+                let mut candidate = current.clone();
+                match site {
+                    Site::Remove(ref path, index) => {
+                        if let JSON::Array(ref mut items) = *Self::at_mut(&mut candidate, path) {
+                            items.remove(index);
+                        }
+                    }
+                    Site::Replace(ref path, ref type_) => {
+                        *Self::at_mut(&mut candidate, path) = type_.random(self.spec, rng, 0);
+                    }
+                }
+                if size(&candidate) >= current_size {
+                    continue;
+                }
+                if fails(&candidate) {
+                    current = candidate;
+                    steps += 1;
+                    continue 'shrink;
+                }
+            }
+            break;
+        }
+        (current, steps)
+    }
+
+    fn at_mut<'b>(value: &'b mut JSON, path: &[Step]) -> &'b mut JSON {
+        let mut current = value;
+        for step in path {
+            let parent = current;
+            current = match *step {
+                Step::Field(ref name) => &mut parent[name.as_str()],
+                Step::Index(index) => &mut parent[index],
+            };
+        }
+        current
+    }
+
+    fn collect_value(&self, value: &JSON, type_: &Type, path: &mut Vec<Step>, sites: &mut Vec<Site>) {
+        match *value {
+            JSON::Array(_) | JSON::Object(_) => sites.push(Site::Replace(path.clone(), type_.clone())),
+            _ => {}
+        }
+        self.collect_type_spec(value, type_.spec(), path, sites)
+    }
+
+    fn collect_type_spec(&self, value: &JSON, spec: &TypeSpec, path: &mut Vec<Step>, sites: &mut Vec<Site>) {
+        match (spec, value) {
+            (&TypeSpec::Array { supports_empty, contents: ref type_ }, &JSON::Array(ref items)) => {
+                for (index, item) in items.iter().enumerate() {
+                    if supports_empty || items.len() > 1 {
+                        sites.push(Site::Remove(path.clone(), index));
+                    }
+                    path.push(Step::Index(index));
+                    self.collect_value(item, type_, path, sites);
+                    path.pop();
+                }
+            }
+            (&TypeSpec::NamedType(ref name), _) => {
+                match self.spec.get_type_by_name(name) {
+                    Some(NamedType::Interface(ref interface)) =>
+                        self.collect_fields(interface, value, path, sites),
+                    Some(NamedType::Typedef(ref type_)) =>
+                        self.collect_type_spec(value, type_.spec(), path, sites),
+                    _ => {}
+                }
+            }
+            (&TypeSpec::TypeSum(_), &JSON::Object(_)) => {
+                // The actual interface is determined by the value.
+                let interface = value["type"].as_str()
+                    .and_then(|name| self.spec.get_node_name(name))
+                    .and_then(|name| self.spec.get_interface_by_name(name));
+                if let Some(interface) = interface {
+                    self.collect_fields(interface, value, path, sites)
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn collect_fields(&self, interface: &Interface, value: &JSON, path: &mut Vec<Step>, sites: &mut Vec<Site>) {
+        for field in interface.contents().fields() {
+            let name = field.name().to_str();
+            path.push(Step::Field(name.to_string()));
+            self.collect_value(&value[name], field.type_(), path, sites);
+            path.pop();
+        }
+    }
+}
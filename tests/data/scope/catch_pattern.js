@@ -0,0 +1,6 @@
+// `eval` is bound by the pattern, so this is not a direct eval.
+try {
+
+} catch ({message, eval}) {
+    eval("foo");
+}
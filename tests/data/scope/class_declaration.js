@@ -0,0 +1,6 @@
+// `Foo` is a lexical declaration, captured by its own method.
+class Foo {
+    bar() {
+        return Foo;
+    }
+}
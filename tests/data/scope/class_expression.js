@@ -0,0 +1,8 @@
+// The name `C` of the class expression is bound only within the class,
+// so the toplevel `C` is not captured.
+var C = 1;
+var D = class C {
+    m() {
+        return C;
+    }
+};
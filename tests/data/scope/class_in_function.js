@@ -0,0 +1,5 @@
+// `C` is lexically declared in the body of `f`, not a parameter.
+function f() {
+    class C {
+    }
+}
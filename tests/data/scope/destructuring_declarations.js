@@ -0,0 +1,3 @@
+// Patterns in `var` and `let` declarations.
+var {x, y: [z]} = o;
+let [u, ...v] = o;
@@ -0,0 +1,3 @@
+// All names introduced by patterns are parameters of `f`.
+function f({a, b: [c, d = a]}, e = 1, ...rest) {
+}
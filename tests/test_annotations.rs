@@ -1,6 +1,173 @@
-fn main() {
-    unimplemented!()
+//! Parse and annotate samples, ensure that the scope annotations are correct.
+
+extern crate binjs;
+#[macro_use]
+extern crate json;
+
+use binjs::generic::*;
+use binjs::source::*;
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use json::JsonValue as JSON;
+
+/// Parse and annotate a sample from `tests/data/scope`.
+fn annotate_sample(name: &str) -> Script {
+    let path = format!("{}/tests/data/scope/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let ast = Shift::new()
+        .parse_file(path)
+        .expect("Could not parse source");
+    let mut script = Script::import(&ast)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_script(&mut script);
+    script
+}
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[test]
+fn test_class_declaration() {
+    let script = annotate_sample("class_declaration.js");
+    let scope = script.scope.expect("Missing toplevel scope");
+    assert_eq!(scope.lexically_declared_names, names(&["Foo"]));
+    assert_eq!(scope.var_declared_names, names(&[]));
+    assert_eq!(scope.captured_names, names(&["Foo"]));
+    assert!(!scope.has_direct_eval);
 }
+
+#[test]
+fn test_class_in_function() {
+    let script = annotate_sample("class_in_function.js");
+    if let Statement::EagerFunctionDeclaration(ref f) = script.statements[0] {
+        assert!(f.parameter_scope.is_none());
+        let scope = f.body_scope.as_ref().expect("Missing body scope");
+        assert_eq!(scope.lexically_declared_names, names(&["C"]));
+        assert_eq!(scope.var_declared_names, names(&[]));
+    } else {
+        panic!("Expected a function declaration");
+    }
+}
+
+#[test]
+fn test_class_expression() {
+    let script = annotate_sample("class_expression.js");
+    let scope = script.scope.expect("Missing toplevel scope");
+    assert_eq!(scope.lexically_declared_names, names(&[]));
+    assert_eq!(scope.var_declared_names, names(&["C", "D"]));
+    assert_eq!(scope.captured_names, names(&[]));
+}
+
+#[test]
+fn test_destructuring_params() {
+    let script = annotate_sample("destructuring_params.js");
+    if let Statement::EagerFunctionDeclaration(ref f) = script.statements[0] {
+        let scope = f.parameter_scope.as_ref().expect("Missing parameter scope");
+        assert_eq!(scope.parameter_names, names(&["a", "c", "d", "e", "rest"]));
+        assert_eq!(scope.captured_names, names(&[]));
+    } else {
+        panic!("Expected a function declaration");
+    }
+}
+
+#[test]
+fn test_destructuring_declarations() {
+    let script = annotate_sample("destructuring_declarations.js");
+    let scope = script.scope.expect("Missing toplevel scope");
+    assert_eq!(scope.var_declared_names, names(&["x", "z"]));
+    assert_eq!(scope.lexically_declared_names, names(&["u", "v"]));
+}
+
+#[test]
+fn test_catch_pattern() {
+    let script = annotate_sample("catch_pattern.js");
+    if let Statement::TryCatchStatement(ref statement) = script.statements[0] {
+        // `eval` is bound by the pattern, so the call is not a direct eval.
+        let scope = statement.catch_clause.body.scope.as_ref()
+            .expect("Missing catch body scope");
+        assert!(!scope.has_direct_eval);
+    } else {
+        panic!("Expected a try/catch statement");
+    }
+}
+
+#[test]
+fn test_module_imports() {
+    // Shift only parses scripts, so build the module by hand, for
+    // import a, { b as c } from "foo";
+    // import * as ns from "bar";
+    // function f() { return a; }
+    let binding = |name: &str| object!{
+        "type" => "BindingIdentifier",
+        "name" => name
+    };
+    let ast = object!{
+        "type" => "Module",
+        "scope" => JSON::Null,
+        "directives" => array![],
+        "items" => array![
+            object!{
+                "type" => "Import",
+                "moduleSpecifier" => "foo",
+                "defaultBinding" => binding("a"),
+                "namedImports" => array![
+                    object!{
+                        "type" => "ImportSpecifier",
+                        "name" => "b",
+                        "binding" => binding("c")
+                    }
+                ]
+            },
+            object!{
+                "type" => "ImportNamespace",
+                "moduleSpecifier" => "bar",
+                "defaultBinding" => JSON::Null,
+                "namespaceBinding" => binding("ns")
+            },
+            object!{
+                "type" => "EagerFunctionDeclaration",
+                "isAsync" => false,
+                "isGenerator" => false,
+                "parameterScope" => JSON::Null,
+                "bodyScope" => JSON::Null,
+                "name" => binding("f"),
+                "params" => object!{
+                    "type" => "FormalParameters",
+                    "items" => array![],
+                    "rest" => JSON::Null
+                },
+                "body" => object!{
+                    "type" => "FunctionBody",
+                    "directives" => array![],
+                    "statements" => array![
+                        object!{
+                            "type" => "ReturnStatement",
+                            "expression" => object!{
+                                "type" => "IdentifierExpression",
+                                "name" => "a"
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    };
+    let mut module = Module::import(&ast)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_module(&mut module);
+
+    // Imports and toplevel functions are lexical declarations of the module.
+    let scope = module.scope.expect("Missing toplevel scope");
+    assert_eq!(scope.lexically_declared_names, names(&["a", "c", "f", "ns"]));
+    assert_eq!(scope.var_declared_names, names(&[]));
+    assert_eq!(scope.captured_names, names(&["a"]));
+}
+
 /* FIXME: Refactoring
 
 
@@ -0,0 +1,47 @@
+extern crate binjs;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::syntax::*;
+use binjs::meta::spec::*;
+use binjs::source::*;
+
+test!(test_comparator_mismatches, {
+    let parser = Shift::new();
+    let mut spec_builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut spec_builder);
+    let spec = spec_builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    let left = parser.parse_str("function foo(x) { return x + 1; }")
+        .expect("Could not parse source");
+    let right = parser.parse_str("function foo(x) { return x + 2; }")
+        .expect("Could not parse source");
+
+    let mismatches = Comparator::mismatches(&spec, &left, &right, &Tolerances::default())
+        .expect("Could not compare ASTs");
+    assert_eq!(mismatches.len(), 1);
+    let mismatch = &mismatches[0];
+    println!("Mismatch: {}", mismatch);
+    assert_eq!(mismatch.json_path, ".statements[0].body.statements[0].expression.right.value");
+    assert_eq!(mismatch.field.as_ref().map(|field| field.to_str()), Some("value"));
+    assert_eq!(mismatch.left.as_f64(), Some(1.));
+    assert_eq!(mismatch.right.as_f64(), Some(2.));
+
+    // Scope annotations may be ignored.
+    let mut annotated = left.clone();
+    library.annotate(&mut annotated);
+    assert!(Comparator::mismatches(&spec, &left, &annotated, &Tolerances::default())
+        .expect("Could not compare ASTs")
+        .len() > 0);
+    let tolerances = Tolerances {
+        ignore_scopes: true,
+        .. Tolerances::default()
+    };
+    assert_eq!(Comparator::mismatches(&spec, &left, &annotated, &tolerances)
+        .expect("Could not compare ASTs")
+        .len(), 0);
+});
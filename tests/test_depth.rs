@@ -0,0 +1,244 @@
+//! Decoding deeply nested ASTs.
+
+extern crate binjs;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::io::decode::{ Decoder, Error };
+use binjs::generic::util::drop_json;
+use binjs::io::{ Deserialization, TokenReaderError };
+use binjs::io::bytes::compress::Compression;
+use binjs::io::bytes::varnum::WriteVarNum;
+use binjs::io::multipart::{ FeatureFlags, ReadLimits, TreeTokenReader, FORMAT_VERSION };
+use binjs::meta::spec::{ SpecBuilder, SpecOptions, ToStr };
+use binjs::specialized::es6::ast::{ Expression, Script, Statement };
+use binjs::specialized::es6::io::{ Deserializer, drop_expression };
+
+use std::io::Cursor;
+
+/// Build a multipart container with uncompressed sections.
+///
+/// `tree` is a sequence of indices in the grammar table or in the strings table.
+fn container(kinds: &[&str], strings: &[&str], tree: &[u32]) -> Vec<u8> {
+    let mut grammar_table = vec![];
+    grammar_table.write_varnum(kinds.len() as u32).unwrap();
+    for kind in kinds {
+        grammar_table.write_varnum(kind.len() as u32).unwrap();
+        grammar_table.extend_from_slice(kind.as_bytes());
+    }
+
+    // The strings table starts with the byte length of each entry.
+    let entries : Vec<Vec<u8>> = strings.iter()
+        .map(|string| {
+            let mut entry = vec![];
+            entry.write_varnum(string.len() as u32).unwrap();
+            entry.extend_from_slice(string.as_bytes());
+            entry
+        })
+        .collect();
+    let mut strings_table = vec![];
+    strings_table.write_varnum(entries.len() as u32).unwrap();
+    for entry in &entries {
+        strings_table.write_varnum(entry.len() as u32).unwrap();
+    }
+    for entry in &entries {
+        strings_table.extend_from_slice(entry);
+    }
+
+    let mut tree_section = vec![];
+    for index in tree {
+        tree_section.write_varnum(*index).unwrap();
+    }
+
+    let mut result = b"BINJS".to_vec();
+    result.write_varnum(FORMAT_VERSION).unwrap();
+    result.write_varnum(FeatureFlags::NONE.0).unwrap();
+    for &(header, ref data) in &[("[GRAMMAR]", grammar_table), ("[STRINGS]", strings_table), ("[TREE]", tree_section)] {
+        result.extend_from_slice(header.as_bytes());
+        Compression::Identity.compress(data, &mut result).unwrap();
+    }
+    result
+}
+
+const SCRIPT: u32 = 0;
+const NULL: u32 = 1;
+const EXPRESSION_STATEMENT: u32 = 2;
+const BINARY_EXPRESSION: u32 = 3;
+const UNARY_EXPRESSION: u32 = 4;
+const IDENTIFIER_EXPRESSION: u32 = 5;
+
+const PLUS: u32 = 0;
+const X: u32 = 1;
+
+/// A script containing a single expression statement, whose expression is
+/// given as a sequence of indices.
+fn script(null_name: &str, expression: &[u32]) -> Vec<u8> {
+    let mut tree = vec![
+        SCRIPT,
+        NULL,                 // scope
+        0,                    // directives
+        1,                    // statements
+        EXPRESSION_STATEMENT,
+    ];
+    tree.extend_from_slice(expression);
+    container(&["Script", null_name, "ExpressionStatement", "BinaryExpression", "UnaryExpression", "IdentifierExpression"],
+        &["+", "x"],
+        &tree)
+}
+
+/// `+ + + ... + x`, with `depth` operators.
+fn unary_chain(depth: usize) -> Vec<u32> {
+    let mut expression = vec![];
+    for _ in 0..depth {
+        expression.push(UNARY_EXPRESSION);
+        expression.push(PLUS);
+    }
+    expression.push(IDENTIFIER_EXPRESSION);
+    expression.push(X);
+    expression
+}
+
+/// `x + x + ... + x`, with `terms` terms. This is a left-deep tree.
+fn binary_chain(terms: usize) -> Vec<u32> {
+    let mut expression = vec![];
+    for _ in 1..terms {
+        expression.push(BINARY_EXPRESSION);
+        expression.push(PLUS);
+    }
+    expression.push(IDENTIFIER_EXPRESSION);
+    expression.push(X);
+    for _ in 1..terms {
+        expression.push(IDENTIFIER_EXPRESSION);
+        expression.push(X);
+    }
+    expression
+}
+
+/// Limits of the reader that accept any depth, so that we test the deserializers.
+fn limits() -> ReadLimits {
+    ReadLimits {
+        max_depth: std::usize::MAX,
+        ..ReadLimits::default()
+    }
+}
+
+test!(test_deserializer_max_depth, {
+    let data = script("", &unary_chain(100));
+
+    let reader = TreeTokenReader::with_limits(Cursor::new(data.clone()), limits())
+        .expect("Could not read container");
+    let mut deserializer = Deserializer::with_max_depth(reader, 50);
+    let result : Result<Script, _> = deserializer.deserialize();
+    match result {
+        Err(TokenReaderError::TooDeep { max: 50 }) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    let reader = TreeTokenReader::with_limits(Cursor::new(data), limits())
+        .expect("Could not read container");
+    let mut deserializer = Deserializer::with_max_depth(reader, 200);
+    let result : Result<Script, _> = deserializer.deserialize();
+    result.expect("Could not decode script");
+});
+
+test!(test_decoder_max_depth, {
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+    let data = script(spec.get_null_name().to_str(), &unary_chain(100));
+
+    let reader = TreeTokenReader::with_limits(Cursor::new(data.clone()), limits())
+        .expect("Could not read container");
+    let mut decoder = Decoder::with_max_depth(&spec, reader, 50);
+    match decoder.decode() {
+        Err(Error::TokenReaderError(TokenReaderError::TooDeep { max: 50 })) => {},
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    let reader = TreeTokenReader::with_limits(Cursor::new(data), limits())
+        .expect("Could not read container");
+    let mut decoder = Decoder::with_max_depth(&spec, reader, 200);
+    decoder.decode()
+        .expect("Could not decode script");
+});
+
+test!(test_deserializer_long_binary_chain, {
+    const TERMS: usize = 100_000;
+    let data = script("", &binary_chain(TERMS));
+
+    let reader = TreeTokenReader::with_limits(Cursor::new(data), limits())
+        .expect("Could not read container");
+    let mut deserializer = Deserializer::new(reader);
+    let result : Result<Script, _> = deserializer.deserialize();
+    let mut script = result.expect("Could not decode script");
+
+    let statement = script.statements.pop()
+        .expect("Missing statement");
+    let expression = match statement {
+        Statement::ExpressionStatement(statement) => {
+            let statement = *statement;
+            statement.expression
+        }
+        _ => panic!("Unexpected statement")
+    };
+
+    // Count the terms along the left spine.
+    let mut terms = 1;
+    {
+        let mut current = &expression;
+        while let Expression::BinaryExpression(ref binary) = *current {
+            match binary.right {
+                Expression::IdentifierExpression(_) => {},
+                _ => panic!("Unexpected right operand")
+            }
+            terms += 1;
+            current = &binary.left;
+        }
+        match *current {
+            Expression::IdentifierExpression(ref identifier) => assert_eq!(identifier.name, "x"),
+            _ => panic!("Unexpected left operand")
+        }
+    }
+    assert_eq!(terms, TERMS);
+
+    // Dropping `expression` recursively would overflow the stack.
+    drop_expression(expression);
+});
+
+test!(test_decoder_long_binary_chain, {
+    const TERMS: usize = 100_000;
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+    let data = script(spec.get_null_name().to_str(), &binary_chain(TERMS));
+
+    let reader = TreeTokenReader::with_limits(Cursor::new(data), limits())
+        .expect("Could not read container");
+    let mut decoder = Decoder::new(&spec, reader);
+    let script = decoder.decode()
+        .expect("Could not decode script");
+
+    // Count the terms along the left spine.
+    let mut terms = 1;
+    {
+        let mut current = &script["statements"][0]["expression"];
+        while current["type"] == "BinaryExpression" {
+            assert_eq!(current["right"]["type"], "IdentifierExpression");
+            terms += 1;
+            current = &current["left"];
+        }
+        assert_eq!(current["type"], "IdentifierExpression");
+        assert_eq!(current["name"], "x");
+    }
+    assert_eq!(terms, TERMS);
+
+    // Dropping `script` recursively would overflow the stack.
+    drop_json(script);
+});
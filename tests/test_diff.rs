@@ -0,0 +1,58 @@
+extern crate binjs;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::diff::*;
+use binjs::meta::spec::*;
+use binjs::source::*;
+
+test!(test_diff_statements, {
+    let parser = Shift::new();
+    let mut spec_builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut spec_builder);
+    let spec = spec_builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    let diff = |left: &str, right: &str| {
+        let left = parser.parse_str(left)
+            .expect("Could not parse source");
+        let right = parser.parse_str(right)
+            .expect("Could not parse source");
+        Diff::diff(&spec, &left, &right)
+            .expect("Could not diff ASTs")
+    };
+
+    // Identical sources.
+    assert_eq!(diff("var x; f(x);", "var x; f(x);").len(), 0);
+
+    // Insertion in the middle of a list.
+    let changes = diff("a(); c();", "a(); b(); c();");
+    assert_eq!(changes.len(), 1);
+    match changes[0] {
+        Change::Insert { index: 1, ref value, .. } =>
+            assert_eq!(value["type"].as_str(), Some("ExpressionStatement")),
+        ref other => panic!("Unexpected change {}", other)
+    }
+
+    // Deletion.
+    let changes = diff("a(); b(); c();", "a(); c();");
+    assert_eq!(changes.len(), 1);
+    match changes[0] {
+        Change::Delete { index: 1, .. } => {},
+        ref other => panic!("Unexpected change {}", other)
+    }
+
+    // Update deep within a node.
+    let changes = diff("a(1); b();", "a(2); b();");
+    assert_eq!(changes.len(), 1);
+    match changes[0] {
+        Change::Update { ref before, ref after, .. } => {
+            assert_eq!(before.as_f64(), Some(1.));
+            assert_eq!(after.as_f64(), Some(2.));
+        }
+        ref other => panic!("Unexpected change {}", other)
+    }
+});
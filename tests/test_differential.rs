@@ -0,0 +1,62 @@
+//! Check that the generic and typed encoders and decoders agree, on the
+//! samples of `tests/data` and on random ASTs.
+
+extern crate binjs;
+extern crate glob;
+extern crate rand;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::pick::{ Pick, Picker };
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::source::{ Shift, SourceParser };
+use binjs::util::differential::Harness;
+
+use rand::{ SeedableRng, XorShiftRng };
+
+const RANDOM_SAMPLES: usize = 50;
+const RANDOM_DEPTH: isize = 5;
+
+test!(test_differential, {
+    let parser = Shift::new();
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+    let harness = Harness::new(&spec);
+
+    let mut failures = 0;
+
+    for pattern in &["tests/data/spidermonkey/ecma_2/**/*.js", "tests/data/scope/*.js"] {
+        let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), pattern);
+        for entry in glob::glob(&path)
+            .expect("Invalid glob pattern")
+        {
+            let entry = entry.expect("Invalid entry");
+            println!("Checking {:?}", entry);
+            let mut ast = parser.parse_file(entry.clone())
+                .expect("Could not parse source");
+            library.annotate(&mut ast);
+            for disagreement in harness.check(&ast) {
+                println!("{:?}: {}", entry, disagreement);
+                failures += 1;
+            }
+        }
+    }
+
+    // Use a fixed seed, so that failures may be reproduced.
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    for i in 0..RANDOM_SAMPLES {
+        let mut ast = Picker.random(&spec, &mut rng, RANDOM_DEPTH);
+        library.annotate(&mut ast);
+        for disagreement in harness.check(&ast) {
+            println!("Random sample {}: {}\n{}", i, disagreement, ast.pretty(2));
+            failures += 1;
+        }
+    }
+
+    assert_eq!(failures, 0);
+});
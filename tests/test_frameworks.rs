@@ -0,0 +1,101 @@
+//! Compare the size of variants of the multipart format on the samples of
+//! `tests/data/frameworks`, checking that each variant round-trips.
+
+extern crate binjs;
+extern crate glob;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::*;
+use binjs::io::*;
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{ TreeTokenReader, TreeTokenWriter, WriteOptions };
+use binjs::source::*;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{ Deserializer, Serializer };
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use std::io::Cursor;
+
+/// Whether the variant of the format named `name`, see `WriteOptions::variants`,
+/// is expected to be smaller than its base without compression.
+fn expected_smaller(name: &str) -> bool {
+    match name {
+        // Columns only reorder bytes, for the benefit of compressors.
+        "columns" => false,
+        // String tables add headers, but each index is taken in a smaller table.
+        "string tables" => false,
+        // Reordering fields only adds field names to the grammar table.
+        "scopes last" => false,
+        // LEB128 takes exactly as many bytes as varnums, prefix varints are faster
+        // to decode but may take more bytes.
+        "leb128" | "prefix varint" => false,
+        // Free names take an extra byte, which references to declared names don't always make up for.
+        "scope names" => false,
+        _ => true
+    }
+}
+
+/// Encode `ast` with `options`, check that it decodes to `ast` and return
+/// the number of bytes.
+fn encode(ast: &Script, options: &WriteOptions) -> usize {
+    let writer = TreeTokenWriter::new(options.clone());
+    let mut serializer = Serializer::new(writer);
+    serializer.serialize(ast)
+        .expect("Could not encode AST");
+    let (data, _) = serializer.done()
+        .expect("Could not finalize AST encoding");
+
+    let reader = TreeTokenReader::new(Cursor::new(&data))
+        .expect("Could not decode AST container");
+    let mut deserializer = Deserializer::new(reader);
+    let decoded : Script = deserializer.deserialize()
+        .expect("Could not decode");
+    assert_eq!(ast, &decoded, "Round trip failed with {:?}", options);
+    data.len()
+}
+
+test!(test_frameworks, {
+    let parser = Shift::new();
+
+    let path = format!("{}/tests/data/frameworks/*.js", env!("CARGO_MANIFEST_DIR"));
+    for entry in glob::glob(&path)
+        .expect("Invalid glob pattern")
+    {
+        let entry = entry.expect("Invalid entry");
+        println!("Parsing {:?}.", entry);
+        let json = parser.parse_file(entry.clone())
+            .expect("Could not parse source");
+        let mut ast = Script::import(&json)
+            .expect("Could not import AST");
+        AnnotationVisitor::new()
+            .annotate_script(&mut ast);
+
+        for compression in &[Compression::Identity, Compression::Brotli] {
+            let base = WriteOptions {
+                grammar_table: compression.clone(),
+                strings_table: compression.clone(),
+                tree: compression.clone(),
+                .. WriteOptions::default()
+            };
+            let reference = encode(&ast, &base);
+            println!("{:?} with {:?}: {} bytes", entry, compression, reference);
+            for (name, options) in base.variants() {
+                let bytes = encode(&ast, &options);
+                println!("{:?} with {:?} and {}: {} bytes ({:.2}%)",
+                    entry,
+                    compression,
+                    name,
+                    bytes,
+                    100. * (bytes as f64) / (reference as f64));
+
+                // Compressors may hide some of the gains, but without compression,
+                // most variants are expected to be smaller.
+                if expected_smaller(name) && *compression == Compression::Identity {
+                    assert!(bytes < reference, "{} should be smaller than the reference", name);
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,40 @@
+//! Replay the inputs that used to crash the fuzz targets.
+
+extern crate binjs;
+extern crate glob;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::util::fuzz::TARGETS;
+
+use std::fs::File;
+use std::io::Read;
+
+test!(test_fuzz_regressions, {
+    for &(target, harness) in TARGETS.iter() {
+        let path = format!("{}/fuzz/regressions/{}/*", env!("CARGO_MANIFEST_DIR"), target);
+        for entry in glob::glob(&path)
+            .expect("Invalid glob pattern")
+        {
+            let entry = entry.expect("Invalid entry");
+            println!("Replaying {:?}", entry);
+            let mut data = vec![];
+            File::open(&entry)
+                .expect("Could not open regression input")
+                .read_to_end(&mut data)
+                .expect("Could not read regression input");
+            harness(&data);
+        }
+    }
+});
+
+test!(test_fuzz_split_ops, {
+    use binjs::util::fuzz::split_ops;
+
+    assert_eq!(split_ops(&[]), (&[] as &[u8], &[] as &[u8]));
+    assert_eq!(split_ops(&[2, 4, 7, 1, 2]), (&[4, 7] as &[u8], &[1, 2] as &[u8]));
+
+    // Fewer operations than announced.
+    assert_eq!(split_ops(&[5, 4]), (&[4] as &[u8], &[] as &[u8]));
+});
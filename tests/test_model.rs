@@ -0,0 +1,93 @@
+//! Learn a model from a corpus, then generate random ASTs from it.
+
+extern crate binjs;
+extern crate glob;
+extern crate rand;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::io::encode::{ Encode, Encoder };
+use binjs::generic::pick::{ Model, ModelPicker, Pick };
+use binjs::generic::pick::model::Distribution;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::source::{ Shift, SourceParser };
+
+use rand::{ SeedableRng, XorShiftRng };
+
+use std::io::Cursor;
+
+const SAMPLES: usize = 20;
+const DEPTH: isize = 5;
+
+test!(test_model_distribution, {
+    let mut distribution = Distribution::default();
+    distribution.add("a".to_string());
+    distribution.add_n("b".to_string(), 3);
+    distribution.add_n("c".to_string(), 2);
+    assert_eq!(distribution.total(), 6);
+
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    for _ in 0..100 {
+        let sampled = distribution.sample(&mut rng, |value| value != "b")
+            .expect("Could not sample");
+        assert!(sampled == "a" || sampled == "c");
+    }
+    assert_eq!(distribution.sample(&mut rng, |_| false), None);
+
+    distribution.truncate(2);
+    assert_eq!(distribution.count(&"a".to_string()), 0);
+    assert_eq!(distribution.total(), 5);
+});
+
+test!(test_model_learn, {
+    let parser = Shift::new();
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    let mut model = Model::new();
+    let path = format!("{}/tests/data/scope/*.js", env!("CARGO_MANIFEST_DIR"));
+    for entry in glob::glob(&path)
+        .expect("Invalid glob pattern")
+    {
+        let entry = entry.expect("Invalid entry");
+        let mut ast = parser.parse_file(entry)
+            .expect("Could not parse source");
+        library.annotate(&mut ast);
+        model.learn(&ast);
+    }
+
+    // The corpus only contains scripts.
+    assert!(model.root().kinds.total() > 0);
+    assert_eq!(model.root().kinds.count(&"Script".to_string()), model.root().kinds.total());
+    let statements = model.field("Script", "statements")
+        .expect("No model for Script.statements");
+    assert!(statements.lengths.total() > 0);
+
+    // Save and reload.
+    let mut saved = vec![];
+    model.save(&mut saved)
+        .expect("Could not save model");
+    let loaded = Model::load(Cursor::new(saved))
+        .expect("Could not load model");
+    assert_eq!(loaded, model);
+
+    // Sample from the model.
+    let picker = ModelPicker::new(&loaded);
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    for i in 0..SAMPLES {
+        let mut ast = picker.random(&spec, &mut rng, DEPTH);
+        assert_eq!(ast["type"].as_str(), Some("Script"), "Sample {}", i);
+        library.annotate(&mut ast);
+
+        let encoder = Encoder::new(&spec, binjs::io::simple::TreeTokenWriter::new());
+        encoder.encode(&ast)
+            .expect("Could not encode sample");
+        encoder.done()
+            .expect("Could not finalize encoding");
+    }
+});
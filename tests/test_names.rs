@@ -0,0 +1,88 @@
+//! Write identifiers relative to the scope annotations, ensure that we can read them.
+
+extern crate binjs;
+
+use binjs::io::*;
+use binjs::io::multipart::*;
+use binjs::source::*;
+use binjs::specialized::es6::ast::*;
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use std::io::Cursor;
+
+/// Parse and annotate a sample from `tests/data`.
+fn annotate_sample(name: &str) -> Script {
+    let path = format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let ast = Shift::new()
+        .parse_file(path)
+        .expect("Could not parse source");
+    let mut script = Script::import(&ast)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_script(&mut script);
+    script
+}
+
+/// Encode a script with the multipart format, return the data and the statistics.
+fn encode(script: &Script, options: WriteOptions) -> (Box<[u8]>, Statistics) {
+    let writer = TreeTokenWriter::new(options);
+    let mut serializer = binjs::specialized::es6::io::Serializer::new(writer);
+    serializer.serialize(script)
+        .expect("Could not encode AST");
+    serializer.done()
+        .expect("Could not finalize AST encoding")
+}
+
+fn decode(data: Box<[u8]>) -> Script {
+    let reader = TreeTokenReader::new(Cursor::new(data))
+        .expect("Could not read header");
+    let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+    deserializer.deserialize()
+        .expect("Could not decode")
+}
+
+#[test]
+fn test_names_roundtrip() {
+    let scope_names = WriteOptions {
+        scope_names: true,
+        .. WriteOptions::default()
+    };
+    for name in &["scope/var_in_fun_plus_arg.js",
+                  "scope/catch_collision.js",
+                  "scope/class_expression.js",
+                  "scope/destructuring_params.js",
+                  "scope/function_expression.js",
+                  "frameworks/moment.min.js"]
+    {
+        println!("Testing {}", name);
+        let original = annotate_sample(name);
+
+        // Combine scope names with each other feature, e.g. field order and dedup change which names are visible.
+        let mut variants = vec![("scope names", scope_names.clone())];
+        variants.extend(scope_names.variants());
+        for (variant, options) in variants {
+            let (_, stats) = encode(&original, WriteOptions {
+                scope_names: false,
+                .. options.clone()
+            });
+            let (data, scope_stats) = encode(&original, options);
+            assert!(scope_stats.strings_table.entries <= stats.strings_table.entries,
+                "Scope names should not add strings in {} with {}", name, variant);
+            assert_eq!(decode(data), original, "Could not read back {} with {}", name, variant);
+        }
+    }
+}
+
+#[test]
+fn test_names_references() {
+    let original = annotate_sample("frameworks/moment.min.js");
+    let (_, stats) = encode(&original, WriteOptions {
+        scope_names: true,
+        .. WriteOptions::default()
+    });
+    assert!(stats.name.entries > 0);
+
+    // Names declared by scope annotations are only written once, as declarations.
+    let (_, reference) = encode(&original, WriteOptions::default());
+    assert!(stats.string.entries < reference.string.entries);
+}
@@ -0,0 +1,57 @@
+//! Check that `ValidPicker` generates valid JavaScript.
+
+extern crate binjs;
+extern crate rand;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::pick::{ Pick, ValidPicker };
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::source::{ Shift, SourceParser };
+
+use rand::{ SeedableRng, XorShiftRng };
+
+const SAMPLES: usize = 20;
+const DEPTH: isize = 5;
+
+test!(test_pick_valid, {
+    let parser = Shift::new();
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    // Use a fixed seed, so that failures may be reproduced.
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    for i in 0..SAMPLES {
+        let ast = ValidPicker.random(&spec, &mut rng, DEPTH);
+
+        // Annotations are consistent.
+        let mut annotated = ast.clone();
+        library.annotate(&mut annotated);
+        assert_eq!(ast, annotated, "Sample {} has inconsistent annotations", i);
+
+        // The source is accepted by a parser that checks early errors.
+        let source = parser.to_source(&spec, &ast)
+            .expect("Could not pretty-print AST");
+        if let Err(err) = parser.parse_str(&source) {
+            panic!("Sample {} is not valid: {:?}\n{}", i, err, source);
+        }
+    }
+});
+
+test!(test_pick_valid_reproducible, {
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    let left = ValidPicker.random(&spec, &mut XorShiftRng::from_seed([5, 6, 7, 8]), DEPTH);
+    let right = ValidPicker.random(&spec, &mut XorShiftRng::from_seed([5, 6, 7, 8]), DEPTH);
+    assert_eq!(left, right);
+});
@@ -38,10 +38,17 @@ fn test_roundtrip() {
                         grammar_table: grammar_table.clone(),
                         strings_table: strings_table.clone(),
                         tree: tree.clone(),
+                        .. WriteOptions::default()
                     });
                 }
             }
         }
+        // Optional features don't interact with the compression of other sections,
+        // one case per feature is sufficient.
+        vec.extend(WriteOptions::default()
+            .variants()
+            .into_iter()
+            .map(|(_, options)| options));
         vec
     };
 
@@ -95,6 +102,36 @@ fn test_roundtrip() {
             debug!(target: "test_roundtrip", "Completed simple round trip for {:?}", entry);
         }
 
+        {
+            progress();
+            debug!(target: "test_roundtrip", "Starting text round trip for {:?}", entry);
+
+            // Roundtrip `text`
+            debug!(target: "test_roundtrip", "Encoding");
+            let writer = binjs::io::text::TreeTokenWriter::new();
+            let mut serializer = binjs::specialized::es6::io::Serializer::new(writer);
+            serializer.serialize(&ast)
+                .expect("Could not encode AST");
+            let (data, _) = serializer.done()
+                .expect("Could not finalize AST encoding");
+
+            progress();
+            debug!(target: "test_roundtrip", "Decoding.");
+            let source = Cursor::new(data);
+            let reader = binjs::io::text::TreeTokenReader::new(source)
+                .expect("Could not parse text");
+            let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+
+            let decoded = deserializer.deserialize()
+                .expect("Could not decode");
+            progress();
+
+            debug!(target: "test_roundtrip", "Checking.");
+            assert_eq!(ast, decoded);
+
+            debug!(target: "test_roundtrip", "Completed text round trip for {:?}", entry);
+        }
+
         // Roundtrip `multipart`
 
         for options in &all_options {
@@ -0,0 +1,55 @@
+//! Round-trip random ASTs through each of `binjs::util::roundtrip::write_options()`.
+//!
+//! Set `BINJS_SEED` to check a single sample, e.g. to reproduce a failure.
+
+extern crate binjs;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::util::roundtrip::{ self, Runner };
+
+const SAMPLES: u32 = 20;
+
+test!(test_roundtrip_random, {
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+
+    let (first_seed, samples) = match std::env::var("BINJS_SEED") {
+        Ok(seed) => (seed.parse().expect("Invalid BINJS_SEED"), 1),
+        Err(_) => (0, SAMPLES)
+    };
+    if let Some(failure) = Runner::new(&spec).run(first_seed, samples) {
+        panic!("{}", failure);
+    }
+});
+
+test!(test_shrink, {
+    let mut builder = SpecBuilder::new();
+    let library = binjs::generic::es6::Library::new(&mut builder);
+    let spec = builder.into_spec(SpecOptions {
+        null: &library.null,
+        root: &library.program,
+    });
+    let runner = Runner::new(&spec);
+
+    // Find a sample with several statements.
+    let seed = (0..)
+        .find(|&seed| runner.generate(seed)["statements"].len() > 1)
+        .unwrap();
+    let ast = runner.generate(seed);
+
+    // Pretend that any script with a statement fails.
+    let (shrunk, steps) = runner.shrink(&ast, &mut roundtrip::rng(seed), |candidate| {
+        candidate["statements"].len() > 0
+    });
+    println!("Shrunk in {} steps:\n{}", steps, shrunk.pretty(2));
+    assert!(steps > 0);
+    assert_eq!(shrunk["statements"].len(), 1);
+    assert!(roundtrip::size(&shrunk) < roundtrip::size(&ast));
+});
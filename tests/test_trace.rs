@@ -0,0 +1,85 @@
+//! Record the tokens written and read while encoding and decoding an AST.
+
+extern crate binjs;
+
+#[macro_use]
+extern crate test_logger;
+
+use binjs::generic::FromJSON;
+use binjs::io::{ Deserialization, TokenSerializer };
+use binjs::io::trace::{ Token, Trace, TraceReader, TracingReader, TracingWriter };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{ Deserializer, Serializer };
+
+use std::io::Cursor;
+
+fn parse(parser: &Shift, source: &str) -> Script {
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    ast
+}
+
+/// Encode `ast` with the simple format, returning the data and the trace of the writer.
+fn encode(ast: &Script) -> (Vec<u8>, Trace) {
+    let writer = TracingWriter::new(binjs::io::simple::TreeTokenWriter::new());
+    let recorder = writer.recorder();
+    let mut serializer = Serializer::new(writer);
+    serializer.serialize(ast)
+        .expect("Could not encode AST");
+    let (data, _) = serializer.done()
+        .expect("Could not finalize AST encoding");
+    (data, recorder.trace())
+}
+
+test!(test_trace_roundtrip, {
+    let parser = Shift::new();
+    let ast = parse(&parser, "function foo(x, y) { return x + y * 2.5; } foo(1, 'a');");
+
+    let (data, written) = encode(&ast);
+    assert!(written.tokens.len() > 0);
+    match written.tokens[0] {
+        Token::TaggedTuple(ref kind) => assert_eq!(kind, "Script"),
+        ref other => panic!("Unexpected token {}", other)
+    }
+
+    let reader = TracingReader::new(binjs::io::simple::TreeTokenReader::new(Cursor::new(data)));
+    let recorder = reader.recorder();
+    let mut deserializer = Deserializer::new(reader);
+    let decoded : Script = deserializer.deserialize()
+        .expect("Could not decode AST");
+    assert_eq!(decoded, ast);
+
+    let read = recorder.trace();
+    assert_eq!(written.divergence(&read), None);
+
+    // Save the trace, load it and replay it.
+    let mut saved = vec![];
+    written.write(&mut saved)
+        .expect("Could not write trace");
+    let loaded = Trace::read(Cursor::new(saved))
+        .expect("Could not read trace");
+    assert_eq!(loaded, written);
+
+    let mut deserializer = Deserializer::new(TraceReader::new(loaded));
+    let replayed : Script = deserializer.deserialize()
+        .expect("Could not replay trace");
+    assert_eq!(replayed, ast);
+});
+
+test!(test_trace_divergence, {
+    let parser = Shift::new();
+    let (_, left) = encode(&parse(&parser, "x + 1;"));
+    let (_, right) = encode(&parse(&parser, "x + 2;"));
+
+    let divergence = left.divergence(&right)
+        .expect("Traces should differ");
+    assert_eq!(divergence.expected, Some(Token::Float(Some(1.))));
+    assert_eq!(divergence.found, Some(Token::Float(Some(2.))));
+    assert!(divergence.path.ends_with("BinaryExpression[2] > LiteralNumericExpression[0]"),
+        "Unexpected path {}", divergence.path);
+});